@@ -0,0 +1,51 @@
+use std::hint::black_box;
+use std::io;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use lox_bytecode_vm::{interpret, Value, VM};
+
+/// Compares the cached singletons against building the same values from scratch.
+fn singletons(c: &mut Criterion) {
+    let mut group = c.benchmark_group("singletons");
+    group.bench_function("boolean()", |b| {
+        b.iter(|| Value::boolean(black_box(true)).is_truthy())
+    });
+    group.bench_function("TRUE", |b| b.iter(|| black_box(Value::TRUE).is_truthy()));
+    group.bench_function("nil()", |b| b.iter(|| Value::nil().is_nil()));
+    group.bench_function("NIL", |b| b.iter(|| black_box(Value::NIL).is_nil()));
+    group.finish();
+}
+
+/// Compares building small integers through a float conversion and the cached encoding.
+fn small_ints(c: &mut Criterion) {
+    let mut group = c.benchmark_group("small_ints");
+    group.bench_function("number()", |b| {
+        b.iter(|| Value::number(black_box(42u8) as f64).as_number())
+    });
+    group.bench_function("small_int()", |b| {
+        b.iter(|| Value::small_int(black_box(42)).as_number())
+    });
+    group.finish();
+}
+
+/// Runs a literal heavy loop, where every literal used to be a constant pool load.
+fn literal_loop(c: &mut Criterion) {
+    let source = r#"
+        var i = 0;
+        var flag = false;
+        while (i < 10000) {
+            flag = !flag and true or nil;
+            i = i + 1;
+        }
+    "#;
+
+    c.bench_function("literal_loop", |b| {
+        b.iter(|| {
+            let mut vm = VM::new(Box::new(io::sink()));
+            interpret(black_box(source), &mut vm);
+        })
+    });
+}
+
+criterion_group!(benches, singletons, small_ints, literal_loop);
+criterion_main!(benches);
@@ -0,0 +1,62 @@
+//! Runs whole Lox programs through the public `interpret` API, so changes to
+//! dispatch, calls, or allocation show up as end-to-end timings. The method
+//! heavy "zoo" benchmark is left out until classes are supported.
+
+use std::hint::black_box;
+use std::io;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use lox_bytecode_vm::{frontend, interpret, try_interpret, VmOptions, VM};
+
+/// The benchmark programs, by name, from the `lox` directory next to this file.
+const PROGRAMS: &[(&str, &str)] = &[
+    ("fib", include_str!("lox/fib.lox")),
+    ("string_concat", include_str!("lox/string_concat.lox")),
+    ("closures", include_str!("lox/closures.lox")),
+];
+
+/// A VM like the `lox-bytecode-vm` binary makes, with the standard library.
+fn new_vm() -> VM<'static> {
+    let options = VmOptions {
+        load_stdlib: true,
+        ..VmOptions::default()
+    };
+    VM::with_options(Box::new(io::sink()), options)
+}
+
+/// Compiles and runs each program on a fresh VM, including stdlib setup.
+fn programs(c: &mut Criterion) {
+    // A program that stops with an error would only measure the error path
+    for (name, source) in PROGRAMS {
+        if let Err(outcome) = try_interpret(source, &mut new_vm()) {
+            let errors: Vec<_> = outcome.errors().iter().map(|e| e.to_string()).collect();
+            panic!("{name} failed: {}", errors.join("\n"));
+        }
+    }
+
+    let mut group = c.benchmark_group("programs");
+    // fib(30) alone takes a noticeable fraction of a second
+    group.sample_size(10);
+    for (name, source) in PROGRAMS {
+        group.bench_function(*name, |b| {
+            b.iter(|| {
+                let mut vm = new_vm();
+                interpret(black_box(source), &mut vm);
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Scans each program into tokens, which is dominated by identifiers and
+/// keywords.
+fn scanning(c: &mut Criterion) {
+    let mut group = c.benchmark_group("scan");
+    for (name, source) in PROGRAMS {
+        group.bench_function(*name, |b| b.iter(|| frontend::scan(black_box(source))));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, programs, scanning);
+criterion_main!(benches);
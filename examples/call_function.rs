@@ -0,0 +1,16 @@
+//! Defines a function in Lox and calls it from Rust.
+
+use std::io;
+
+use lox_bytecode_vm::{interpret, Value, VM};
+
+fn main() {
+    let mut vm = VM::new(Box::new(io::stdout()));
+
+    interpret("fun add(a, b) { return a + b; }", &mut vm);
+
+    match vm.call("add", &[Value::number(1.0), Value::number(2.0)]) {
+        Ok(sum) => println!("add(1, 2) = {}", sum.as_number()),
+        Err(e) => eprintln!("{e}"),
+    }
+}
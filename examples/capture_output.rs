@@ -0,0 +1,22 @@
+//! Collects everything a script prints into buffers instead of stdout and stderr,
+//! switching to a fresh output buffer for each evaluation.
+
+use lox_bytecode_vm::{interpret, VM};
+
+fn main() {
+    let mut first = Vec::new();
+    let mut second = Vec::new();
+    let mut errors = Vec::new();
+
+    let mut vm = VM::new(Box::new(&mut first));
+    vm.set_error_writer(Box::new(&mut errors));
+    interpret("var greeting = \"hello\"; print greeting;", &mut vm);
+
+    vm.set_writer(Box::new(&mut second));
+    interpret("print greeting + \" again\"; print undefined;", &mut vm);
+    drop(vm);
+
+    println!("first:\n{}", String::from_utf8_lossy(&first));
+    println!("second:\n{}", String::from_utf8_lossy(&second));
+    println!("errors:\n{}", String::from_utf8_lossy(&errors));
+}
@@ -0,0 +1,37 @@
+//! Plugs a host-provided time source into the `clock` and `clockMono` natives,
+//! as a wasm host would with `Date.now()` and `performance.now()`.
+
+use std::{
+    io,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use lox_bytecode_vm::{interpret, HostClock, Shared, VmOptions, VM};
+
+/// A clock that advances by one millisecond every time it is read.
+#[derive(Debug, Default)]
+struct SteppingClock {
+    millis: AtomicU64,
+}
+
+impl HostClock for SteppingClock {
+    fn wall(&self) -> f64 {
+        1_700_000_000.0 + self.monotonic()
+    }
+
+    fn monotonic(&self) -> f64 {
+        let millis = self.millis.fetch_add(1, Ordering::Relaxed) + 1;
+        millis as f64 / 1000.0
+    }
+}
+
+fn main() {
+    let options = VmOptions {
+        clock: Shared::new(SteppingClock::default()),
+        ..VmOptions::default()
+    };
+    let mut vm = VM::with_options(Box::new(io::stdout()), options);
+
+    // Prints 0.001 and 0.002.
+    interpret("print clockMono(); print clockMono();", &mut vm);
+}
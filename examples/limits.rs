@@ -0,0 +1,19 @@
+//! Runs an unbounded recursion under a small call depth limit.
+
+use std::io;
+
+use lox_bytecode_vm::{interpret, VmOptions, VM};
+
+fn main() {
+    let options = VmOptions {
+        max_frames: 16,
+        ..VmOptions::default()
+    };
+    let mut vm = VM::with_options(Box::new(io::stdout()), options);
+
+    // Reports a stack overflow after 16 nested calls instead of 64.
+    interpret(
+        "fun recurse(n) { print n; return recurse(n + 1); } recurse(1);",
+        &mut vm,
+    );
+}
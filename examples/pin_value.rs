@@ -0,0 +1,45 @@
+//! Registers a native that holds on to a `Value` between calls by pinning it.
+
+use std::{io, sync::Mutex};
+
+use lox_bytecode_vm::{interpret, Heap, Native, PinHandle, RuntimeError, Value, VM};
+
+/// Remembers its argument, returning the one it was given last time.
+#[derive(Default)]
+struct Remember(Mutex<Option<PinHandle>>);
+
+impl Native for Remember {
+    fn name(&self) -> &str {
+        "remember"
+    }
+
+    fn arity(&self) -> u8 {
+        1
+    }
+
+    fn doc(&self) -> &str {
+        "Remembers a value, returning the previous one."
+    }
+
+    fn call(&self, heap: &mut Heap, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let mut last = self.0.lock().unwrap();
+        // Nothing in the VM refers to the value once the call returns, so it
+        // must be pinned to stay alive
+        let previous = last.replace(heap.pin(args[0]));
+        Ok(previous.map_or(Value::nil(), |handle| heap.unpin(handle)))
+    }
+}
+
+fn main() {
+    let mut vm = VM::new(Box::new(io::stdout()));
+    vm.define_native(Remember::default());
+
+    interpret(
+        r#"
+        remember("first");
+        print remember("second"); // first
+        print remember(nil); // second
+        "#,
+        &mut vm,
+    );
+}
@@ -0,0 +1,29 @@
+//! Runs a script one pipeline stage at a time, inspecting the output of each.
+
+use std::io;
+
+use lox_bytecode_vm::{bytecode, frontend, runtime, VM};
+
+fn main() {
+    let source = "fun square(n) { return n * n; } print square(7);";
+    let mut vm = VM::new(Box::new(io::stdout()));
+
+    let tokens = frontend::scan(source).expect("scan failed");
+    println!("{} tokens", tokens.len());
+
+    let statements = frontend::parse(source, tokens).expect("parse failed");
+    println!("{} statements", statements.len());
+
+    let mut diagnostics = vec![];
+    let function = bytecode::compile(source, statements, vm.symbols_mut(), &mut diagnostics)
+        .expect("compile failed");
+    for diagnostic in diagnostics {
+        println!("{diagnostic}");
+    }
+    println!("{} bytes of top-level bytecode", function.chunk.code.len());
+
+    // Prints 49.
+    if let Err(e) = runtime::execute(function, &mut vm) {
+        eprintln!("{e}");
+    }
+}
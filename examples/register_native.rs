@@ -0,0 +1,38 @@
+//! Registers a Rust function as a Lox native and calls it from a script.
+
+use std::io;
+
+use lox_bytecode_vm::{interpret, Heap, Native, RuntimeError, Value, VM};
+
+/// Returns the larger of two numbers.
+struct Max;
+
+impl Native for Max {
+    fn name(&self) -> &str {
+        "max"
+    }
+
+    fn arity(&self) -> u8 {
+        2
+    }
+
+    fn doc(&self) -> &str {
+        "Returns the larger of two numbers."
+    }
+
+    fn call(&self, _heap: &mut Heap, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let (a, b) = (args[0], args[1]);
+        if a.is_number() && b.is_number() {
+            Ok(Value::number(a.as_number().max(b.as_number())))
+        } else {
+            Err(RuntimeError::OperandMismatch(0, "numbers".to_string()))
+        }
+    }
+}
+
+fn main() {
+    let mut vm = VM::new(Box::new(io::stdout()));
+    vm.define_native(Max);
+
+    interpret("print max(3, 7);", &mut vm);
+}
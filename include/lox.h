@@ -0,0 +1,43 @@
+/* C API of lox-bytecode-vm, built with `cargo build --release --features ffi`.
+ * See src/ffi.rs for the full documentation of each function. */
+
+#ifndef LOX_H
+#define LOX_H
+
+#include <stdbool.h>
+#include <stdint.h>
+
+typedef enum LoxStatus {
+    LOX_OK = 0,
+    LOX_COMPILE_ERROR = 1,
+    LOX_RUNTIME_ERROR = 2,
+    LOX_EXIT = 3,
+    LOX_INVALID_ARGUMENT = 4,
+} LoxStatus;
+
+typedef enum LoxValueTag {
+    LOX_NIL = 0,
+    LOX_BOOL = 1,
+    LOX_NUMBER = 2,
+} LoxValueTag;
+
+/* Booleans are stored in `number` as 0 or 1. */
+typedef struct LoxValue {
+    LoxValueTag tag;
+    double number;
+} LoxValue;
+
+/* Stores its result in `result`. Returning false stops the program with a
+ * runtime error. */
+typedef bool (*LoxNativeFn)(void *user_data, const LoxValue *args, uint8_t argc,
+                            LoxValue *result);
+
+typedef struct LoxVm LoxVm;
+
+LoxVm *lox_vm_new(void);
+LoxStatus lox_vm_interpret(LoxVm *vm, const char *source);
+LoxStatus lox_vm_register_native(LoxVm *vm, const char *name, uint8_t arity,
+                                 LoxNativeFn function, void *user_data);
+void lox_vm_free(LoxVm *vm);
+
+#endif
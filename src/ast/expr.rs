@@ -1,4 +1,6 @@
-use crate::core::token::Token;
+use crate::core::token::{Token, TokenType};
+
+use super::stmt::Stmt;
 
 /// Enum to represent different types of expressions in the AST.
 #[derive(Debug, Clone)]
@@ -16,6 +18,8 @@ pub enum Expr {
     Set(Box<Expr>, Token, Box<Expr>),
     This(Token),
     Super(Token, Token),
+    Yield(Token, Box<Expr>),
+    Lambda(Token, Vec<Token>, Vec<Stmt>),
 }
 
 /// A struct that visits `Expr`
@@ -33,9 +37,58 @@ pub trait ExprVisitor<T> {
     fn visit_set(&mut self, obj: Expr, prop: Token, value: Expr) -> T;
     fn visit_this(&mut self, token: Token) -> T;
     fn visit_super(&mut self, super_token: Token, prop: Token) -> T;
+    fn visit_yield(&mut self, token: Token, value: Expr) -> T;
+    fn visit_lambda(&mut self, token: Token, params: Vec<Token>, body: Vec<Stmt>) -> T;
 }
 
 impl Expr {
+    /// Returns the token the expression is reported at, such as the operator of
+    /// a binary expression.
+    pub fn token(&self) -> &Token {
+        match self {
+            Expr::Grouping(expr) => expr.token(),
+            Expr::Literal(token)
+            | Expr::Unary(token, _)
+            | Expr::Binary(token, _, _)
+            | Expr::Variable(token)
+            | Expr::Assign(token, _)
+            | Expr::And(token, _, _)
+            | Expr::Or(token, _, _)
+            | Expr::Call(_, _, token)
+            | Expr::Get(_, token)
+            | Expr::Set(_, token, _)
+            | Expr::This(token)
+            | Expr::Super(token, _)
+            | Expr::Yield(token, _)
+            | Expr::Lambda(token, _, _) => token,
+        }
+    }
+
+    /// Whether the expression is truthy, if it is a literal, or a literal in
+    /// parentheses, whose value is known without running it.
+    pub fn truthiness(&self) -> Option<bool> {
+        match self {
+            Expr::Grouping(expr) => expr.truthiness(),
+            Expr::Literal(token) => match token.token {
+                TokenType::False | TokenType::Nil => Some(false),
+                TokenType::True | TokenType::Number | TokenType::String => Some(true),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Whether the expression, parsed from `source`, calls something named
+    /// `assert`, which compiling with `strip` leaves out.
+    pub fn is_assert_call(&self, source: &str) -> bool {
+        match self {
+            Expr::Call(callee, _, _) => {
+                matches!(&**callee, Expr::Variable(id) if id.lexeme(source) == "assert")
+            }
+            _ => false,
+        }
+    }
+
     pub fn accept<T>(self, visitor: &mut impl ExprVisitor<T>) -> T {
         match self {
             Expr::Literal(token) => visitor.visit_literal(token),
@@ -53,6 +106,8 @@ impl Expr {
             Expr::Set(obj, prop, value) => visitor.visit_set(*obj, prop, *value),
             Expr::This(token) => visitor.visit_this(token),
             Expr::Super(super_token, prop) => visitor.visit_super(super_token, prop),
+            Expr::Yield(token, value) => visitor.visit_yield(token, *value),
+            Expr::Lambda(token, params, body) => visitor.visit_lambda(token, params, body),
         }
     }
 }
@@ -1,2 +1,5 @@
+//! The syntax tree produced by [`crate::frontend::parse`]: a script is a list
+//! of [`stmt::Stmt`]s, which contain [`expr::Expr`]s.
+
 pub mod expr;
 pub mod stmt;
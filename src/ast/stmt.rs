@@ -2,8 +2,20 @@ use crate::core::token::Token;
 
 use super::expr::Expr;
 
+/// What kind of member of a class a method declares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MethodKind {
+    /// A method, called with arguments like a function
+    Method,
+    /// A `get name { ... }` member, run when the property is read
+    Getter,
+    /// A `set name(value) { ... }` member, run with the value assigned to the
+    /// property
+    Setter,
+}
+
 /// Enum to represent different types of statements in the AST.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Stmt {
     Print(Token, Expr),
     Expr(Token, Expr),
@@ -11,9 +23,23 @@ pub enum Stmt {
     Block(Vec<Stmt>),
     If(Token, Expr, Box<Stmt>, Option<Box<Stmt>>),
     While(Token, Expr, Box<Stmt>),
+    /// A `do <body> while (<condition>);` loop, which runs its body before
+    /// testing the condition
+    DoWhile(Token, Box<Stmt>, Expr),
+    ForEach(Token, Token, Expr, Box<Stmt>),
     DeclareFunc(Token, Vec<Token>, Vec<Stmt>),
     Return(Token, Expr),
-    DeclareClass(Token, Option<Token>, Vec<(Token, Vec<Token>, Vec<Stmt>)>),
+    DeclareClass(
+        Token,
+        Option<Token>,
+        Vec<(MethodKind, Token, Vec<Token>, Vec<Stmt>)>,
+    ),
+    /// A statement marked with a `//@debug` pragma, which is left out when
+    /// compiling with `strip`
+    Debug(Token, Box<Stmt>),
+    /// A statement that failed to parse, starting at the token. The parser
+    /// reports the error separately, and carries on with the next statement.
+    Error(Token),
 }
 
 /// A struct that visits `Stmt`
@@ -30,17 +56,40 @@ pub trait StmtVisitor<T> {
         else_block: Option<Box<Stmt>>,
     ) -> T;
     fn visit_while(&mut self, token: Token, condition: Expr, while_block: Stmt) -> T;
+    fn visit_do_while(&mut self, token: Token, body: Stmt, condition: Expr) -> T;
+    fn visit_foreach(&mut self, token: Token, id: Token, collection: Expr, body: Stmt) -> T;
     fn visit_declare_func(&mut self, id: Token, params: Vec<Token>, body: Vec<Stmt>) -> T;
     fn visit_return(&mut self, token: Token, expr: Expr) -> T;
     fn visit_declare_class(
         &mut self,
         id: Token,
         parent: Option<Token>,
-        methods: Vec<(Token, Vec<Token>, Vec<Stmt>)>,
+        methods: Vec<(MethodKind, Token, Vec<Token>, Vec<Stmt>)>,
     ) -> T;
+    fn visit_debug(&mut self, pragma: Token, stmt: Stmt) -> T;
+    fn visit_error(&mut self, token: Token) -> T;
 }
 
 impl Stmt {
+    /// Returns the token the statement is reported at, if it has one.
+    pub fn token(&self) -> Option<&Token> {
+        match self {
+            Stmt::Print(token, _)
+            | Stmt::Expr(token, _)
+            | Stmt::DeclareVar(token, _)
+            | Stmt::If(token, _, _, _)
+            | Stmt::While(token, _, _)
+            | Stmt::DoWhile(token, _, _)
+            | Stmt::ForEach(token, _, _, _)
+            | Stmt::DeclareFunc(token, _, _)
+            | Stmt::Return(token, _)
+            | Stmt::DeclareClass(token, _, _)
+            | Stmt::Debug(token, _)
+            | Stmt::Error(token) => Some(token),
+            Stmt::Block(_) => None,
+        }
+    }
+
     pub fn accept<T>(self, visiter: &mut impl StmtVisitor<T>) -> T {
         match self {
             Stmt::Print(token, expr) => visiter.visit_print(token, expr),
@@ -51,11 +100,17 @@ impl Stmt {
                 visiter.visit_if(token, expr, *if_block, else_block)
             }
             Stmt::While(token, expr, stmt) => visiter.visit_while(token, expr, *stmt),
+            Stmt::DoWhile(token, stmt, expr) => visiter.visit_do_while(token, *stmt, expr),
+            Stmt::ForEach(token, id, collection, body) => {
+                visiter.visit_foreach(token, id, collection, *body)
+            }
             Stmt::DeclareFunc(id, params, body) => visiter.visit_declare_func(id, params, body),
             Stmt::Return(token, expr) => visiter.visit_return(token, expr),
             Stmt::DeclareClass(id, parent, methods) => {
                 visiter.visit_declare_class(id, parent, methods)
             }
+            Stmt::Debug(pragma, stmt) => visiter.visit_debug(pragma, *stmt),
+            Stmt::Error(token) => visiter.visit_error(token),
         }
     }
 }
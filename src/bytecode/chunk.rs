@@ -1,15 +1,72 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write},
+};
+
 use crate::{
     VM,
-    core::{OpCode, Value},
-    object::Object,
+    core::{format_number, shared::Shared, OpCode, Operand, Value},
+    object::{Function, Object},
+    runtime::Heap,
 };
 
+use super::DebugInfo;
+
+/// Where in the source a byte of code was compiled from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineInfo {
+    pub line: u32,
+    /// The column counting from 1, or 0 if unknown
+    pub column: u32,
+}
+
+/// An entry in a chunk's constant pool. Unlike a [`Value`], a constant does
+/// not depend on the heap, so a chunk's constants mean the same thing in any VM
+/// until [`Chunk::link`] resolves them.
+#[derive(Debug, Clone)]
+pub enum Constant {
+    Number(f64),
+    String(Shared<str>),
+    /// A function declared in the chunk's code, which `Closure` wraps
+    Function(Shared<Function>),
+}
+
+impl PartialEq for Constant {
+    /// Functions are compared by identity, other constants by value.
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Constant::Number(a), Constant::Number(b)) => a == b,
+            (Constant::String(a), Constant::String(b)) => a == b,
+            (Constant::Function(a), Constant::Function(b)) => Shared::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Constant {
+    /// Formats the constant as `print` would.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constant::Number(n) => write!(f, "{}", format_number(*n)),
+            Constant::String(s) => write!(f, "{s}"),
+            Constant::Function(function) => write!(f, "<fn {}>", function.display_name()),
+        }
+    }
+}
+
 pub struct Chunk {
     pub code: Vec<u8>,
-    /// Run-length encoding of line numbers
+    /// Run-length encoding of source locations
     /// <https://en.wikipedia.org/wiki/Run-length_encoding>
-    pub lines: Vec<(u32, usize)>,
-    pub constants: Vec<Value>,
+    pub lines: Vec<(LineInfo, usize)>,
+    pub constants: Vec<Constant>,
+    /// The values of `constants` in the heap the chunk was linked against, in
+    /// the same order
+    values: Vec<Value>,
+    /// The index of the first byte of code compiled from each line
+    line_starts: BTreeMap<u32, usize>,
+    /// Names and source locations, if the compiler was asked to record them
+    pub debug: Option<DebugInfo>,
 }
 
 impl Chunk {
@@ -17,59 +74,202 @@ impl Chunk {
         Self {
             code: Vec::new(),
             constants: Vec::new(),
+            values: Vec::new(),
             lines: Vec::new(),
+            line_starts: BTreeMap::new(),
+            debug: None,
         }
     }
 
     // Writes a single byte to the code instructions array
     pub fn write_byte(&mut self, byte: u8, line: u32) {
+        self.write(byte, LineInfo { line, column: 0 });
+    }
+
+    /// Writes a single byte compiled from `info` to the code instructions array.
+    pub fn write(&mut self, byte: u8, info: LineInfo) {
+        self.line_starts.entry(info.line).or_insert(self.code.len());
         self.code.push(byte);
 
-        if let Some(last_line) = self.lines.last_mut() {
-            if last_line.0 == line {
-                last_line.1 += 1;
+        match self.lines.last_mut() {
+            Some(last) if last.0 == info => last.1 += 1,
+            _ => self.lines.push((info, 1)),
+        }
+    }
+
+    /// Inserts `count` `Nop` bytes before the byte at `index`, attributing them to
+    /// the line of the byte before it.
+    pub fn insert_bytes(&mut self, index: usize, count: usize) {
+        self.code
+            .splice(index..index, std::iter::repeat_n(OpCode::Nop as u8, count));
+
+        // Bytes inserted at the very start belong to the first line
+        for start in self.line_starts.values_mut() {
+            if *start >= index.max(1) {
+                *start += count;
+            }
+        }
+
+        if let Some(debug) = &mut self.debug {
+            debug.insert_bytes(index, count);
+        }
+
+        let mut offset = index.saturating_sub(1);
+        for line in &mut self.lines {
+            if offset >= line.1 {
+                offset -= line.1;
             } else {
-                self.lines.push((line, 1));
+                line.1 += count;
+                return;
             }
-        } else {
-            self.lines.push((line, 1))
+        }
+    }
+
+    /// Removes the code from the byte at `len` on, along with where it was
+    /// compiled from.
+    pub fn truncate(&mut self, len: usize) {
+        self.code.truncate(len);
+        self.line_starts.retain(|_, start| *start < len);
+
+        let mut remaining = len;
+        self.lines.retain_mut(|(_, count)| {
+            *count = (*count).min(remaining);
+            remaining -= *count;
+            *count > 0
+        });
+
+        if let Some(debug) = &mut self.debug {
+            debug.truncate(len);
         }
     }
 
     // Adds a constant to the chunk's constant pool.
     //
     // Returns the index of the constant in the constant pool.
-    pub fn add_constant(&mut self, constant: Value) -> usize {
+    pub fn add_constant(&mut self, constant: Constant) -> usize {
         self.constants.push(constant);
         self.constants.len() - 1
     }
 
-    pub fn get_line(&self, mut offset: usize) -> u32 {
+    /// Resolves the constants added since the last call to the values the VM
+    /// loads, allocating strings and functions in `heap`, which must be the heap
+    /// of the VM that will run the chunk. The chunks of nested functions are
+    /// linked first, unless they are shared with something else, since only then
+    /// can they still be changed.
+    pub fn link(&mut self, heap: &mut Heap) {
+        for constant in &mut self.constants[self.values.len()..] {
+            let value = match constant {
+                Constant::Number(n) => Value::whole_number(*n),
+                Constant::String(s) => heap.push_str(s.to_string()).into(),
+                Constant::Function(function) => {
+                    if let Some(nested) = Shared::get_mut(function) {
+                        nested.chunk.link(heap);
+                    }
+                    heap.push(Object::Function(function.clone())).into()
+                }
+            };
+            self.values.push(value);
+        }
+    }
+
+    /// Copies the chunk without the values it was linked to, so that the copy
+    /// can be linked against another heap. The functions declared in it are
+    /// copied the same way, since they are linked along with it.
+    #[cfg(feature = "threads")]
+    pub(crate) fn unlinked(&self) -> Chunk {
+        let constants = self
+            .constants
+            .iter()
+            .map(|constant| match constant {
+                Constant::Function(function) => {
+                    Constant::Function(Shared::new(function.unlinked()))
+                }
+                constant => constant.clone(),
+            })
+            .collect();
+        Chunk {
+            code: self.code.clone(),
+            lines: self.lines.clone(),
+            constants,
+            values: Vec::new(),
+            line_starts: self.line_starts.clone(),
+            debug: self.debug.clone(),
+        }
+    }
+
+    /// Returns the function constant at `index`, if it is one.
+    pub fn function(&self, index: usize) -> Option<&Shared<Function>> {
+        match self.constants.get(index)? {
+            Constant::Function(function) => Some(function),
+            _ => None,
+        }
+    }
+
+    /// Returns the linked values of the constants, see [`Chunk::link`].
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+
+    pub fn get_line(&self, offset: usize) -> u32 {
+        self.line_info(offset).map_or(0, |info| info.line)
+    }
+
+    /// Returns where in the source the byte at `offset` was compiled from.
+    pub fn line_info(&self, mut offset: usize) -> Option<LineInfo> {
         for line in &self.lines {
             if offset >= line.1 {
                 offset -= line.1;
             } else {
-                return line.0;
+                return Some(line.0);
             }
         }
-        0
+        None
+    }
+
+    /// Returns the index of the first byte compiled from `line`, or from the
+    /// closest line after it if `line` has no code, such as where to stop for a
+    /// breakpoint on `line`.
+    pub fn first_offset(&self, line: u32) -> Option<usize> {
+        self.line_starts
+            .range(line..)
+            .next()
+            .map(|(_, &offset)| offset)
     }
 
+    /// Prints the whole chunk under the heading `name`, see [`Self::disassembly`].
     pub fn disassemble(&self, name: &str, vm: &VM) {
-        eprintln!("== {} ==", name);
+        eprint!("{}", self.disassembly(name, vm));
+    }
+
+    /// Disassembles the whole chunk under the heading `name`. This does not need
+    /// the chunk to be running, so locals and upvalues are shown by index and,
+    /// with [debug info](DebugInfo), by name rather than by their current value.
+    pub fn disassembly(&self, name: &str, vm: &VM) -> String {
+        let mut out = format!("== {} ==\n", name);
         let mut offset = 0;
 
         let len = self.code.len();
         while offset < len {
-            offset = self.disassemble_instruction(offset, vm);
+            offset = self.write_instruction(offset, vm, false, &mut out);
         }
+        out
     }
 
-    pub fn disassemble_instruction(&self, mut offset: usize, vm: &VM) -> usize {
+    /// Appends the instruction at `offset` to `out`, returning the offset of the
+    /// next instruction. The chunk must be the one `vm` is running, as locals and
+    /// upvalues are shown with their current values.
+    pub fn disassemble_instruction(&self, offset: usize, vm: &VM, out: &mut String) -> usize {
+        self.write_instruction(offset, vm, true, out)
+    }
+
+    /// Appends the instruction at `offset` to `out`, showing the current values of
+    /// locals and upvalues in `vm` if `live`.
+    fn write_instruction(&self, mut offset: usize, vm: &VM, live: bool, out: &mut String) -> usize {
         let instruction = self.code[offset];
         let line = self.get_line(offset);
 
-        eprint!(
+        let _ = write!(
+            out,
             "{:04} {}",
             offset,
             if offset > 0 && line == self.get_line(offset - 1) {
@@ -80,33 +280,30 @@ impl Chunk {
         );
 
         offset += match OpCode::try_from(instruction) {
-            Ok(op) => match op {
-                OpCode::LoadConstant
-                | OpCode::DefineGlobal
-                | OpCode::GetGlobal
-                | OpCode::SetGlobal => self.disassemble_constant_instruction(op, 1, offset, vm),
-                OpCode::LoadConstantLong
-                | OpCode::DefineGlobalLong
-                | OpCode::GetGlobalLong
-                | OpCode::SetGlobalLong => self.disassemble_constant_instruction(op, 3, offset, vm),
-                OpCode::GetLocal | OpCode::SetLocal => {
-                    self.disassemble_stack_instruction(op, 1, offset, vm)
-                }
-                OpCode::GetLocalLong | OpCode::SetLocalLong => {
-                    self.disassemble_stack_instruction(op, 3, offset, vm)
+            Ok(op) => {
+                let operands = op.operand_width() as usize;
+                match op.info().operand {
+                    Operand::None => self.disassemble_simple_instruction(op, out),
+                    Operand::Constant => {
+                        self.disassemble_constant_instruction(op, operands, offset, out)
+                    }
+                    Operand::Global => {
+                        self.disassemble_global_instruction(op, operands, offset, vm, out)
+                    }
+                    Operand::Local => {
+                        self.disassemble_stack_instruction(op, operands, offset, vm, live, out)
+                    }
+                    Operand::Upvalue => {
+                        self.disassemble_upvalue_instruction(op, operands, offset, vm, live, out)
+                    }
+                    Operand::Number | Operand::Jump => {
+                        self.disassemble_num_instruction(op, operands, offset, out)
+                    }
+                    Operand::Closure => self.disassemble_closure(op, operands, offset, out),
                 }
-                OpCode::Call => self.disassemble_num_instruction(op, 1, offset),
-                OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => {
-                    self.disassemble_num_instruction(op, 2, offset)
-                }
-                OpCode::GetUpvalue | OpCode::SetUpvalue => {
-                    self.disassemble_upvalue_instruction(op, 1, offset, vm)
-                }
-                OpCode::Closure => self.disassemble_closure(op, 1, offset, vm),
-                _ => self.disassemble_simple_instruction(op),
-            },
+            }
             Err(_) => {
-                eprintln!("Invalid Opcode '{}'", instruction);
+                let _ = writeln!(out, "Invalid Opcode '{}'", instruction);
                 1
             }
         };
@@ -114,25 +311,17 @@ impl Chunk {
         offset
     }
 
+    /// Reads the little-endian operand of `operands` bytes following the
+    /// instruction at `offset`. Bytes past the end of the chunk read as 0.
     fn read_operand(&self, operands: usize, offset: usize) -> usize {
-        if operands == 3 {
-            let low_byte = self.code[offset + 1] as usize;
-            let mid_byte = self.code[offset + 2] as usize;
-            let high_byte = self.code[offset + 3] as usize;
-            (high_byte << 16) | (mid_byte << 8) | low_byte
-        } else if operands == 2 {
-            let low_byte = self.code[offset + 1] as usize;
-            let high_byte = self.code[offset + 2] as usize;
-            (high_byte << 8) | low_byte
-        } else if operands == 1 {
-            self.code[offset + 1] as usize
-        } else {
-            panic!("<read_operand> only acepts 1, 2, or 3")
-        }
+        (0..operands).fold(0, |operand, i| {
+            let byte = self.code.get(offset + 1 + i).copied().unwrap_or(0);
+            operand | (byte as usize) << (8 * i)
+        })
     }
 
-    fn disassemble_simple_instruction(&self, op: OpCode) -> usize {
-        eprintln!("{:?}", op);
+    fn disassemble_simple_instruction(&self, op: OpCode, out: &mut String) -> usize {
+        let _ = writeln!(out, "{:?}", op);
         1
     }
 
@@ -142,16 +331,37 @@ impl Chunk {
         op: OpCode,
         operands: usize,
         offset: usize,
-        vm: &VM,
+        out: &mut String,
     ) -> usize {
         let constant_idx = self.read_operand(operands, offset);
-        let constant = self.constants[constant_idx];
-        eprintln!(
-            "{:<16?} {:>4} '{:?}'",
-            op,
-            constant_idx,
-            vm.format_value(&constant)
-        );
+        let _ = match self.constants.get(constant_idx) {
+            Some(constant) => writeln!(
+                out,
+                "{:<16?} {:>4} '{:?}'",
+                op,
+                constant_idx,
+                constant.to_string()
+            ),
+            None => writeln!(out, "{:<16?} {:>4} <invalid constant>", op, constant_idx),
+        };
+        operands + 1
+    }
+
+    /// Disassemble instruction that takes the symbol id of a global
+    fn disassemble_global_instruction(
+        &self,
+        op: OpCode,
+        operands: usize,
+        offset: usize,
+        vm: &VM,
+        out: &mut String,
+    ) -> usize {
+        let symbol = self.read_operand(operands, offset);
+        let name = vm
+            .symbols()
+            .name(symbol as u32)
+            .unwrap_or("<invalid symbol>");
+        let _ = writeln!(out, "{:<16?} {:>4} '{}'", op, symbol, name);
         operands + 1
     }
 
@@ -162,15 +372,29 @@ impl Chunk {
         operands: usize,
         offset: usize,
         vm: &VM,
+        live: bool,
+        out: &mut String,
     ) -> usize {
         let stack_idx = self.read_operand(operands, offset);
-        let stack_value = vm.stack_get(stack_idx);
-        eprintln!(
-            "{:<16?} {:>4} '{:}'",
-            op,
-            stack_idx,
-            vm.format_value(&stack_value)
-        );
+        let _ = if live {
+            let stack_value = vm.stack_get(stack_idx);
+            writeln!(
+                out,
+                "{:<16?} {:>4} '{:}'",
+                op,
+                stack_idx,
+                vm.format_value(&stack_value)
+            )
+        } else {
+            match self
+                .debug
+                .as_ref()
+                .and_then(|d| d.local_name(stack_idx, offset))
+            {
+                Some(name) => writeln!(out, "{:<16?} {:>4} {}", op, stack_idx, name),
+                None => writeln!(out, "{:<16?} {:>4}", op, stack_idx),
+            }
+        };
         operands + 1
     }
 
@@ -181,43 +405,70 @@ impl Chunk {
         operands: usize,
         offset: usize,
         vm: &VM,
+        live: bool,
+        out: &mut String,
     ) -> usize {
         let upvalue_idx = self.read_operand(operands, offset);
-        let upvalue = vm.upvalue_get(upvalue_idx as u8);
-        eprintln!(
-            "{:<16?} {:>4} '{}'",
-            op,
-            upvalue_idx,
-            vm.format_value(&upvalue)
-        );
+        let _ = if live {
+            let upvalue = vm.upvalue_get(upvalue_idx as u8);
+            writeln!(
+                out,
+                "{:<16?} {:>4} '{}'",
+                op,
+                upvalue_idx,
+                vm.format_value(&upvalue)
+            )
+        } else {
+            match self
+                .debug
+                .as_ref()
+                .and_then(|d| d.upvalue_name(upvalue_idx))
+            {
+                Some(name) => writeln!(out, "{:<16?} {:>4} {}", op, upvalue_idx, name),
+                None => writeln!(out, "{:<16?} {:>4}", op, upvalue_idx),
+            }
+        };
         operands + 1
     }
 
     // Disassemble instruction that takes a number as an argument (rather than indexing somehwere).
-    fn disassemble_num_instruction(&self, op: OpCode, operands: usize, offset: usize) -> usize {
+    fn disassemble_num_instruction(
+        &self,
+        op: OpCode,
+        operands: usize,
+        offset: usize,
+        out: &mut String,
+    ) -> usize {
         let number = self.read_operand(operands, offset);
-        eprintln!("{:<16?} {:>4}", op, number);
+        let _ = writeln!(out, "{:<16?} {:>4}", op, number);
         operands + 1
     }
 
-    fn disassemble_closure(&self, op: OpCode, operands: usize, offset: usize, vm: &VM) -> usize {
+    fn disassemble_closure(
+        &self,
+        op: OpCode,
+        operands: usize,
+        offset: usize,
+        out: &mut String,
+    ) -> usize {
         let mut operands = operands;
-        let heap_idx = self.read_operand(operands, offset);
+        let constant_idx = self.read_operand(operands, offset);
         operands += 1;
 
-        let function_idx = Value::object(heap_idx);
-        eprintln!(
-            "{:<16?} {:>4} '{}'",
-            op,
-            heap_idx,
-            vm.format_value(&function_idx)
-        );
-        if let Some(Object::Function(function)) = vm.heap_get(&function_idx) {
+        if let Some(function) = self.function(constant_idx) {
+            let _ = writeln!(
+                out,
+                "{:<16?} {:>4} '<fn {}>'",
+                op,
+                constant_idx,
+                function.display_name()
+            );
             for _ in 0..function.upvalue_count {
                 operands += 2;
             }
         } else {
-            panic!("Closure on non function.")
+            let _ = writeln!(out, "{:<16?} {:>4}", op, constant_idx);
+            let _ = writeln!(out, "<closure on non function>");
         }
 
         operands
@@ -1,20 +1,17 @@
-use std::rc::Rc;
-
 use crate::{
     ast::{
         expr::{Expr, ExprVisitor},
-        stmt::{Stmt, StmtVisitor},
+        stmt::{MethodKind, Stmt, StmtVisitor},
     },
     core::{
         errors::{CompileError, InterpretError, PanicError},
         token::{Token, TokenType},
         OpCode, Value,
     },
-    frontend::{Parser, Scanner},
-    object::{Function, Object},
+    frontend::parse_number,
 };
 
-use super::{Compiler, FunctionType, Return};
+use super::{Compiler, Constant, FunctionType, Return};
 
 impl StmtVisitor<Return> for Compiler<'_> {
     fn visit_print(&mut self, token: Token, expr: Expr) -> Return {
@@ -24,30 +21,30 @@ impl StmtVisitor<Return> for Compiler<'_> {
     }
 
     fn visit_expr(&mut self, token: Token, expr: Expr) -> Return {
+        if self.strip && expr.is_assert_call(self.source) {
+            return Ok(());
+        }
         self.compile_expr(expr)?;
         self.emit_byte(OpCode::Pop as u8, token.line);
         Ok(())
     }
 
     fn visit_declare_var(&mut self, id: Token, expr: Option<Expr>) -> Return {
-        self.declare_local(id.lexeme.clone(), id.line)?;
+        let name = self.lexeme(&id);
+        self.declare_variable(name, id.line)?;
 
         match expr {
             Some(expr) => self.compile_expr(expr)?,
-            None => self.emit_constant_instruction(OpCode::LoadConstant, Value::nil(), id.line),
-        }
-
-        if self.scope_depth == 0 {
-            let object = self.heap.as_mut().unwrap().push_str(id.lexeme);
-            self.emit_constant_instruction(OpCode::DefineGlobal, object, id.line);
+            None => self.emit_byte(OpCode::Nil as u8, id.line),
         }
 
-        self.define_local();
+        self.define_variable(name, id.line);
         Ok(())
     }
 
     fn visit_block(&mut self, statements: Vec<Stmt>) -> Return {
         self.begin_scope();
+        self.hoist_functions(&statements)?;
         for stmt in statements {
             self.compile_stmt(stmt)?;
         }
@@ -63,28 +60,81 @@ impl StmtVisitor<Return> for Compiler<'_> {
         if_block: Stmt,
         else_block: Option<Box<Stmt>>,
     ) -> Return {
-        self.compile_expr(condition)?;
-
-        let if_offset = self.emit_jump_instruction(OpCode::JumpIfFalse, token.line);
-        self.emit_byte(OpCode::Pop as u8, token.line); // removes condition value off stack
-        self.compile_stmt(if_block)?;
-
-        // send JUMP here to include it inside the if_block
-        let else_offset = self.emit_jump_instruction(OpCode::Jump, token.line);
+        // The `else if`s of a chain are compiled in turn rather than nested, so
+        // that a long chain does not recurse
+        let (mut token, mut condition, mut if_block, mut else_block) =
+            (token, condition, if_block, else_block);
+        let mut exits = vec![];
+        let mut unreachable = None;
+        loop {
+            // Only the branch a literal condition takes is kept
+            match condition.truthiness() {
+                Some(true) => {
+                    self.compile_stmt(if_block)?;
+                    let constants = self.get_chunk().constants.len();
+                    unreachable.get_or_insert((self.get_code_length(), constants));
+                }
+                Some(false) => self.compile_unreachable(if_block)?,
+                None => {
+                    self.compile_expr(condition)?;
+
+                    let if_offset = self.emit_jump_instruction(OpCode::JumpIfFalse, token.line);
+                    self.emit_byte(OpCode::Pop as u8, token.line); // removes condition value off stack
+                    self.compile_stmt(if_block)?;
+
+                    // send JUMP here to include it inside the if_block
+                    exits.push((
+                        self.emit_jump_instruction(OpCode::Jump, token.line),
+                        token.line,
+                    ));
+
+                    self.patch_jump_instruction(if_offset, token.line)?;
+                    self.emit_byte(OpCode::Pop as u8, token.line); // removes condition value off stack
+                }
+            }
 
-        self.patch_jump_instruction(if_offset, token.line)?;
-        self.emit_byte(OpCode::Pop as u8, token.line); // removes condition value off stack
+            match else_block.map(|else_block| *else_block) {
+                Some(Stmt::If(next, next_condition, next_block, next_else)) => {
+                    self.column = next.column.into();
+                    self.line = next.line;
+                    (token, condition, if_block, else_block) =
+                        (next, next_condition, *next_block, next_else);
+                }
+                Some(else_block) => {
+                    self.compile_stmt(else_block)?;
+                    break;
+                }
+                None => break,
+            }
+        }
 
-        if let Some(else_block) = else_block {
-            self.compile_stmt(*else_block)?;
+        // Everything after a branch that is always taken is left out
+        if let Some((offset, constants)) = unreachable {
+            // along with the exits of the branches after it
+            self.discard_code(offset, constants);
+            let jumps = self.state.jumps.len();
+            exits.retain(|&(exit, _)| exit < jumps);
+        }
+        for (exit, line) in exits.into_iter().rev() {
+            self.patch_jump_instruction(exit, line)?;
         }
-        self.patch_jump_instruction(else_offset, token.line)?;
         Ok(())
     }
 
     fn visit_while(&mut self, token: Token, condition: Expr, while_block: Stmt) -> Return {
         let loop_start = self.get_code_length();
 
+        // A literal condition is never tested: the loop either never runs or
+        // runs until something returns from it
+        match condition.truthiness() {
+            Some(true) => {
+                self.compile_stmt(while_block)?;
+                return self.emit_loop_instruction(loop_start, token.line);
+            }
+            Some(false) => return self.compile_unreachable(while_block),
+            None => (),
+        }
+
         self.compile_expr(condition)?;
         let offset = self.emit_jump_instruction(OpCode::JumpIfFalse, token.line);
         self.emit_byte(OpCode::Pop as u8, token.line); // removes condition value off stack
@@ -98,74 +148,79 @@ impl StmtVisitor<Return> for Compiler<'_> {
         Ok(())
     }
 
-    fn visit_declare_func(&mut self, id: Token, params: Vec<Token>, body: Vec<Stmt>) -> Return {
-        self.declare_local(id.lexeme.clone(), id.line)?;
-
-        // Now, self.heap is None, and if we try to access it, we will get panic error. In general,
-        // any compiler code should not access enclosing.heap
-        let heap = self.heap.take();
-        let mut new_compiler = Compiler {
-            statements: Parser::new(Scanner::new("")), // placeholder, never actually used
-            heap,
-            function: Function::new(id.lexeme.clone(), params.len() as u8),
-            scope_depth: 1,
-            locals: vec![],
-            function_type: FunctionType::Function,
-            upvalues: Vec::new(),
-            enclosing: Some(self as *mut Self), // should usually be safe, since we create and
-        };
+    fn visit_do_while(&mut self, token: Token, body: Stmt, condition: Expr) -> Return {
+        let loop_start = self.get_code_length();
+        self.compile_stmt(body)?;
+
+        // A literal condition is never tested: the body either runs once or
+        // runs until something returns from it
+        match condition.truthiness() {
+            Some(true) => return self.emit_loop_instruction(loop_start, token.line),
+            Some(false) => return Ok(()),
+            None => (),
+        }
 
-        // This block is reserved for operations that new_compiler does, we should never touch
-        // `self` in this block manually
-        {
-            // [ <fn> ] [ arg1 ] [ arg2 ]
-            new_compiler.declare_local(id.lexeme.clone(), id.line)?;
-            new_compiler.define_local();
-            for param in params {
-                new_compiler.declare_local(param.lexeme, param.line)?;
-                new_compiler.define_local();
-            }
-            for stmt in body {
-                new_compiler.compile_stmt(stmt)?;
-            }
+        self.compile_expr(condition)?;
+        let offset = self.emit_jump_instruction(OpCode::JumpIfFalse, token.line);
+        self.emit_byte(OpCode::Pop as u8, token.line); // removes condition value off stack
+        self.emit_loop_instruction(loop_start, token.line)?;
+        self.patch_jump_instruction(offset, token.line)?;
+        // removes the condition value that ended the loop off stack
+        self.emit_byte(OpCode::Pop as u8, token.line);
 
-            // Default 'return nil'. Frame exits at first return, so it will not run if there
-            // is already a return in the function
-            new_compiler.emit_constant_instruction(OpCode::LoadConstant, Value::nil(), id.line);
-            new_compiler.emit_byte(OpCode::Return as u8, id.line);
-        }
+        Ok(())
+    }
 
-        let upvalues = new_compiler.upvalues;
-        let new_function = new_compiler.function; // get the compiled function
-        self.heap = new_compiler.heap.take(); // take back our original heap
+    fn visit_foreach(&mut self, token: Token, id: Token, collection: Expr, body: Stmt) -> Return {
+        self.begin_scope();
 
-        if upvalues.len() > 256 {
-            panic!("Cannot have more than 256 upvalues in a closure.")
-        }
+        // The iterator is kept in a local that cannot be named from Lox code
+        self.compile_expr(collection)?;
+        self.emit_byte(OpCode::IterNew as u8, token.line);
+        self.declare_local("for in".to_string(), token.line)?;
+        self.define_local();
+        self.mark_used();
 
-        let function_idx = self
-            .heap
-            .as_mut()
-            .unwrap()
-            .push(Object::Function(Rc::new(new_function)));
-        self.emit_operand_instruction(OpCode::Closure, function_idx.as_object(), id.line);
+        let loop_start = self.get_code_length();
+        let exit_offset = self.emit_jump_instruction(OpCode::IterNext, token.line);
 
-        for upvalue in upvalues {
-            self.emit_byte(if upvalue.is_local { 1 } else { 0 } as u8, id.line);
-            self.emit_byte(upvalue.index as u8, id.line);
-        }
+        // Each iteration gets a fresh variable, so closures capture the current element
+        self.begin_scope();
+        self.declare_local(self.lexeme(&id).to_string(), id.line)?;
+        self.define_local();
+        self.compile_stmt(body)?;
+        self.end_scope();
 
-        if self.scope_depth == 0 {
-            let function_name_idx = self.heap.as_mut().unwrap().push_str(id.lexeme);
-            self.emit_constant_instruction(OpCode::DefineGlobal, function_name_idx, id.line);
+        self.emit_loop_instruction(loop_start, token.line)?;
+        self.patch_jump_instruction(exit_offset, token.line)?;
+
+        self.end_scope();
+        Ok(())
+    }
+
+    fn visit_declare_func(&mut self, id: Token, params: Vec<Token>, body: Vec<Stmt>) -> Return {
+        let name = self.lexeme(&id);
+        let hoisted = self.resolve_hoisted(name);
+        if hoisted.is_none() {
+            self.declare_variable(name, id.line)?;
+            self.mark_function();
         }
 
-        self.define_local();
+        self.compile_function(name.to_string(), &id, params, body)?;
+
+        match hoisted {
+            // Fill in the slot reserved at the start of the scope
+            Some(index) => {
+                self.emit_operand_instruction(OpCode::SetLocal, index, id.line);
+                self.emit_byte(OpCode::Pop as u8, id.line);
+            }
+            None => self.define_variable(name, id.line),
+        }
         Ok(())
     }
 
     fn visit_return(&mut self, token: Token, expr: Expr) -> Return {
-        if self.function_type == FunctionType::Main {
+        if self.state.function_type == FunctionType::Main {
             return Err(InterpretError::Compile(CompileError::TopReturn(token.line)));
         }
         self.compile_expr(expr)?;
@@ -175,48 +230,59 @@ impl StmtVisitor<Return> for Compiler<'_> {
 
     fn visit_declare_class(
         &mut self,
-        id: Token,
-        parent: Option<Token>,
-        methods: Vec<(Token, Vec<Token>, Vec<Stmt>)>,
+        _id: Token,
+        _parent: Option<Token>,
+        _methods: Vec<(MethodKind, Token, Vec<Token>, Vec<Stmt>)>,
     ) -> Return {
+        // Classes are not compiled yet, which is not worth reporting on top of
+        // the syntax errors that already stop the program
+        if self.syntax_errors {
+            return Ok(());
+        }
         Err(InterpretError::UnImplemented)
     }
+
+    fn visit_debug(&mut self, _pragma: Token, stmt: Stmt) -> Return {
+        if self.strip {
+            return Ok(());
+        }
+        self.compile_stmt(stmt)
+    }
+
+    fn visit_error(&mut self, _token: Token) -> Return {
+        // The parser has already reported the error
+        self.mark_all_used();
+        Ok(())
+    }
 }
 
 impl ExprVisitor<Return> for Compiler<'_> {
     fn visit_literal(&mut self, token: Token) -> Return {
         match &token.token {
             TokenType::Number => {
-                self.emit_constant_instruction(
-                    OpCode::LoadConstant,
-                    Value::number(token.lexeme.parse().unwrap()),
-                    token.line,
-                );
-            }
-            TokenType::True => {
-                self.emit_constant_instruction(
-                    OpCode::LoadConstant,
-                    Value::boolean(true),
-                    token.line,
-                );
+                let number = parse_number(self.lexeme(&token)).unwrap();
+                match Value::as_small_int(number) {
+                    Some(n) => {
+                        self.emit_byte(OpCode::LoadSmallInt as u8, token.line);
+                        self.emit_byte(n, token.line);
+                    }
+                    None => self.emit_constant_instruction(
+                        OpCode::LoadConstant,
+                        Constant::Number(number),
+                        token.line,
+                    )?,
+                }
             }
-            TokenType::False => {
+            TokenType::True => self.emit_byte(OpCode::True as u8, token.line),
+            TokenType::False => self.emit_byte(OpCode::False as u8, token.line),
+            TokenType::Nil => self.emit_byte(OpCode::Nil as u8, token.line),
+            TokenType::String => {
+                let string = self.lexeme(&token).replace("\"", "");
                 self.emit_constant_instruction(
                     OpCode::LoadConstant,
-                    Value::boolean(false),
+                    Constant::String(string.into()),
                     token.line,
-                );
-            }
-            TokenType::Nil => {
-                self.emit_constant_instruction(OpCode::LoadConstant, Value::nil(), token.line);
-            }
-            TokenType::String => {
-                let object_idx = self
-                    .heap
-                    .as_mut()
-                    .unwrap()
-                    .push_str(token.lexeme.replace("\"", ""));
-                self.emit_constant_instruction(OpCode::LoadConstant, object_idx, token.line);
+                )?;
             }
             _ => {
                 return Err(InterpretError::Panic(PanicError::InvalidToken(
@@ -239,6 +305,10 @@ impl ExprVisitor<Return> for Compiler<'_> {
                 self.compile_expr(expr)?;
                 self.emit_byte(OpCode::Not as u8, operator.line);
             }
+            TokenType::Tilde => {
+                self.compile_expr(expr)?;
+                self.emit_byte(OpCode::BitNot as u8, operator.line);
+            }
             _ => {
                 return Err(InterpretError::Panic(PanicError::InvalidToken(
                     operator.line,
@@ -252,29 +322,21 @@ impl ExprVisitor<Return> for Compiler<'_> {
     }
 
     fn visit_binary(&mut self, operator: Token, left: Expr, right: Expr) -> Return {
-        let opcode = match operator.token {
-            TokenType::Plus => OpCode::Add,
-            TokenType::Minus => OpCode::Subtract,
-            TokenType::Star => OpCode::Multiply,
-            TokenType::Slash => OpCode::Divide,
-            TokenType::EqualEqual => OpCode::Equal,
-            TokenType::BangEqual => OpCode::NotEqual,
-            TokenType::LessThan => OpCode::LessThan,
-            TokenType::LessEqual => OpCode::LessEqual,
-            TokenType::GreaterThan => OpCode::GreaterThan,
-            TokenType::GreaterEqual => OpCode::GreaterEqual,
-            _ => {
-                return Err(InterpretError::Panic(PanicError::InvalidToken(
-                    operator.line,
-                    operator.token,
-                    "<compiler.visit_binary>".to_string(),
-                )))
-            }
-        };
+        // Walk down a chain such as `a + b + c`, which nests to the left, in a
+        // loop instead of recursing once for each operator
+        let mut chain = vec![(operator, right)];
+        let mut left = left;
+        while let Expr::Binary(operator, inner, right) = left {
+            chain.push((operator, *right));
+            left = *inner;
+        }
 
         self.compile_expr(left)?;
-        self.compile_expr(right)?;
-        self.emit_byte(opcode as u8, operator.line);
+        for (operator, right) in chain.into_iter().rev() {
+            self.column = operator.column.into();
+            self.compile_expr(right)?;
+            self.emit_binary(&operator)?;
+        }
 
         Ok(())
     }
@@ -284,13 +346,15 @@ impl ExprVisitor<Return> for Compiler<'_> {
     }
 
     fn visit_variable(&mut self, id: Token) -> Return {
-        if let Some(index) = self.resolve_local(&id.lexeme, id.line)? {
+        let name = self.lexeme(&id);
+        if let Some(index) = self.resolve_local(name, id.line)? {
             self.emit_operand_instruction(OpCode::GetLocal, index, id.line);
-        } else if let Some(index) = self.resolve_upvalue(&id.lexeme, id.line)? {
+        } else if let Some(index) = self.resolve_upvalue(name, id.line)? {
             self.emit_operand_instruction(OpCode::GetUpvalue, index, id.line);
         } else {
-            let variable_idx = self.heap.as_mut().unwrap().push_str(id.lexeme);
-            self.emit_constant_instruction(OpCode::GetGlobal, variable_idx, id.line);
+            self.state.global_refs.push((name.to_string(), id.line));
+            let symbol = self.symbols.intern(name);
+            self.emit_operand_instruction(OpCode::GetGlobal, symbol as usize, id.line);
         }
 
         Ok(())
@@ -299,13 +363,15 @@ impl ExprVisitor<Return> for Compiler<'_> {
     fn visit_assignment(&mut self, id: Token, assignment: Expr) -> Return {
         self.compile_expr(assignment)?;
 
-        if let Some(index) = self.resolve_local(&id.lexeme, id.line)? {
+        let name = self.lexeme(&id);
+        if let Some(index) = self.resolve_local(name, id.line)? {
             self.emit_operand_instruction(OpCode::SetLocal, index, id.line);
-        } else if let Some(index) = self.resolve_upvalue(&id.lexeme, id.line)? {
+        } else if let Some(index) = self.resolve_upvalue(name, id.line)? {
             self.emit_operand_instruction(OpCode::SetUpvalue, index, id.line);
         } else {
-            let object = self.heap.as_mut().unwrap().push_str(id.lexeme);
-            self.emit_constant_instruction(OpCode::SetGlobal, object, id.line);
+            self.state.global_refs.push((name.to_string(), id.line));
+            let symbol = self.symbols.intern(name);
+            self.emit_operand_instruction(OpCode::SetGlobal, symbol as usize, id.line);
         }
 
         Ok(())
@@ -325,12 +391,7 @@ impl ExprVisitor<Return> for Compiler<'_> {
     // Returns first true, or last value
     fn visit_or(&mut self, token: Token, left: Expr, right: Expr) -> Return {
         self.compile_expr(left)?;
-        let else_offset = self.emit_jump_instruction(OpCode::JumpIfFalse, token.line);
-        let end_offset = self.emit_jump_instruction(OpCode::Jump, token.line);
-
-        // left == false, jump past the end jump, and go to the right expr
-        // left == true, visit the end jump instruction, which jumps to the end, skipping right
-        self.patch_jump_instruction(else_offset, token.line)?;
+        let end_offset = self.emit_jump_instruction(OpCode::JumpIfTrue, token.line);
         self.emit_byte(OpCode::Pop as u8, token.line);
 
         self.compile_expr(right)?;
@@ -351,19 +412,68 @@ impl ExprVisitor<Return> for Compiler<'_> {
         Ok(())
     }
 
-    fn visit_get(&mut self, obj: Expr, prop: Token) -> Return {
+    fn visit_get(&mut self, _obj: Expr, _prop: Token) -> Return {
         Err(InterpretError::UnImplemented)
     }
 
-    fn visit_set(&mut self, obj: Expr, prop: Token, value: Expr) -> Return {
+    fn visit_set(&mut self, _obj: Expr, _prop: Token, _value: Expr) -> Return {
         Err(InterpretError::UnImplemented)
     }
 
-    fn visit_this(&mut self, token: Token) -> Return {
+    fn visit_this(&mut self, _token: Token) -> Return {
         Err(InterpretError::UnImplemented)
     }
 
-    fn visit_super(&mut self, super_token: Token, prop: Token) -> Return {
+    fn visit_super(&mut self, _super_token: Token, _prop: Token) -> Return {
         Err(InterpretError::UnImplemented)
     }
+
+    fn visit_lambda(&mut self, token: Token, params: Vec<Token>, body: Vec<Stmt>) -> Return {
+        // The closure's own slot gets a name no identifier can resolve to
+        let slot = Token::synthetic(token.token, token.line, token.column);
+        self.compile_function(String::new(), &slot, params, body)
+    }
+
+    fn visit_yield(&mut self, token: Token, value: Expr) -> Return {
+        if self.state.function_type == FunctionType::Main {
+            return Err(InterpretError::Compile(CompileError::TopYield(token.line)));
+        }
+        self.compile_expr(value)?;
+        self.emit_byte(OpCode::Yield as u8, token.line);
+        Ok(())
+    }
+}
+
+impl Compiler<'_> {
+    /// Emits the instruction for the binary `operator`, whose operands are on
+    /// top of the stack.
+    fn emit_binary(&mut self, operator: &Token) -> Return {
+        let opcode = match operator.token {
+            TokenType::Plus => OpCode::Add,
+            TokenType::Minus => OpCode::Subtract,
+            TokenType::Star => OpCode::Multiply,
+            TokenType::Slash => OpCode::Divide,
+            TokenType::Ampersand => OpCode::BitAnd,
+            TokenType::Pipe => OpCode::BitOr,
+            TokenType::Caret => OpCode::BitXor,
+            TokenType::LessLess => OpCode::ShiftLeft,
+            TokenType::GreaterGreater => OpCode::ShiftRight,
+            TokenType::EqualEqual => OpCode::Equal,
+            TokenType::BangEqual => OpCode::NotEqual,
+            TokenType::LessThan => OpCode::LessThan,
+            TokenType::LessEqual => OpCode::LessEqual,
+            TokenType::GreaterThan => OpCode::GreaterThan,
+            TokenType::GreaterEqual => OpCode::GreaterEqual,
+            _ => {
+                return Err(InterpretError::Panic(PanicError::InvalidToken(
+                    operator.line,
+                    operator.token,
+                    "<compiler.visit_binary>".to_string(),
+                )))
+            }
+        };
+
+        self.emit_byte(opcode as u8, operator.line);
+        Ok(())
+    }
 }
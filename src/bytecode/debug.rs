@@ -0,0 +1,81 @@
+use super::LineInfo;
+
+/// Names and source locations of a compiled function, for debuggers and the
+/// disassembler. Only recorded when compiling
+/// [with debug symbols](super::Compiler::with_debug_symbols).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DebugInfo {
+    /// Every local of the function, in the order they went out of scope
+    pub locals: Vec<LocalName>,
+    /// The names of the variables the function's upvalues capture, by index
+    pub upvalues: Vec<String>,
+    /// Where in the source the function was compiled from
+    pub span: Span,
+}
+
+/// The name of a local variable, and the bytes of code over which it is in its
+/// slot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalName {
+    /// The slot of the variable in its function's frame
+    pub slot: usize,
+    pub name: String,
+    /// The index of the first byte run with the variable in scope
+    pub start: usize,
+    /// The index of the byte that removes the variable from its slot
+    pub end: usize,
+}
+
+/// A range of source code, from the start of its first token to the start of
+/// its last.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: LineInfo,
+    pub end: LineInfo,
+}
+
+impl Default for Span {
+    fn default() -> Self {
+        let unknown = LineInfo { line: 0, column: 0 };
+        Self {
+            start: unknown,
+            end: unknown,
+        }
+    }
+}
+
+impl DebugInfo {
+    /// Returns the name of the local in `slot` while the byte at `offset` runs.
+    pub fn local_name(&self, slot: usize, offset: usize) -> Option<&str> {
+        self.locals
+            .iter()
+            .find(|local| local.slot == slot && (local.start..local.end).contains(&offset))
+            .map(|local| local.name.as_str())
+    }
+
+    /// Returns the name of the variable captured by upvalue `index`.
+    pub fn upvalue_name(&self, index: usize) -> Option<&str> {
+        self.upvalues.get(index).map(String::as_str)
+    }
+
+    /// Forgets the locals that come into scope at or after byte `len`, and ends
+    /// the others there at the latest.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.locals.retain(|local| local.start < len);
+        for local in &mut self.locals {
+            local.end = local.end.min(len);
+        }
+    }
+
+    /// Moves the ranges of locals past `count` bytes inserted before `index`.
+    pub(crate) fn insert_bytes(&mut self, index: usize, count: usize) {
+        for local in &mut self.locals {
+            if local.start >= index {
+                local.start += count;
+            }
+            if local.end >= index {
+                local.end += count;
+            }
+        }
+    }
+}
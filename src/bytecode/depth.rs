@@ -0,0 +1,70 @@
+use crate::core::{OpCode, Operand, StackEffect};
+
+use super::Chunk;
+
+/// Returns the most values a frame running `chunk` holds on the stack at once,
+/// starting with `base` values: the function's own slot and its arguments.
+/// Every path through the code is followed, using the stack effects in the
+/// opcode table. Closures are looked up in the constants to skip their upvalue
+/// bytes.
+pub(crate) fn max_stack(chunk: &Chunk, base: usize) -> usize {
+    let code = &chunk.code;
+    let mut seen = vec![false; code.len()];
+    let mut pending = vec![(0, base as isize)];
+    let mut max = base as isize;
+
+    while let Some((offset, depth)) = pending.pop() {
+        if offset >= code.len() || seen[offset] {
+            continue;
+        }
+        seen[offset] = true;
+
+        let Ok(op) = OpCode::try_from(code[offset]) else {
+            continue;
+        };
+        let info = op.info();
+        let width = info.operand_width as usize;
+        let operand = (0..width).fold(0, |operand, i| {
+            let byte = code.get(offset + 1 + i).copied().unwrap_or(0);
+            operand | (byte as usize) << (8 * i)
+        });
+
+        let mut next = offset + 1 + width;
+        if info.operand == Operand::Closure
+            && let Some(function) = chunk.function(operand)
+        {
+            next += 2 * function.upvalue_count;
+        }
+
+        let after = match info.stack_effect {
+            StackEffect::Fixed(effect) => depth + effect as isize,
+            StackEffect::PopOperand => depth - operand as isize,
+            StackEffect::Leave => continue,
+        };
+        max = max.max(after);
+
+        match op {
+            OpCode::Jump | OpCode::JumpLong => pending.push((next + operand, after)),
+            OpCode::Loop | OpCode::LoopLong => {
+                if let Some(target) = next.checked_sub(operand) {
+                    pending.push((target, after));
+                }
+            }
+            OpCode::JumpIfFalse
+            | OpCode::JumpIfFalseLong
+            | OpCode::JumpIfTrue
+            | OpCode::JumpIfTrueLong => {
+                pending.push((next + operand, after));
+                pending.push((next, after));
+            }
+            // The element is only pushed when the iterator does not jump out
+            OpCode::IterNext | OpCode::IterNextLong => {
+                pending.push((next + operand, depth));
+                pending.push((next, after));
+            }
+            _ => pending.push((next, after)),
+        }
+    }
+
+    max.max(0) as usize
+}
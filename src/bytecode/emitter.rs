@@ -1,92 +1,206 @@
 use crate::core::{
     errors::{CompileError, InterpretError},
-    OpCode, Value,
+    OpCode,
 };
 
-use super::{chunk::Chunk, Compiler, Return};
+use super::{
+    chunk::{Chunk, Constant, LineInfo},
+    Compiler, Return, LONG_OPERAND_MAX,
+};
+
+/// A jump instruction emitted into the function being compiled. Jumps are kept
+/// track of so that they can be moved when code before them grows.
+pub(crate) struct JumpSite {
+    /// Index of the jump's opcode in the chunk
+    offset: usize,
+    /// Index of the instruction the jump lands on, once it is known
+    target: Option<usize>,
+}
 
 /// Implementation responsible for emitting bytecode to the chunk
 impl Compiler<'_> {
     pub(crate) fn get_chunk(&mut self) -> &mut Chunk {
-        &mut self.function.chunk
+        &mut self.state.function.chunk
     }
 
     pub(crate) fn get_code_length(&self) -> usize {
-        self.function.chunk.code.len()
+        self.state.function.chunk.code.len()
     }
+    /// Throws away the code emitted from byte `offset` on, and the constants
+    /// added from index `constants` on, along with the jumps in that code.
+    pub(crate) fn discard_code(&mut self, offset: usize, constants: usize) {
+        let chunk = self.get_chunk();
+        chunk.truncate(offset);
+        chunk.constants.truncate(constants);
+        self.state.jumps.retain(|jump| jump.offset < offset);
+    }
+
     /// Emits a single byte to the chunk
     pub(crate) fn emit_byte(&mut self, byte: u8, line: u32) {
-        self.get_chunk().write_byte(byte, line);
+        let column = self.column;
+        self.get_chunk().write(byte, LineInfo { line, column });
     }
 
     /// Emits instruction `op` that expects one operand pointing to an index on the
-    /// constants pool. If the operand does not point to the operand pool, use
-    /// `emit_operand_instruction` instead.
-    pub(crate) fn emit_constant_instruction(&mut self, op: OpCode, operand: Value, line: u32) {
-        let constant_idx = self.get_chunk().add_constant(operand);
+    /// constants pool, such as `LoadConstant` or `Closure`. If the operand does not
+    /// point to the operand pool, use `emit_operand_instruction` instead.
+    pub(crate) fn emit_constant_instruction(
+        &mut self,
+        op: OpCode,
+        operand: Constant,
+        line: u32,
+    ) -> Return {
+        let count = self.state.function.chunk.constants.len();
+        if count > LONG_OPERAND_MAX {
+            return Err(InterpretError::Compile(CompileError::TooManyConstants(
+                line,
+                count + 1,
+            )));
+        }
 
+        let constant_idx = self.get_chunk().add_constant(operand);
         self.emit_operand_instruction(op, constant_idx, line);
+        Ok(())
     }
 
-    /// Emits instruction `op` that expects one operand `index`. If the operand exceeds
-    /// u8 (255), this functions emit the long version of `op`, encoding the single `index`
-    /// operand as 3 operands.
+    /// Emits instruction `op` that expects one operand `index`. If the operand does
+    /// not fit in the operand of `op`, such as an index over 255, this function emits
+    /// the long version of `op` instead. The operand must fit in one of the two.
     pub(crate) fn emit_operand_instruction(&mut self, op: OpCode, index: usize, line: u32) {
-        if index > 255 {
-            self.emit_byte(op.to_long() as u8, line);
-            self.emit_byte((index & 255) as u8, line);
-            self.emit_byte(((index >> 8) & 255) as u8, line);
-            self.emit_byte(((index >> 16) & 255) as u8, line);
-        } else {
-            self.emit_byte(op as u8, line);
-            self.emit_byte(index as u8, line);
+        let fits = |op: OpCode| index >> (8 * op.operand_width()) == 0;
+        let op = if fits(op) { op } else { op.to_long() };
+        assert!(fits(op), "operand {index} does not fit in {op:?}");
+
+        self.emit_byte(op as u8, line);
+        for i in 0..op.operand_width() {
+            self.emit_byte(((index >> (8 * i)) & 255) as u8, line);
         }
     }
 
-    /// Emits a jump instruction `op` and returns the index that the instruction was
-    /// inserted at
+    /// Emits a jump instruction `op` and returns a handle to pass to
+    /// `patch_jump_instruction` once the jump's target is known.
     pub(crate) fn emit_jump_instruction(&mut self, op: OpCode, line: u32) -> usize {
         self.emit_byte(op as u8, line);
-        // 2 byte operand for jumps
-        self.emit_byte(OpCode::Nop as u8, line);
-        self.emit_byte(OpCode::Nop as u8, line);
+        // Short operand for jumps, widened to the long version when patching if needed
+        let width = op.operand_width() as usize;
+        for _ in 0..width {
+            self.emit_byte(OpCode::Nop as u8, line);
+        }
 
-        self.get_code_length() - 2
+        self.state.jumps.push(JumpSite {
+            offset: self.get_code_length() - 1 - width,
+            target: None,
+        });
+        self.state.jumps.len() - 1
     }
 
-    /// Patches the jump distance
-    pub(crate) fn patch_jump_instruction(&mut self, offset: usize, line: u32) -> Return {
-        let code = &mut self.get_chunk().code;
-        // -2 because our jump instruction has 2 operands
-        let jump_distance = code.len() - offset - 2;
+    /// Patches the jump `jump` to land on the next instruction emitted
+    pub(crate) fn patch_jump_instruction(&mut self, jump: usize, line: u32) -> Return {
+        self.state.jumps[jump].target = Some(self.get_code_length());
+        self.resolve_jump(jump, line)
+    }
 
-        if jump_distance > u16::MAX as usize {
-            return Err(InterpretError::Compile(CompileError::LargeJump(
-                line,
-                jump_distance,
-            )));
-        };
+    /// Emits a jump backwards to `loop_start`
+    pub(crate) fn emit_loop_instruction(&mut self, loop_start: usize, line: u32) -> Return {
+        let jump = self.emit_jump_instruction(OpCode::Loop, line);
+        self.state.jumps[jump].target = Some(loop_start);
+        self.resolve_jump(jump, line)
+    }
 
-        code[offset] = (jump_distance & 255) as u8;
-        code[offset + 1] = ((jump_distance >> 8) & 255) as u8;
+    /// Writes the distance of `jump` into its operand. If the distance does not fit
+    /// in 2 bytes, the jump is widened to its long version, which moves the code
+    /// after it, so any other jump that then no longer fits is widened as well.
+    fn resolve_jump(&mut self, jump: usize, line: u32) -> Return {
+        let mut unresolved = vec![jump];
+
+        while let Some(jump) = unresolved.pop() {
+            if self.write_jump_distance(jump) {
+                continue;
+            }
+
+            let JumpSite { offset, .. } = self.state.jumps[jump];
+            if self.jump_op(offset).info().long.is_none() {
+                return Err(InterpretError::Compile(CompileError::LargeJump(
+                    line,
+                    self.jump_distance(jump),
+                )));
+            }
+
+            unresolved.extend(self.widen_jump(jump));
+            unresolved.push(jump);
+        }
 
         Ok(())
     }
 
-    pub(crate) fn emit_loop_instruction(&mut self, loop_start: usize, line: u32) -> Return {
-        self.emit_byte(OpCode::Loop as u8, line);
-
-        let jump_distance = self.get_code_length() - loop_start + 2;
-        if jump_distance > u16::MAX as usize {
-            return Err(InterpretError::Compile(CompileError::LargeJump(
-                line,
-                jump_distance,
-            )));
+    /// Replaces `jump` with its long version, and returns the already patched
+    /// jumps over it whose distance no longer fits in their operand.
+    fn widen_jump(&mut self, jump: usize) -> Vec<usize> {
+        let offset = self.state.jumps[jump].offset;
+        let op = self.jump_op(offset);
+        let long = op.to_long();
+
+        // Code at or after `index` moves `grown` bytes further
+        let index = offset + 1 + op.operand_width() as usize;
+        let grown = (long.operand_width() - op.operand_width()) as usize;
+        self.get_chunk().insert_bytes(index, grown);
+        self.get_chunk().code[offset] = long as u8;
+
+        let moved = |position: usize| {
+            if position >= index {
+                position + grown
+            } else {
+                position
+            }
         };
+        for site in &mut self.state.jumps {
+            site.offset = moved(site.offset);
+            site.target = site.target.map(moved);
+        }
 
-        self.emit_byte((jump_distance & 255) as u8, line);
-        self.emit_byte(((jump_distance >> 8) & 255) as u8, line);
+        let patched: Vec<usize> = (0..self.state.jumps.len())
+            .filter(|&other| other != jump && self.state.jumps[other].target.is_some())
+            .collect();
+        patched
+            .into_iter()
+            .filter(|&other| !self.write_jump_distance(other))
+            .collect()
+    }
 
-        Ok(())
+    /// Writes the distance of the patched jump `jump` into its operand, returning
+    /// false if it does not fit.
+    fn write_jump_distance(&mut self, jump: usize) -> bool {
+        let offset = self.state.jumps[jump].offset;
+        let width = self.jump_width(offset);
+        let distance = self.jump_distance(jump);
+
+        if distance >> (8 * width) != 0 {
+            return false;
+        }
+
+        let code = &mut self.get_chunk().code;
+        for i in 0..width {
+            code[offset + 1 + i] = ((distance >> (8 * i)) & 255) as u8;
+        }
+        true
+    }
+
+    /// Returns the number of bytes between the end of the patched jump `jump`
+    /// and its target.
+    fn jump_distance(&self, jump: usize) -> usize {
+        let JumpSite { offset, target } = self.state.jumps[jump];
+        let target = target.unwrap_or(offset);
+        let end = offset + 1 + self.jump_width(offset);
+        target.abs_diff(end)
+    }
+
+    /// Returns the jump instruction at `offset`
+    fn jump_op(&self, offset: usize) -> OpCode {
+        OpCode::try_from(self.state.function.chunk.code[offset]).unwrap_or(OpCode::Jump)
+    }
+
+    /// Returns the operand size of the jump instruction at `offset`
+    fn jump_width(&self, offset: usize) -> usize {
+        self.jump_op(offset).operand_width() as usize
     }
 }
@@ -0,0 +1,97 @@
+use crate::{
+    ast::stmt::Stmt,
+    core::errors::{Diagnostic, InterpretError},
+    object::Function,
+    runtime::Symbols,
+};
+
+use super::Compiler;
+
+/// How many top-level statements go into each function by default.
+const DEFAULT_BATCH_SIZE: usize = 64;
+
+/// Starts compiling `statements`, as produced by a [`crate::frontend::Parser`]
+/// reading `source`, a batch of top-level statements at a time, see
+/// [`IncrementalCompiler`].
+pub fn compile_incremental<I>(source: &str, statements: I) -> IncrementalCompiler<'_, I>
+where
+    I: Iterator<Item = Result<Stmt, InterpretError>>,
+{
+    IncrementalCompiler {
+        source,
+        statements,
+        batch_size: DEFAULT_BATCH_SIZE,
+        known_globals: None,
+        strip: false,
+    }
+}
+
+/// Compiles a script into a series of top-level functions, each holding the
+/// next batch of its statements, so that the VM can run one batch while the
+/// parser has yet to reach the rest. Running the functions in order has the
+/// same effect as running the whole script, except that a compile error only
+/// stops the batches from its own onwards.
+///
+/// The VM's symbols are only borrowed while a batch is compiled, leaving the VM
+/// free to run the previous one with [`crate::runtime::execute`].
+pub struct IncrementalCompiler<'s, I> {
+    source: &'s str,
+    statements: I,
+    batch_size: usize,
+    /// Globals defined before the script, and by the batches compiled so far, if
+    /// every global the script uses should be checked for a definition
+    known_globals: Option<Vec<String>>,
+    strip: bool,
+}
+
+impl<I> IncrementalCompiler<'_, I>
+where
+    I: Iterator<Item = Result<Stmt, InterpretError>>,
+{
+    /// Puts up to `size` top-level statements in each function, at least one.
+    pub fn with_batch_size(mut self, size: usize) -> Self {
+        self.batch_size = size.max(1);
+        self
+    }
+
+    /// Rejects uses of globals that are neither in `known` nor defined by the
+    /// batch or an earlier one, see [`Compiler::with_known_globals`]. As a batch
+    /// is checked before later ones are parsed, a function may not use a global
+    /// declared further down the script.
+    pub fn with_known_globals(mut self, known: Vec<String>) -> Self {
+        self.known_globals = Some(known);
+        self
+    }
+
+    /// Leaves out assertions and debug statements, see [`Compiler::with_strip`].
+    pub fn with_strip(mut self) -> Self {
+        self.strip = true;
+        self
+    }
+
+    /// Parses the next batch of statements and compiles it into a top-level
+    /// function, numbering globals in `symbols` and adding warnings to
+    /// `diagnostics`. Returns `None` once the script is used up.
+    pub fn next_function(
+        &mut self,
+        symbols: &mut Symbols,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Option<Result<Function, Vec<InterpretError>>> {
+        let batch: Vec<_> = self.statements.by_ref().take(self.batch_size).collect();
+        if batch.is_empty() {
+            return None;
+        }
+
+        let mut compiler = Compiler::new(symbols);
+        compiler.source = self.source;
+        compiler.known_globals = self.known_globals.clone();
+        compiler.strip = self.strip;
+
+        let errors = compiler.compile_statements(batch);
+        if let Some(known) = &compiler.known_globals {
+            let defined = known.iter().chain(&compiler.global_defs).cloned();
+            self.known_globals = Some(defined.collect());
+        }
+        Some(compiler.finish(errors, diagnostics))
+    }
+}
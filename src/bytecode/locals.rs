@@ -1,9 +1,12 @@
-use crate::core::{
-    errors::{CompileError, InterpretError},
-    OpCode,
+use crate::{
+    ast::stmt::Stmt,
+    core::{
+        errors::{CompileError, Diagnostic, InterpretError, Warning},
+        OpCode,
+    },
 };
 
-use super::{Compiler, Return};
+use super::{Compiler, FunctionState, LocalName, Return, LONG_OPERAND_MAX, UPVALUE_MAX};
 
 #[derive(Debug)]
 pub struct Local {
@@ -11,20 +14,35 @@ pub struct Local {
     depth: usize,
     init: bool,
     is_captured: bool,
+    /// The line the local was declared on
+    line: u32,
+    /// Whether the local is ever referenced, or should be treated as if it were
+    used: bool,
+    is_function: bool,
+    /// The index of the first byte of code run with the local defined
+    start: usize,
 }
 
 pub struct CompilerUpvalue {
     pub(crate) index: usize,
     pub(crate) is_local: bool,
+    /// The name of the captured variable
+    pub(crate) name: String,
 }
 
 impl Local {
-    pub fn new(name: String, depth: usize) -> Self {
+    pub fn new(name: String, depth: usize, line: u32) -> Self {
+        // Names starting with '_' are unused on purpose
+        let used = name.starts_with('_');
         Self {
             name,
             depth,
             init: false,
             is_captured: false,
+            line,
+            used,
+            is_function: false,
+            start: 0,
         }
     }
 
@@ -39,129 +57,297 @@ impl Local {
 
 impl Compiler<'_> {
     pub(crate) fn begin_scope(&mut self) {
-        self.scope_depth += 1;
+        self.state.scope_depth += 1;
     }
 
     pub(crate) fn end_scope(&mut self) {
-        self.scope_depth -= 1;
+        self.state.scope_depth -= 1;
 
         let index = self
+            .state
             .locals
             .iter()
-            .rposition(|l| l.depth <= self.scope_depth)
+            .rposition(|l| l.depth <= self.state.scope_depth)
             .unwrap_or(0);
 
-        let to_remove = self.locals.split_off(index + 1);
+        let to_remove = self.state.locals.split_off(index + 1);
+        self.state
+            .late_bound
+            .retain(|(_, depth, _)| *depth <= self.state.scope_depth);
 
-        self.remove_locals(to_remove);
+        self.warn_unused(&to_remove);
+        self.record_local_names(index + 1, &to_remove);
+        self.remove_locals(index + 1, to_remove);
     }
 
-    pub(crate) fn remove_locals(&mut self, locals: Vec<Local>) {
-        for local in locals.iter().rev() {
+    /// Records the names of `locals`, which start at `first_slot` and go out of
+    /// scope at the current end of the chunk, if compiling with debug symbols.
+    pub(crate) fn record_local_names(&mut self, first_slot: usize, locals: &[Local]) {
+        let end = self.get_code_length();
+        let Some(debug) = &mut self.state.function.chunk.debug else {
+            return;
+        };
+
+        for (i, local) in locals.iter().enumerate() {
+            debug.locals.push(LocalName {
+                slot: first_slot + i,
+                name: local.name.clone(),
+                start: local.start,
+                end,
+            });
+        }
+    }
+
+    /// Reports a warning for each of `locals` that was never referenced.
+    pub(crate) fn warn_unused(&mut self, locals: &[Local]) {
+        for local in locals.iter().filter(|l| !l.used) {
+            let warning = if local.is_function {
+                Warning::UnusedFunction(local.line, local.name.clone())
+            } else {
+                Warning::UnusedVariable(local.line, local.name.clone())
+            };
+            self.state.diagnostics.push(Diagnostic::Warning(warning));
+        }
+    }
+
+    /// Pops `locals`, which start at `first_slot`, off the stack, closing the
+    /// upvalues of those that are captured.
+    pub(crate) fn remove_locals(&mut self, first_slot: usize, locals: Vec<Local>) {
+        for (i, local) in locals.iter().enumerate().rev() {
             if local.is_captured {
-                self.emit_byte(OpCode::CloseUpvalue as u8, 0);
+                self.emit_operand_instruction(OpCode::CloseUpvalue, first_slot + i, self.line);
             } else {
-                self.emit_byte(OpCode::Pop as u8, 0);
+                self.emit_byte(OpCode::Pop as u8, self.line);
             }
         }
     }
 
+    /// Declares the variable `name` in the current scope. Globals are looked up
+    /// by name when used, so only locals need declaring ahead of their value.
+    pub(crate) fn declare_variable(&mut self, name: &str, line: u32) -> Return {
+        if self.state.scope_depth == 0 {
+            return Ok(());
+        }
+
+        self.declare_local(name.to_string(), line)
+    }
+
+    /// Defines the variable `name` declared by [`Self::declare_variable`], whose
+    /// value is on top of the stack. A global is popped into its symbol's slot,
+    /// while a local keeps the value in its stack slot.
+    pub(crate) fn define_variable(&mut self, name: &str, line: u32) {
+        if self.state.scope_depth > 0 {
+            self.define_local();
+            return;
+        }
+
+        self.global_defs.push(name.to_string());
+        let symbol = self.symbols.intern(name);
+        self.emit_operand_instruction(OpCode::DefineGlobal, symbol as usize, line);
+    }
+
     /// Declares a local variable `name` with the current scope depth, storing
     /// it into the internal locals array
     pub(crate) fn declare_local(&mut self, name: String, line: u32) -> Return {
-        if self.scope_depth == 0 {
-            return Ok(());
+        if self.state.locals.len() > LONG_OPERAND_MAX {
+            return Err(InterpretError::Compile(CompileError::TooManyLocals(
+                line,
+                self.state.locals.len() + 1,
+            )));
         }
 
         if self
+            .state
             .locals
             .iter()
-            .any(|l| l.depth == self.scope_depth && l.name == name)
+            .any(|l| l.depth == self.state.scope_depth && l.name == name)
         {
             return Err(InterpretError::Compile(CompileError::AlreadyDeclared(
                 line, name,
             )));
         }
 
-        self.locals.push(Local::new(name, self.scope_depth));
+        if let Some((_, _, used_at)) = self
+            .state
+            .late_bound
+            .iter()
+            .find(|(n, depth, _)| *depth == self.state.scope_depth && *n == name)
+        {
+            let warning = Warning::LateLocal(line, name.clone(), *used_at);
+            self.state.diagnostics.push(Diagnostic::Warning(warning));
+        }
+
+        self.state
+            .locals
+            .push(Local::new(name, self.state.scope_depth, line));
 
         Ok(())
     }
 
     pub(crate) fn define_local(&mut self) {
-        if self.scope_depth == 0 {
+        let last = self.state.locals.len() - 1;
+        self.state.locals[last].start = self.get_code_length();
+        self.state.locals[last].initialize();
+    }
+
+    /// Marks the most recently declared local as a function, for diagnostics.
+    /// Does nothing for globals, which are never declared as locals.
+    pub(crate) fn mark_function(&mut self) {
+        if self.state.scope_depth == 0 {
             return;
         }
 
-        let last = self.locals.len() - 1;
-        self.locals[last].initialize();
+        if let Some(local) = self.state.locals.last_mut() {
+            local.is_function = true;
+        }
+    }
+
+    /// Exempts the most recently declared local from unused warnings, for locals
+    /// such as parameters that are not declared by the user's statements.
+    pub(crate) fn mark_used(&mut self) {
+        if let Some(local) = self.state.locals.last_mut() {
+            local.used = true;
+        }
+    }
+
+    /// Exempts every local in scope, including those of enclosing functions, from
+    /// unused warnings, as a statement that failed to parse may have used them.
+    pub(crate) fn mark_all_used(&mut self) {
+        let states = self.enclosing.iter_mut().chain([&mut self.state]);
+        for local in states.flat_map(|state| &mut state.locals) {
+            local.used = true;
+        }
+    }
+
+    /// Reserves a nil local for every function declared directly in `statements`,
+    /// so that functions in a scope can refer to ones declared later in it.
+    pub(crate) fn hoist_functions(&mut self, statements: &[Stmt]) -> Return {
+        if self.state.scope_depth == 0 {
+            return Ok(());
+        }
+
+        for stmt in statements {
+            if let Stmt::DeclareFunc(id, _, _) = stmt {
+                self.emit_byte(OpCode::Nil as u8, id.line);
+                self.declare_local(self.lexeme(id).to_string(), id.line)?;
+                self.define_local();
+                self.mark_function();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the slot reserved for the function `name` by [`Self::hoist_functions`]
+    /// in the current scope.
+    pub(crate) fn resolve_hoisted(&self, name: &str) -> Option<usize> {
+        if self.state.scope_depth == 0 {
+            return None;
+        }
+
+        // Any other local with this name in the current scope would have been
+        // rejected as a redeclaration, so a match must be the hoisted slot.
+        self.state
+            .locals
+            .iter()
+            .rposition(|l| l.depth == self.state.scope_depth && l.name == name)
     }
 
     pub(crate) fn resolve_local(
-        &self,
+        &mut self,
+        name: &str,
+        line: u32,
+    ) -> Result<Option<usize>, InterpretError> {
+        self.state.resolve_local(name, line)
+    }
+
+    pub(crate) fn resolve_upvalue(
+        &mut self,
         name: &str,
         line: u32,
     ) -> Result<Option<usize>, InterpretError> {
+        self.state.resolve_upvalue(&mut self.enclosing, name, line)
+    }
+}
+
+impl FunctionState {
+    fn resolve_local(&mut self, name: &str, line: u32) -> Result<Option<usize>, InterpretError> {
         match self.locals.iter().rposition(|l| l.name == *name) {
             None => Ok(None),
             Some(index) => {
-                let local = self.locals.get(index).unwrap();
+                let local = self.locals.get_mut(index).unwrap();
                 if !local.init {
                     Err(InterpretError::Compile(CompileError::SelfInitialization(
                         line,
                     )))
                 } else {
+                    local.used = true;
                     Ok(Some(index))
                 }
             }
         }
     }
 
-    pub(crate) fn resolve_upvalue(
+    /// Resolves `name` as an upvalue of this function, captured from the
+    /// innermost of `enclosing`, the states of the functions it is nested in.
+    fn resolve_upvalue(
         &mut self,
+        enclosing: &mut [FunctionState],
         name: &str,
         line: u32,
     ) -> Result<Option<usize>, InterpretError> {
-        match self.enclosing {
+        let Some((outer, rest)) = enclosing.split_last_mut() else {
+            return Ok(None);
+        };
+
+        if let Some(stack_index) = outer.resolve_local(name, line)? {
+            outer.locals[stack_index].capture();
+            let i = self.add_upvalue(stack_index, true, name, line)?;
+            return Ok(Some(i));
+        }
+        match outer.resolve_upvalue(rest, name, line)? {
+            Some(index) => Ok(Some(self.add_upvalue(index, false, name, line)?)),
             None => Ok(None),
-            Some(enclosing) => {
-                let local = unsafe { (*enclosing).resolve_local(name, line)? };
-                match local {
-                    Some(stack_index) => {
-                        unsafe {
-                            (*enclosing).locals[stack_index].capture();
-                        }
-                        let i = self.add_upvalue(stack_index, true);
-                        Ok(Some(i))
-                    }
-                    None => {
-                        let upvalue = unsafe { (*enclosing).resolve_upvalue(name, line) }?;
-                        match upvalue {
-                            Some(stack_index) => Ok(Some(self.add_upvalue(stack_index, false))),
-                            None => Ok(None),
-                        }
-                    }
-                }
-            }
         }
     }
 
-    fn add_upvalue(&mut self, stack_index: usize, is_local: bool) -> usize {
+    fn add_upvalue(
+        &mut self,
+        stack_index: usize,
+        is_local: bool,
+        name: &str,
+        line: u32,
+    ) -> Result<usize, InterpretError> {
         let existing_index = self
             .upvalues
             .iter()
             .position(|up| up.index == stack_index && up.is_local == is_local);
 
-        match existing_index {
-            Some(index) => index,
-            None => {
-                self.upvalues.push(CompilerUpvalue {
-                    index: stack_index,
-                    is_local,
-                });
-                self.function.upvalue_count += 1;
-                self.upvalues.len() - 1
-            }
+        if let Some(index) = existing_index {
+            return Ok(index);
         }
+
+        if self.upvalues.len() >= UPVALUE_MAX {
+            return Err(InterpretError::Compile(CompileError::TooManyUpvalues(
+                line,
+                self.upvalues.len() + 1,
+            )));
+        }
+        // Locals past the long-operand limit of other instructions can still be
+        // read and written, but not captured
+        if is_local && stack_index >= UPVALUE_MAX {
+            return Err(InterpretError::Compile(CompileError::TooManyLocals(
+                line,
+                stack_index + 1,
+            )));
+        }
+
+        self.upvalues.push(CompilerUpvalue {
+            index: stack_index,
+            is_local,
+            name: name.to_string(),
+        });
+        self.function.upvalue_count += 1;
+        Ok(self.upvalues.len() - 1)
     }
 }
@@ -1,21 +1,61 @@
+//! The third stage of the pipeline: [`compile`] turns [`Stmt`]s into a
+//! [`Function`] whose [`Chunk`] holds the script's bytecode.
+
 mod chunk;
 mod compiler;
+mod debug;
+mod depth;
 mod emitter;
+mod incremental;
 mod locals;
+pub mod peephole;
 
-pub use chunk::Chunk;
+pub use crate::core::{OpCode, OpInfo, Operand, StackEffect};
+pub use chunk::{Chunk, Constant, LineInfo};
+pub use debug::{DebugInfo, LocalName, Span};
+pub use incremental::{compile_incremental, IncrementalCompiler};
 
 use crate::{
     ast::{expr::Expr, stmt::Stmt},
-    core::{errors::InterpretError, OpCode},
-    frontend::Parser,
+    core::{
+        errors::{CompileError, Diagnostic, InterpretError},
+        shared::Shared,
+        token::Token,
+    },
     object::Function,
-    runtime::{Heap, FRAME_MAX},
+    runtime::{Symbols, FRAME_MAX},
 };
+use emitter::JumpSite;
 use locals::{CompilerUpvalue, Local};
+use peephole::Peephole;
 
 type Return = Result<(), InterpretError>;
 
+/// Upvalues, and the stack slots of the locals they capture, are encoded in a
+/// single byte with no long form.
+pub const UPVALUE_MAX: usize = 256;
+/// The largest operand of a long instruction, such as [`OpCode::LoadConstantLong`].
+pub const LONG_OPERAND_MAX: usize = (1 << 24) - 1;
+/// How deeply statements and expressions may be nested by default before the
+/// compiler gives up, rather than overflow the stack on a generated syntax tree.
+/// Chains of binary operators, such as `a + b + c`, are compiled in a loop and
+/// only count once.
+pub const MAX_COMPILE_DEPTH: usize = 256;
+
+/// Compiles `statements`, as produced by [`crate::frontend::parse`] from
+/// `source`, into the
+/// top-level function of a script. Globals are numbered in `symbols`, which must
+/// be the symbols of the VM that will load it with [`VM::load`](crate::VM::load).
+/// Warnings are added to `diagnostics`, whether or not compilation succeeds.
+pub fn compile(
+    source: &str,
+    statements: Vec<Stmt>,
+    symbols: &mut Symbols,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Result<Function, Vec<InterpretError>> {
+    Compiler::new(symbols).compile(source, statements.into_iter().map(Ok), diagnostics)
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 #[repr(u8)]
 enum FunctionType {
@@ -23,63 +63,432 @@ enum FunctionType {
     Function,
 }
 
+/// Compiles statements into the bytecode of a script's top-level function, see
+/// [`compile`].
+///
+/// Functions are compiled one at a time: the compiler keeps the state of the
+/// function it is in, and of each function enclosing it, on a stack.
 pub struct Compiler<'a> {
-    statements: Parser<'a>,
+    /// The function being compiled
+    state: FunctionState,
+    /// The functions the one being compiled is nested in, innermost last
+    enclosing: Vec<FunctionState>,
+    /// The global names and function ids of the VM the program is compiled for
+    symbols: &'a mut Symbols,
+    /// The source code of the statements being compiled, which their tokens
+    /// point into
+    source: &'a str,
+    /// Globals defined by top-level declarations
+    global_defs: Vec<String>,
+    /// Globals defined before this program, if every global the program uses
+    /// should be checked for a definition
+    known_globals: Option<Vec<String>>,
+    /// The column of the expression or statement being compiled, recorded with
+    /// each byte emitted
+    column: u32,
+    /// The line of the last statement compiled, given to the bytecode the
+    /// compiler adds on its own, like the pops at the end of a block
+    line: u32,
+    /// Whether to leave out `assert(...)` calls and statements marked `//@debug`
+    strip: bool,
+    /// How many statements and expressions enclose the one being compiled
+    depth: usize,
+    /// How many statements and expressions may enclose the one being compiled
+    max_depth: usize,
+    /// The rewrites made to the code of each function once it is compiled
+    peephole: Peephole,
+    /// Whether the parser has reported a syntax error in the program
+    syntax_errors: bool,
+}
+
+/// What the compiler keeps track of for each function it is in.
+struct FunctionState {
     function_type: FunctionType,
     function: Function,
-    heap: Option<&'a mut Heap>,
     /// The depth of nested scopes the compiler is currently in, 0 is the global scope
     scope_depth: usize,
     locals: Vec<Local>,
     upvalues: Vec<CompilerUpvalue>,
-    enclosing: Option<*mut Self>,
+    /// Every jump emitted into this function's chunk
+    jumps: Vec<JumpSite>,
+    /// Names this function, or a function nested in it, looks up as globals, with
+    /// the line of the lookup
+    global_refs: Vec<(String, u32)>,
+    /// The `global_refs` of functions declared in the currently open scopes, as
+    /// (name, scope depth of the function, line). A local declared later in the
+    /// same scope with one of these names is not seen by the function, which is
+    /// worth a warning.
+    late_bound: Vec<(String, usize, u32)>,
+    /// Warnings found so far, including those of nested functions
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl FunctionState {
+    /// Starts compiling `function`, whose body is a scope of its own unless it
+    /// is the top-level function.
+    fn new(function: Function, function_type: FunctionType) -> Self {
+        let (scope_depth, locals) = match function_type {
+            // The top-level function's slot is reserved
+            FunctionType::Main => (0, vec![Local::new("".to_string(), 0, 0)]),
+            FunctionType::Function => (1, vec![]),
+        };
+        Self {
+            function_type,
+            function,
+            scope_depth,
+            locals,
+            upvalues: Vec::new(),
+            jumps: Vec::new(),
+            global_refs: Vec::new(),
+            late_bound: Vec::new(),
+            diagnostics: Vec::new(),
+        }
+    }
 }
 
 impl<'a> Compiler<'a> {
-    pub fn new(statements: Parser<'a>, heap: &'a mut Heap) -> Self {
+    /// Creates a compiler for programs to run on the VM whose symbols are
+    /// `symbols`, see [`VM::symbols_mut`](crate::VM::symbols_mut).
+    pub fn new(symbols: &'a mut Symbols) -> Self {
+        let mut function = Function::new("main".to_string(), 0);
+        function.id = symbols.next_function_id();
+
         Compiler {
-            statements,
-            heap: Some(heap),
-            function: Function::new("main".to_string(), 0),
-            scope_depth: 0,
-            locals: vec![Local::new("".to_string(), 0)],
-            function_type: FunctionType::Main,
-            upvalues: Vec::with_capacity(FRAME_MAX),
-            enclosing: None,
+            state: FunctionState::new(function, FunctionType::Main),
+            enclosing: Vec::with_capacity(FRAME_MAX),
+            symbols,
+            source: "",
+            global_defs: Vec::new(),
+            known_globals: None,
+            column: 0,
+            line: 1,
+            strip: false,
+            depth: 0,
+            max_depth: MAX_COMPILE_DEPTH,
+            peephole: Peephole::default(),
+            syntax_errors: false,
         }
     }
 
-    /// Compiles the statements in the compiler into a chunk of bytecode to be used
-    /// by the virtual machine. This function consumes the compiler instance.
-    pub fn compile(mut self) -> Result<Function, Vec<InterpretError>> {
+    /// Makes the compiler reject uses of globals that are neither defined by the
+    /// program nor in `known`.
+    pub fn with_known_globals(mut self, known: Vec<String>) -> Self {
+        self.known_globals = Some(known);
+        self
+    }
+
+    /// Makes the compiler leave out statements that only call `assert`, and
+    /// statements marked with a `//@debug` comment, as if they were not there.
+    pub fn with_strip(mut self) -> Self {
+        self.strip = true;
+        self
+    }
+
+    /// Makes the compiler reject statements and expressions nested more than
+    /// `limit` levels deep, instead of [`MAX_COMPILE_DEPTH`].
+    pub fn with_max_depth(mut self, limit: usize) -> Self {
+        self.max_depth = limit;
+        self
+    }
+
+    /// Makes the compiler rewrite the code of each function with `peephole`
+    /// instead of every rule in [`peephole`](self::peephole). An empty
+    /// [`Peephole::new`] leaves the code as compiled.
+    pub fn with_peephole(mut self, peephole: Peephole) -> Self {
+        self.peephole = peephole;
+        self
+    }
+
+    /// Makes the compiler record [`DebugInfo`] in the chunk of every function,
+    /// such as which local each slot holds, so [`Chunk::disassembly`] can show
+    /// variables by name.
+    pub fn with_debug_symbols(mut self) -> Self {
+        self.state.function.chunk.debug = Some(DebugInfo::default());
+        self
+    }
+
+    /// Compiles `statements`, parsed from `source`, into a chunk of bytecode to
+    /// be used by the virtual machine, adding any warnings to `diagnostics`. This
+    /// function consumes the compiler instance.
+    pub fn compile(
+        mut self,
+        source: &'a str,
+        statements: impl IntoIterator<Item = Result<Stmt, InterpretError>>,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Result<Function, Vec<InterpretError>> {
+        self.source = source;
+        let errors = self.compile_statements(statements);
+        self.finish(errors, diagnostics)
+    }
+
+    /// Compiles `statements` into the chunk, returning the errors found.
+    fn compile_statements(
+        &mut self,
+        statements: impl IntoIterator<Item = Result<Stmt, InterpretError>>,
+    ) -> Vec<InterpretError> {
         let mut errors = vec![];
 
-        while let Some(stmt) = self.statements.next() {
+        let mut statements = statements.into_iter().peekable();
+        while let Some(stmt) = statements.next() {
+            let last = statements.peek().is_none();
             match stmt {
                 Ok(stmt) => {
-                    if let Err(e) = self.compile_stmt(stmt) {
+                    let compiled = match stmt {
+                        Stmt::Expr(token, expr) if last => self.compile_result(token, expr),
+                        stmt => self.compile_stmt(stmt),
+                    };
+                    if let Err(e) = compiled {
                         errors.push(e);
+                        // The statement may have stopped inside a scope, so compile the
+                        // next one from the top level
+                        self.state.scope_depth = 0;
+                        self.state.locals.truncate(1);
+                        self.state.late_bound.clear();
                     }
                 }
                 Err(e) => {
+                    self.syntax_errors |= matches!(e, InterpretError::Syntax(_));
                     errors.push(e);
                 }
             }
         }
+        errors
+    }
+
+    /// Ends the compiled function with a return, unless `errors` were found,
+    /// adding any warnings to `diagnostics`.
+    fn finish(
+        mut self,
+        mut errors: Vec<InterpretError>,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Result<Function, Vec<InterpretError>> {
+        diagnostics.append(&mut self.state.diagnostics);
+        errors.extend(self.check_globals());
 
         if !errors.is_empty() {
             return Err(errors);
         }
 
-        self.emit_byte(OpCode::Return as u8, 2);
-        Ok(self.function)
+        // Attribute the implicit return to the end of the script
+        let line = self
+            .state
+            .function
+            .chunk
+            .lines
+            .last()
+            .map_or(self.line, |(info, _)| info.line);
+        self.emit_byte(OpCode::Return as u8, line);
+        self.peephole.optimize(&mut self.state.function.chunk);
+        self.finish_max_stack();
+        let start = self.state.function.chunk.line_info(0);
+        self.finish_debug_info(start.unwrap_or(LineInfo { line, column: 0 }));
+        Ok(self.state.function)
+    }
+
+    /// Fills in the debug info that is only known once the function is compiled,
+    /// if compiling with debug symbols. `start` is where the function begins, and
+    /// it ends with the furthest code compiled into it.
+    fn finish_debug_info(&mut self, start: LineInfo) {
+        let chunk = &mut self.state.function.chunk;
+        let end = chunk
+            .lines
+            .iter()
+            .map(|(info, _)| *info)
+            .chain([start])
+            .max_by_key(|info| (info.line, info.column))
+            .unwrap_or(start);
+        if let Some(debug) = &mut chunk.debug {
+            debug.span = Span { start, end };
+            debug.upvalues = self
+                .state
+                .upvalues
+                .iter()
+                .map(|up| up.name.clone())
+                .collect();
+        }
+    }
+
+    /// Records the most stack values the function needs, which the VM checks and
+    /// reserves before calling it.
+    fn finish_max_stack(&mut self) {
+        // The function's own slot, and its arguments
+        let base = self.state.function.arity as usize + 1;
+        self.state.function.max_stack = depth::max_stack(&self.state.function.chunk, base);
+    }
+
+    /// Reports the first use of every global that is never defined, if the
+    /// compiler was created [with known globals](Self::with_known_globals).
+    fn check_globals(&self) -> Vec<InterpretError> {
+        let Some(known) = &self.known_globals else {
+            return vec![];
+        };
+
+        let mut refs = self.state.global_refs.clone();
+        refs.sort_by_key(|(_, line)| *line);
+
+        let mut reported: Vec<&str> = vec![];
+        let mut errors = vec![];
+        for (name, line) in &refs {
+            let defined = known.contains(name) || self.global_defs.contains(name);
+            if defined || reported.contains(&name.as_str()) {
+                continue;
+            }
+            reported.push(name);
+            errors.push(InterpretError::Compile(CompileError::UndefinedGlobal(
+                *line,
+                name.clone(),
+            )));
+        }
+        errors
+    }
+
+    /// Returns the text of `token` in the source code being compiled.
+    fn lexeme(&self, token: &Token) -> &'a str {
+        token.lexeme(self.source)
+    }
+
+    /// Runs `compile` one level of nesting deeper, failing with an error on
+    /// `line` if the code is nested more than `max_depth` levels.
+    fn nested(&mut self, line: u32, compile: impl FnOnce(&mut Self) -> Return) -> Return {
+        if self.depth >= self.max_depth {
+            return Err(InterpretError::Compile(CompileError::TooDeeplyNested(line)));
+        }
+
+        self.depth += 1;
+        let compiled = compile(self);
+        self.depth -= 1;
+        compiled
     }
 
     fn compile_expr(&mut self, expression: Expr) -> Return {
-        expression.accept(self)
+        let token = expression.token();
+        let column = std::mem::replace(&mut self.column, token.column.into());
+        let compiled = self.nested(token.line, |compiler| expression.accept(compiler));
+        self.column = column;
+        compiled
     }
 
     fn compile_stmt(&mut self, statement: Stmt) -> Return {
-        statement.accept(self)
+        let column = self.column;
+        let mut line = 0;
+        if let Some(token) = statement.token() {
+            self.column = token.column.into();
+            self.line = token.line;
+            line = token.line;
+        }
+        let compiled = self.nested(line, |compiler| statement.accept(compiler));
+        self.column = column;
+        compiled
+    }
+
+    /// Compiles the final expression statement of a script without popping its
+    /// value, so that the script returns it.
+    fn compile_result(&mut self, token: Token, expr: Expr) -> Return {
+        if self.strip && expr.is_assert_call(self.source) {
+            return Ok(());
+        }
+        self.line = token.line;
+        self.compile_expr(expr)?;
+        self.state.function.returns_value = true;
+        Ok(())
+    }
+
+    /// Compiles `statement`, which can never run, only for its errors and
+    /// warnings, leaving none of its code in the chunk.
+    fn compile_unreachable(&mut self, statement: Stmt) -> Return {
+        let offset = self.get_code_length();
+        let constants = self.get_chunk().constants.len();
+        self.compile_stmt(statement)?;
+        self.discard_code(offset, constants);
+        Ok(())
+    }
+
+    /// Compiles the parameters and body of the function whose state is on top,
+    /// declared by `id`.
+    fn compile_function_body(&mut self, id: &Token, params: Vec<Token>, body: Vec<Stmt>) -> Return {
+        // [ <fn> ] [ arg1 ] [ arg2 ]
+        self.declare_local(self.lexeme(id).to_string(), id.line)?;
+        self.define_local();
+        self.mark_used();
+        for param in params {
+            self.declare_local(self.lexeme(&param).to_string(), param.line)?;
+            self.define_local();
+            self.mark_used();
+        }
+        self.hoist_functions(&body)?;
+        for stmt in body {
+            self.compile_stmt(stmt)?;
+        }
+
+        // Default 'return nil'. Frame exits at first return, so it will not run if there
+        // is already a return in the function
+        self.emit_byte(OpCode::Nil as u8, id.line);
+        self.emit_byte(OpCode::Return as u8, id.line);
+        self.peephole.optimize(&mut self.state.function.chunk);
+        self.finish_max_stack();
+
+        // The function's outermost scope is never ended, so check its locals here
+        let locals = std::mem::take(&mut self.state.locals);
+        self.warn_unused(&locals);
+        self.record_local_names(0, &locals);
+        self.finish_debug_info(LineInfo {
+            line: id.line,
+            column: id.column.into(),
+        });
+        Ok(())
+    }
+
+    /// Compiles a function named `name` with its own state, then emits the
+    /// `Closure` instruction that creates it at runtime. `id` names the local in
+    /// the function's own slot, through which it can call itself, and gives the
+    /// line it is declared on.
+    fn compile_function(
+        &mut self,
+        name: String,
+        id: &Token,
+        params: Vec<Token>,
+        body: Vec<Stmt>,
+    ) -> Return {
+        let mut function = Function::new(name, params.len() as u8);
+        function.id = self.symbols.next_function_id();
+        function.line = id.line;
+        if self.state.function.chunk.debug.is_some() {
+            function.chunk.debug = Some(DebugInfo::default());
+        }
+
+        let state = FunctionState::new(function, FunctionType::Function);
+        self.enclosing
+            .push(std::mem::replace(&mut self.state, state));
+        let column = std::mem::replace(&mut self.column, id.column.into());
+        let compiled = self.compile_function_body(id, params, body);
+        self.column = column;
+
+        // Go back to the enclosing function, even if this one failed to compile
+        let outer = self
+            .enclosing
+            .pop()
+            .expect("the enclosing function's state");
+        let mut inner = std::mem::replace(&mut self.state, outer);
+        compiled?;
+
+        self.state.diagnostics.append(&mut inner.diagnostics);
+        for (name, line) in inner.global_refs {
+            if self.state.scope_depth > 0 {
+                self.state
+                    .late_bound
+                    .push((name.clone(), self.state.scope_depth, line));
+            }
+            self.state.global_refs.push((name, line));
+        }
+
+        let function = Constant::Function(Shared::new(inner.function));
+        self.emit_constant_instruction(OpCode::Closure, function, id.line)?;
+
+        for upvalue in inner.upvalues {
+            self.emit_byte(if upvalue.is_local { 1 } else { 0 } as u8, id.line);
+            self.emit_byte(upvalue.index as u8, id.line);
+        }
+
+        Ok(())
     }
 }
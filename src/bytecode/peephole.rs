@@ -0,0 +1,462 @@
+//! A peephole optimizer, which rewrites short runs of instructions in a compiled
+//! [`Chunk`] into fewer or cheaper ones. What it rewrites is up to the [`Rule`]s
+//! it is given; [`Peephole::default`] has every rule in this module.
+//!
+//! Jumps are followed through the rewrite: a run of instructions is only
+//! replaced when nothing jumps into the middle of it, and jump distances are
+//! worked out again once the chunk is written back.
+
+use crate::core::{OpCode, Operand};
+
+use super::chunk::{Chunk, Constant, LineInfo};
+
+/// An instruction of the chunk being optimized.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    /// The opcode, always the short version; the long one is picked when the
+    /// chunk is written back, if the operand needs it
+    pub op: OpCode,
+    /// The operand, or for a jump, the index of the instruction it lands on
+    pub operand: usize,
+    /// The upvalue bytes that follow a `Closure`
+    pub upvalues: Vec<u8>,
+    /// Where in the source the instruction was compiled from
+    pub info: LineInfo,
+}
+
+impl Instruction {
+    /// Creates an instruction with no upvalue bytes.
+    pub fn new(op: OpCode, operand: usize, info: LineInfo) -> Self {
+        Self {
+            op,
+            operand,
+            upvalues: Vec::new(),
+            info,
+        }
+    }
+
+    /// Whether the operand is the index of the instruction jumped to.
+    pub fn is_jump(&self) -> bool {
+        self.op.info().operand == Operand::Jump
+    }
+}
+
+/// The place in a chunk's code where a [`Rule`] is tried.
+pub struct Window<'a> {
+    /// Every instruction of the chunk
+    pub code: &'a [Instruction],
+    /// The index in `code` of the first instruction the rule may replace
+    pub at: usize,
+    /// How many instructions from `at` on may be replaced: up to the next one
+    /// that something jumps to
+    run: usize,
+}
+
+impl<'a> Window<'a> {
+    /// Returns the instructions from `at` on that may be replaced.
+    pub fn replaceable(&self) -> &'a [Instruction] {
+        &self.code[self.at..self.at + self.run]
+    }
+
+    /// Returns the next `n` instructions from `at` on, if all may be replaced.
+    pub fn take(&self, n: usize) -> Option<&'a [Instruction]> {
+        self.replaceable().get(..n)
+    }
+}
+
+/// A rewrite of some runs of instructions into equivalent ones.
+pub trait Rule {
+    /// Returns how many instructions to replace from the start of `window`, at
+    /// most as many as [`Window::replaceable`] returns, and what to replace them
+    /// with, if the rule applies there. The replacement may load constants the
+    /// rule adds to `constants`.
+    fn rewrite(
+        &self,
+        window: &Window,
+        constants: &mut Vec<Constant>,
+    ) -> Option<(usize, Vec<Instruction>)>;
+}
+
+/// Applies a list of [`Rule`]s to chunks until none of them applies anywhere.
+pub struct Peephole {
+    rules: Vec<Box<dyn Rule>>,
+}
+
+impl Default for Peephole {
+    /// A peephole optimizer with every rule in this module.
+    fn default() -> Self {
+        Self::new()
+            .with_rule(PopLocal)
+            .with_rule(InvertJump)
+            .with_rule(NegateConstant)
+            .with_rule(PopN)
+    }
+}
+
+impl Peephole {
+    /// Creates a peephole optimizer with no rules, which leaves chunks as they are.
+    pub fn new() -> Self {
+        Self { rules: Vec::new() }
+    }
+
+    /// Adds `rule`, which is tried after the rules added before it.
+    pub fn with_rule(mut self, rule: impl Rule + 'static) -> Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    /// Rewrites the code of `chunk` with the rules, keeping its line info and
+    /// the ranges of its locals in step.
+    pub fn optimize(&self, chunk: &mut Chunk) {
+        if self.rules.is_empty() {
+            return;
+        }
+
+        let (mut code, index_at) = decode(chunk);
+        // The instruction each original one ended up in
+        let mut origin: Vec<usize> = (0..=code.len()).collect();
+
+        loop {
+            let (rewritten, moved) = self.pass(&code, &mut chunk.constants);
+            let Some(rewritten) = rewritten else {
+                break;
+            };
+            code = rewritten;
+            for index in &mut origin {
+                *index = moved[*index];
+            }
+        }
+
+        let offsets = encode(chunk, &code);
+        if let Some(debug) = &mut chunk.debug {
+            let offset = |old: usize| offsets[origin[index_at[old.min(index_at.len() - 1)]]];
+            for local in &mut debug.locals {
+                local.start = offset(local.start);
+                local.end = offset(local.end);
+            }
+        }
+    }
+
+    /// Tries the rules at every instruction of `code` once. Returns the code
+    /// rewritten, if any rule applied, and the index in it of each instruction
+    /// of `code`, and of its end.
+    fn pass(
+        &self,
+        code: &[Instruction],
+        constants: &mut Vec<Constant>,
+    ) -> (Option<Vec<Instruction>>, Vec<usize>) {
+        let mut targeted = vec![false; code.len() + 1];
+        for instruction in code.iter().filter(|i| i.is_jump()) {
+            targeted[instruction.operand] = true;
+        }
+        // The first instruction after each one that something jumps to
+        let mut next_target = vec![code.len(); code.len() + 1];
+        for i in (0..code.len()).rev() {
+            next_target[i] = if targeted[i + 1] {
+                i + 1
+            } else {
+                next_target[i + 1]
+            };
+        }
+
+        let mut out = Vec::with_capacity(code.len());
+        let mut moved = Vec::with_capacity(code.len() + 1);
+        let mut changed = false;
+        let mut at = 0;
+        while at < code.len() {
+            let window = Window {
+                code,
+                at,
+                run: next_target[at] - at,
+            };
+            let rewrite = self.rules.iter().find_map(|rule| {
+                rule.rewrite(&window, constants)
+                    .filter(|&(len, _)| len > 0 && len <= window.run)
+            });
+
+            match rewrite {
+                Some((len, replacement)) => {
+                    moved.extend(std::iter::repeat_n(out.len(), len));
+                    out.extend(replacement);
+                    at += len;
+                    changed = true;
+                }
+                None => {
+                    moved.push(out.len());
+                    out.push(code[at].clone());
+                    at += 1;
+                }
+            }
+        }
+        moved.push(out.len());
+
+        if !changed {
+            return (None, moved);
+        }
+        for instruction in out.iter_mut().filter(|i| i.is_jump()) {
+            instruction.operand = moved[instruction.operand];
+        }
+        (Some(out), moved)
+    }
+}
+
+/// Replaces `SetLocal; Pop`, as compiled for an assignment to a local used as a
+/// statement, with `PopLocal`.
+pub struct PopLocal;
+
+impl Rule for PopLocal {
+    fn rewrite(
+        &self,
+        window: &Window,
+        _constants: &mut Vec<Constant>,
+    ) -> Option<(usize, Vec<Instruction>)> {
+        let [set, pop] = window.take(2)? else {
+            return None;
+        };
+        (set.op == OpCode::SetLocal && pop.op == OpCode::Pop).then(|| {
+            (
+                2,
+                vec![Instruction::new(OpCode::PopLocal, set.operand, set.info)],
+            )
+        })
+    }
+}
+
+/// Replaces `Not` followed by a conditional jump with the opposite jump, when
+/// the condition is popped both where the jump lands and where it does not, so
+/// that which of the two values is left there does not matter.
+pub struct InvertJump;
+
+impl Rule for InvertJump {
+    fn rewrite(
+        &self,
+        window: &Window,
+        _constants: &mut Vec<Constant>,
+    ) -> Option<(usize, Vec<Instruction>)> {
+        let [not, jump] = window.take(2)? else {
+            return None;
+        };
+        let inverse = match jump.op {
+            OpCode::JumpIfFalse => OpCode::JumpIfTrue,
+            OpCode::JumpIfTrue => OpCode::JumpIfFalse,
+            _ => return None,
+        };
+        let popped = |instruction: Option<&Instruction>| {
+            instruction.is_some_and(|instruction| instruction.op == OpCode::Pop)
+        };
+
+        let code = window.code;
+        let next = code.get(window.at + 2);
+        (not.op == OpCode::Not && popped(next) && popped(code.get(jump.operand)))
+            .then(|| (2, vec![Instruction::new(inverse, jump.operand, jump.info)]))
+    }
+}
+
+/// Replaces loading a number constant and negating it with loading the
+/// negated number.
+pub struct NegateConstant;
+
+impl Rule for NegateConstant {
+    fn rewrite(
+        &self,
+        window: &Window,
+        constants: &mut Vec<Constant>,
+    ) -> Option<(usize, Vec<Instruction>)> {
+        let [load, negate] = window.take(2)? else {
+            return None;
+        };
+        if load.op != OpCode::LoadConstant || negate.op != OpCode::Negate {
+            return None;
+        }
+        let Some(&Constant::Number(n)) = constants.get(load.operand) else {
+            return None;
+        };
+        // Negating the constant at runtime gives a float, while a whole number
+        // constant is loaded as an integer if it fits
+        if -n == i32::MIN as f64 {
+            return None;
+        }
+
+        let shared = window
+            .code
+            .iter()
+            .filter(|i| i.op == OpCode::LoadConstant && i.operand == load.operand)
+            .count()
+            > 1;
+        let index = if shared {
+            constants.push(Constant::Number(-n));
+            constants.len() - 1
+        } else {
+            constants[load.operand] = Constant::Number(-n);
+            load.operand
+        };
+        Some((
+            2,
+            vec![Instruction::new(OpCode::LoadConstant, index, load.info)],
+        ))
+    }
+}
+
+/// Replaces a run of `Pop`s, as compiled at the end of a scope, with one `PopN`.
+pub struct PopN;
+
+impl Rule for PopN {
+    fn rewrite(
+        &self,
+        window: &Window,
+        _constants: &mut Vec<Constant>,
+    ) -> Option<(usize, Vec<Instruction>)> {
+        let code = window.replaceable();
+        let pops = code
+            .iter()
+            .take(u8::MAX as usize)
+            .take_while(|i| i.op == OpCode::Pop)
+            .count();
+        (pops > 1).then(|| {
+            (
+                pops,
+                vec![Instruction::new(OpCode::PopN, pops, code[0].info)],
+            )
+        })
+    }
+}
+
+/// Returns the short version of `op`, or `op` if it is one. Long versions come
+/// right after their short one in [`OpCode`].
+fn short_version(op: OpCode) -> OpCode {
+    (op as u8)
+        .checked_sub(1)
+        .and_then(|byte| OpCode::try_from(byte).ok())
+        .filter(|short| short.info().long == Some(op))
+        .unwrap_or(op)
+}
+
+/// Splits the code of `chunk` into instructions, returning them and the index of
+/// the instruction each byte, and the end of the code, belongs to.
+fn decode(chunk: &Chunk) -> (Vec<Instruction>, Vec<usize>) {
+    let code = &chunk.code;
+    let infos: Vec<LineInfo> = chunk
+        .lines
+        .iter()
+        .flat_map(|&(info, count)| std::iter::repeat_n(info, count))
+        .collect();
+    let mut instructions = vec![];
+    let mut index_at = Vec::with_capacity(code.len() + 1);
+    let mut ends = vec![];
+
+    let mut offset = 0;
+    while offset < code.len() {
+        let op = OpCode::try_from(code[offset]).unwrap_or(OpCode::Nop);
+        let info = op.info();
+        let width = info.operand_width as usize;
+        let operand = (0..width).fold(0, |operand, i| {
+            let byte = code.get(offset + 1 + i).copied().unwrap_or(0);
+            operand | (byte as usize) << (8 * i)
+        });
+
+        let mut next = offset + 1 + width;
+        let mut upvalues = Vec::new();
+        if info.operand == Operand::Closure
+            && let Some(function) = chunk.function(operand)
+        {
+            let end = (next + 2 * function.upvalue_count).min(code.len());
+            upvalues = code[next..end].to_vec();
+            next = end;
+        }
+        let next = next.min(code.len());
+
+        index_at.extend(std::iter::repeat_n(instructions.len(), next - offset));
+        ends.push(next);
+        instructions.push(Instruction {
+            op: short_version(op),
+            operand,
+            upvalues,
+            info: infos
+                .get(offset)
+                .copied()
+                .unwrap_or(LineInfo { line: 0, column: 0 }),
+        });
+        offset = next;
+    }
+    index_at.push(instructions.len());
+
+    // Jump operands become the index of the instruction landed on
+    for (i, instruction) in instructions.iter_mut().enumerate() {
+        if instruction.is_jump() {
+            let end = ends[i];
+            let target = match instruction.op {
+                OpCode::Loop => end.saturating_sub(instruction.operand),
+                _ => end + instruction.operand,
+            };
+            instruction.operand = index_at[target.min(code.len())];
+        }
+    }
+
+    (instructions, index_at)
+}
+
+/// Writes `code` back into `chunk` in place of its old code, returning the
+/// offset of each instruction, and of the end of the code.
+fn encode(chunk: &mut Chunk, code: &[Instruction]) -> Vec<usize> {
+    let fits = |operand: usize, op: OpCode| operand >> (8 * op.operand_width()) == 0;
+
+    // Jumps start short, and are made long until every distance fits
+    let mut long: Vec<bool> = code
+        .iter()
+        .map(|i| !i.is_jump() && !fits(i.operand, i.op))
+        .collect();
+    let offsets = loop {
+        let mut offsets = Vec::with_capacity(code.len() + 1);
+        let mut offset = 0;
+        for (i, instruction) in code.iter().enumerate() {
+            offsets.push(offset);
+            let op = if long[i] {
+                instruction.op.to_long()
+            } else {
+                instruction.op
+            };
+            offset += 1 + op.operand_width() as usize + instruction.upvalues.len();
+        }
+        offsets.push(offset);
+
+        let mut widened = false;
+        for (i, instruction) in code.iter().enumerate() {
+            if instruction.is_jump() && !long[i] {
+                let end = offsets[i + 1];
+                if !fits(offsets[instruction.operand].abs_diff(end), instruction.op) {
+                    long[i] = true;
+                    widened = true;
+                }
+            }
+        }
+        if !widened {
+            break offsets;
+        }
+    };
+
+    let debug = chunk.debug.take();
+    chunk.truncate(0);
+    for (i, instruction) in code.iter().enumerate() {
+        let op = if long[i] {
+            instruction.op.to_long()
+        } else {
+            instruction.op
+        };
+        let operand = if instruction.is_jump() {
+            offsets[instruction.operand].abs_diff(offsets[i + 1])
+        } else {
+            instruction.operand
+        };
+
+        chunk.write(op as u8, instruction.info);
+        for byte in 0..op.operand_width() {
+            chunk.write((operand >> (8 * byte)) as u8, instruction.info);
+        }
+        for &byte in &instruction.upvalues {
+            chunk.write(byte, instruction.info);
+        }
+    }
+    chunk.debug = debug;
+
+    offsets
+}
@@ -16,14 +16,74 @@ pub enum InterpretError {
     Panic(PanicError),
     #[error("Not implemented.")]
     UnImplemented,
+    /// The program called `exit(code)`. This is how a program ends early, not an
+    /// error, so it is never reported.
+    #[error("Exited with code {0}.")]
+    Exit(i32),
+}
+
+/// Why [`crate::try_interpret`] failed.
+#[derive(Debug, Clone)]
+pub enum InterpretOutcome {
+    /// The source could not be scanned, parsed, or compiled.
+    CompileError(Vec<InterpretError>),
+    /// The program stopped with an error while running.
+    RuntimeError(InterpretError),
+    /// The program stopped early by calling `exit(code)`.
+    Exit(i32),
+}
+
+impl InterpretOutcome {
+    /// Returns every error that caused the failure.
+    pub fn errors(&self) -> &[InterpretError] {
+        match self {
+            InterpretOutcome::CompileError(errors) => errors,
+            InterpretOutcome::RuntimeError(error) => std::slice::from_ref(error),
+            InterpretOutcome::Exit(_) => &[],
+        }
+    }
+
+    /// Returns the process exit code for the failure: 65 (`EX_DATAERR`) for
+    /// compile errors, 70 (`EX_SOFTWARE`) for runtime errors, and the code the
+    /// program passed to `exit`.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            InterpretOutcome::CompileError(_) => 65,
+            InterpretOutcome::RuntimeError(_) => 70,
+            InterpretOutcome::Exit(code) => *code,
+        }
+    }
+}
+
+/// Something the compiler noticed that does not stop the program from running.
+#[derive(Debug, Error, Clone)]
+pub enum Diagnostic {
+    #[error("{0}")]
+    Warning(Warning),
+}
+
+#[derive(Debug, Error, Clone)]
+pub enum Warning {
+    #[error("[line {0}]: Warning: Local variable '{1}' is never used.")]
+    UnusedVariable(u32, String),
+    #[error("[line {0}]: Warning: Local function '{1}' is never used.")]
+    UnusedFunction(u32, String),
+    #[error(
+        "[line {0}]: Warning: '{1}' is declared after a function in this scope uses it on line {2}, so the function will not see it; only functions are hoisted."
+    )]
+    LateLocal(u32, String, u32),
 }
 
 #[derive(Debug, Error, Clone)]
 pub enum ScanError {
-    #[error("[line {0}]: Error: Unterminated string.")]
-    UnterminatedString(u32),
-    #[error("[line {0}]: Error at '{1}': Unexpected character.")]
-    UnexpectedCharacter(u32, char),
+    /// A string with no closing quote, at the line and column of its opening
+    /// quote, with the rest of that line.
+    #[error("[line {0}:{1}]: Error at '{2}': Unterminated string.")]
+    UnterminatedString(u32, u16, String),
+    #[error("[line {0}:{1}]: Error at '{2}': Unexpected character.")]
+    UnexpectedCharacter(u32, u16, char),
+    #[error("[line {0}:{1}]: Error at '{2}': Malformed number.")]
+    MalformedNumber(u32, u16, String),
 }
 
 #[derive(Debug, Error, Clone)]
@@ -40,6 +100,10 @@ pub enum SyntaxError {
     TooManyArgs(u32),
     #[error("[line {0}]: Cannot have more than 255 parameters.")]
     TooManyParams(u32),
+    #[error("[line {0}]: Error at '{1}': A setter takes exactly one parameter.")]
+    SetterArity(u32, String),
+    #[error("[line {0}]: Error: Code is nested too deeply.")]
+    TooDeeplyNested(u32),
 }
 
 #[derive(Debug, Error, Clone)]
@@ -52,6 +116,14 @@ pub enum CompileError {
     AlreadyDeclared(u32, String),
     #[error("[line {0}]: Error: Too much code to jump over ({1} bytes).")]
     LargeJump(u32, usize),
+    #[error("[line {0}]: Error: Too many closure variables in function ({1}).")]
+    TooManyUpvalues(u32, usize),
+    #[error("[line {0}]: Error: Too many local variables in function ({1}).")]
+    TooManyLocals(u32, usize),
+    #[error("[line {0}]: Error: Too many constants in one chunk ({1}).")]
+    TooManyConstants(u32, usize),
+    #[error("[line {0}]: Error: Code is nested too deeply.")]
+    TooDeeplyNested(u32),
 
     #[error("[line {0}]: Error: Cannot return from top level code.")]
     TopReturn(u32),
@@ -65,6 +137,10 @@ pub enum CompileError {
     ReturnValueInInit(u32),
     #[error("[line {0}]: Error at '{1}': A class cannot inherit from itself.")]
     SelfInheritance(u32, String),
+    #[error("[line {0}]: Error: Cannot yield from top level code.")]
+    TopYield(u32),
+    #[error("[line {0}]: Error: '{1}' is never defined.")]
+    UndefinedGlobal(u32, String),
 }
 
 #[derive(Debug, Error, Clone)]
@@ -73,6 +149,10 @@ pub enum RuntimeError {
     NameError(u32, String),
     #[error("[line {0}]: Error: Operand(s) must be {1}.")]
     OperandMismatch(u32, String),
+    #[error(
+        "[line {0}]: Error: Cannot add a string and a number, convert the number with str() first."
+    )]
+    StringNumberAdd(u32),
     #[error("[line {0}]: Error at '{1}': Object is not a callable.")]
     InvalidCall(u32, String),
     #[error("[line {0}]: Error: Expected {1} arguments, but received {2}.")]
@@ -81,8 +161,30 @@ pub enum RuntimeError {
     InvalidPropertyAccess(u32, String, String),
     #[error("[line {0}] Error: '{1}' attempting to inherit from non-class value '{2}'.")]
     InheritFromNonClass(u32, String, String),
-    #[error("[line {0} Error: Stack overflow.")]
+    #[error("[line {0}]: Error: Stack overflow.")]
     StackOverflow(u32),
+    #[error("[line {0}]: Error: Cannot yield outside of a coroutine.")]
+    YieldOutsideCoroutine(u32),
+    #[error("[line {0}]: Error: Cannot resume {1} coroutine.")]
+    CoroutineNotResumable(u32, String),
+    #[error("[line {0}]: Error: '{1}' is not iterable.")]
+    NotIterable(u32, String),
+    #[error("[line {0}]: Error: Assertion failed: {1}")]
+    AssertionFailed(u32, String),
+    #[error("[line {0}]: Error: Stopped at watchpoint on '{1}'.")]
+    Stopped(u32, String),
+    #[error("[line {0}]: Error: Native function '{1}' failed.")]
+    NativeFailed(u32, String),
+    #[error("[line {0}]: Error: Division by zero.")]
+    DivisionByZero(u32),
+    #[error("[line {0}]: Error: Native function '{1}' can only be called by an async run.")]
+    AsyncNative(u32, String),
+    #[error("[line {0}]: Error: '{1}' cannot be sent to another thread.")]
+    NotSendable(u32, String),
+    #[error("[line {0}]: Error: '{1}' cannot be cloned.")]
+    NotCloneable(u32, String),
+    #[error("[line {0}]: Error: Thread failed: {1}")]
+    ThreadFailed(u32, String),
 }
 
 #[derive(Debug, Error, Clone)]
@@ -95,4 +197,8 @@ pub enum PanicError {
     NonObjectVariable(u32),
     #[error("[line {0}]: Invalid token '{1:?}' passed to {2}")]
     InvalidToken(u32, TokenType, String),
+    #[error("[line {0}]: Malformed bytecode: {1}.")]
+    MalformedBytecode(u32, String),
+    #[error("[line {0}]: Error: Could not write output: {1}")]
+    WriteFailed(u32, String),
 }
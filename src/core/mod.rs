@@ -1,8 +1,9 @@
 pub mod errors;
+pub mod shared;
 pub mod token;
 
 mod opcode;
 mod value;
 
-pub use opcode::OpCode;
-pub use value::Value;
+pub use opcode::{OpCode, OpInfo, Operand, StackEffect};
+pub use value::{format_number, HeapRef, Value};
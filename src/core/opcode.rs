@@ -1,6 +1,6 @@
 use derive_more::TryFrom;
 
-#[derive(Debug, TryFrom, Clone, Copy)]
+#[derive(Debug, TryFrom, Clone, Copy, PartialEq, Eq)]
 #[try_from(repr)]
 #[repr(u8)]
 pub enum OpCode {
@@ -17,6 +17,47 @@ pub enum OpCode {
     /// Long version of [`OpCode::LoadConstantLong`]
     LoadConstantLong,
 
+    /// Pushes `nil` onto the stack, without going through the constant pool.
+    ///
+    /// ### Operand
+    /// - None
+    ///
+    /// ### Stack effect
+    /// - Before: `[]`
+    /// - After: `[nil]`
+    Nil,
+
+    /// Pushes `true` onto the stack, without going through the constant pool.
+    ///
+    /// ### Operand
+    /// - None
+    ///
+    /// ### Stack effect
+    /// - Before: `[]`
+    /// - After: `[true]`
+    True,
+
+    /// Pushes `false` onto the stack, without going through the constant pool.
+    ///
+    /// ### Operand
+    /// - None
+    ///
+    /// ### Stack effect
+    /// - Before: `[]`
+    /// - After: `[false]`
+    False,
+
+    /// Pushes an integer between 0 and 255 onto the stack, without going through
+    /// the constant pool.
+    ///
+    /// ### Operand
+    /// - 1 byte: the integer
+    ///
+    /// ### Stack effect
+    /// - Before: `[]`
+    /// - After: `[value]`
+    LoadSmallInt,
+
     /// Negates the value on top of the stack.
     ///
     /// ### Operand
@@ -37,6 +78,16 @@ pub enum OpCode {
     /// - After: `[!value]`
     Not,
 
+    /// Flips the bits of the integer part of the value on top of the stack.
+    ///
+    /// ### Operand
+    /// - None
+    ///
+    /// ### Stack effect
+    /// - Before: `[value]`
+    /// - After: `[~value]`
+    BitNot,
+
     /// Adds the top two values on the stack.
     ///
     /// ### Operand
@@ -77,6 +128,57 @@ pub enum OpCode {
     /// - After: `[b/a]`
     Divide,
 
+    /// Bitwise AND of the integer parts of the top two values.
+    ///
+    /// ### Operand
+    /// - None
+    ///
+    /// ### Stack effect
+    /// - Before: `[b, a]` TOP
+    /// - After: `[b & a]`
+    BitAnd,
+
+    /// Bitwise OR of the integer parts of the top two values.
+    ///
+    /// ### Operand
+    /// - None
+    ///
+    /// ### Stack effect
+    /// - Before: `[b, a]` TOP
+    /// - After: `[b | a]`
+    BitOr,
+
+    /// Bitwise XOR of the integer parts of the top two values.
+    ///
+    /// ### Operand
+    /// - None
+    ///
+    /// ### Stack effect
+    /// - Before: `[b, a]` TOP
+    /// - After: `[b ^ a]`
+    BitXor,
+
+    /// Shifts the integer part of the second value left by the top value, modulo 64.
+    ///
+    /// ### Operand
+    /// - None
+    ///
+    /// ### Stack effect
+    /// - Before: `[b, a]` TOP
+    /// - After: `[b << a]`
+    ShiftLeft,
+
+    /// Shifts the integer part of the second value right by the top value, modulo
+    /// 64, keeping its sign.
+    ///
+    /// ### Operand
+    /// - None
+    ///
+    /// ### Stack effect
+    /// - Before: `[b, a]` TOP
+    /// - After: `[b >> a]`
+    ShiftRight,
+
     /// Compares the top two values for equality.
     ///
     /// ### Operand
@@ -157,12 +259,22 @@ pub enum OpCode {
     /// - After: `[]`
     Pop,
 
+    /// Removes a number of values from the top of the stack.
+    ///
+    /// ### Operand
+    /// - 1 byte: how many values to remove
+    ///
+    /// ### Stack effect
+    /// - Before: `[value1, ..., valueN]`
+    /// - After: `[]`
+    PopN,
+
     /// Defines a new global variable and initializes it to the top value
     /// on the stack.
     ///
     /// ### Operand
-    /// - 1 byte: index into constant pool for variable name
-    /// - 3 bytes: index into constant pool for variable name (index > 255)
+    /// - 1 byte: symbol id of the variable name
+    /// - 3 bytes: symbol id of the variable name (id > 255)
     ///
     /// ### Stack effect
     /// - Before: `[value]`
@@ -174,8 +286,8 @@ pub enum OpCode {
     /// Pushes the value of a global variable onto the stack.
     ///
     /// ### Operand
-    /// - 1 byte: index into constant pool for variable name
-    /// - 3 bytes: index into constant pool for variable name (index > 255)
+    /// - 1 byte: symbol id of the variable name
+    /// - 3 bytes: symbol id of the variable name (id > 255)
     ///
     /// ### Stack effect
     /// - Before: `[]`
@@ -187,8 +299,8 @@ pub enum OpCode {
     /// Sets the global variable to the top value of the stack.
     ///
     /// ### Operand
-    /// - 1 byte: index into constant pool for variable name
-    /// - 3 bytes: index into constant pool for variable name (index > 255)
+    /// - 1 byte: symbol id of the variable name
+    /// - 3 bytes: symbol id of the variable name (id > 255)
     ///
     /// ### Stack effect
     /// - Before: `[value]`
@@ -200,8 +312,8 @@ pub enum OpCode {
     /// Pushes the value of a local variable onto the stack.
     ///
     /// ### Operand
-    /// - 1 byte: index into stack for variable name
-    /// - 3 bytes: index into stack for variable name (index > 255)
+    /// - 1 byte: slot of the variable in the current frame
+    /// - 3 bytes: slot of the variable in the current frame (slot > 255)
     ///
     /// ### Stack effect
     /// - Before: `[]`
@@ -213,8 +325,8 @@ pub enum OpCode {
     /// Sets the local variable to the top value of the stack.
     ///
     /// ### Operand
-    /// - 1 byte: index into stack for variable name
-    /// - 3 bytes: index into stack for variable name (index > 255)
+    /// - 1 byte: slot of the variable in the current frame
+    /// - 3 bytes: slot of the variable in the current frame (slot > 255)
     ///
     /// ### Stack effect
     /// - Before: `[value]`
@@ -223,6 +335,19 @@ pub enum OpCode {
     /// Long version of  [`OpCode::SetLocal`]
     SetLocalLong,
 
+    /// Sets the local variable to the top value of the stack, and removes it.
+    ///
+    /// ### Operand
+    /// - 1 byte: slot of the variable in the current frame
+    /// - 3 bytes: slot of the variable in the current frame (slot > 255)
+    ///
+    /// ### Stack effect
+    /// - Before: `[value]`
+    /// - After: `[]`
+    PopLocal,
+    /// Long version of  [`OpCode::PopLocal`]
+    PopLocalLong,
+
     /// Index into upvalue array, cannot have more than 256 upvalues
     GetUpvalue,
     SetUpvalue,
@@ -231,31 +356,77 @@ pub enum OpCode {
     ///
     /// ### Operand
     /// - 2 bytes: the number of bytes to jump
+    /// - 4 bytes: the number of bytes to jump (distance > 65535)
     ///
     /// ### Stack effect
     /// - Before: `[value]`
     /// - After: `[value]`
     Jump,
+    /// Long version of [`OpCode::Jump`]
+    JumpLong,
 
     /// Jump a # of bytes if the top value of the stack is false.
     ///
     /// ### Operand
     /// - 2 bytes: the number of bytes to jump
+    /// - 4 bytes: the number of bytes to jump (distance > 65535)
     ///
     /// ### Stack effect
     /// - Before: `[value]`
     /// - After: `[value]`
     JumpIfFalse,
+    /// Long version of [`OpCode::JumpIfFalse`]
+    JumpIfFalseLong,
+
+    /// Jump a # of bytes if the top value of the stack is true.
+    ///
+    /// ### Operand
+    /// - 2 bytes: the number of bytes to jump
+    /// - 4 bytes: the number of bytes to jump (distance > 65535)
+    ///
+    /// ### Stack effect
+    /// - Before: `[value]`
+    /// - After: `[value]`
+    JumpIfTrue,
+    /// Long version of [`OpCode::JumpIfTrue`]
+    JumpIfTrueLong,
 
     /// Jump a # of bytes backwards.
     ///
     /// ### Operand
     /// - 2 bytes: the number of bytes to jump
+    /// - 4 bytes: the number of bytes to jump (distance > 65535)
     ///
     /// ### Stack effect
     /// - Before: `[value]`
     /// - After: `[value]`
     Loop,
+    /// Long version of [`OpCode::Loop`]
+    LoopLong,
+
+    /// Replaces the collection on top of the stack with an iterator over its elements.
+    ///
+    /// ### Operand
+    /// - None
+    ///
+    /// ### Stack effect
+    /// - Before: `[collection]`
+    /// - After: `[iterator]`
+    IterNew,
+
+    /// Advances the iterator on top of the stack, pushing its next element. If
+    /// the iterator is exhausted, jumps forward a # of bytes instead.
+    ///
+    /// ### Operand
+    /// - 2 bytes: the number of bytes to jump
+    /// - 4 bytes: the number of bytes to jump (distance > 65535)
+    ///
+    /// ### Stack effect
+    /// - Before: `[iterator]`
+    /// - After: `[iterator, element]`, or `[iterator]` when exhausted
+    IterNext,
+    /// Long version of [`OpCode::IterNext`]
+    IterNextLong,
 
     /// Calls the function at the n'th position from the top
     /// of the stack..
@@ -281,8 +452,8 @@ pub enum OpCode {
     /// Crates a closure from a function and stuffs it into the heap
     ///
     /// ### Operand
-    /// - 1 byte: index into the heap of where function is located
-    /// - 3 bytes: index into the heap of where function is located
+    /// - 1 byte: index into the constant pool of the function
+    /// - 3 bytes: index into the constant pool of the function
     ///
     /// ### Stack effect
     /// - Before: `[]`
@@ -291,22 +462,533 @@ pub enum OpCode {
     /// Long version of  [`OpCode::Closure`]
     ClosureLong,
 
+    /// Closes every open upvalue for the local variable in the slot or any slot
+    /// above it, so that closures keep the captured values once they leave the
+    /// stack, and removes the top value.
+    ///
+    /// ### Operand
+    /// - 1 byte: slot of the variable in the current frame. Only the first 256
+    ///   slots can be captured.
+    ///
+    /// ### Stack effect
+    /// - Before: `[value]`
+    /// - After: `[]`
     CloseUpvalue,
 
+    /// Suspends the running coroutine, handing the top value of the stack to the
+    /// `resume` call that started it.
+    ///
+    /// ### Operand
+    /// - None
+    ///
+    /// ### Stack effect
+    /// - Before: `[value]`
+    /// - After: `[resumed]`, once the coroutine is resumed again
+    Yield,
+
+    /// Starts or continues the coroutine below the top of the stack, passing it
+    /// the top value. Execution continues in the coroutine until it yields or returns.
+    ///
+    /// ### Operand
+    /// - None
+    ///
+    /// ### Stack effect
+    /// - Before: `[coroutine, value]`
+    /// - After: `[result]`, once the coroutine yields or returns
+    Resume,
+
+    /// Stops the program, with the integer on top of the stack as its exit code.
+    ///
+    /// ### Operand
+    /// - None
+    ///
+    /// ### Stack effect
+    /// - Before: `[code]`
+    /// - After: the VM stops with [`InterpretError::Exit`](crate::core::errors::InterpretError::Exit)
+    Exit,
+
+    /// Runs the function on top of the stack on another thread, in a VM of its
+    /// own, replacing it with a number that identifies the thread.
+    ///
+    /// ### Operand
+    /// - None
+    ///
+    /// ### Stack effect
+    /// - Before: `[function]`
+    /// - After: `[thread]`
+    Spawn,
+
     /// No operation, discards the byte.
     Nop,
 }
 
+/// What the operand of an instruction refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    /// The instruction has no operand
+    None,
+    /// An index into the chunk's constant pool
+    Constant,
+    /// The symbol id of a global's name
+    Global,
+    /// The slot of a local in the current frame
+    Local,
+    /// An index into the current closure's upvalues
+    Upvalue,
+    /// A plain number, such as an argument count
+    Number,
+    /// The number of bytes to jump
+    Jump,
+    /// The constant index of a function, followed by two bytes for each of its
+    /// upvalues: whether it captures a local, and the index it captures
+    Closure,
+}
+
+/// How an instruction changes the number of values on the stack of its frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackEffect {
+    /// Pushes this many values, or pops them if negative. For [`OpCode::IterNext`],
+    /// this is the effect when it does not jump.
+    Fixed(i8),
+    /// Pops as many values as its operand, as [`OpCode::Call`] replaces the
+    /// callee and its arguments with the result
+    PopOperand,
+    /// Leaves the frame, so nothing after it runs on this stack
+    Leave,
+}
+
+/// How an instruction is encoded and what it does to the stack, as given by
+/// [`OpCode::info`]. The compiler, the VM, and the disassembler all read
+/// instructions through this table, so they cannot disagree on an encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpInfo {
+    pub op: OpCode,
+    /// How many bytes of operand follow the opcode. The upvalue bytes of a
+    /// closure come after these.
+    pub operand_width: u8,
+    /// The version of the instruction with a wider operand, for operands that do
+    /// not fit in `operand_width` bytes
+    pub long: Option<OpCode>,
+    pub operand: Operand,
+    pub stack_effect: StackEffect,
+}
+
+const fn info(
+    op: OpCode,
+    operand_width: u8,
+    long: Option<OpCode>,
+    operand: Operand,
+    stack_effect: StackEffect,
+) -> OpInfo {
+    OpInfo {
+        op,
+        operand_width,
+        long,
+        operand,
+        stack_effect,
+    }
+}
+
+/// Every opcode's [`OpInfo`], indexed by opcode.
+static TABLE: [OpInfo; OpCode::COUNT] = [
+    info(
+        OpCode::LoadConstant,
+        1,
+        Some(OpCode::LoadConstantLong),
+        Operand::Constant,
+        StackEffect::Fixed(1),
+    ),
+    info(
+        OpCode::LoadConstantLong,
+        3,
+        None,
+        Operand::Constant,
+        StackEffect::Fixed(1),
+    ),
+    info(OpCode::Nil, 0, None, Operand::None, StackEffect::Fixed(1)),
+    info(OpCode::True, 0, None, Operand::None, StackEffect::Fixed(1)),
+    info(OpCode::False, 0, None, Operand::None, StackEffect::Fixed(1)),
+    info(
+        OpCode::LoadSmallInt,
+        1,
+        None,
+        Operand::Number,
+        StackEffect::Fixed(1),
+    ),
+    info(
+        OpCode::Negate,
+        0,
+        None,
+        Operand::None,
+        StackEffect::Fixed(0),
+    ),
+    info(OpCode::Not, 0, None, Operand::None, StackEffect::Fixed(0)),
+    info(
+        OpCode::BitNot,
+        0,
+        None,
+        Operand::None,
+        StackEffect::Fixed(0),
+    ),
+    info(OpCode::Add, 0, None, Operand::None, StackEffect::Fixed(-1)),
+    info(
+        OpCode::Subtract,
+        0,
+        None,
+        Operand::None,
+        StackEffect::Fixed(-1),
+    ),
+    info(
+        OpCode::Multiply,
+        0,
+        None,
+        Operand::None,
+        StackEffect::Fixed(-1),
+    ),
+    info(
+        OpCode::Divide,
+        0,
+        None,
+        Operand::None,
+        StackEffect::Fixed(-1),
+    ),
+    info(
+        OpCode::BitAnd,
+        0,
+        None,
+        Operand::None,
+        StackEffect::Fixed(-1),
+    ),
+    info(
+        OpCode::BitOr,
+        0,
+        None,
+        Operand::None,
+        StackEffect::Fixed(-1),
+    ),
+    info(
+        OpCode::BitXor,
+        0,
+        None,
+        Operand::None,
+        StackEffect::Fixed(-1),
+    ),
+    info(
+        OpCode::ShiftLeft,
+        0,
+        None,
+        Operand::None,
+        StackEffect::Fixed(-1),
+    ),
+    info(
+        OpCode::ShiftRight,
+        0,
+        None,
+        Operand::None,
+        StackEffect::Fixed(-1),
+    ),
+    info(
+        OpCode::Equal,
+        0,
+        None,
+        Operand::None,
+        StackEffect::Fixed(-1),
+    ),
+    info(
+        OpCode::NotEqual,
+        0,
+        None,
+        Operand::None,
+        StackEffect::Fixed(-1),
+    ),
+    info(
+        OpCode::LessThan,
+        0,
+        None,
+        Operand::None,
+        StackEffect::Fixed(-1),
+    ),
+    info(
+        OpCode::LessEqual,
+        0,
+        None,
+        Operand::None,
+        StackEffect::Fixed(-1),
+    ),
+    info(
+        OpCode::GreaterThan,
+        0,
+        None,
+        Operand::None,
+        StackEffect::Fixed(-1),
+    ),
+    info(
+        OpCode::GreaterEqual,
+        0,
+        None,
+        Operand::None,
+        StackEffect::Fixed(-1),
+    ),
+    info(
+        OpCode::Print,
+        0,
+        None,
+        Operand::None,
+        StackEffect::Fixed(-1),
+    ),
+    info(OpCode::Pop, 0, None, Operand::None, StackEffect::Fixed(-1)),
+    info(
+        OpCode::PopN,
+        1,
+        None,
+        Operand::Number,
+        StackEffect::PopOperand,
+    ),
+    info(
+        OpCode::DefineGlobal,
+        1,
+        Some(OpCode::DefineGlobalLong),
+        Operand::Global,
+        StackEffect::Fixed(-1),
+    ),
+    info(
+        OpCode::DefineGlobalLong,
+        3,
+        None,
+        Operand::Global,
+        StackEffect::Fixed(-1),
+    ),
+    info(
+        OpCode::GetGlobal,
+        1,
+        Some(OpCode::GetGlobalLong),
+        Operand::Global,
+        StackEffect::Fixed(1),
+    ),
+    info(
+        OpCode::GetGlobalLong,
+        3,
+        None,
+        Operand::Global,
+        StackEffect::Fixed(1),
+    ),
+    info(
+        OpCode::SetGlobal,
+        1,
+        Some(OpCode::SetGlobalLong),
+        Operand::Global,
+        StackEffect::Fixed(0),
+    ),
+    info(
+        OpCode::SetGlobalLong,
+        3,
+        None,
+        Operand::Global,
+        StackEffect::Fixed(0),
+    ),
+    info(
+        OpCode::GetLocal,
+        1,
+        Some(OpCode::GetLocalLong),
+        Operand::Local,
+        StackEffect::Fixed(1),
+    ),
+    info(
+        OpCode::GetLocalLong,
+        3,
+        None,
+        Operand::Local,
+        StackEffect::Fixed(1),
+    ),
+    info(
+        OpCode::SetLocal,
+        1,
+        Some(OpCode::SetLocalLong),
+        Operand::Local,
+        StackEffect::Fixed(0),
+    ),
+    info(
+        OpCode::SetLocalLong,
+        3,
+        None,
+        Operand::Local,
+        StackEffect::Fixed(0),
+    ),
+    info(
+        OpCode::PopLocal,
+        1,
+        Some(OpCode::PopLocalLong),
+        Operand::Local,
+        StackEffect::Fixed(-1),
+    ),
+    info(
+        OpCode::PopLocalLong,
+        3,
+        None,
+        Operand::Local,
+        StackEffect::Fixed(-1),
+    ),
+    info(
+        OpCode::GetUpvalue,
+        1,
+        None,
+        Operand::Upvalue,
+        StackEffect::Fixed(1),
+    ),
+    info(
+        OpCode::SetUpvalue,
+        1,
+        None,
+        Operand::Upvalue,
+        StackEffect::Fixed(0),
+    ),
+    info(
+        OpCode::Jump,
+        2,
+        Some(OpCode::JumpLong),
+        Operand::Jump,
+        StackEffect::Fixed(0),
+    ),
+    info(
+        OpCode::JumpLong,
+        4,
+        None,
+        Operand::Jump,
+        StackEffect::Fixed(0),
+    ),
+    info(
+        OpCode::JumpIfFalse,
+        2,
+        Some(OpCode::JumpIfFalseLong),
+        Operand::Jump,
+        StackEffect::Fixed(0),
+    ),
+    info(
+        OpCode::JumpIfFalseLong,
+        4,
+        None,
+        Operand::Jump,
+        StackEffect::Fixed(0),
+    ),
+    info(
+        OpCode::JumpIfTrue,
+        2,
+        Some(OpCode::JumpIfTrueLong),
+        Operand::Jump,
+        StackEffect::Fixed(0),
+    ),
+    info(
+        OpCode::JumpIfTrueLong,
+        4,
+        None,
+        Operand::Jump,
+        StackEffect::Fixed(0),
+    ),
+    info(
+        OpCode::Loop,
+        2,
+        Some(OpCode::LoopLong),
+        Operand::Jump,
+        StackEffect::Fixed(0),
+    ),
+    info(
+        OpCode::LoopLong,
+        4,
+        None,
+        Operand::Jump,
+        StackEffect::Fixed(0),
+    ),
+    info(
+        OpCode::IterNew,
+        0,
+        None,
+        Operand::None,
+        StackEffect::Fixed(0),
+    ),
+    info(
+        OpCode::IterNext,
+        2,
+        Some(OpCode::IterNextLong),
+        Operand::Jump,
+        StackEffect::Fixed(1),
+    ),
+    info(
+        OpCode::IterNextLong,
+        4,
+        None,
+        Operand::Jump,
+        StackEffect::Fixed(1),
+    ),
+    info(
+        OpCode::Call,
+        1,
+        None,
+        Operand::Number,
+        StackEffect::PopOperand,
+    ),
+    info(OpCode::Return, 0, None, Operand::None, StackEffect::Leave),
+    info(
+        OpCode::Closure,
+        1,
+        Some(OpCode::ClosureLong),
+        Operand::Closure,
+        StackEffect::Fixed(1),
+    ),
+    info(
+        OpCode::ClosureLong,
+        3,
+        None,
+        Operand::Closure,
+        StackEffect::Fixed(1),
+    ),
+    info(
+        OpCode::CloseUpvalue,
+        1,
+        None,
+        Operand::Local,
+        StackEffect::Fixed(-1),
+    ),
+    info(OpCode::Yield, 0, None, Operand::None, StackEffect::Fixed(0)),
+    info(
+        OpCode::Resume,
+        0,
+        None,
+        Operand::None,
+        StackEffect::Fixed(-1),
+    ),
+    info(OpCode::Exit, 0, None, Operand::None, StackEffect::Leave),
+    info(OpCode::Spawn, 0, None, Operand::None, StackEffect::Fixed(0)),
+    info(OpCode::Nop, 0, None, Operand::None, StackEffect::Fixed(0)),
+];
+
+// Every entry must sit at the index of its opcode
+const _: () = {
+    let mut i = 0;
+    while i < TABLE.len() {
+        assert!(TABLE[i].op as usize == i);
+        i += 1;
+    }
+};
+
 impl OpCode {
+    /// How many opcodes there are.
+    pub const COUNT: usize = OpCode::Nop as usize + 1;
+
+    /// Returns how the instruction is encoded and what it does to the stack.
+    pub fn info(self) -> &'static OpInfo {
+        &TABLE[self as usize]
+    }
+
+    /// Returns how many bytes of operand follow the opcode, see
+    /// [`OpInfo::operand_width`].
+    #[inline]
+    pub fn operand_width(self) -> u8 {
+        self.info().operand_width
+    }
+
+    /// Returns the version of the instruction with a wider operand, or the
+    /// instruction itself if it has none.
     pub fn to_long(self) -> Self {
-        match self {
-            OpCode::LoadConstant => OpCode::LoadConstantLong,
-            OpCode::DefineGlobal => OpCode::DefineGlobalLong,
-            OpCode::GetGlobal => OpCode::GetGlobalLong,
-            OpCode::GetLocal => OpCode::GetLocalLong,
-            OpCode::SetLocal => OpCode::SetLocalLong,
-            OpCode::Closure => OpCode::ClosureLong,
-            _ => self,
-        }
+        self.info().long.unwrap_or(self)
     }
 }
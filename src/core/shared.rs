@@ -0,0 +1,44 @@
+//! Types that change with the `send` feature. Without it, the VM uses [`Rc`]
+//! and accepts any writer; with it, the VM uses [`Arc`] and requires `Send`
+//! writers and `Send + Sync` natives and clocks, so it can be moved between
+//! threads.
+
+use std::io::Write;
+
+#[cfg(not(feature = "send"))]
+use std::rc::Rc;
+#[cfg(feature = "send")]
+use std::sync::Arc;
+
+/// The reference-counted pointer for objects shared between the heap and frames.
+#[cfg(not(feature = "send"))]
+pub type Shared<T> = Rc<T>;
+/// The reference-counted pointer for objects shared between the heap and frames.
+#[cfg(feature = "send")]
+pub type Shared<T> = Arc<T>;
+
+/// A writer for program output or errors.
+#[cfg(not(feature = "send"))]
+pub type Writer<'a> = Box<dyn Write + 'a>;
+/// A writer for program output or errors.
+#[cfg(feature = "send")]
+pub type Writer<'a> = Box<dyn Write + Send + 'a>;
+
+/// The future returned by an async native, see [`crate::AsyncNative`]. The
+/// `tokio` feature implies `send`, so that a run awaiting one can be spawned.
+#[cfg(feature = "tokio")]
+pub type NativeFuture = std::pin::Pin<
+    Box<dyn std::future::Future<Output = Result<crate::Value, crate::RuntimeError>> + Send>,
+>;
+
+/// Implemented by every type, or with the `send` feature, by every `Send + Sync` type.
+#[cfg(not(feature = "send"))]
+pub trait MaybeSendSync {}
+#[cfg(not(feature = "send"))]
+impl<T: ?Sized> MaybeSendSync for T {}
+
+/// Implemented by every type, or with the `send` feature, by every `Send + Sync` type.
+#[cfg(feature = "send")]
+pub trait MaybeSendSync: Send + Sync {}
+#[cfg(feature = "send")]
+impl<T: ?Sized + Send + Sync> MaybeSendSync for T {}
@@ -23,17 +23,26 @@ pub enum TokenType {
     GreaterThan,
     LessEqual,
     GreaterEqual,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
+    LessLess,
+    GreaterGreater,
+    Arrow,
     String,
     Number,
     Identifier,
 
     And,
     Class,
+    Do,
     Else,
     False,
     For,
     Fun,
     If,
+    In,
     Nil,
     Or,
     Print,
@@ -43,23 +52,62 @@ pub enum TokenType {
     True,
     Var,
     While,
+    Yield,
+
+    /// A `//@debug` comment, which marks the statement after it as debugging code
+    DebugPragma,
 
     Eof,
 }
 
+/// The bytes of the source code a token was scanned from, `start..end`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: u32,
+    pub end: u32,
+}
+
 /// Struct to encapsolate all useful information about a token.
 #[derive(Debug, Clone)]
 pub struct Token {
     /// The type of the token.
     pub token: TokenType,
-    /// The actual string representation of the token.
-    pub lexeme: String,
+    /// Where the token's text is in the source code, see [`Token::lexeme`].
+    /// Tokens the parser makes up, such as the `nil` of a bare `return`, have an
+    /// empty span.
+    pub span: Span,
     /// The line number where the token was found.
     pub line: u32,
+    /// The column where the token starts, counting from 1. Kept small so that
+    /// tokens, and the syntax trees holding them, stay small on deep recursion.
+    pub column: u16,
+}
+
+impl Token {
+    /// Creates a token that does not appear in the source code, such as the
+    /// condition of `for (;;)`, at the given position.
+    pub fn synthetic(token: TokenType, line: u32, column: u16) -> Self {
+        Self {
+            token,
+            span: Span::default(),
+            line,
+            column,
+        }
+    }
+
+    /// Returns the text of the token in `source`, the source code it was
+    /// scanned from.
+    pub fn lexeme<'s>(&self, source: &'s str) -> &'s str {
+        &source[self.span.start as usize..self.span.end as usize]
+    }
 }
 
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?} '{}'", self.token, self.lexeme)
+        write!(
+            f,
+            "{:?} at {}..{}",
+            self.token, self.span.start, self.span.end
+        )
     }
 }
@@ -1,8 +1,37 @@
-static OBJ_TAG: u64 = 0x8000000000000000;
-static QNAN: u64 = 0x7ffc000000000000;
-static NIL_TAG: u64 = 1;
-static FALSE_TAG: u64 = 2;
-static TRUE_TAG: u64 = 3;
+use crate::{object::Object, runtime::Heap};
+
+const OBJ_TAG: u64 = 0x8000000000000000;
+const QNAN: u64 = 0x7ffc000000000000;
+const NIL_TAG: u64 = 1;
+const FALSE_TAG: u64 = 2;
+const TRUE_TAG: u64 = 3;
+/// Marks an integer, whose 32 bits are stored in the low bits
+const INT_TAG: u64 = 0x0001000000000000;
+
+/// A reference to an object in a [`Heap`], handed out by [`Heap::push`] and
+/// taken by [`Heap::get`]. Outside this crate, the only ways to get one are
+/// from the heap or from a [`Value`] that refers to an object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HeapRef(usize);
+
+impl HeapRef {
+    #[inline]
+    pub(crate) fn new(index: usize) -> Self {
+        Self(index)
+    }
+
+    /// Returns the index of the object, as in `<object:N>`.
+    #[inline]
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+impl From<HeapRef> for Value {
+    fn from(object: HeapRef) -> Self {
+        Value::object(object)
+    }
+}
 
 #[derive(Clone, Copy)]
 pub struct Value {
@@ -16,9 +45,9 @@ impl std::fmt::Debug for Value {
         } else if self.is_boolean() {
             write!(f, "{}", self.as_boolean())
         } else if self.is_number() {
-            write!(f, "{}", self.as_number())
-        } else if self.is_object() {
-            write!(f, "<object:{}>", self.as_object())
+            write!(f, "{}", format_number(self.as_number()))
+        } else if let Some(object) = self.as_object() {
+            write!(f, "<object:{}>", object.index())
         } else {
             write!(f, "<unknown>")
         }
@@ -26,30 +55,68 @@ impl std::fmt::Debug for Value {
 }
 
 impl PartialEq for Value {
+    /// Values are compared by bits, except that an integer equals the float with
+    /// the same value, and every NaN equals every other. Unlike floating point
+    /// `==`, this makes equality reflexive, so values can be hashed and used as
+    /// keys, and `0` and `-0` stay apart. Lox's `==` compares values this way.
     fn eq(&self, other: &Self) -> bool {
-        self.bits == other.bits
+        if self.bits == other.bits {
+            return true;
+        }
+        self.is_number() && other.is_number() && self.number_bits() == other.number_bits()
+    }
+}
+
+impl Eq for Value {}
+
+impl std::hash::Hash for Value {
+    /// Hashes numbers by their value as a float, so that values that are equal
+    /// hash the same.
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        if self.is_number() {
+            self.number_bits().hash(state);
+        } else {
+            self.bits.hash(state);
+        }
     }
 }
 
 impl Value {
+    /// Whether the value counts as true in a condition: everything except `nil`
+    /// and `false`.
     pub fn is_truthy(&self) -> bool {
-        match self {
-            n if n.is_nil() => false,
-            b if b.is_boolean() => b.as_boolean(),
-            n if n.is_number() => true,
-            o if o.is_object() => true,
-            _ => panic!("Inavlid bit sequence for value"),
+        !self.is_nil() && *self != Self::FALSE
+    }
+
+    /// Names the type of the value, as returned by the `type` native: "nil",
+    /// "boolean", "number", "string", "function", "coroutine", or "iterator".
+    /// Objects are looked up in `heap`.
+    pub fn kind(&self, heap: &Heap) -> &'static str {
+        if self.is_boolean() {
+            return "boolean";
+        } else if self.is_number() {
+            return "number";
+        }
+
+        match heap.get_value(self) {
+            Some(Object::String(_)) => "string",
+            Some(Object::Function(_) | Object::Closure(_) | Object::Native(_)) => "function",
+            Some(Object::Coroutine(_)) => "coroutine",
+            Some(Object::Iter(_)) => "iterator",
+            None => "nil",
         }
     }
 }
 
 // Nil
 impl Value {
+    pub const NIL: Self = Self {
+        bits: QNAN | NIL_TAG,
+    };
+
     #[inline]
     pub fn nil() -> Self {
-        Self {
-            bits: QNAN | NIL_TAG,
-        }
+        Self::NIL
     }
 
     #[inline]
@@ -60,10 +127,19 @@ impl Value {
 
 // Boolean
 impl Value {
+    pub const TRUE: Self = Self {
+        bits: QNAN | TRUE_TAG,
+    };
+    pub const FALSE: Self = Self {
+        bits: QNAN | FALSE_TAG,
+    };
+
     #[inline]
     pub fn boolean(b: bool) -> Self {
-        Self {
-            bits: QNAN | (if b { TRUE_TAG } else { FALSE_TAG }),
+        if b {
+            Self::TRUE
+        } else {
+            Self::FALSE
         }
     }
 
@@ -80,28 +156,109 @@ impl Value {
 
 // Number
 impl Value {
+    /// Returns the number `n`. Every NaN is stored as the same quiet NaN, since
+    /// others may have the bits of a tagged value such as `nil`.
     #[inline]
     pub fn number(n: f64) -> Self {
+        if n.is_nan() {
+            return Self {
+                bits: f64::NAN.to_bits(),
+            };
+        }
         Self { bits: n.to_bits() }
     }
 
+    /// Returns the number `n`, for integers small enough to be encoded directly in
+    /// a [`OpCode::LoadSmallInt`](super::OpCode::LoadSmallInt) operand.
+    #[inline]
+    pub const fn small_int(n: u8) -> Self {
+        Self::int(n as i32)
+    }
+
+    /// Returns the number `n`, as an integer if it is a whole number that fits in
+    /// one. Negative zero stays a float.
+    pub fn whole_number(n: f64) -> Self {
+        let fits = n >= i32::MIN as f64 && n <= i32::MAX as f64;
+        if fits && n.fract() == 0.0 && !(n == 0.0 && n.is_sign_negative()) {
+            Self::int(n as i32)
+        } else {
+            Self::number(n)
+        }
+    }
+
+    /// Returns the number `n`, as an integer if it fits in one.
+    pub fn from_i64(n: i64) -> Self {
+        match i32::try_from(n) {
+            Ok(n) => Self::int(n),
+            Err(_) => Self::number(n as f64),
+        }
+    }
+
+    /// Returns the integer value of `n` if it fits in a
+    /// [`OpCode::LoadSmallInt`](super::OpCode::LoadSmallInt) operand.
+    pub fn as_small_int(n: f64) -> Option<u8> {
+        if n.fract() == 0.0 && (0.0..=u8::MAX as f64).contains(&n) && n.is_sign_positive() {
+            Some(n as u8)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the value is a number, either a float or an integer.
     #[inline]
     pub fn is_number(&self) -> bool {
-        (self.bits & QNAN) != QNAN
+        (self.bits & QNAN) != QNAN || self.is_int()
     }
 
+    /// Returns the number as a float, converting integers.
     #[inline]
     pub fn as_number(&self) -> f64 {
-        f64::from_bits(self.bits)
+        if self.is_int() {
+            self.as_int() as f64
+        } else {
+            f64::from_bits(self.bits)
+        }
+    }
+
+    /// Returns the bits of the number as a float, the same for every NaN.
+    fn number_bits(&self) -> u64 {
+        let n = self.as_number();
+        if n.is_nan() {
+            f64::NAN.to_bits()
+        } else {
+            n.to_bits()
+        }
+    }
+}
+
+// Integer. Integers are numbers, stored without going through a float so that
+// arithmetic on them is exact and cheap, and become floats when a result does
+// not fit in 32 bits.
+impl Value {
+    #[inline]
+    pub const fn int(n: i32) -> Self {
+        Self {
+            bits: QNAN | INT_TAG | n as u32 as u64,
+        }
+    }
+
+    #[inline]
+    pub fn is_int(&self) -> bool {
+        self.bits & (OBJ_TAG | QNAN | INT_TAG) == (QNAN | INT_TAG)
+    }
+
+    #[inline]
+    pub fn as_int(&self) -> i32 {
+        self.bits as u32 as i32
     }
 }
 
 // Object
 impl Value {
     #[inline]
-    pub fn object(ptr: usize) -> Self {
+    pub fn object(object: HeapRef) -> Self {
         Self {
-            bits: OBJ_TAG | QNAN | ptr as u64,
+            bits: OBJ_TAG | QNAN | object.0 as u64,
         }
     }
 
@@ -110,8 +267,28 @@ impl Value {
         self.bits & (QNAN | OBJ_TAG) == (QNAN | OBJ_TAG)
     }
 
+    /// Returns the object this value refers to, or `None` if it is not an object.
     #[inline]
-    pub fn as_object(&self) -> usize {
-        (self.bits & !(QNAN | OBJ_TAG)) as usize
+    pub fn as_object(&self) -> Option<HeapRef> {
+        self.is_object()
+            .then_some(HeapRef((self.bits & !(QNAN | OBJ_TAG)) as usize))
+    }
+}
+
+/// Formats a number as Lox prints it: integers without a trailing `.0`, other
+/// numbers with the fewest digits that read back as the same number, and very
+/// large or small magnitudes in scientific notation, as in `1e+21` or `1e-7`.
+pub fn format_number(n: f64) -> String {
+    let magnitude = n.abs();
+    if magnitude != 0.0 && magnitude.is_finite() && !(1e-6..1e21).contains(&magnitude) {
+        let s = format!("{n:e}");
+        match s.split_once('e') {
+            Some((mantissa, exponent)) if !exponent.starts_with('-') => {
+                format!("{mantissa}e+{exponent}")
+            }
+            _ => s,
+        }
+    } else {
+        format!("{n}")
     }
 }
@@ -0,0 +1,229 @@
+//! A C API, enabled by the `ffi` feature, for embedding the interpreter in
+//! hosts not written in Rust. `include/lox.h` declares these functions for C.
+//!
+//! A VM is created with [`lox_vm_new`], runs source code with
+//! [`lox_vm_interpret`], and is destroyed with [`lox_vm_free`]. Program output
+//! goes to stdout, and errors to stderr.
+
+use std::{
+    ffi::{c_char, c_void, CStr},
+    io,
+};
+
+use crate::{
+    core::{errors::RuntimeError, Value},
+    object::native::Native,
+    runtime::Heap,
+    try_interpret, InterpretOutcome, VM,
+};
+
+/// The result of a call through the C API. The values are part of the API and
+/// never change.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoxStatus {
+    Ok = 0,
+    /// The source code did not compile
+    CompileError = 1,
+    /// The program stopped with a runtime error
+    RuntimeError = 2,
+    /// The program called `exit`
+    Exit = 3,
+    /// A pointer was null, or a string was not valid UTF-8
+    InvalidArgument = 4,
+}
+
+/// The type of a [`LoxValue`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoxValueTag {
+    Nil = 0,
+    Bool = 1,
+    Number = 2,
+}
+
+/// A Lox value passed to or returned from a native function. Booleans are
+/// stored in `number` as 0 or 1.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoxValue {
+    pub tag: LoxValueTag,
+    pub number: f64,
+}
+
+impl LoxValue {
+    fn from_value(value: Value) -> Option<Self> {
+        let (tag, number) = if value.is_nil() {
+            (LoxValueTag::Nil, 0.0)
+        } else if value.is_boolean() {
+            (LoxValueTag::Bool, value.as_boolean() as u8 as f64)
+        } else if value.is_number() {
+            (LoxValueTag::Number, value.as_number())
+        } else {
+            return None;
+        };
+        Some(Self { tag, number })
+    }
+
+    fn to_value(self) -> Value {
+        match self.tag {
+            LoxValueTag::Nil => Value::nil(),
+            LoxValueTag::Bool => Value::boolean(self.number != 0.0),
+            LoxValueTag::Number => Value::number(self.number),
+        }
+    }
+}
+
+/// A native function implemented by the host. It is given the `user_data` it
+/// was registered with and `argc` arguments, and stores its result in
+/// `result`. Returning false stops the program with a runtime error.
+pub type LoxNativeFn = unsafe extern "C" fn(
+    user_data: *mut c_void,
+    args: *const LoxValue,
+    argc: u8,
+    result: *mut LoxValue,
+) -> bool;
+
+/// A VM created by [`lox_vm_new`].
+pub struct LoxVm(VM<'static>);
+
+/// A native registered through [`lox_vm_register_native`].
+struct HostNative {
+    name: String,
+    arity: u8,
+    function: LoxNativeFn,
+    user_data: *mut c_void,
+}
+
+// The host promises, by registering `user_data`, that the function may use it
+// from whichever thread runs the VM
+unsafe impl Send for HostNative {}
+unsafe impl Sync for HostNative {}
+
+impl Native for HostNative {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn doc(&self) -> &str {
+        "A native function provided by the host."
+    }
+
+    fn arity(&self) -> u8 {
+        self.arity
+    }
+
+    fn call(&self, _heap: &mut Heap, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let args = args
+            .into_iter()
+            .map(LoxValue::from_value)
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| {
+                RuntimeError::OperandMismatch(0, "numbers, booleans, or nil".to_string())
+            })?;
+
+        let mut result = LoxValue {
+            tag: LoxValueTag::Nil,
+            number: 0.0,
+        };
+        // SAFETY: the host registered `function` to be called with these arguments
+        let ok = unsafe {
+            (self.function)(self.user_data, args.as_ptr(), args.len() as u8, &mut result)
+        };
+        if !ok {
+            return Err(RuntimeError::NativeFailed(0, self.name.clone()));
+        }
+        Ok(result.to_value())
+    }
+}
+
+/// Reads a NUL-terminated UTF-8 string from the host.
+///
+/// # Safety
+///
+/// `s` must be null or point to a NUL-terminated string.
+unsafe fn host_str<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        return None;
+    }
+    // SAFETY: guaranteed by the caller
+    unsafe { CStr::from_ptr(s) }.to_str().ok()
+}
+
+/// Creates a VM with the default options. It must be destroyed with
+/// [`lox_vm_free`].
+#[unsafe(no_mangle)]
+pub extern "C" fn lox_vm_new() -> *mut LoxVm {
+    Box::into_raw(Box::new(LoxVm(VM::new(Box::new(io::stdout())))))
+}
+
+/// Compiles and runs `source` on `vm`, reporting errors to stderr.
+///
+/// # Safety
+///
+/// `vm` must come from [`lox_vm_new`], and `source` must be null or point to
+/// a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lox_vm_interpret(vm: *mut LoxVm, source: *const c_char) -> LoxStatus {
+    // SAFETY: guaranteed by the caller
+    let (Some(vm), Some(source)) = (unsafe { vm.as_mut() }, unsafe { host_str(source) }) else {
+        return LoxStatus::InvalidArgument;
+    };
+
+    match try_interpret(source, &mut vm.0) {
+        Ok(_) => LoxStatus::Ok,
+        Err(outcome) => {
+            for e in outcome.errors() {
+                vm.0.report(e);
+            }
+            match outcome {
+                InterpretOutcome::CompileError(_) => LoxStatus::CompileError,
+                InterpretOutcome::RuntimeError(_) => LoxStatus::RuntimeError,
+                InterpretOutcome::Exit(_) => LoxStatus::Exit,
+            }
+        }
+    }
+}
+
+/// Defines the global function `name` on `vm`, which calls `function` with
+/// `user_data` and `arity` arguments.
+///
+/// # Safety
+///
+/// `vm` must come from [`lox_vm_new`], `name` must be null or point to a
+/// NUL-terminated string, and `function` must be safe to call with
+/// `user_data` for as long as `vm` lives.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lox_vm_register_native(
+    vm: *mut LoxVm,
+    name: *const c_char,
+    arity: u8,
+    function: LoxNativeFn,
+    user_data: *mut c_void,
+) -> LoxStatus {
+    // SAFETY: guaranteed by the caller
+    let (Some(vm), Some(name)) = (unsafe { vm.as_mut() }, unsafe { host_str(name) }) else {
+        return LoxStatus::InvalidArgument;
+    };
+
+    vm.0.define_native(HostNative {
+        name: name.to_string(),
+        arity,
+        function,
+        user_data,
+    });
+    LoxStatus::Ok
+}
+
+/// Destroys a VM created by [`lox_vm_new`]. Does nothing when `vm` is null.
+///
+/// # Safety
+///
+/// `vm` must be null or come from [`lox_vm_new`], and must not be used again.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lox_vm_free(vm: *mut LoxVm) {
+    if !vm.is_null() {
+        // SAFETY: guaranteed by the caller
+        drop(unsafe { Box::from_raw(vm) });
+    }
+}
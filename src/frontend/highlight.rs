@@ -0,0 +1,57 @@
+use crate::core::token::{Span, TokenType};
+
+use super::Scanner;
+
+/// What a piece of source code is, for choosing how to color it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenClass {
+    /// A keyword such as `var` or `while`
+    Keyword,
+    /// `true`, `false`, or `nil`
+    Literal,
+    Number,
+    String,
+    Identifier,
+    /// An operator such as `+` or `==`
+    Operator,
+    /// Parentheses, braces, `;`, `,`, and `.`
+    Punctuation,
+    /// A `//@debug` comment
+    Pragma,
+}
+
+impl TokenClass {
+    /// Returns the class of tokens of type `token`, or `None` for the end of
+    /// the file.
+    pub fn of(token: TokenType) -> Option<Self> {
+        use TokenType::*;
+
+        let class = match token {
+            And | Class | Do | Else | For | Fun | If | In | Or | Print | Return | Super | This
+            | Var | While | Yield => TokenClass::Keyword,
+            True | False | Nil => TokenClass::Literal,
+            Number => TokenClass::Number,
+            String => TokenClass::String,
+            Identifier => TokenClass::Identifier,
+            LeftParen | RightParen | LeftBrace | RightBrace | Semicolon | Comma | Dot => {
+                TokenClass::Punctuation
+            }
+            Star | Slash | Plus | Minus | Equal | EqualEqual | BangEqual | Bang | LessThan
+            | GreaterThan | LessEqual | GreaterEqual | Ampersand | Pipe | Caret | Tilde
+            | LessLess | GreaterGreater | Arrow => TokenClass::Operator,
+            DebugPragma => TokenClass::Pragma,
+            Eof => return None,
+        };
+        Some(class)
+    }
+}
+
+/// Splits `source` into the spans of its tokens along with their classes, in
+/// order, for coloring it. Whitespace, comments, and anything that could not be
+/// scanned are left out, so this works on code that is still being written.
+pub fn highlight(source: &str) -> Vec<(Span, TokenClass)> {
+    Scanner::new(source)
+        .filter_map(Result::ok)
+        .filter_map(|token| Some((token.span, TokenClass::of(token.token)?)))
+        .collect()
+}
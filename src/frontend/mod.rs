@@ -1,5 +1,62 @@
+//! The first two stages of the pipeline: [`scan`] turns source code into
+//! [`Token`]s, and [`parse`] turns tokens into [`Stmt`]s.
+
+mod highlight;
 mod parser;
 mod scanner;
 
-pub use parser::Parser;
-pub use scanner::Scanner;
+pub use crate::core::token::{Span, Token, TokenType};
+pub use highlight::{highlight, TokenClass};
+pub use parser::{Parser, MAX_NESTING};
+pub use scanner::{is_identifier_start, parse_number, Scanner, KEYWORDS};
+
+use crate::{ast::stmt::Stmt, core::errors::InterpretError};
+
+/// Splits `source` into tokens, ending with a [`TokenType::Eof`] token. Returns
+/// every error found if any part of `source` could not be scanned.
+pub fn scan(source: &str) -> Result<Vec<Token>, Vec<InterpretError>> {
+    collect(Scanner::new(source))
+}
+
+/// Parses `tokens`, as produced by [`scan`] from `source`, into the statements
+/// of a script. Returns every error found if any statement could not be parsed.
+pub fn parse(source: &str, tokens: Vec<Token>) -> Result<Vec<Stmt>, Vec<InterpretError>> {
+    collect(Parser::new(source, tokens.into_iter().map(Ok)))
+}
+
+/// Parses `tokens` like [`parse`], but also returns the statements when some
+/// could not be parsed, with [`Stmt::Error`] standing in for the parts that
+/// failed, along with every error found. Tools such as editors can use this to
+/// get a best-effort tree of code that is still being written.
+pub fn parse_partial(source: &str, tokens: Vec<Token>) -> (Vec<Stmt>, Vec<InterpretError>) {
+    let mut statements = vec![];
+    let mut errors = vec![];
+
+    for item in Parser::new(source, tokens.into_iter().map(Ok)) {
+        match item {
+            Ok(stmt) => statements.push(stmt),
+            Err(e) => errors.push(e),
+        }
+    }
+    (statements, errors)
+}
+
+fn collect<T>(
+    items: impl Iterator<Item = Result<T, InterpretError>>,
+) -> Result<Vec<T>, Vec<InterpretError>> {
+    let mut values = vec![];
+    let mut errors = vec![];
+
+    for item in items {
+        match item {
+            Ok(v) => values.push(v),
+            Err(e) => errors.push(e),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(values)
+    } else {
+        Err(errors)
+    }
+}
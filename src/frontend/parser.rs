@@ -1,26 +1,76 @@
-use std::{iter::Peekable, vec};
+use std::{collections::VecDeque, iter::Peekable, vec};
 
 use crate::{
-    ast::{expr::Expr, stmt::Stmt},
+    ast::{
+        expr::Expr,
+        stmt::{MethodKind, Stmt},
+    },
     core::{
         errors::{InterpretError, SyntaxError},
         token::{Token, TokenType},
     },
-    frontend::scanner::Scanner,
 };
 
+/// How deeply statements and expressions may be nested by default. Deeper input
+/// is reported as an error instead of overflowing the stack while it is parsed,
+/// compiled, or walked, even on the small stacks of spawned threads. The
+/// `else if`s of a chain do not count as nesting.
+pub const MAX_NESTING: usize = 128;
+
 /// An iterator over the statements in the code.
-pub struct Parser<'a> {
+pub struct Parser<'a, I: Iterator<Item = Result<Token, InterpretError>>> {
+    /// The source code the tokens were scanned from.
+    source: &'a str,
     /// An iterator over the tokens in the code.
-    tokens: Peekable<Scanner<'a>>,
+    tokens: Peekable<I>,
+    /// How many statements and expressions enclose the one being parsed
+    depth: usize,
+    /// How many statements and expressions may enclose the one being parsed
+    max_depth: usize,
+    /// Errors in statements nested in a block, which the parser recovered from
+    /// and has yet to yield
+    errors: VecDeque<InterpretError>,
+    /// A top-level statement to yield once the errors in it have been yielded
+    recovered: Option<Stmt>,
 }
 
-impl<'a> Parser<'a> {
-    /// Creates a new parser from the given scanner.
-    pub fn new(tokens: Scanner<'a>) -> Self {
+impl<'a, I: Iterator<Item = Result<Token, InterpretError>>> Parser<'a, I> {
+    /// Creates a new parser from the given tokens, usually a [`Scanner`](super::Scanner),
+    /// scanned from `source`.
+    pub fn new(source: &'a str, tokens: I) -> Self {
         Self {
+            source,
             tokens: tokens.peekable(),
+            depth: 0,
+            max_depth: MAX_NESTING,
+            errors: VecDeque::new(),
+            recovered: None,
+        }
+    }
+
+    /// Makes the parser reject input nested more than `limit` levels deep,
+    /// instead of [`MAX_NESTING`]. A higher limit needs a bigger stack to parse,
+    /// compile, and walk the deepest input it allows.
+    pub fn with_max_nesting(mut self, limit: usize) -> Self {
+        self.max_depth = limit;
+        self
+    }
+
+    /// Runs `parse` one level of nesting deeper, failing if the input is nested
+    /// more than `max_depth` levels.
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, InterpretError>,
+    ) -> Result<T, InterpretError> {
+        if self.depth >= self.max_depth {
+            let line = self.peek()?.line;
+            return Err(InterpretError::Syntax(SyntaxError::TooDeeplyNested(line)));
         }
+
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
     }
 
     /// Advances to the next token to parse. If there are no more tokens to parse,
@@ -38,9 +88,13 @@ impl<'a> Parser<'a> {
     /// An `UnexpectedEOF` error is returned, because `peek()` is only called when
     /// the grammar expects another function
     fn peek(&mut self) -> Result<&Token, InterpretError> {
+        if let Some(e) = self.scan_error() {
+            return Err(e);
+        }
+
         match self.tokens.peek() {
             Some(Ok(t)) => Ok(t),
-            Some(Err(e)) => Err(e.to_owned()),
+            Some(Err(_)) => unreachable!("scan errors are taken above"),
             None => Err(InterpretError::Syntax(SyntaxError::UnexpectedEOF)),
         }
     }
@@ -48,9 +102,13 @@ impl<'a> Parser<'a> {
     /// Advances to the next token to parse if the next token is in `tokens`. If
     /// the token is not in `tokens`, an `SyntaxError::ExpectedChar` error is returned.
     fn consume(&mut self, token: TokenType) -> Result<Token, InterpretError> {
+        if let Some(e) = self.scan_error() {
+            return Err(e);
+        }
+
         let next_token = match self.tokens.peek() {
             Some(Ok(t)) => t,
-            Some(Err(e)) => return Err(e.to_owned()),
+            Some(Err(_)) => unreachable!("scan errors are taken above"),
             None => return Err(InterpretError::Syntax(SyntaxError::UnexpectedEOF)),
         };
 
@@ -59,45 +117,63 @@ impl<'a> Parser<'a> {
         } else {
             Err(InterpretError::Syntax(SyntaxError::ExpectedChar(
                 next_token.line,
-                next_token.lexeme.to_owned(),
+                next_token.lexeme(self.source).to_owned(),
                 format!("{:?}", token),
             )))
         }
     }
 
-    /// Synchronizes the parser by discarding tokens until it finds a token that
-    /// highly represents the start of a new statement. This is used to recover from
-    /// errors.
-    fn synchronize(&mut self) {
-        // Discard the value, since we know its going to be an error
-        self.advance().ok();
-        loop {
-            let cur_token = match self.advance() {
-                Ok(t) => t.token,
-                Err(InterpretError::Syntax(SyntaxError::UnexpectedEOF)) => return,
-                Err(_) => TokenType::Nil, // Anything that doesn't match below should work
-            };
+    /// Advances past the next token if it is `token`, returning whether it was.
+    /// A scan error is left for the next call to `peek()` or `consume()` to report.
+    fn match_token(&mut self, token: TokenType) -> bool {
+        self.tokens
+            .next_if(|next| matches!(next, Ok(t) if t.token == token))
+            .is_some()
+    }
 
-            if cur_token == TokenType::Semicolon {
-                return;
-            }
+    /// Takes the next token if the scanner failed to read it, returning its error.
+    /// The error is taken rather than peeked so that it is reported only once.
+    fn scan_error(&mut self) -> Option<InterpretError> {
+        match self.tokens.peek() {
+            Some(Err(_)) => self.tokens.next().and_then(Result::err),
+            _ => None,
+        }
+    }
 
-            let next_token = match self.peek() {
-                Ok(t) => &t.token,
-                Err(InterpretError::Syntax(SyntaxError::UnexpectedEOF)) => return,
-                Err(_) => &TokenType::Nil,
+    /// Synchronizes the parser by discarding tokens until the start of the next
+    /// statement, skipping over any blocks on the way, to recover from an error.
+    /// In a block, it stops before the brace that closes the block, so that the
+    /// rest of the block is kept. Scan errors in the discarded tokens are kept to
+    /// be reported with the rest.
+    fn synchronize(&mut self, in_block: bool) {
+        let mut depth = 0;
+        loop {
+            let token = match self.tokens.peek() {
+                Some(Ok(t)) if t.token == TokenType::Eof => return,
+                Some(Ok(t)) => t.token,
+                Some(Err(_)) => {
+                    let error = self.scan_error();
+                    self.errors.extend(error);
+                    continue;
+                }
+                None => return,
             };
 
-            match next_token {
-                TokenType::Class
-                | TokenType::Fun
-                | TokenType::Var
-                | TokenType::If
-                | TokenType::While
-                | TokenType::Print
-                | TokenType::Return => return,
+            match token {
+                TokenType::RightBrace if depth == 0 && in_block => return,
+                TokenType::RightBrace if depth > 0 => depth -= 1,
+                TokenType::LeftBrace => depth += 1,
                 _ => (),
             }
+            self.tokens.next();
+
+            let next_starts_statement = matches!(
+                self.tokens.peek(),
+                Some(Ok(t)) if starts_statement(&t.token)
+            );
+            if depth == 0 && (token == TokenType::Semicolon || next_starts_statement) {
+                return;
+            }
         }
     }
 
@@ -123,8 +199,12 @@ impl<'a> Parser<'a> {
 
     fn declare_var(&mut self) -> Result<Stmt, InterpretError> {
         let identifier_token = self.consume(TokenType::Identifier)?;
+        self.declare_var_initializer(identifier_token)
+    }
 
-        if let Ok(_equals) = self.consume(TokenType::Equal) {
+    /// Parses the rest of a variable declaration, after its identifier.
+    fn declare_var_initializer(&mut self, identifier_token: Token) -> Result<Stmt, InterpretError> {
+        if self.match_token(TokenType::Equal) {
             let initializer = self.expression()?;
             self.consume(TokenType::Semicolon)?;
             Ok(Stmt::DeclareVar(identifier_token, Some(initializer)))
@@ -135,8 +215,31 @@ impl<'a> Parser<'a> {
     }
 
     fn declare_func(&mut self) -> Result<Stmt, InterpretError> {
+        let (identifier_token, params, body) = self.function()?;
+        Ok(Stmt::DeclareFunc(identifier_token, params, body))
+    }
+
+    /// Parses the name, parameters, and body of a function or method.
+    fn function(&mut self) -> Result<(Token, Vec<Token>, Vec<Stmt>), InterpretError> {
         let identifier_token = self.consume(TokenType::Identifier)?;
+        self.function_rest(identifier_token)
+    }
+
+    /// Parses the parameters and body of a function or method named by
+    /// `identifier_token`.
+    fn function_rest(
+        &mut self,
+        identifier_token: Token,
+    ) -> Result<(Token, Vec<Token>, Vec<Stmt>), InterpretError> {
+        let (params, closing) = self.parameters()?;
+        let body = self.function_body(closing)?;
+
+        Ok((identifier_token, params, body))
+    }
 
+    /// Parses a parenthesized parameter list, returning it along with the
+    /// closing parenthesis.
+    fn parameters(&mut self) -> Result<(Vec<Token>, Token), InterpretError> {
         let mut params = Vec::new();
 
         self.consume(TokenType::LeftParen)?;
@@ -155,7 +258,7 @@ impl<'a> Parser<'a> {
 
                     let param = self.consume(TokenType::Identifier)?;
                     params.push(param);
-                    if self.consume(TokenType::Comma).is_err() {
+                    if !self.match_token(TokenType::Comma) {
                         break;
                     }
                 }
@@ -163,25 +266,26 @@ impl<'a> Parser<'a> {
         }
         let closing = self.consume(TokenType::RightParen)?;
 
-        let body = match self.statement()? {
-            Stmt::Block(v) => v,
-            _ => {
-                return Err(InterpretError::Syntax(SyntaxError::ExpectedChar(
-                    closing.line,
-                    ")".to_string(),
-                    "function body".to_string(),
-                )))
-            }
-        };
+        Ok((params, closing))
+    }
 
-        Ok(Stmt::DeclareFunc(identifier_token, params, body))
+    /// Parses the block body of a function, following its parameter list.
+    fn function_body(&mut self, closing: Token) -> Result<Vec<Stmt>, InterpretError> {
+        match self.statement()? {
+            Stmt::Block(v) => Ok(v),
+            _ => Err(InterpretError::Syntax(SyntaxError::ExpectedChar(
+                closing.line,
+                ")".to_string(),
+                "function body".to_string(),
+            ))),
+        }
     }
 
     fn declare_class(&mut self) -> Result<Stmt, InterpretError> {
         let identifier_token = self.consume(TokenType::Identifier)?;
         let mut methods = Vec::new();
 
-        let superclass = if self.consume(TokenType::LessThan).is_ok() {
+        let superclass = if self.match_token(TokenType::LessThan) {
             Some(self.consume(TokenType::Identifier)?)
         } else {
             None
@@ -196,18 +300,7 @@ impl<'a> Parser<'a> {
                 TokenType::RightBrace | TokenType::Eof => {
                     break;
                 }
-                _ => {
-                    let method = self.declare_func()?;
-                    match method {
-                        Stmt::DeclareFunc(id, params, body) => {
-                            methods.push((id, params, body));
-                        }
-                        _ => {
-                            // This should never happen
-                            panic!("parser.decalre_func() did not return function statement.")
-                        }
-                    }
-                }
+                _ => methods.push(self.method()?),
             }
         }
 
@@ -216,7 +309,43 @@ impl<'a> Parser<'a> {
         Ok(Stmt::DeclareClass(identifier_token, superclass, methods))
     }
 
+    /// Parses a member of a class body: a method, a `get name { ... }` getter, or
+    /// a `set name(value) { ... }` setter. `get` and `set` are only keywords
+    /// when a name follows them, so methods may still be called `get` or `set`.
+    fn method(&mut self) -> Result<(MethodKind, Token, Vec<Token>, Vec<Stmt>), InterpretError> {
+        let identifier_token = self.consume(TokenType::Identifier)?;
+        let kind = match identifier_token.lexeme(self.source) {
+            "get" => MethodKind::Getter,
+            "set" => MethodKind::Setter,
+            _ => MethodKind::Method,
+        };
+        if kind == MethodKind::Method || self.peek()?.token != TokenType::Identifier {
+            let (name, params, body) = self.function_rest(identifier_token)?;
+            return Ok((MethodKind::Method, name, params, body));
+        }
+
+        let name = self.advance()?;
+        let (params, body) = match kind {
+            MethodKind::Getter => (Vec::new(), self.function_body(name.clone())?),
+            _ => {
+                let (params, closing) = self.parameters()?;
+                if params.len() != 1 {
+                    return Err(InterpretError::Syntax(SyntaxError::SetterArity(
+                        name.line,
+                        name.lexeme(self.source).to_owned(),
+                    )));
+                }
+                (params, self.function_body(closing)?)
+            }
+        };
+        Ok((kind, name, params, body))
+    }
+
     fn statement(&mut self) -> Result<Stmt, InterpretError> {
+        self.nested(Self::statement_inner)
+    }
+
+    fn statement_inner(&mut self) -> Result<Stmt, InterpretError> {
         let t = self.peek()?;
 
         match t.token {
@@ -236,6 +365,10 @@ impl<'a> Parser<'a> {
                 self.advance()?;
                 self.while_stmt()
             }
+            TokenType::Do => {
+                self.advance()?;
+                self.do_while_stmt()
+            }
             TokenType::For => {
                 self.advance()?;
                 self.for_stmt()
@@ -244,6 +377,11 @@ impl<'a> Parser<'a> {
                 let actual = self.advance()?;
                 self.return_stmt(actual)
             }
+            TokenType::DebugPragma => {
+                let pragma = self.advance()?;
+                let stmt = self.declaration()?;
+                Ok(Stmt::Debug(pragma, Box::new(stmt)))
+            }
             _ => self.expression_stmt(),
         }
     }
@@ -259,9 +397,21 @@ impl<'a> Parser<'a> {
 
         loop {
             let token = self.peek()?;
-            match token.token {
-                TokenType::RightBrace | TokenType::Eof => break,
-                _ => statements.push(self.declaration()?),
+            if matches!(token.token, TokenType::RightBrace | TokenType::Eof) {
+                break;
+            }
+
+            // Stand an error node in for a statement that fails to parse, and
+            // carry on with the next one
+            let start = token.clone();
+            match self.declaration() {
+                Ok(stmt) => statements.push(stmt),
+                Err(e @ InterpretError::Syntax(SyntaxError::UnexpectedEOF)) => return Err(e),
+                Err(e) => {
+                    self.errors.push_back(e);
+                    self.synchronize(true);
+                    statements.push(Stmt::Error(start));
+                }
             }
         }
 
@@ -270,24 +420,32 @@ impl<'a> Parser<'a> {
     }
 
     fn if_stmt(&mut self) -> Result<Stmt, InterpretError> {
-        // Match the pattern (<condition>)
-        let token = self.consume(TokenType::LeftParen)?;
-        let condition = self.expression()?;
-        self.consume(TokenType::RightParen)?;
+        // An `else if` continues the chain at the same depth, so that long
+        // chains are not mistaken for deeply nested code
+        let mut branches = vec![];
+        let mut else_block = None;
+        loop {
+            // Match the pattern (<condition>)
+            let token = self.consume(TokenType::LeftParen)?;
+            let condition = self.expression()?;
+            self.consume(TokenType::RightParen)?;
+            branches.push((token, condition, self.statement()?));
 
-        let if_block = self.statement()?;
+            if !self.match_token(TokenType::Else) {
+                break;
+            }
+            if !self.match_token(TokenType::If) {
+                else_block = Some(Box::new(self.statement()?));
+                break;
+            }
+        }
 
-        if self.consume(TokenType::Else).is_ok() {
-            let else_block = self.statement()?;
-            Ok(Stmt::If(
-                token,
-                condition,
-                Box::new(if_block),
-                Some(Box::new(else_block)),
-            ))
-        } else {
-            Ok(Stmt::If(token, condition, Box::new(if_block), None))
+        let mut stmt = None;
+        for (token, condition, if_block) in branches.into_iter().rev() {
+            let else_block = stmt.map(Box::new).or(else_block.take());
+            stmt = Some(Stmt::If(token, condition, Box::new(if_block), else_block));
         }
+        Ok(stmt.expect("an if statement has at least one branch"))
     }
 
     fn while_stmt(&mut self) -> Result<Stmt, InterpretError> {
@@ -300,9 +458,22 @@ impl<'a> Parser<'a> {
         Ok(Stmt::While(token, condition, Box::new(while_block)))
     }
 
+    fn do_while_stmt(&mut self) -> Result<Stmt, InterpretError> {
+        let body = self.statement()?;
+
+        // Match the pattern while (<condition>);
+        self.consume(TokenType::While)?;
+        let token = self.consume(TokenType::LeftParen)?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen)?;
+        self.consume(TokenType::Semicolon)?;
+
+        Ok(Stmt::DoWhile(token, Box::new(body), condition))
+    }
+
     fn for_stmt(&mut self) -> Result<Stmt, InterpretError> {
         let left_paren = self.consume(TokenType::LeftParen)?;
-        let line = left_paren.line;
+        let (line, column) = (left_paren.line, left_paren.column);
 
         let initializer = match self.peek()?.token {
             TokenType::Semicolon => {
@@ -311,7 +482,11 @@ impl<'a> Parser<'a> {
             }
             TokenType::Var => {
                 self.advance()?;
-                Some(self.declare_var()?)
+                let identifier_token = self.consume(TokenType::Identifier)?;
+                if self.match_token(TokenType::In) {
+                    return self.foreach_stmt(left_paren, identifier_token);
+                }
+                Some(self.declare_var_initializer(identifier_token)?)
             }
             _ => Some(self.expression_stmt()?),
         };
@@ -341,11 +516,7 @@ impl<'a> Parser<'a> {
             None => {
                 body = Stmt::While(
                     left_paren,
-                    Expr::Literal(Token {
-                        token: TokenType::True,
-                        lexeme: "true".to_string(),
-                        line,
-                    }),
+                    Expr::Literal(Token::synthetic(TokenType::True, line, column)),
                     Box::new(body),
                 );
             }
@@ -358,16 +529,21 @@ impl<'a> Parser<'a> {
         Ok(body)
     }
 
+    /// Parses the rest of `for (var <id> in <collection>) <body>`, after `in`.
+    fn foreach_stmt(&mut self, left_paren: Token, id: Token) -> Result<Stmt, InterpretError> {
+        let collection = self.expression()?;
+        self.consume(TokenType::RightParen)?;
+        let body = self.statement()?;
+
+        Ok(Stmt::ForEach(left_paren, id, collection, Box::new(body)))
+    }
+
     fn return_stmt(&mut self, token: Token) -> Result<Stmt, InterpretError> {
-        if self.consume(TokenType::Semicolon).is_ok() {
-            let line = token.line;
+        if self.match_token(TokenType::Semicolon) {
+            let (line, column) = (token.line, token.column);
             return Ok(Stmt::Return(
                 token,
-                Expr::Literal(Token {
-                    token: TokenType::Nil,
-                    lexeme: "nil".to_string(),
-                    line,
-                }),
+                Expr::Literal(Token::synthetic(TokenType::Nil, line, column)),
             ));
         }
         let expr = self.expression()?;
@@ -382,18 +558,22 @@ impl<'a> Parser<'a> {
     }
 
     fn expression(&mut self) -> Result<Expr, InterpretError> {
-        self.assignment()
+        self.nested(Self::assignment)
     }
 
     fn assignment(&mut self) -> Result<Expr, InterpretError> {
-        let expr = self.logic_or()?;
+        if self.peek()?.token == TokenType::Yield {
+            return self.yield_expr();
+        }
+
+        let expr = self.binary(0)?;
 
         let t = self.peek()?;
 
         match t.token {
             TokenType::Equal => {
                 let actual = self.advance()?;
-                let value = self.assignment()?;
+                let value = self.nested(Self::assignment)?;
 
                 match expr {
                     Expr::Variable(id) => Ok(Expr::Assign(id, Box::new(value))),
@@ -407,115 +587,39 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn logic_or(&mut self) -> Result<Expr, InterpretError> {
-        let mut expr = self.logic_and()?;
-
-        loop {
-            let t = self.peek()?;
-
-            match t.token {
-                TokenType::Or => {
-                    let actual = self.advance()?;
-                    let right = self.logic_and()?;
-                    expr = Expr::Or(actual, Box::new(expr), Box::new(right))
-                }
-                _ => break,
-            }
-        }
-
-        Ok(expr)
-    }
-
-    fn logic_and(&mut self) -> Result<Expr, InterpretError> {
-        let mut expr = self.equality()?;
-
-        loop {
-            let t = self.peek()?;
-
-            match t.token {
-                TokenType::And => {
-                    let actual = self.advance()?;
-                    let right = self.equality()?;
-                    expr = Expr::And(actual, Box::new(expr), Box::new(right))
-                }
-                _ => break,
-            }
-        }
-
-        Ok(expr)
-    }
-
-    fn equality(&mut self) -> Result<Expr, InterpretError> {
-        let mut expr = self.comparison()?;
-
-        loop {
-            let t = self.peek()?;
-
-            match t.token {
-                TokenType::EqualEqual | TokenType::BangEqual => {
-                    let op = self.advance()?;
-                    let right = self.comparison()?;
-                    expr = Expr::Binary(op, Box::new(expr), Box::new(right))
-                }
-                _ => break,
-            }
-        }
-
-        Ok(expr)
-    }
-
-    fn comparison(&mut self) -> Result<Expr, InterpretError> {
-        let mut expr = self.term()?;
-
-        loop {
-            let t = self.peek()?;
-
-            match t.token {
-                TokenType::LessEqual
-                | TokenType::LessThan
-                | TokenType::GreaterEqual
-                | TokenType::GreaterThan => {
-                    let op = self.advance()?;
-                    let right = self.term()?;
-                    expr = Expr::Binary(op, Box::new(expr), Box::new(right))
-                }
-                _ => break,
-            }
-        }
-
-        Ok(expr)
-    }
-
-    fn term(&mut self) -> Result<Expr, InterpretError> {
-        let mut expr = self.factor()?;
-
-        loop {
-            let t = self.peek()?;
+    /// Parses `yield <expr>`. A bare `yield` yields `nil`.
+    fn yield_expr(&mut self) -> Result<Expr, InterpretError> {
+        let token = self.advance()?;
 
-            match t.token {
-                TokenType::Plus | TokenType::Minus => {
-                    let op = self.advance()?;
-                    let right = self.factor()?;
-                    expr = Expr::Binary(op, Box::new(expr), Box::new(right))
-                }
-                _ => break,
+        let value = match self.peek()?.token {
+            TokenType::Semicolon | TokenType::RightParen | TokenType::Comma => {
+                Expr::Literal(Token::synthetic(TokenType::Nil, token.line, token.column))
             }
-        }
+            _ => self.nested(Self::assignment)?,
+        };
 
-        Ok(expr)
+        Ok(Expr::Yield(token, Box::new(value)))
     }
 
-    fn factor(&mut self) -> Result<Expr, InterpretError> {
+    /// Parses a chain of binary operators that bind at least as tightly as
+    /// `min_precedence`, see [`precedence`]. Climbing the precedence levels in
+    /// one loop, rather than a function per level, keeps the stack each
+    /// parenthesized expression takes small.
+    fn binary(&mut self, min_precedence: u8) -> Result<Expr, InterpretError> {
         let mut expr = self.unary()?;
 
         loop {
             let t = self.peek()?;
 
-            match t.token {
-                TokenType::Star | TokenType::Slash => {
+            match precedence(&t.token) {
+                Some(level) if level >= min_precedence => {
                     let op = self.advance()?;
-                    let right = self.unary()?;
-                    expr = Expr::Binary(op, Box::new(expr), Box::new(right))
+                    let right = self.binary(level + 1)?;
+                    expr = match op.token {
+                        TokenType::Or => Expr::Or(op, Box::new(expr), Box::new(right)),
+                        TokenType::And => Expr::And(op, Box::new(expr), Box::new(right)),
+                        _ => Expr::Binary(op, Box::new(expr), Box::new(right)),
+                    }
                 }
                 _ => break,
             }
@@ -528,9 +632,9 @@ impl<'a> Parser<'a> {
         let t = self.peek()?;
 
         match t.token {
-            TokenType::Bang | TokenType::Minus => {
+            TokenType::Bang | TokenType::Minus | TokenType::Tilde => {
                 let op = self.advance()?;
-                let expr = self.unary()?;
+                let expr = self.nested(Self::unary)?;
                 Ok(Expr::Unary(op, Box::new(expr)))
             }
             _ => self.call(),
@@ -542,7 +646,7 @@ impl<'a> Parser<'a> {
 
         loop {
             let mut args = Vec::new();
-            if self.consume(TokenType::LeftParen).is_ok() {
+            if self.match_token(TokenType::LeftParen) {
                 loop {
                     let t = self.peek()?;
 
@@ -557,7 +661,7 @@ impl<'a> Parser<'a> {
                                 )));
                             }
                             args.push(self.expression()?);
-                            if self.consume(TokenType::Comma).is_err() {
+                            if !self.match_token(TokenType::Comma) {
                                 break;
                             }
                         }
@@ -567,7 +671,7 @@ impl<'a> Parser<'a> {
                 let closing = self.consume(TokenType::RightParen)?;
 
                 expr = Expr::Call(Box::new(expr), args, closing);
-            } else if self.consume(TokenType::Dot).is_ok() {
+            } else if self.match_token(TokenType::Dot) {
                 let prop = self.consume(TokenType::Identifier)?;
                 expr = Expr::Get(Box::new(expr), prop);
             } else {
@@ -579,6 +683,16 @@ impl<'a> Parser<'a> {
     }
 
     fn primary(&mut self) -> Result<Expr, InterpretError> {
+        // Leave a token that cannot start an expression, such as a ';', for the
+        // parser to resynchronize on
+        let source = self.source;
+        let next = self.peek()?;
+        if !starts_expression(&next.token) {
+            return Err(InterpretError::Syntax(SyntaxError::ExpectedExpression(
+                next.line,
+                next.lexeme(source).to_owned(),
+            )));
+        }
         let t = self.advance()?;
 
         let expr = match &t.token {
@@ -594,6 +708,7 @@ impl<'a> Parser<'a> {
                 Expr::Grouping(Box::new(expr))
             }
             TokenType::This => Expr::This(t),
+            TokenType::Fun if self.peek()?.token == TokenType::LeftParen => self.lambda(t)?,
             TokenType::Super => {
                 self.consume(TokenType::Dot)?;
                 let prop = self.consume(TokenType::Identifier)?;
@@ -602,35 +717,119 @@ impl<'a> Parser<'a> {
             }
             _ => {
                 return Err(InterpretError::Syntax(SyntaxError::ExpectedExpression(
-                    t.line, t.lexeme,
+                    t.line,
+                    t.lexeme(self.source).to_owned(),
                 )))
             }
         };
 
         Ok(expr)
     }
+
+    /// Parses an anonymous function after its `fun` keyword. Besides a block
+    /// body, `fun (a) => expr` is shorthand for `fun (a) { return expr; }`.
+    fn lambda(&mut self, token: Token) -> Result<Expr, InterpretError> {
+        let (params, closing) = self.parameters()?;
+
+        let body = match self.consume(TokenType::Arrow) {
+            Ok(arrow) => vec![Stmt::Return(arrow, self.expression()?)],
+            Err(_) => self.function_body(closing)?,
+        };
+
+        Ok(Expr::Lambda(token, params, body))
+    }
 }
 
-impl Iterator for Parser<'_> {
+impl<I: Iterator<Item = Result<Token, InterpretError>>> Iterator for Parser<'_, I> {
     type Item = Result<Stmt, InterpretError>;
 
+    /// Yields every error in a statement before the statement itself, in which
+    /// [`Stmt::Error`] stands in for each part that failed to parse.
     fn next(&mut self) -> Option<Self::Item> {
-        match self.tokens.peek() {
-            Some(Ok(token)) => {
-                if token.token == TokenType::Eof {
-                    return None;
-                }
-            }
-            Some(Err(_)) => (),
-            None => return None,
+        if let Some(e) = self.errors.pop_front() {
+            return Some(Err(e));
         }
+        if let Some(stmt) = self.recovered.take() {
+            return Some(Ok(stmt));
+        }
+
+        let start = match self.tokens.peek() {
+            Some(Ok(token)) if token.token == TokenType::Eof => return None,
+            Some(Ok(token)) => Some(token.clone()),
+            Some(Err(_)) => None,
+            None => return None,
+        };
 
-        match self.declaration() {
-            Ok(s) => Some(Ok(s)),
+        let stmt = match self.declaration() {
+            Ok(stmt) => Some(stmt),
             Err(e) => {
-                self.synchronize();
+                self.errors.push_back(e);
+                self.synchronize(false);
+                start.map(Stmt::Error)
+            }
+        };
+
+        match self.errors.pop_front() {
+            Some(e) => {
+                self.recovered = stmt;
                 Some(Err(e))
             }
+            None => stmt.map(Ok),
         }
     }
 }
+
+/// Whether `token` begins a statement, so that the parser can resume there after
+/// an error.
+fn starts_statement(token: &TokenType) -> bool {
+    matches!(
+        token,
+        TokenType::Class
+            | TokenType::Fun
+            | TokenType::Var
+            | TokenType::If
+            | TokenType::While
+            | TokenType::Do
+            | TokenType::Print
+            | TokenType::Return
+    )
+}
+
+/// Whether `token` may begin an expression.
+fn starts_expression(token: &TokenType) -> bool {
+    matches!(
+        token,
+        TokenType::Identifier
+            | TokenType::True
+            | TokenType::False
+            | TokenType::Nil
+            | TokenType::String
+            | TokenType::Number
+            | TokenType::LeftParen
+            | TokenType::This
+            | TokenType::Fun
+            | TokenType::Super
+    )
+}
+
+/// How tightly `token` binds as a binary operator, from `or` up to `*` and `/`,
+/// or `None` if it is not one. Bitwise operators bind tighter than comparisons,
+/// so `a & mask == 0` compares the masked value.
+fn precedence(token: &TokenType) -> Option<u8> {
+    match token {
+        TokenType::Or => Some(0),
+        TokenType::And => Some(1),
+        TokenType::EqualEqual | TokenType::BangEqual => Some(2),
+        TokenType::LessEqual
+        | TokenType::LessThan
+        | TokenType::GreaterEqual
+        | TokenType::GreaterThan => Some(3),
+        TokenType::Pipe => Some(4),
+        TokenType::Caret => Some(5),
+        TokenType::Ampersand => Some(6),
+        TokenType::LessLess | TokenType::GreaterGreater => Some(7),
+        TokenType::Plus | TokenType::Minus => Some(8),
+        TokenType::Star | TokenType::Slash => Some(9),
+        _ => None,
+    }
+}
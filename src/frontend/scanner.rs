@@ -1,14 +1,115 @@
 use crate::core::errors::{InterpretError, ScanError};
-use crate::core::token::{Token, TokenType};
+use crate::core::token::{Span, Token, TokenType};
 use std::iter::Peekable;
 use std::str::Chars;
 
-/// An iterator over the tokens in the source code.
+/// The words the scanner reads as keywords rather than identifiers.
+pub const KEYWORDS: &[&str] = &[
+    "and", "class", "do", "else", "false", "for", "fun", "if", "in", "nil", "or", "print",
+    "return", "super", "this", "true", "var", "while", "yield",
+];
+
+/// Returns the keyword `ident` spells, if any. Looking at the first letters
+/// before comparing the whole word rules out most identifiers straight away.
+fn keyword(ident: &str) -> Option<TokenType> {
+    let bytes = ident.as_bytes();
+    let (token, word) = match bytes.first()? {
+        b'a' => (TokenType::And, "and"),
+        b'c' => (TokenType::Class, "class"),
+        b'd' => (TokenType::Do, "do"),
+        b'e' => (TokenType::Else, "else"),
+        b'f' => match bytes.get(1)? {
+            b'a' => (TokenType::False, "false"),
+            b'o' => (TokenType::For, "for"),
+            b'u' => (TokenType::Fun, "fun"),
+            _ => return None,
+        },
+        b'i' => match bytes.get(1)? {
+            b'f' => (TokenType::If, "if"),
+            b'n' => (TokenType::In, "in"),
+            _ => return None,
+        },
+        b'n' => (TokenType::Nil, "nil"),
+        b'o' => (TokenType::Or, "or"),
+        b'p' => (TokenType::Print, "print"),
+        b'r' => (TokenType::Return, "return"),
+        b's' => (TokenType::Super, "super"),
+        b't' => match bytes.get(1)? {
+            b'h' => (TokenType::This, "this"),
+            b'r' => (TokenType::True, "true"),
+            _ => return None,
+        },
+        b'v' => (TokenType::Var, "var"),
+        b'w' => (TokenType::While, "while"),
+        b'y' => (TokenType::Yield, "yield"),
+        _ => return None,
+    };
+    (ident == word).then_some(token)
+}
+
+/// Returns whether an identifier can start with `ch`: an underscore, or a letter
+/// as Unicode defines it for identifiers (`XID_Start`), such as `é` or `π`.
+pub fn is_identifier_start(ch: char) -> bool {
+    ch == '_' || unicode_ident::is_xid_start(ch)
+}
+
+/// Returns the value of a number literal, or `None` if `lexeme` is not one.
+///
+/// Besides decimals such as `12` and `1.5`, literals may have an exponent
+/// (`1.5e3`), be written in hexadecimal (`0xFF`) or binary (`0b1010`), and
+/// separate digits with underscores (`1_000_000`). An underscore must sit
+/// between two digits.
+pub fn parse_number(lexeme: &str) -> Option<f64> {
+    let (digits, radix) = match lexeme.get(..2) {
+        Some("0x" | "0X") => (&lexeme[2..], 16),
+        Some("0b" | "0B") => (&lexeme[2..], 2),
+        _ => (lexeme, 10),
+    };
+
+    let bytes = digits.as_bytes();
+    let is_digit = |i: usize| bytes.get(i).is_some_and(|&b| (b as char).is_digit(radix));
+    for (i, &b) in bytes.iter().enumerate() {
+        if b == b'_' && (i == 0 || !is_digit(i - 1) || !is_digit(i + 1)) {
+            return None;
+        }
+    }
+    let digits = digits.replace('_', "");
+    if !digits.starts_with(|ch: char| ch.is_digit(radix)) {
+        return None;
+    }
+
+    if radix == 10 {
+        if !digits
+            .chars()
+            .all(|ch| ch.is_ascii_digit() || matches!(ch, '.' | 'e' | 'E' | '+' | '-'))
+        {
+            return None;
+        }
+        digits.parse().ok()
+    } else {
+        digits.chars().try_fold(0.0, |n, ch| {
+            Some(n * radix as f64 + ch.to_digit(radix)? as f64)
+        })
+    }
+}
+
+/// An iterator over the tokens in the source code. Columns count characters,
+/// not bytes, while spans are byte offsets into the source.
 pub struct Scanner<'a> {
+    /// The source code being scanned.
+    source: &'a str,
     /// An iterator over the characters in the source code.
     chars: Peekable<Chars<'a>>,
+    /// The byte offset in `source` of the next character read from `chars`.
+    offset: usize,
     /// The current line number processed to in the source code.
     line: u32,
+    /// The column of the next character read from `chars`, counting from 1.
+    column: u16,
+    /// The column the token being scanned starts at.
+    start_column: u16,
+    /// The byte offset in `source` the token being scanned starts at.
+    start: usize,
     /// Whether the end of the file has been reached.
     eof: bool,
     /// Temporary store for a character that was skipped over.
@@ -16,59 +117,97 @@ pub struct Scanner<'a> {
 }
 
 impl<'a> Scanner<'a> {
-    /// Creates a new scanner for the given source code.
+    /// Creates a new scanner for the given source code, skipping a leading byte
+    /// order mark and `#!` line.
     pub fn new(source: &'a str) -> Self {
+        let offset = Self::preamble_len(source);
         Self {
-            chars: source.chars().peekable(),
+            source,
+            chars: source[offset..].chars().peekable(),
+            offset,
             line: 1,
+            column: 1,
+            start_column: 1,
+            start: 0,
             eof: false,
             unget: None,
         }
     }
 
+    /// Returns how many bytes at the start of `source` are not Lox code: a UTF-8
+    /// byte order mark, and a `#!` line that lets the script be run directly
+    /// on Unix. The newline ending the `#!` line is left to be scanned, so line
+    /// numbers stay the same.
+    fn preamble_len(source: &str) -> usize {
+        let mut len = source
+            .strip_prefix('\u{feff}')
+            .map_or(0, |_| '\u{feff}'.len_utf8());
+        if source[len..].starts_with("#!") {
+            len += source[len..].find('\n').unwrap_or(source.len() - len);
+        }
+        len
+    }
+
     /// Tokenizes a string from the source code.
     ///
     /// Returns a `ScanError::UnterminatedString` if the string is not terminated.
-    fn tokenize_string(&mut self) -> Result<(TokenType, String), InterpretError> {
-        let mut lexeme = String::from('"');
+    fn tokenize_string(&mut self) -> Result<TokenType, InterpretError> {
+        let line = self.line;
         loop {
             match self.peek() {
                 Some('"') => {
-                    lexeme.push('"');
                     self.advance();
                     break;
                 }
                 Some('\n') => {
-                    lexeme.push('\n');
                     self.line += 1;
                     self.advance();
                 }
                 None => {
-                    println!("HERE");
+                    let rest = &self.source[self.start..];
+                    let snippet = rest.lines().next().unwrap_or(rest);
                     return Err(InterpretError::Scan(ScanError::UnterminatedString(
-                        self.line,
+                        line,
+                        self.start_column,
+                        snippet.to_string(),
                     )));
                 }
-                Some(&ch) => {
-                    lexeme.push(ch);
+                Some(_) => {
                     self.advance();
                 }
             }
         }
 
-        Ok((TokenType::String, lexeme))
+        Ok(TokenType::String)
     }
 
-    /// Tokenizes a number from the source code.
+    /// Tokenizes a number from the source code, whose first digit `first` has
+    /// already been read.
     ///
     /// Numbers cannot be preceded by decimals nor can they be end with a decimal.
-    fn tokenize_number(&mut self, init: char) -> Result<(TokenType, String), InterpretError> {
-        let mut lexeme = String::from(init);
+    /// Letters, digits, and underscores after the number are read as part of it,
+    /// so that a malformed literal such as `0xZ` or `1_` is reported as a whole.
+    ///
+    /// Returns a `ScanError::MalformedNumber` if [`parse_number`] rejects it.
+    fn tokenize_number(&mut self, first: char) -> Result<TokenType, InterpretError> {
+        let mut radix = 10;
+        if first == '0' {
+            match self.peek() {
+                Some('x' | 'X') => radix = 16,
+                Some('b' | 'B') => radix = 2,
+                _ => (),
+            }
+            if radix != 10 {
+                self.advance();
+            }
+        }
+
         let mut has_decimal = false;
+        let mut has_exponent = false;
 
         while let Some(&d) = self.peek() {
-            if d == '.' {
-                if has_decimal {
+            if d == '.' && radix == 10 {
+                if has_decimal || has_exponent {
                     break;
                 }
 
@@ -76,7 +215,6 @@ impl<'a> Scanner<'a> {
                 if let Some(&next_char) = self.peek() {
                     if next_char.is_ascii_digit() {
                         has_decimal = true;
-                        lexeme.push('.');
                     } else {
                         self.unget = Some('.');
                         break;
@@ -85,58 +223,52 @@ impl<'a> Scanner<'a> {
                     self.unget = Some('.');
                     break;
                 }
-            } else if d.is_ascii_digit() {
-                lexeme.push(d);
+            } else if matches!(d, 'e' | 'E') && radix == 10 && !has_exponent {
+                has_exponent = true;
+                self.advance();
+                if let Some('+' | '-') = self.peek() {
+                    self.advance();
+                }
+            } else if d.is_ascii_alphanumeric() || d == '_' {
                 self.advance();
             } else {
                 break;
             }
         }
 
-        Ok((TokenType::Number, lexeme))
+        let end = self.offset - self.unget.map_or(0, char::len_utf8);
+        let lexeme = &self.source[self.start..end];
+        match parse_number(lexeme) {
+            Some(_) => Ok(TokenType::Number),
+            None => Err(InterpretError::Scan(ScanError::MalformedNumber(
+                self.line,
+                self.start_column,
+                lexeme.to_string(),
+            ))),
+        }
     }
 
     /// Tokenizes an identifier from the source code.
     ///
-    /// Identifiers can contain letters, digits, and underscores.
-    fn tokenize_identifier(&mut self, init: char) -> Result<(TokenType, String), InterpretError> {
-        let mut lexeme = String::from(init);
-
+    /// Identifiers follow Unicode's rules, see [`is_identifier_start`], and go on
+    /// with letters, digits, combining marks, and underscores (`XID_Continue`).
+    fn tokenize_identifier(&mut self) -> Result<TokenType, InterpretError> {
         while let Some(&ch) = self.peek() {
-            if ch.is_alphanumeric() || ch == '_' {
-                lexeme.push(ch);
+            if unicode_ident::is_xid_continue(ch) {
                 self.advance();
             } else {
                 break;
             }
         }
 
-        Ok((
-            match lexeme.as_str() {
-                "and" => TokenType::And,
-                "class" => TokenType::Class,
-                "else" => TokenType::Else,
-                "false" => TokenType::False,
-                "for" => TokenType::For,
-                "fun" => TokenType::Fun,
-                "if" => TokenType::If,
-                "nil" => TokenType::Nil,
-                "or" => TokenType::Or,
-                "print" => TokenType::Print,
-                "return" => TokenType::Return,
-                "super" => TokenType::Super,
-                "this" => TokenType::This,
-                "true" => TokenType::True,
-                "var" => TokenType::Var,
-                "while" => TokenType::While,
-                _ => TokenType::Identifier,
-            },
-            lexeme,
-        ))
+        let lexeme = &self.source[self.start..self.offset];
+        Ok(keyword(lexeme).unwrap_or(TokenType::Identifier))
     }
 
-    /// Skips over all whitespace and comments in the source code.
-    fn skip_whitespace(&mut self) {
+    /// Skips over all whitespace and comments in the source code, stopping after
+    /// a `//@debug` comment. Returns the line, column, and byte offset of that
+    /// comment, if any.
+    fn skip_whitespace(&mut self) -> Option<(u32, u16, usize)> {
         while let Some(&c) = self.peek() {
             match c {
                 ' ' | '\r' | '\t' => {
@@ -147,14 +279,19 @@ impl<'a> Scanner<'a> {
                     self.advance();
                 }
                 '/' => {
+                    let (column, start) = (self.column, self.offset);
                     self.advance(); // skips over first '/'
                     match self.peek() {
                         // if the second character is also a '/'
                         Some(&'/') => {
                             self.advance(); // skips over the second '/'
-                            while self.peek() != Some(&'\n') && self.peek().is_some() {
+                            while self.peek().is_some_and(|&ch| ch != '\n') {
                                 self.advance();
                             }
+                            let comment = &self.source[start + 2..self.offset];
+                            if comment.trim_end() == "@debug" {
+                                return Some((self.line, column, start));
+                            }
                         }
                         _ => {
                             self.unget = Some('/');
@@ -165,6 +302,7 @@ impl<'a> Scanner<'a> {
                 _ => break,
             }
         }
+        None
     }
 
     /// Advance the internal character iterator by one character. If there is some value
@@ -175,7 +313,14 @@ impl<'a> Scanner<'a> {
             self.unget = None;
             unget
         } else {
-            self.chars.next()
+            let ch = self.chars.next();
+            match ch {
+                Some('\n') => self.column = 1,
+                Some(_) => self.column = self.column.saturating_add(1),
+                None => (),
+            }
+            self.offset += ch.map_or(0, char::len_utf8);
+            ch
         }
     }
 
@@ -188,11 +333,18 @@ impl<'a> Scanner<'a> {
         }
     }
 
-    fn add_token(&mut self, token: TokenType, lexeme: String, line: u32) -> Token {
+    /// Creates a token spanning from `self.start` to the next character, which
+    /// may have been given back to `unget`.
+    fn add_token(&mut self, token: TokenType, line: u32) -> Token {
+        let end = self.offset - self.unget.map_or(0, char::len_utf8);
         Token {
             token,
-            lexeme,
+            span: Span {
+                start: self.start as u32,
+                end: end as u32,
+            },
             line,
+            column: self.start_column,
         }
     }
 }
@@ -201,7 +353,18 @@ impl Iterator for Scanner<'_> {
     type Item = Result<Token, InterpretError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.skip_whitespace();
+        if let Some((line, column, start)) = self.skip_whitespace() {
+            self.start_column = column;
+            self.start = start;
+            return Some(Ok(self.add_token(TokenType::DebugPragma, line)));
+        }
+
+        // A character given back to `unget` was read from the column, and the
+        // byte, before. Only ASCII characters are given back.
+        (self.start_column, self.start) = match self.unget {
+            Some(_) => (self.column - 1, self.offset - 1),
+            None => (self.column, self.offset),
+        };
 
         let &c = match self.peek() {
             Some(c) => c,
@@ -210,11 +373,7 @@ impl Iterator for Scanner<'_> {
                     return None;
                 } else {
                     self.eof = true;
-                    return Some(Ok(self.add_token(
-                        TokenType::Eof,
-                        "".to_string(),
-                        self.line,
-                    )));
+                    return Some(Ok(self.add_token(TokenType::Eof, self.line)));
                 }
             }
         };
@@ -222,60 +381,74 @@ impl Iterator for Scanner<'_> {
         self.advance();
 
         let result = match c {
-            '(' => Ok((TokenType::LeftParen, "(".to_string())),
-            ')' => Ok((TokenType::RightParen, ")".to_string())),
-            '{' => Ok((TokenType::LeftBrace, "{".to_string())),
-            '}' => Ok((TokenType::RightBrace, "}".to_string())),
-            '*' => Ok((TokenType::Star, "*".to_string())),
-            ';' => Ok((TokenType::Semicolon, ";".to_string())),
-            '+' => Ok((TokenType::Plus, "+".to_string())),
-            '-' => Ok((TokenType::Minus, "-".to_string())),
-            '.' => Ok((TokenType::Dot, ".".to_string())),
-            ',' => Ok((TokenType::Comma, ",".to_string())),
-            '/' => Ok((TokenType::Slash, "/".to_string())),
+            '(' => Ok(TokenType::LeftParen),
+            ')' => Ok(TokenType::RightParen),
+            '{' => Ok(TokenType::LeftBrace),
+            '}' => Ok(TokenType::RightBrace),
+            '*' => Ok(TokenType::Star),
+            ';' => Ok(TokenType::Semicolon),
+            '+' => Ok(TokenType::Plus),
+            '-' => Ok(TokenType::Minus),
+            '.' => Ok(TokenType::Dot),
+            ',' => Ok(TokenType::Comma),
+            '/' => Ok(TokenType::Slash),
+            '&' => Ok(TokenType::Ampersand),
+            '|' => Ok(TokenType::Pipe),
+            '^' => Ok(TokenType::Caret),
+            '~' => Ok(TokenType::Tilde),
             '=' => {
                 if self.peek() == Some(&'=') {
                     self.advance();
-                    Ok((TokenType::EqualEqual, "==".to_string()))
+                    Ok(TokenType::EqualEqual)
+                } else if self.peek() == Some(&'>') {
+                    self.advance();
+                    Ok(TokenType::Arrow)
                 } else {
-                    Ok((TokenType::Equal, "=".to_string()))
+                    Ok(TokenType::Equal)
                 }
             }
             '!' => {
                 if self.peek() == Some(&'=') {
                     self.advance();
-                    Ok((TokenType::BangEqual, "!=".to_string()))
+                    Ok(TokenType::BangEqual)
                 } else {
-                    Ok((TokenType::Bang, "!".to_string()))
+                    Ok(TokenType::Bang)
                 }
             }
             '<' => {
                 if self.peek() == Some(&'=') {
                     self.advance();
-                    Ok((TokenType::LessEqual, "<=".to_string()))
+                    Ok(TokenType::LessEqual)
+                } else if self.peek() == Some(&'<') {
+                    self.advance();
+                    Ok(TokenType::LessLess)
                 } else {
-                    Ok((TokenType::LessThan, "<".to_string()))
+                    Ok(TokenType::LessThan)
                 }
             }
             '>' => {
                 if self.peek() == Some(&'=') {
                     self.advance();
-                    Ok((TokenType::GreaterEqual, ">=".to_string()))
+                    Ok(TokenType::GreaterEqual)
+                } else if self.peek() == Some(&'>') {
+                    self.advance();
+                    Ok(TokenType::GreaterGreater)
                 } else {
-                    Ok((TokenType::GreaterThan, ">".to_string()))
+                    Ok(TokenType::GreaterThan)
                 }
             }
             '"' => self.tokenize_string(),
             d if d.is_ascii_digit() => self.tokenize_number(d),
-            ch if ch.is_alphabetic() || ch == '_' => self.tokenize_identifier(ch),
+            ch if is_identifier_start(ch) => self.tokenize_identifier(),
             c => Err(InterpretError::Scan(ScanError::UnexpectedCharacter(
                 self.line.to_owned(),
+                self.start_column,
                 c,
             ))),
         };
 
         match result {
-            Ok((token, lexeme)) => Some(Ok(self.add_token(token, lexeme, self.line))),
+            Ok(token) => Some(Ok(self.add_token(token, self.line))),
             Err(e) => Some(Err(e)),
         }
     }
@@ -0,0 +1,70 @@
+//! Entry points for fuzzing each stage of the pipeline, called by the targets in
+//! the `fuzz` directory. Every entry point accepts arbitrary bytes and must not
+//! panic, whatever they contain.
+
+use std::io;
+
+use crate::{
+    bytecode::Compiler,
+    frontend::{Parser, Scanner},
+    pipeline,
+    runtime::{Symbols, VmOptions, VM},
+};
+
+/// Natives whose results differ between the engines, or between runs.
+const UNCOMPARABLE_NATIVES: &[&str] = &["clock", "create", "resume", "status", "hasFeature"];
+
+/// Scans `data` into tokens.
+pub fn scan(data: &[u8]) {
+    let Ok(source) = std::str::from_utf8(data) else {
+        return;
+    };
+    Scanner::new(source).for_each(drop);
+}
+
+/// Scans and parses `data` into statements.
+pub fn parse(data: &[u8]) {
+    let Ok(source) = std::str::from_utf8(data) else {
+        return;
+    };
+    Parser::new(source, Scanner::new(source)).for_each(drop);
+}
+
+/// Compiles `data` to bytecode, without running it.
+pub fn compile(data: &[u8]) {
+    let Ok(source) = std::str::from_utf8(data) else {
+        return;
+    };
+
+    let mut symbols = Symbols::new();
+    let parser = Parser::new(source, Scanner::new(source));
+    let _ = Compiler::new(&mut symbols).compile(source, parser, &mut vec![]);
+}
+
+/// Compiles and runs `data` on a VM, discarding its output. Programs that never
+/// finish are left to the fuzzer's timeout.
+pub fn run(data: &[u8]) {
+    let Ok(source) = std::str::from_utf8(data) else {
+        return;
+    };
+
+    let mut vm = VM::new(Box::new(io::sink()));
+    vm.set_error_writer(Box::new(io::sink()));
+    let _ = crate::try_interpret(source, &mut vm);
+}
+
+/// Runs `data` on both engines, panicking if they disagree.
+pub fn compare(data: &[u8]) {
+    let Ok(source) = std::str::from_utf8(data) else {
+        return;
+    };
+    if UNCOMPARABLE_NATIVES
+        .iter()
+        .any(|name| source.contains(name))
+    {
+        return;
+    }
+
+    let comparison = pipeline::compare(source, VmOptions::default());
+    assert!(!comparison.diverged(), "{comparison}");
+}
@@ -1,35 +1,130 @@
-mod ast;
-mod bytecode;
+//! A bytecode VM interpreter for Lox.
+//!
+//! [`interpret`] runs source code directly. Each stage it goes through is also
+//! available on its own, so custom passes can be inserted between them:
+//! [`frontend::scan`], [`frontend::parse`], [`bytecode::compile`], and
+//! [`runtime::execute`]. The [`pipeline`] module gathers the stages in one
+//! place, along with a tree-walk interpreter that can run the same programs.
+
+pub mod ast;
+pub mod bytecode;
 mod core;
-mod frontend;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod frontend;
+#[cfg(fuzzing)]
+pub mod fuzz;
 mod object;
-mod runtime;
-
-use std::io::Write;
-use std::rc::Rc;
+pub mod pipeline;
+pub mod repl;
+pub mod runtime;
+mod treewalk;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 use bytecode::Compiler;
 use frontend::Parser;
 use frontend::Scanner;
-use object::Closure;
-use runtime::Frame;
 
-pub use runtime::VM;
+pub use core::errors::{Diagnostic, InterpretError, InterpretOutcome, RuntimeError, Warning};
+#[cfg(feature = "tokio")]
+pub use core::shared::NativeFuture;
+pub use core::shared::{MaybeSendSync, Shared, Writer};
+pub use core::{format_number, HeapRef, Value};
+#[cfg(feature = "tokio")]
+pub use object::native::AsyncNative;
+pub use object::{
+    native::{Native, NativeMeta},
+    Function, Object,
+};
+pub use runtime::{
+    GcReason, GcStats, Heap, HeapEntry, HostClock, PinHandle, ProcessCapability, Rng, RunState,
+    SystemClock, TraceFlags, VmOptions, VmState, WatchAction, WatchHandler, WatchHit, VM,
+};
 
-pub fn interpret(source: &str, vm: &mut VM, mut err_writer: impl Write) {
-    let scanner = Scanner::new(source);
-    let parser = Parser::new(scanner);
-
-    let main = Compiler::new(parser, vm.heap_mut()).compile();
-    match main {
-        Ok(main) => {
-            let frame = Frame::new(Rc::new(Closure::new(Rc::new(main), 0)), 0);
-            if let Err(e) = vm.run(frame) {
-                writeln!(err_writer, "{e}").unwrap();
+/// Compiles and runs `source` on `vm`, returning the value of its final
+/// expression statement, if it ends with one and runs without errors. Errors
+/// and warnings are written to the VM's error writer, see
+/// [`VM::set_error_writer`].
+pub fn interpret(source: &str, vm: &mut VM) -> Option<Value> {
+    match try_interpret(source, vm) {
+        Ok(value) => value,
+        Err(outcome) => {
+            for e in outcome.errors() {
+                vm.report(e);
             }
+            None
+        }
+    }
+}
+
+/// Compiles and runs `source` on `vm`, returning the value of its final
+/// expression statement if it ends with one, or the errors that stopped it.
+/// Warnings are still written to the VM's error writer.
+pub fn try_interpret(source: &str, vm: &mut VM) -> Result<Option<Value>, InterpretOutcome> {
+    let scanner = Scanner::new(source);
+    let parser = Parser::new(source, scanner).with_max_nesting(vm.options().max_nesting);
+
+    let known_globals = vm.options().strict_globals.then(|| vm.global_names());
+    let strip = vm.options().strip;
+    let mut compiler = Compiler::new(vm.symbols_mut());
+    if let Some(known) = known_globals {
+        compiler = compiler.with_known_globals(known);
+    }
+    if strip {
+        compiler = compiler.with_strip();
+    }
+
+    let mut diagnostics = vec![];
+    let main = compiler.compile(source, parser, &mut diagnostics);
+    for diagnostic in diagnostics {
+        vm.report(diagnostic);
+    }
+
+    let main = main.map_err(InterpretOutcome::CompileError)?;
+    run_main(main, vm)
+}
+
+/// Like [`try_interpret`], but runs `source` a batch of top-level statements at
+/// a time as it is parsed, so that a very large script starts running long
+/// before its end is reached. A compile error stops the script only once the
+/// batches before it have run. See [`bytecode::IncrementalCompiler`].
+pub fn try_interpret_incremental(
+    source: &str,
+    vm: &mut VM,
+) -> Result<Option<Value>, InterpretOutcome> {
+    let parser =
+        Parser::new(source, Scanner::new(source)).with_max_nesting(vm.options().max_nesting);
+    let mut batches = bytecode::compile_incremental(source, parser);
+    if vm.options().strict_globals {
+        batches = batches.with_known_globals(vm.global_names());
+    }
+    if vm.options().strip {
+        batches = batches.with_strip();
+    }
+
+    let mut diagnostics = vec![];
+    let mut value = None;
+    while let Some(main) = batches.next_function(vm.symbols_mut(), &mut diagnostics) {
+        for diagnostic in diagnostics.drain(..) {
+            vm.report(diagnostic);
         }
-        Err(errs) => errs
-            .iter()
-            .for_each(|e| writeln!(err_writer, "{e}").unwrap()),
+        value = run_main(main.map_err(InterpretOutcome::CompileError)?, vm)?;
     }
+    Ok(value)
 }
+
+/// Runs the top-level function of a script on `vm`.
+fn run_main(main: Function, vm: &mut VM) -> Result<Option<Value>, InterpretOutcome> {
+    let frame = vm.load(main);
+    vm.run(frame).map_err(|error| match error {
+        InterpretError::Exit(code) => InterpretOutcome::Exit(code),
+        error => InterpretOutcome::RuntimeError(error),
+    })
+}
+
+#[cfg(feature = "send")]
+const _: () = {
+    fn assert_send<T: Send>() {}
+    let _ = assert_send::<VM<'static>>;
+};
@@ -1,46 +1,450 @@
 use std::{
+    collections::HashMap,
     env::args,
     fs::File,
     io::{self, Read, Write},
     process::exit,
 };
 
-use lox_bytecode_vm::interpret;
-use lox_bytecode_vm::VM;
+#[cfg(feature = "readline")]
+use lox_bytecode_vm::frontend;
+use lox_bytecode_vm::{
+    pipeline, repl::Session, try_interpret, try_interpret_incremental, InterpretOutcome,
+    ProcessCapability, TraceFlags, Value, VmOptions, WatchAction, WatchHit, VM,
+};
 
-fn repl() {
-    let mut vm = VM::new(Box::new(std::io::stdout()));
+fn repl(trace: TraceFlags, gc_log: bool) {
+    let options = VmOptions {
+        load_stdlib: true,
+        gc_log,
+        ..VmOptions::default()
+    };
+    let mut vm = VM::with_options(Box::new(std::io::stdout()), options);
+    vm.set_trace(trace);
+    vm.set_watch_handler(Box::new(pause));
+    let mut session = Session::new();
+    // The globals every session starts with, which `:vars` leaves out until
+    // they are redefined
+    let builtins: HashMap<String, Value> = vm
+        .globals()
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect();
+    let mut reader = LineReader::new();
     loop {
-        print!("> ");
+        reader.set_completions(vm.global_names());
+        let Some(line) = reader.read_line("> ") else {
+            break;
+        };
+        let line = line.trim();
+        match line {
+            ":help" => help(&vm),
+            ":vars" => {
+                for (name, value) in vm.globals() {
+                    if builtins.get(name) != Some(&value) {
+                        println!("{name} = {}", vm.heap().format(&value));
+                    }
+                }
+            }
+            ":undo" => {
+                if !session.undo(&mut vm) {
+                    println!("Nothing to undo.");
+                }
+            }
+            _ if line.starts_with(":watch ") => vm.watch(line[7..].trim()),
+            _ if line.starts_with(":unwatch ") => {
+                let name = line[9..].trim();
+                if !vm.unwatch(name) {
+                    println!("'{name}' is not watched.");
+                }
+            }
+            _ if line.starts_with(":load ") => {
+                let path = line[6..].trim();
+                match session.load(path, &mut vm) {
+                    Ok(result) => {
+                        report(result);
+                    }
+                    Err(e) => println!("Could not read '{path}': {e}"),
+                }
+            }
+            _ if line.starts_with(":time ") => {
+                // A bare expression is printed, statements are run as they are
+                let code = line[6..].trim();
+                let source = if code.ends_with([';', '}']) {
+                    code.to_string()
+                } else {
+                    format!("print {code};")
+                };
+                let (result, timing) = session.time(&source, &mut vm);
+                report(result);
+                println!("{timing}");
+            }
+            _ if line.starts_with(":save ") => {
+                let path = line[6..].trim();
+                if let Err(e) = session.save(path) {
+                    println!("Could not write '{path}': {e}");
+                }
+            }
+            _ => {
+                // Echo what an expression statement ending the line evaluates to
+                if let Some(value) = report(session.run(line, &mut vm))
+                    && !value.is_nil()
+                {
+                    println!("{}", vm.heap().format(&value));
+                }
+            }
+        }
+    }
+}
+
+// Reports the errors of a line run in the REPL, exiting if it called exit().
+// Returns the value the line ended with, if any.
+fn report(result: Result<Option<Value>, InterpretOutcome>) -> Option<Value> {
+    match result {
+        Ok(value) => value,
+        Err(InterpretOutcome::Exit(code)) => exit(code),
+        Err(outcome) => {
+            for e in outcome.errors() {
+                eprintln!("{e}");
+            }
+            None
+        }
+    }
+}
+
+/// Reads the REPL's lines from stdin. Built with the `readline` feature, lines
+/// can be edited, earlier ones recalled with the arrow keys or searched with
+/// Ctrl-R, names completed with Tab, and the history is kept in
+/// `~/.lox_history` between sessions.
+struct LineReader {
+    #[cfg(feature = "readline")]
+    editor: rustyline::Editor<Completions, rustyline::history::DefaultHistory>,
+    #[cfg(feature = "readline")]
+    history_path: Option<std::path::PathBuf>,
+}
+
+#[cfg(feature = "readline")]
+impl LineReader {
+    fn new() -> Self {
+        let mut editor = rustyline::Editor::new().expect("Failed to set up line editing");
+        editor.set_helper(Some(Completions::default()));
+        let history_path =
+            std::env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".lox_history"));
+        if let Some(path) = &history_path {
+            // There is no history yet the first time
+            let _ = editor.load_history(path);
+        }
+        Self {
+            editor,
+            history_path,
+        }
+    }
+
+    // Makes Tab complete `names`, along with the keywords
+    fn set_completions(&mut self, names: Vec<String>) {
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.names = names;
+        }
+    }
+
+    // Returns the next line, or `None` at the end of input. Ctrl-C clears the line.
+    fn read_line(&mut self, prompt: &str) -> Option<String> {
+        use rustyline::error::ReadlineError;
+
+        loop {
+            match self.editor.readline(prompt) {
+                Ok(line) => {
+                    if !line.trim().is_empty() {
+                        let _ = self.editor.add_history_entry(line.as_str());
+                        if let Some(path) = &self.history_path {
+                            let _ = self.editor.append_history(path);
+                        }
+                    }
+                    return Some(line);
+                }
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => return None,
+                Err(e) => panic!("Failed to read line: {e}"),
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "readline"))]
+impl LineReader {
+    fn new() -> Self {
+        Self {}
+    }
+
+    fn set_completions(&mut self, _names: Vec<String>) {}
+
+    // Returns the next line, or `None` at the end of input
+    fn read_line(&mut self, prompt: &str) -> Option<String> {
+        print!("{prompt}");
         io::stdout().flush().unwrap();
 
         let mut line = String::new();
-        io::stdin()
+        match io::stdin()
             .read_line(&mut line)
-            .expect("Failed to read line");
+            .expect("Failed to read line")
+        {
+            0 => None,
+            _ => Some(line),
+        }
+    }
+}
 
-        interpret(&line, &mut vm, io::stderr());
+/// Completes the word before the cursor in the REPL with the names of globals
+/// and natives, or a keyword.
+#[cfg(feature = "readline")]
+#[derive(Default)]
+struct Completions {
+    names: Vec<String>,
+}
+
+#[cfg(feature = "readline")]
+impl rustyline::completion::Completer for Completions {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos]
+            .char_indices()
+            .rev()
+            .take_while(|&(_, ch)| ch.is_alphanumeric() || ch == '_')
+            .last()
+            .map_or(pos, |(i, _)| i);
+        let prefix = &line[start..pos];
+        if prefix.is_empty() {
+            return Ok((pos, Vec::new()));
+        }
+
+        let mut candidates: Vec<_> = self
+            .names
+            .iter()
+            .map(String::as_str)
+            .chain(frontend::KEYWORDS.iter().copied())
+            .filter(|name| name.starts_with(prefix))
+            .map(str::to_string)
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        Ok((start, candidates))
     }
 }
 
-fn run_file(path: &str) {
+#[cfg(feature = "readline")]
+impl rustyline::hint::Hinter for Completions {
+    type Hint = String;
+}
+
+#[cfg(feature = "readline")]
+impl rustyline::highlight::Highlighter for Completions {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> std::borrow::Cow<'l, str> {
+        let mut colored = String::with_capacity(line.len());
+        let mut end = 0;
+        for (span, class) in frontend::highlight(line) {
+            let (start, stop) = (span.start as usize, span.end as usize);
+            colored.push_str(&line[end..start]);
+            match color(class) {
+                Some(code) => {
+                    colored.push_str(&format!("\x1b[{code}m{}\x1b[0m", &line[start..stop]))
+                }
+                None => colored.push_str(&line[start..stop]),
+            }
+            end = stop;
+        }
+        colored.push_str(&line[end..]);
+        colored.into()
+    }
+
+    fn highlight_char(
+        &self,
+        _line: &str,
+        _pos: usize,
+        _kind: rustyline::highlight::CmdKind,
+    ) -> bool {
+        true
+    }
+}
+
+// Returns the ANSI color code for tokens of `class`, if they are colored
+#[cfg(feature = "readline")]
+fn color(class: frontend::TokenClass) -> Option<u8> {
+    use frontend::TokenClass;
+
+    match class {
+        TokenClass::Keyword => Some(35),
+        TokenClass::Literal | TokenClass::Number => Some(33),
+        TokenClass::String => Some(32),
+        TokenClass::Pragma => Some(90),
+        TokenClass::Identifier | TokenClass::Operator | TokenClass::Punctuation => None,
+    }
+}
+
+#[cfg(feature = "readline")]
+impl rustyline::validate::Validator for Completions {}
+
+#[cfg(feature = "readline")]
+impl rustyline::Helper for Completions {}
+
+// Pauses the REPL on a write to a watched global until the user continues or
+// stops, listing the locals of the frame that wrote it on 'locals'
+fn pause(hit: &WatchHit) -> WatchAction {
+    print!("{hit}. Paused, press enter to continue, or type 'locals' or 'stop': ");
+    loop {
+        io::stdout().flush().unwrap();
+        let mut answer = String::new();
+        match io::stdin().read_line(&mut answer) {
+            Ok(_) if answer.trim() == "locals" => {
+                for (slot, (name, value)) in hit.locals.iter().enumerate() {
+                    match name {
+                        Some(name) => println!("  {name} = {value}"),
+                        None => println!("  [{slot}] = {value}"),
+                    }
+                }
+                print!("Paused: ");
+            }
+            Ok(_) if answer.trim() == "stop" => return WatchAction::Stop,
+            _ => return WatchAction::Continue,
+        }
+    }
+}
+
+// Lists the native functions available to scripts
+fn help(vm: &VM) {
+    println!("Commands:");
+    println!("  :help          Lists commands and native functions");
+    println!("  :vars          Lists the globals defined in this session, by name");
+    println!("  :undo          Undoes the last line that was run");
+    println!("  :load path     Runs the file at 'path' in this session");
+    println!("  :save path     Writes the lines run so far without errors to 'path'");
+    println!("  :time code     Runs an expression or statements, reporting what it cost");
+    println!("  :watch name    Pauses whenever the global 'name' is assigned");
+    println!("  :unwatch name  Stops watching the global 'name'");
+    println!("Native functions:");
+    for native in vm.natives() {
+        let signature = format!("{}/{}", native.name, native.arity);
+        println!("  {signature:<14} {}", native.doc);
+    }
+}
+
+fn read_file(path: &str) -> String {
     let mut file = File::open(path).expect("Failed to open file");
     let mut contents = String::new();
     file.read_to_string(&mut contents)
         .expect("Failed to read file");
+    contents
+}
+
+// Runs a script, parsing it all before running it unless `incremental` is set
+fn run_file(path: &str, args: &[String], trace: TraceFlags, gc_log: bool, incremental: bool) {
+    let contents = read_file(path);
+
+    let options = VmOptions {
+        load_stdlib: true,
+        args: args.to_vec(),
+        env_access: true,
+        process: ProcessCapability::Full,
+        gc_log,
+        ..VmOptions::default()
+    };
+    let mut vm = VM::with_options(Box::new(std::io::stdout()), options);
+    vm.set_trace(trace);
+    let result = if incremental {
+        try_interpret_incremental(&contents, &mut vm)
+    } else {
+        try_interpret(&contents, &mut vm)
+    };
+    if let Err(outcome) = result {
+        for e in outcome.errors() {
+            eprintln!("{e}");
+        }
+        exit(outcome.exit_code());
+    }
+}
+
+// Runs the script on both engines, exiting with 1 if they disagree
+fn compare_file(path: &str) {
+    let contents = read_file(path);
 
-    let mut vm = VM::new(Box::new(std::io::stdout()));
-    interpret(&contents, &mut vm, io::stderr());
+    let options = VmOptions {
+        load_stdlib: true,
+        ..VmOptions::default()
+    };
+    let comparison = pipeline::compare(&contents, options);
+    if comparison.diverged() {
+        eprint!("{comparison}");
+        exit(1);
+    }
+
+    let run = comparison.bytecode;
+    print!("{}", run.output);
+    if let Err(outcome) = run.outcome {
+        for e in outcome.errors() {
+            eprintln!("{e}");
+        }
+        exit(outcome.exit_code());
+    }
+}
+
+// Parses `--trace`, which traces everything, or `--trace=` followed by a comma
+// separated list of what to trace
+fn parse_trace(arg: &str) -> Option<TraceFlags> {
+    let Some(list) = arg.strip_prefix("--trace=") else {
+        return (arg == "--trace").then_some(TraceFlags::ALL);
+    };
+
+    let mut trace = TraceFlags::default();
+    for flag in list.split(',') {
+        match flag {
+            "instructions" => trace.instructions = true,
+            "stack" => trace.stack = true,
+            "heap" => trace.heap = true,
+            "globals" => trace.globals = true,
+            "calls" => trace.calls = true,
+            _ => return None,
+        }
+    }
+    Some(trace)
+}
+
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "Usage: {program} [--compare script | [--trace[=instructions,stack,heap,globals,calls]] [--gc-log] [[--incremental] script [args...]]]"
+    );
+    exit(64);
 }
 
 fn main() {
-    let args: Vec<_> = args().collect();
+    let mut args: Vec<_> = args().collect();
+    if args.len() == 3 && args[1] == "--compare" {
+        compare_file(&args[2]);
+        return;
+    }
+
+    let mut trace = TraceFlags::default();
+    if args.get(1).is_some_and(|arg| arg.starts_with("--trace")) {
+        trace = parse_trace(&args.remove(1)).unwrap_or_else(|| usage(&args[0]));
+    }
+    let gc_log = args.get(1).is_some_and(|arg| arg == "--gc-log");
+    if gc_log {
+        args.remove(1);
+    }
+    let incremental = args.get(1).is_some_and(|arg| arg == "--incremental");
+    if incremental {
+        args.remove(1);
+    }
+
     if args.len() == 1 {
-        repl();
-    } else if args.len() == 2 {
-        run_file(&args[1]);
+        repl(trace, gc_log);
+    } else if !args[1].starts_with("--") {
+        run_file(&args[1], &args[2..], trace, gc_log, incremental);
     } else {
-        eprintln!("Usage: {} [script]", args[0]);
-        exit(64);
+        usage(&args[0]);
     }
 }
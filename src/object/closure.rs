@@ -1,20 +1,20 @@
-use std::rc::Rc;
+use crate::core::Value;
 
-use super::Function;
-
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Closure {
-    pub function: Rc<Function>,
-    pub upvalue_count: u8,
+    /// The function the closure runs, an [`Object::Function`](super::Object::Function)
+    /// in the heap
+    pub function: Value,
+    pub upvalue_count: usize,
     pub upvalues: Vec<usize>, // Index into VM upvalues array, is this extra level of indirection worth it?
 }
 
 impl Closure {
-    pub fn new(function: Rc<Function>, upvalue_count: u8) -> Self {
+    pub fn new(function: Value, upvalue_count: usize) -> Self {
         Self {
             function,
             upvalue_count,
-            upvalues: Vec::with_capacity(upvalue_count as usize),
+            upvalues: Vec::with_capacity(upvalue_count),
         }
     }
 }
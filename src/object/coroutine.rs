@@ -0,0 +1,70 @@
+use crate::{
+    core::{OpCode, Value},
+    runtime::Frame,
+};
+
+use super::Function;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CoroutineStatus {
+    Suspended,
+    Running,
+    Dead,
+}
+
+impl CoroutineStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CoroutineStatus::Suspended => "suspended",
+            CoroutineStatus::Running => "running",
+            CoroutineStatus::Dead => "dead",
+        }
+    }
+}
+
+/// A function that can suspend itself with `yield` and later continue from where
+/// it left off. While suspended, the coroutine owns its frames and stack values.
+#[derive(Clone)]
+pub struct Coroutine {
+    /// The closure the coroutine runs
+    pub function: Value,
+    pub status: CoroutineStatus,
+    /// Whether the coroutine has been resumed at least once
+    pub started: bool,
+    /// Saved frames, innermost first, with each `fp` relative to the coroutine's stack
+    pub frames: Vec<Frame>,
+    /// Saved values of the coroutine's slice of the VM stack
+    pub stack: Vec<Value>,
+    /// Upvalues that pointed into the saved stack, as (upvalue index, relative stack index)
+    pub upvalues: Vec<(usize, usize)>,
+}
+
+impl Coroutine {
+    pub fn new(function: Value) -> Self {
+        Self {
+            function,
+            status: CoroutineStatus::Suspended,
+            started: false,
+            frames: Vec::new(),
+            stack: Vec::new(),
+            upvalues: Vec::new(),
+        }
+    }
+}
+
+/// Builds the `resume(co, value)` function, which hands its arguments to
+/// [`OpCode::Resume`] and returns whatever the coroutine yields or returns.
+pub fn resume_function() -> Function {
+    let mut function = Function::new("resume".to_string(), 2);
+    let chunk = &mut function.chunk;
+
+    // [ <fn> ] [ co ] [ value ]
+    chunk.write_byte(OpCode::GetLocal as u8, 0);
+    chunk.write_byte(1, 0);
+    chunk.write_byte(OpCode::GetLocal as u8, 0);
+    chunk.write_byte(2, 0);
+    chunk.write_byte(OpCode::Resume as u8, 0);
+    chunk.write_byte(OpCode::Return as u8, 0);
+
+    function
+}
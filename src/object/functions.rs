@@ -1,15 +1,29 @@
-use crate::bytecode::Chunk;
+use std::borrow::Cow;
+
+use crate::{bytecode::Chunk, core::OpCode, runtime::VM};
 
 pub struct Function {
+    /// The declared name, empty for anonymous functions
     pub name: String,
     pub arity: u8,
     pub chunk: Chunk,
     pub upvalue_count: usize,
+    /// Tells apart functions compiled into the same heap, in compilation order
+    pub id: u32,
+    /// The line the function was declared on
+    pub line: u32,
+    /// The most values the function's frame holds on the stack at once, counting
+    /// its own slot and arguments, as worked out by the compiler. 0 for functions
+    /// not built by the compiler.
+    pub max_stack: usize,
+    /// Whether the function is a script that returns the value of its final
+    /// expression statement, rather than one that returns nothing
+    pub returns_value: bool,
 }
 
 impl std::fmt::Debug for Function {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "<fn {}>", self.name)
+        write!(f, "<fn {} #{}>", self.display_name(), self.id)
     }
 }
 
@@ -20,6 +34,76 @@ impl Function {
             arity,
             chunk: Chunk::new(),
             upvalue_count: 0,
+            id: 0,
+            line: 0,
+            max_stack: 0,
+            returns_value: false,
+        }
+    }
+
+    /// Returns the name the function is printed with. Anonymous functions are
+    /// told apart by the line they were declared on.
+    pub fn display_name(&self) -> Cow<'_, str> {
+        if self.name.is_empty() {
+            Cow::Owned(format!("anonymous (line {})", self.line))
+        } else {
+            Cow::Borrowed(&self.name)
         }
     }
+
+    /// Returns the header of the function's disassembly.
+    pub fn header(&self) -> String {
+        format!("{} #{} (line {})", self.display_name(), self.id, self.line)
+    }
+
+    /// Copies the function with an [unlinked](Chunk::unlinked) chunk.
+    #[cfg(feature = "threads")]
+    pub(crate) fn unlinked(&self) -> Function {
+        Function {
+            name: self.name.clone(),
+            chunk: self.chunk.unlinked(),
+            ..*self
+        }
+    }
+
+    /// Prints the function's bytecode under its [header](Self::header).
+    pub fn disassemble(&self, vm: &VM) {
+        self.chunk.disassemble(&self.header(), vm);
+    }
+
+    /// Returns the function's bytecode under its [header](Self::header), see
+    /// [`Chunk::disassembly`].
+    pub fn disassembly(&self, vm: &VM) -> String {
+        self.chunk.disassembly(&self.header(), vm)
+    }
+}
+
+/// Builds the `exit(code)` function, which runs [`OpCode::Exit`] to stop the
+/// program with `code` as its exit code.
+pub fn exit_function() -> Function {
+    let mut function = Function::new("exit".to_string(), 1);
+    let chunk = &mut function.chunk;
+
+    // [ <fn> ] [ code ]
+    chunk.write_byte(OpCode::GetLocal as u8, 0);
+    chunk.write_byte(1, 0);
+    chunk.write_byte(OpCode::Exit as u8, 0);
+
+    function
+}
+
+/// Builds the `spawn(fn)` function, which runs [`OpCode::Spawn`] to start `fn`
+/// on another thread.
+#[cfg(feature = "threads")]
+pub fn spawn_function() -> Function {
+    let mut function = Function::new("spawn".to_string(), 1);
+    let chunk = &mut function.chunk;
+
+    // [ <fn> ] [ fn ]
+    chunk.write_byte(OpCode::GetLocal as u8, 0);
+    chunk.write_byte(1, 0);
+    chunk.write_byte(OpCode::Spawn as u8, 0);
+    chunk.write_byte(OpCode::Return as u8, 0);
+
+    function
 }
@@ -0,0 +1,18 @@
+use crate::core::Value;
+
+/// The state of a `for (var x in collection)` loop over `target`.
+#[derive(Debug, Clone)]
+pub struct Iter {
+    pub target: Value,
+    /// How far into `target` the loop is. For strings, this is a byte offset.
+    pub position: usize,
+}
+
+impl Iter {
+    pub fn new(target: Value) -> Self {
+        Self {
+            target,
+            position: 0,
+        }
+    }
+}
@@ -1,20 +1,51 @@
 mod closure;
+mod coroutine;
 mod functions;
+mod iter;
 
 pub mod native;
 
-use std::rc::Rc;
-
 pub use closure::Closure;
-pub use functions::Function;
+pub use coroutine::{resume_function, Coroutine, CoroutineStatus};
+#[cfg(feature = "threads")]
+pub use functions::spawn_function;
+pub use functions::{exit_function, Function};
+pub use iter::Iter;
 use native::Native;
 
-use crate::core::Value;
+use crate::core::shared::Shared;
 
+#[derive(Clone)]
 pub enum Object {
-    String(Rc<str>),
-    Function(Rc<Function>),
-    Native(Rc<dyn Native>),
-    Closure(Rc<Closure>),
-    UpValue(Value),
+    String(Shared<str>),
+    Function(Shared<Function>),
+    Native(Shared<dyn Native>),
+    Closure(Closure),
+    Coroutine(Coroutine),
+    Iter(Iter),
+}
+
+impl Object {
+    /// Names the kind of object, such as "closure" or "iterator".
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Object::String(_) => "string",
+            Object::Function(_) => "function",
+            Object::Native(_) => "native",
+            Object::Closure(_) => "closure",
+            Object::Coroutine(_) => "coroutine",
+            Object::Iter(_) => "iterator",
+        }
+    }
+
+    /// Returns how many references share the object's contents, or `None` for
+    /// objects that are not reference counted.
+    pub fn ref_count(&self) -> Option<usize> {
+        match self {
+            Object::String(s) => Some(Shared::strong_count(s)),
+            Object::Function(f) => Some(Shared::strong_count(f)),
+            Object::Native(n) => Some(Shared::strong_count(n)),
+            Object::Closure(_) | Object::Coroutine(_) | Object::Iter(_) => None,
+        }
+    }
 }
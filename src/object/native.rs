@@ -1,29 +1,313 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{process::Command, sync::Mutex};
 
-use crate::core::{errors::RuntimeError, Value};
+use crate::{
+    core::{
+        errors::RuntimeError,
+        shared::{MaybeSendSync, Shared},
+        Value,
+    },
+    runtime::{Heap, HostClock, Rng},
+};
 
-pub trait Native {
+use super::{Coroutine, Object};
+
+#[cfg(feature = "tokio")]
+use crate::core::shared::NativeFuture;
+
+/// Language features this backend supports, as reported by `hasFeature`.
+#[cfg(not(feature = "threads"))]
+pub const FEATURES: &[&str] = &["closures", "coroutines", "for-in"];
+/// Language features this backend supports, as reported by `hasFeature`.
+#[cfg(feature = "threads")]
+pub const FEATURES: &[&str] = &["closures", "coroutines", "for-in", "threads"];
+
+/// Describes a native function, for listing the natives a VM provides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NativeMeta<'a> {
+    pub name: &'a str,
+    pub arity: u8,
+    pub doc: &'a str,
+}
+
+pub trait Native: MaybeSendSync {
+    fn name(&self) -> &str;
+    fn arity(&self) -> u8;
+    fn call(&self, heap: &mut Heap, args: Vec<Value>) -> Result<Value, RuntimeError>;
+
+    /// A one line description of what the function does, shown by the REPL's
+    /// `:help` command.
+    fn doc(&self) -> &str {
+        ""
+    }
+
+    fn meta(&self) -> NativeMeta<'_> {
+        NativeMeta {
+            name: self.name(),
+            arity: self.arity(),
+            doc: self.doc(),
+        }
+    }
+
+    /// The async function this native wraps, if it was defined with
+    /// [`crate::VM::define_async_native`].
+    #[cfg(feature = "tokio")]
+    fn as_async(&self) -> Option<&dyn AsyncNative> {
+        None
+    }
+}
+
+/// A native function that returns a future, which [`crate::VM::run_async`]
+/// awaits before carrying on with the script. Calling one from any other kind
+/// of run is a runtime error.
+///
+/// The future cannot borrow the heap, so a native returning an object, such
+/// as a string, allocates it in `call` before creating the future.
+#[cfg(feature = "tokio")]
+pub trait AsyncNative: MaybeSendSync {
     fn name(&self) -> &str;
     fn arity(&self) -> u8;
-    fn call(&self, args: Vec<Value>) -> Result<Value, RuntimeError>;
+    fn call(&self, heap: &mut Heap, args: Vec<Value>) -> NativeFuture;
+
+    /// A one line description of what the function does, shown by the REPL's
+    /// `:help` command.
+    fn doc(&self) -> &str {
+        ""
+    }
+}
+
+/// Puts an [`AsyncNative`] in the heap alongside the synchronous natives.
+#[cfg(feature = "tokio")]
+pub(crate) struct Async<N>(pub N);
+
+#[cfg(feature = "tokio")]
+impl<N: AsyncNative> Native for Async<N> {
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    fn doc(&self) -> &str {
+        self.0.doc()
+    }
+
+    fn arity(&self) -> u8 {
+        self.0.arity()
+    }
+
+    fn call(&self, _heap: &mut Heap, _args: Vec<Value>) -> Result<Value, RuntimeError> {
+        Err(RuntimeError::AsyncNative(0, self.name().to_string()))
+    }
+
+    fn as_async(&self) -> Option<&dyn AsyncNative> {
+        Some(&self.0)
+    }
 }
 
-pub struct Clock;
+/// `clock()` returns the wall clock time in seconds since the Unix epoch.
+pub struct Clock(pub Shared<dyn HostClock>);
 impl Native for Clock {
     fn name(&self) -> &str {
         "clock"
     }
 
+    fn doc(&self) -> &str {
+        "Returns the wall clock time in seconds since the Unix epoch."
+    }
+
+    fn arity(&self) -> u8 {
+        0
+    }
+
+    fn call(&self, _heap: &mut Heap, _args: Vec<Value>) -> Result<Value, RuntimeError> {
+        Ok(Value::number(self.0.wall()))
+    }
+}
+
+/// `clockMono()` returns seconds from a monotonic clock, for measuring elapsed time.
+pub struct ClockMono(pub Shared<dyn HostClock>);
+impl Native for ClockMono {
+    fn name(&self) -> &str {
+        "clockMono"
+    }
+
+    fn doc(&self) -> &str {
+        "Returns seconds from a monotonic clock, for measuring elapsed time."
+    }
+
+    fn arity(&self) -> u8 {
+        0
+    }
+
+    fn call(&self, _heap: &mut Heap, _args: Vec<Value>) -> Result<Value, RuntimeError> {
+        Ok(Value::number(self.0.monotonic()))
+    }
+}
+
+/// `random()` returns a number from 0 up to, but not including, 1.
+pub struct Random(pub Shared<Rng>);
+impl Native for Random {
+    fn name(&self) -> &str {
+        "random"
+    }
+
+    fn doc(&self) -> &str {
+        "Returns a random number from 0 up to, but not including, 1."
+    }
+
     fn arity(&self) -> u8 {
         0
     }
 
-    fn call(&self, args: Vec<Value>) -> Result<Value, RuntimeError> {
-        let time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards.");
+    fn call(&self, _heap: &mut Heap, _args: Vec<Value>) -> Result<Value, RuntimeError> {
+        Ok(Value::number(self.0.next_f64()))
+    }
+}
+
+/// `randomInt(lo, hi)` returns a whole number from `lo` to `hi`, both included.
+pub struct RandomInt(pub Shared<Rng>);
+impl Native for RandomInt {
+    fn name(&self) -> &str {
+        "randomInt"
+    }
+
+    fn doc(&self) -> &str {
+        "Returns a random whole number from lo to hi, both included."
+    }
+
+    fn arity(&self) -> u8 {
+        2
+    }
+
+    fn call(&self, _heap: &mut Heap, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let is_integer = |v: &Value| v.is_number() && v.as_number().fract() == 0.0;
+        let (lo, hi) = (args[0], args[1]);
+        if !is_integer(&lo) || !is_integer(&hi) || lo.as_number() > hi.as_number() {
+            return Err(RuntimeError::OperandMismatch(
+                0,
+                "integers, the first no greater than the second".to_string(),
+            ));
+        }
+
+        let (lo, hi) = (lo.as_number() as i64, hi.as_number() as i64);
+        let span = hi.wrapping_sub(lo) as u64;
+        let offset = match span.checked_add(1) {
+            Some(bound) => self.0.below(bound),
+            None => self.0.next_u64(),
+        };
+        Ok(Value::from_i64(lo.wrapping_add(offset as i64)))
+    }
+}
+
+/// `seed(n)` starts the numbers `random` and `randomInt` return over, so that
+/// the same seed always gives the same numbers.
+pub struct Seed(pub Shared<Rng>);
+impl Native for Seed {
+    fn name(&self) -> &str {
+        "seed"
+    }
+
+    fn doc(&self) -> &str {
+        "Seeds the random numbers, so the same seed gives the same numbers."
+    }
+
+    fn arity(&self) -> u8 {
+        1
+    }
 
-        Ok(Value::number(time.as_secs_f64()))
+    fn call(&self, _heap: &mut Heap, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        if !args[0].is_number() {
+            return Err(RuntimeError::OperandMismatch(0, "a number".to_string()));
+        }
+        self.0.seed(args[0].as_number().to_bits());
+        Ok(Value::NIL)
+    }
+}
+
+/// A native taking one number, such as `sin(x)`, for the functions in [`MATH`].
+#[derive(Debug, Clone, Copy)]
+pub struct MathFn {
+    pub name: &'static str,
+    pub doc: &'static str,
+    pub function: fn(f64) -> f64,
+}
+
+/// The math natives taking one number, defined alongside `sqrt`, `pow`, and the
+/// `PI` and `E` globals.
+pub const MATH: &[MathFn] = &[
+    MathFn {
+        name: "sin",
+        doc: "Returns the sine of an angle in radians.",
+        function: f64::sin,
+    },
+    MathFn {
+        name: "cos",
+        doc: "Returns the cosine of an angle in radians.",
+        function: f64::cos,
+    },
+    MathFn {
+        name: "tan",
+        doc: "Returns the tangent of an angle in radians.",
+        function: f64::tan,
+    },
+    MathFn {
+        name: "log",
+        doc: "Returns the natural logarithm of a number.",
+        function: f64::ln,
+    },
+    MathFn {
+        name: "exp",
+        doc: "Returns e raised to the power of a number.",
+        function: f64::exp,
+    },
+];
+
+/// The constants defined as globals along with the math natives.
+pub const MATH_CONSTANTS: &[(&str, f64)] =
+    &[("PI", std::f64::consts::PI), ("E", std::f64::consts::E)];
+
+impl Native for MathFn {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn doc(&self) -> &str {
+        self.doc
+    }
+
+    fn arity(&self) -> u8 {
+        1
+    }
+
+    fn call(&self, _heap: &mut Heap, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        if args[0].is_number() {
+            Ok(Value::number((self.function)(args[0].as_number())))
+        } else {
+            Err(RuntimeError::OperandMismatch(0, "number".to_string()))
+        }
+    }
+}
+
+/// `pow(base, exponent)` returns `base` raised to the power of `exponent`.
+pub struct Pow;
+impl Native for Pow {
+    fn name(&self) -> &str {
+        "pow"
+    }
+
+    fn doc(&self) -> &str {
+        "Returns a number raised to the power of another."
+    }
+
+    fn arity(&self) -> u8 {
+        2
+    }
+
+    fn call(&self, _heap: &mut Heap, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let (base, exponent) = (args[0], args[1]);
+        if base.is_number() && exponent.is_number() {
+            Ok(Value::number(base.as_number().powf(exponent.as_number())))
+        } else {
+            Err(RuntimeError::OperandMismatch(0, "numbers".to_string()))
+        }
     }
 }
 
@@ -33,11 +317,15 @@ impl Native for Sqrt {
         "sqrt"
     }
 
+    fn doc(&self) -> &str {
+        "Returns the square root of a number."
+    }
+
     fn arity(&self) -> u8 {
         1
     }
 
-    fn call(&self, args: Vec<Value>) -> Result<Value, RuntimeError> {
+    fn call(&self, _heap: &mut Heap, args: Vec<Value>) -> Result<Value, RuntimeError> {
         let arg = args[0];
 
         if arg.is_number() {
@@ -47,3 +335,468 @@ impl Native for Sqrt {
         }
     }
 }
+
+/// `type(value)` returns the name of the value's type, see [`Value::kind`].
+pub struct Type;
+impl Native for Type {
+    fn name(&self) -> &str {
+        "type"
+    }
+
+    fn doc(&self) -> &str {
+        "Returns the name of a value's type, such as \"number\" or \"string\"."
+    }
+
+    fn arity(&self) -> u8 {
+        1
+    }
+
+    fn call(&self, heap: &mut Heap, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let kind = args[0].kind(heap);
+        Ok(heap.push_str(kind.to_string()).into())
+    }
+}
+
+/// `clone(value)` returns a copy of `value` that shares no state with it.
+/// Strings, which are immutable and interned, and functions and natives, which
+/// hold no state of their own, are returned as they are. Closures that capture
+/// variables and coroutines cannot be cloned, since those variables and saved
+/// stacks live in the VM.
+pub struct CloneValue;
+impl Native for CloneValue {
+    fn name(&self) -> &str {
+        "clone"
+    }
+
+    fn doc(&self) -> &str {
+        "Returns a copy of a value that shares no state with it."
+    }
+
+    fn arity(&self) -> u8 {
+        1
+    }
+
+    fn call(&self, heap: &mut Heap, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let value = args[0];
+        match heap.get_value(&value) {
+            None | Some(Object::String(_) | Object::Function(_) | Object::Native(_)) => Ok(value),
+            Some(Object::Closure(closure)) if closure.upvalues.is_empty() => Ok(value),
+            Some(Object::Closure(_) | Object::Coroutine(_) | Object::Iter(_)) => {
+                Err(RuntimeError::NotCloneable(0, heap.format(&value)))
+            }
+        }
+    }
+}
+
+/// `str(value)` converts a value to the string `print` would write for it.
+pub struct Str;
+impl Native for Str {
+    fn name(&self) -> &str {
+        "str"
+    }
+
+    fn doc(&self) -> &str {
+        "Converts a value to the string print would write for it."
+    }
+
+    fn arity(&self) -> u8 {
+        1
+    }
+
+    fn call(&self, heap: &mut Heap, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let string = heap.format(&args[0]);
+        Ok(heap.push_str(string).into())
+    }
+}
+
+/// `assert(condition, message)` stops the program with `message` if `condition`
+/// is falsey. Compiling with `strip` removes the calls.
+pub struct Assert;
+impl Native for Assert {
+    fn name(&self) -> &str {
+        "assert"
+    }
+
+    fn doc(&self) -> &str {
+        "Stops the program with a message if a condition is false or nil."
+    }
+
+    fn arity(&self) -> u8 {
+        2
+    }
+
+    fn call(&self, heap: &mut Heap, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        if args[0].is_truthy() {
+            Ok(Value::nil())
+        } else {
+            Err(RuntimeError::AssertionFailed(0, heap.format(&args[1])))
+        }
+    }
+}
+
+/// `create(fn)` wraps a function taking zero or one arguments into a new coroutine.
+pub struct Create;
+impl Native for Create {
+    fn name(&self) -> &str {
+        "create"
+    }
+
+    fn doc(&self) -> &str {
+        "Wraps a function taking zero or one arguments into a new coroutine."
+    }
+
+    fn arity(&self) -> u8 {
+        1
+    }
+
+    fn call(&self, heap: &mut Heap, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        match heap.get_value(&args[0]) {
+            Some(Object::Closure(c))
+                if heap.function(&c.function).is_some_and(|f| f.arity <= 1) =>
+            {
+                let coroutine = Coroutine::new(args[0]);
+                Ok(heap.push(Object::Coroutine(coroutine)).into())
+            }
+            _ => Err(RuntimeError::OperandMismatch(
+                0,
+                "a function with at most 1 parameter".to_string(),
+            )),
+        }
+    }
+}
+
+/// `status(co)` returns "suspended", "running", or "dead".
+pub struct Status;
+impl Native for Status {
+    fn name(&self) -> &str {
+        "status"
+    }
+
+    fn doc(&self) -> &str {
+        "Returns \"suspended\", \"running\", or \"dead\" for a coroutine."
+    }
+
+    fn arity(&self) -> u8 {
+        1
+    }
+
+    fn call(&self, heap: &mut Heap, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        match heap.get_value(&args[0]) {
+            Some(Object::Coroutine(co)) => {
+                let status = co.status.name().to_string();
+                Ok(heap.push_str(status).into())
+            }
+            _ => Err(RuntimeError::OperandMismatch(0, "a coroutine".to_string())),
+        }
+    }
+}
+
+/// `version()` returns the interpreter's crate version, e.g. "0.1.0".
+pub struct Version;
+impl Native for Version {
+    fn name(&self) -> &str {
+        "version"
+    }
+
+    fn doc(&self) -> &str {
+        "Returns the interpreter's version string."
+    }
+
+    fn arity(&self) -> u8 {
+        0
+    }
+
+    fn call(&self, heap: &mut Heap, _args: Vec<Value>) -> Result<Value, RuntimeError> {
+        Ok(heap.push_str(env!("CARGO_PKG_VERSION").to_string()).into())
+    }
+}
+
+/// `hasFeature(name)` returns whether this backend supports the language feature
+/// `name`, such as "classes" or "lists".
+pub struct HasFeature;
+impl Native for HasFeature {
+    fn name(&self) -> &str {
+        "hasFeature"
+    }
+
+    fn doc(&self) -> &str {
+        "Returns whether this backend supports a language feature, such as \"classes\"."
+    }
+
+    fn arity(&self) -> u8 {
+        1
+    }
+
+    fn call(&self, heap: &mut Heap, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        match heap.get_value(&args[0]) {
+            Some(Object::String(name)) => Ok(Value::boolean(FEATURES.contains(&&**name))),
+            _ => Err(RuntimeError::OperandMismatch(0, "a string".to_string())),
+        }
+    }
+}
+
+/// `argCount()` returns how many arguments were passed to the script after its
+/// path, as in `lox script.lox a b c`.
+pub struct ArgCount(pub Shared<[String]>);
+impl Native for ArgCount {
+    fn name(&self) -> &str {
+        "argCount"
+    }
+
+    fn doc(&self) -> &str {
+        "Returns how many arguments were passed to the script."
+    }
+
+    fn arity(&self) -> u8 {
+        0
+    }
+
+    fn call(&self, _heap: &mut Heap, _args: Vec<Value>) -> Result<Value, RuntimeError> {
+        Ok(Value::from_i64(self.0.len() as i64))
+    }
+}
+
+/// `arg(i)` returns the `i`th argument passed to the script as a string, or nil
+/// if there are not that many.
+pub struct Arg(pub Shared<[String]>);
+impl Native for Arg {
+    fn name(&self) -> &str {
+        "arg"
+    }
+
+    fn doc(&self) -> &str {
+        "Returns the script argument at an index as a string, or nil."
+    }
+
+    fn arity(&self) -> u8 {
+        1
+    }
+
+    fn call(&self, heap: &mut Heap, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let index = args[0];
+        if !index.is_number() || index.as_number().fract() != 0.0 {
+            return Err(RuntimeError::OperandMismatch(0, "an integer".to_string()));
+        }
+
+        let index = index.as_number();
+        match self.0.get(index as usize) {
+            Some(arg) if index >= 0.0 => Ok(heap.push_str(arg.clone()).into()),
+            _ => Ok(Value::nil()),
+        }
+    }
+}
+
+/// `env(name)` returns the value of the environment variable `name`, or nil if
+/// it is unset or the host has not allowed environment access.
+pub struct EnvVar(pub bool);
+impl Native for EnvVar {
+    fn name(&self) -> &str {
+        "env"
+    }
+
+    fn doc(&self) -> &str {
+        "Returns an environment variable as a string, or nil."
+    }
+
+    fn arity(&self) -> u8 {
+        1
+    }
+
+    fn call(&self, heap: &mut Heap, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let name = match heap.get_value(&args[0]) {
+            Some(Object::String(name)) => name.to_string(),
+            _ => return Err(RuntimeError::OperandMismatch(0, "a string".to_string())),
+        };
+
+        if !self.0 {
+            return Ok(Value::nil());
+        }
+        match std::env::var(name) {
+            Ok(value) => Ok(heap.push_str(value).into()),
+            Err(_) => Ok(Value::nil()),
+        }
+    }
+}
+
+/// The output and errors of the last command run by `exec`, read back with
+/// `execOutput` and `execErrors`.
+pub type ExecResult = Shared<Mutex<(String, String)>>;
+
+/// `exec(cmd)` runs `cmd` with the system shell and returns its exit status, or
+/// -1 if it was stopped by a signal. Only defined with
+/// [`ProcessCapability::Full`](crate::runtime::ProcessCapability::Full).
+pub struct Exec(pub ExecResult);
+impl Native for Exec {
+    fn name(&self) -> &str {
+        "exec"
+    }
+
+    fn doc(&self) -> &str {
+        "Runs a shell command and returns its exit status."
+    }
+
+    fn arity(&self) -> u8 {
+        1
+    }
+
+    fn call(&self, heap: &mut Heap, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let cmd = match heap.get_value(&args[0]) {
+            Some(Object::String(cmd)) => cmd.to_string(),
+            _ => return Err(RuntimeError::OperandMismatch(0, "a string".to_string())),
+        };
+
+        let mut command = if cfg!(windows) {
+            let mut command = Command::new("cmd");
+            command.arg("/C");
+            command
+        } else {
+            let mut command = Command::new("sh");
+            command.arg("-c");
+            command
+        };
+        let output = command
+            .arg(cmd)
+            .output()
+            .map_err(|_| RuntimeError::NativeFailed(0, self.name().to_string()))?;
+
+        *self.0.lock().unwrap() = (
+            String::from_utf8_lossy(&output.stdout).into_owned(),
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        );
+        Ok(Value::from_i64(output.status.code().unwrap_or(-1) as i64))
+    }
+}
+
+/// `execOutput()` returns what the last command run by `exec` wrote to stdout.
+pub struct ExecOutput(pub ExecResult);
+impl Native for ExecOutput {
+    fn name(&self) -> &str {
+        "execOutput"
+    }
+
+    fn doc(&self) -> &str {
+        "Returns what the last command run by exec wrote to stdout."
+    }
+
+    fn arity(&self) -> u8 {
+        0
+    }
+
+    fn call(&self, heap: &mut Heap, _args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let output = self.0.lock().unwrap().0.clone();
+        Ok(heap.push_str(output).into())
+    }
+}
+
+/// `execErrors()` returns what the last command run by `exec` wrote to stderr.
+pub struct ExecErrors(pub ExecResult);
+impl Native for ExecErrors {
+    fn name(&self) -> &str {
+        "execErrors"
+    }
+
+    fn doc(&self) -> &str {
+        "Returns what the last command run by exec wrote to stderr."
+    }
+
+    fn arity(&self) -> u8 {
+        0
+    }
+
+    fn call(&self, heap: &mut Heap, _args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let errors = self.0.lock().unwrap().1.clone();
+        Ok(heap.push_str(errors).into())
+    }
+}
+
+/// `cwd()` returns the process's working directory. Only defined with
+/// [`ProcessCapability::CurrentDir`](crate::runtime::ProcessCapability::CurrentDir) or above.
+pub struct Cwd;
+impl Native for Cwd {
+    fn name(&self) -> &str {
+        "cwd"
+    }
+
+    fn doc(&self) -> &str {
+        "Returns the working directory."
+    }
+
+    fn arity(&self) -> u8 {
+        0
+    }
+
+    fn call(&self, heap: &mut Heap, _args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let dir = std::env::current_dir()
+            .map_err(|_| RuntimeError::NativeFailed(0, self.name().to_string()))?;
+        Ok(heap.push_str(dir.to_string_lossy().into_owned()).into())
+    }
+}
+
+/// `chdir(path)` changes the process's working directory, returning whether it
+/// could. This affects the whole host process, not only the VM.
+pub struct Chdir;
+impl Native for Chdir {
+    fn name(&self) -> &str {
+        "chdir"
+    }
+
+    fn doc(&self) -> &str {
+        "Changes the working directory, returning whether it could."
+    }
+
+    fn arity(&self) -> u8 {
+        1
+    }
+
+    fn call(&self, heap: &mut Heap, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        match heap.get_value(&args[0]) {
+            Some(Object::String(path)) => {
+                Ok(Value::boolean(std::env::set_current_dir(&**path).is_ok()))
+            }
+            _ => Err(RuntimeError::OperandMismatch(0, "a string".to_string())),
+        }
+    }
+}
+
+/// `gc()` collects garbage before the next instruction runs.
+pub struct Gc;
+impl Native for Gc {
+    fn name(&self) -> &str {
+        "gc"
+    }
+
+    fn doc(&self) -> &str {
+        "Collects garbage before the next instruction runs."
+    }
+
+    fn arity(&self) -> u8 {
+        0
+    }
+
+    fn call(&self, heap: &mut Heap, _args: Vec<Value>) -> Result<Value, RuntimeError> {
+        heap.request_gc();
+        Ok(Value::NIL)
+    }
+}
+
+/// `heapDump()` returns a description of every object in the heap, see
+/// [`Heap::dump`].
+pub struct HeapDump;
+impl Native for HeapDump {
+    fn name(&self) -> &str {
+        "heapDump"
+    }
+
+    fn doc(&self) -> &str {
+        "Returns a listing of the objects in the heap, with their indices."
+    }
+
+    fn arity(&self) -> u8 {
+        0
+    }
+
+    fn call(&self, heap: &mut Heap, _args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let dump = heap.dump();
+        Ok(heap.push_str(dump).into())
+    }
+}
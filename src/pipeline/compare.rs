@@ -0,0 +1,115 @@
+use std::{fmt, io};
+
+use crate::{core::errors::InterpretOutcome, runtime::VmOptions};
+
+use super::{Engine, EngineKind};
+
+/// What one engine printed while running a program, and how the run ended.
+/// Warnings are not recorded, since only the bytecode engine reports them.
+#[derive(Debug, Clone)]
+pub struct EngineRun {
+    pub kind: EngineKind,
+    pub output: String,
+    pub outcome: Result<(), InterpretOutcome>,
+}
+
+impl EngineRun {
+    /// Runs `source` on a new engine of the given kind.
+    pub fn new(kind: EngineKind, source: &str, options: VmOptions) -> Self {
+        let mut output = Vec::new();
+        let mut engine = Engine::with_options(kind, Box::new(&mut output), options);
+        engine.set_error_writer(Box::new(io::sink()));
+        let outcome = engine.try_interpret(source);
+        drop(engine);
+
+        Self {
+            kind,
+            output: String::from_utf8_lossy(&output).into_owned(),
+            outcome,
+        }
+    }
+
+    /// Describes how the run ended, as "ok", "compile error", "runtime error", or
+    /// "exit".
+    pub fn status(&self) -> &'static str {
+        match &self.outcome {
+            Ok(()) => "ok",
+            Err(InterpretOutcome::CompileError(_)) => "compile error",
+            Err(InterpretOutcome::RuntimeError(_)) => "runtime error",
+            Err(InterpretOutcome::Exit(_)) => "exit",
+        }
+    }
+
+    /// Returns the messages of the errors that ended the run.
+    pub fn errors(&self) -> Vec<String> {
+        match &self.outcome {
+            Ok(()) => vec![],
+            Err(outcome) => outcome.errors().iter().map(|e| e.to_string()).collect(),
+        }
+    }
+}
+
+/// The runs of the same program on both engines. Formatting a comparison
+/// describes where the runs diverged.
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    pub tree_walk: EngineRun,
+    pub bytecode: EngineRun,
+}
+
+impl Comparison {
+    /// Whether the engines printed different output.
+    pub fn output_diverged(&self) -> bool {
+        self.tree_walk.output != self.bytecode.output
+    }
+
+    /// Whether the runs ended differently, or with different errors.
+    pub fn errors_diverged(&self) -> bool {
+        self.tree_walk.status() != self.bytecode.status()
+            || self.tree_walk.errors() != self.bytecode.errors()
+    }
+
+    pub fn diverged(&self) -> bool {
+        self.output_diverged() || self.errors_diverged()
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.diverged() {
+            return write!(f, "The engines agree.");
+        }
+
+        if self.output_diverged() {
+            writeln!(f, "The engines printed different output.")?;
+            writeln!(f, "tree-walk:\n{}", self.tree_walk.output)?;
+            writeln!(f, "bytecode:\n{}", self.bytecode.output)?;
+        }
+        if self.errors_diverged() {
+            writeln!(f, "The engines ended differently.")?;
+            for run in [&self.tree_walk, &self.bytecode] {
+                let name = match run.kind {
+                    EngineKind::TreeWalk => "tree-walk",
+                    EngineKind::Bytecode => "bytecode",
+                };
+                writeln!(f, "{name}: {}", run.status())?;
+                for error in run.errors() {
+                    writeln!(f, "  {error}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs `source` on both engines, configured by `options`, so their output and
+/// errors can be checked against each other.
+pub fn compare(source: &str, mut options: VmOptions) -> Comparison {
+    // Both engines must draw the same random numbers
+    let seed = options.rng().next_u64();
+    options.random_seed.get_or_insert(seed);
+    Comparison {
+        tree_walk: EngineRun::new(EngineKind::TreeWalk, source, options.clone()),
+        bytecode: EngineRun::new(EngineKind::Bytecode, source, options),
+    }
+}
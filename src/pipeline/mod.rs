@@ -0,0 +1,105 @@
+//! The whole pipeline in one place. Source code goes through a [`Scanner`], a
+//! [`Parser`], and a [`Compiler`] before running on a [`VM`]. Each stage
+//! consumes the iterator produced by the one before, so they can be composed
+//! by hand, see `examples/pipeline.rs`.
+//!
+//! An [`Engine`] runs source code from start to finish, either through the
+//! bytecode pipeline or on a [`TreeWalker`], which interprets the syntax tree
+//! directly. Both engines should behave the same on every program, which makes
+//! the tree-walker useful for checking the compiler: [`compare`] runs a
+//! program on both and reports where they diverge.
+
+mod compare;
+
+pub use crate::bytecode::Compiler;
+pub use crate::frontend::{Parser, Scanner};
+pub use crate::runtime::VM;
+pub use crate::treewalk::TreeWalker;
+pub use compare::{compare, Comparison, EngineRun};
+
+use crate::{
+    core::{errors::InterpretOutcome, shared::Writer},
+    runtime::VmOptions,
+};
+
+/// Which way an [`Engine`] runs source code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EngineKind {
+    /// Walk the syntax tree directly
+    TreeWalk,
+    /// Compile to bytecode and run it on a [`VM`]
+    Bytecode,
+}
+
+/// Runs Lox source code with the interpreter selected by an [`EngineKind`].
+pub enum Engine<'a> {
+    TreeWalk(Box<TreeWalker<'a>>),
+    Bytecode(Box<VM<'a>>),
+}
+
+impl<'a> Engine<'a> {
+    pub fn new(kind: EngineKind, writer: Writer<'a>) -> Self {
+        Self::with_options(kind, writer, VmOptions::default())
+    }
+
+    /// Creates an engine that writes program output to `writer`, configured by
+    /// `options`.
+    pub fn with_options(kind: EngineKind, writer: Writer<'a>, options: VmOptions) -> Self {
+        match kind {
+            EngineKind::TreeWalk => {
+                Engine::TreeWalk(Box::new(TreeWalker::with_options(writer, options)))
+            }
+            EngineKind::Bytecode => Engine::Bytecode(Box::new(VM::with_options(writer, options))),
+        }
+    }
+
+    pub fn kind(&self) -> EngineKind {
+        match self {
+            Engine::TreeWalk(_) => EngineKind::TreeWalk,
+            Engine::Bytecode(_) => EngineKind::Bytecode,
+        }
+    }
+
+    /// Makes program output go to `writer` from now on, returning the previous writer.
+    pub fn set_writer(&mut self, writer: Writer<'a>) -> Writer<'a> {
+        match self {
+            Engine::TreeWalk(walker) => walker.set_writer(writer),
+            Engine::Bytecode(vm) => vm.set_writer(writer),
+        }
+    }
+
+    /// Makes errors go to `writer` from now on, returning the previous writer.
+    /// Defaults to stderr.
+    pub fn set_error_writer(&mut self, writer: Writer<'a>) -> Writer<'a> {
+        match self {
+            Engine::TreeWalk(walker) => walker.set_error_writer(writer),
+            Engine::Bytecode(vm) => vm.set_error_writer(writer),
+        }
+    }
+
+    /// Runs `source`, returning the errors that stopped it, if any. Like
+    /// [`crate::try_interpret`], the bytecode engine still writes warnings to
+    /// the error writer.
+    pub fn try_interpret(&mut self, source: &str) -> Result<(), InterpretOutcome> {
+        match self {
+            Engine::TreeWalk(walker) => walker.interpret(source),
+            // Only the bytecode engine returns the value of a script, which the
+            // engines are not compared on
+            Engine::Bytecode(vm) => crate::try_interpret(source, vm).map(|_| ()),
+        }
+    }
+
+    /// Runs `source`, writing any errors to the error writer.
+    pub fn interpret(&mut self, source: &str) {
+        let Err(outcome) = self.try_interpret(source) else {
+            return;
+        };
+
+        for e in outcome.errors() {
+            match self {
+                Engine::TreeWalk(walker) => walker.report(e),
+                Engine::Bytecode(vm) => vm.report(e),
+            }
+        }
+    }
+}
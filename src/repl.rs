@@ -0,0 +1,120 @@
+//! The state of an interactive session, kept apart from the terminal so that
+//! the REPL's commands can be driven and tested from Rust.
+
+use std::{collections::VecDeque, fmt, fs, io, path::Path};
+
+use crate::{try_interpret, InterpretOutcome, Value, VmState, VM};
+
+/// How many runs [`Session::undo`] can take back.
+pub const UNDO_LIMIT: usize = 100;
+
+/// What a run timed by [`Session::time`] cost.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timing {
+    /// How long compiling and running took, by the VM's clock
+    pub seconds: f64,
+    /// How many instructions were run, see [`VM::instructions_run`]
+    pub instructions: u64,
+    /// How many objects were allocated, see [`Heap::allocations`](crate::Heap::allocations)
+    pub allocations: usize,
+}
+
+impl fmt::Display for Timing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.3}ms, {} instructions, {} objects allocated",
+            self.seconds * 1000.0,
+            self.instructions,
+            self.allocations
+        )
+    }
+}
+
+/// The code a REPL has run on a [`VM`], remembered so it can be undone or saved.
+#[derive(Default)]
+pub struct Session {
+    /// The VM's state before each recent run, oldest first, along with how long
+    /// the transcript was then
+    history: VecDeque<(VmState, usize)>,
+    /// The code of each run that succeeded, in order
+    transcript: Vec<String>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `source` on `vm`, adding it to the transcript if it succeeds. Returns
+    /// the value of its final expression statement, if it ends with one.
+    pub fn run(&mut self, source: &str, vm: &mut VM) -> Result<Option<Value>, InterpretOutcome> {
+        if self.history.len() == UNDO_LIMIT {
+            self.history.pop_front();
+        }
+        self.history
+            .push_back((vm.snapshot(), self.transcript.len()));
+
+        let value = try_interpret(source, vm)?;
+        self.transcript.push(source.to_string());
+        Ok(value)
+    }
+
+    /// Runs `source` on `vm` like [`Session::run`], measuring what it cost.
+    pub fn time(
+        &mut self,
+        source: &str,
+        vm: &mut VM,
+    ) -> (Result<Option<Value>, InterpretOutcome>, Timing) {
+        let clock = vm.options().clock.clone();
+        let (start, instructions, allocations) = (
+            clock.monotonic(),
+            vm.instructions_run(),
+            vm.heap().allocations(),
+        );
+        let result = self.run(source, vm);
+        let timing = Timing {
+            seconds: clock.monotonic() - start,
+            instructions: vm.instructions_run() - instructions,
+            allocations: vm.heap().allocations() - allocations,
+        };
+        (result, timing)
+    }
+
+    /// Runs the file at `path` on `vm` as a single run, like [`Session::run`].
+    /// Fails without running anything if the file cannot be read.
+    pub fn load(
+        &mut self,
+        path: impl AsRef<Path>,
+        vm: &mut VM,
+    ) -> io::Result<Result<Option<Value>, InterpretOutcome>> {
+        let source = fs::read_to_string(path)?;
+        Ok(self.run(source.trim_end(), vm))
+    }
+
+    /// Writes the transcript to the file at `path`, one run after another, so
+    /// loading it again repeats the session.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut contents = self.transcript.join("\n");
+        if !contents.is_empty() {
+            contents.push('\n');
+        }
+        fs::write(path, contents)
+    }
+
+    /// Puts `vm` back the way it was before the last run, taking the run out of
+    /// the transcript. Returns `false` if there is nothing to undo.
+    pub fn undo(&mut self, vm: &mut VM) -> bool {
+        let Some((state, len)) = self.history.pop_back() else {
+            return false;
+        };
+        vm.restore(state);
+        self.transcript.truncate(len);
+        true
+    }
+
+    /// Returns the code of each run that succeeded, in order.
+    pub fn transcript(&self) -> &[String] {
+        &self.transcript
+    }
+}
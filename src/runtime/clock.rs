@@ -0,0 +1,86 @@
+use std::fmt::Debug;
+
+use crate::core::shared::MaybeSendSync;
+
+/// The time source behind the `clock` and `clockMono` natives.
+///
+/// [`SystemClock`] relies on `std::time`, which is unavailable on targets such
+/// as `wasm32-unknown-unknown`; hosts there can supply their own, e.g. one
+/// backed by `performance.now()`.
+pub trait HostClock: Debug + MaybeSendSync {
+    /// Seconds since the Unix epoch.
+    fn wall(&self) -> f64;
+
+    /// Seconds since an arbitrary fixed point. Never decreases.
+    fn monotonic(&self) -> f64;
+}
+
+/// A [`HostClock`] using the operating system's clocks, or on
+/// `wasm32-unknown-unknown`, JavaScript's `Date.now()` and `performance.now()`
+/// when the `wasm` feature is enabled.
+#[derive(Debug)]
+pub struct SystemClock {
+    start: f64,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self {
+            start: sys::monotonic(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HostClock for SystemClock {
+    fn wall(&self) -> f64 {
+        sys::wall()
+    }
+
+    fn monotonic(&self) -> f64 {
+        sys::monotonic() - self.start
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+mod sys {
+    use std::{
+        sync::OnceLock,
+        time::{Instant, SystemTime, UNIX_EPOCH},
+    };
+
+    pub fn wall() -> f64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards.")
+            .as_secs_f64()
+    }
+
+    pub fn monotonic() -> f64 {
+        static BASE: OnceLock<Instant> = OnceLock::new();
+        BASE.get_or_init(Instant::now).elapsed().as_secs_f64()
+    }
+}
+
+// `std::time` panics on this target, so the time comes from JavaScript, or
+// without the bindings to reach it, stands still at zero
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+mod sys {
+    #[cfg(feature = "wasm")]
+    pub use crate::wasm::{monotonic, wall};
+
+    #[cfg(not(feature = "wasm"))]
+    pub fn wall() -> f64 {
+        0.0
+    }
+
+    #[cfg(not(feature = "wasm"))]
+    pub fn monotonic() -> f64 {
+        0.0
+    }
+}
@@ -1,35 +1,57 @@
-use std::rc::Rc;
-
-use crate::object::Closure;
+use crate::{
+    core::{shared::Shared, Value},
+    object::Function,
+};
 
 // TODO: Allocate frames from continuous memory
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Frame {
     /// Index into a chunk's code
     pub ip: usize,
     /// Index into the VM's stack
     pub fp: usize,
-    pub closure: Rc<Closure>,
+    /// The closure being run, or nil for a frame that runs no closure, such as
+    /// the one the VM starts with
+    pub closure: Value,
+    /// The closure's function, held here so that running it needs no lookup in
+    /// the heap
+    pub function: Shared<Function>,
 
     pub caller: Option<Box<Frame>>,
 }
 
 impl Frame {
-    pub fn new(closure: Rc<Closure>, fp: usize) -> Self {
+    pub fn new(closure: Value, function: Shared<Function>, fp: usize) -> Self {
         Self {
             ip: 0,
             fp,
             closure,
+            function,
             caller: None,
         }
     }
 
-    pub fn with_caller(closure: Rc<Closure>, fp: usize, caller: Box<Frame>) -> Self {
+    pub fn with_caller(
+        closure: Value,
+        function: Shared<Function>,
+        fp: usize,
+        caller: Box<Frame>,
+    ) -> Self {
         Self {
             ip: 0,
             fp,
             closure,
+            function,
             caller: Some(caller),
         }
     }
+
+    /// Returns a frame that runs nothing, at the stack index `fp`.
+    pub(crate) fn empty(fp: usize) -> Self {
+        Self::new(
+            Value::NIL,
+            Shared::new(Function::new("".to_string(), 0)),
+            fp,
+        )
+    }
 }
@@ -0,0 +1,49 @@
+use crate::{
+    core::{errors::InterpretError, Value},
+    object::native::{Async, AsyncNative},
+};
+
+use super::{Frame, VM};
+
+impl VM<'_> {
+    /// Registers `native` as a global function, like [`VM::define_native`], but
+    /// one that can only be called while the script is run by [`VM::run_async`].
+    pub fn define_async_native(&mut self, native: impl AsyncNative + 'static) {
+        self.define_native(Async(native));
+    }
+
+    /// Runs `frame` as the top-level code of a script, like [`VM::run`], but
+    /// yields to the async runtime after every `batch` instructions and awaits
+    /// the futures returned by async natives, so that a long script does not
+    /// block the other tasks on its thread.
+    pub async fn run_async(
+        &mut self,
+        frame: Frame,
+        batch: u64,
+    ) -> Result<Option<Value>, InterpretError> {
+        self.start(frame)?;
+        self.in_async = true;
+        let result = self.run_batches(batch.max(1)).await;
+        self.in_async = false;
+        self.pending = None;
+        result?;
+        Ok(self.finish())
+    }
+
+    /// Runs the script that has been started until it finishes, awaiting
+    /// between batches.
+    async fn run_batches(&mut self, batch: u64) -> Result<(), InterpretError> {
+        loop {
+            let limit = self.instructions.saturating_add(batch);
+            let done = self.execute_until(limit)?;
+            if let Some(future) = self.pending.take() {
+                let value = future.await.map_err(InterpretError::Runtime)?;
+                self.stack_push(value);
+            } else if done {
+                return Ok(());
+            } else {
+                tokio::task::yield_now().await;
+            }
+        }
+    }
+}
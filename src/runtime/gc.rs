@@ -0,0 +1,204 @@
+use std::{fmt, io::Write};
+
+use crate::{
+    core::Value,
+    object::{Closure, Function, Object},
+};
+
+use super::{frame::Frame, upvalue::VMUpvalue, VM};
+
+/// How much the heap may grow past the objects that survived a collection
+/// before the next one.
+const GC_HEAP_GROW_FACTOR: usize = 2;
+
+/// Why the VM collected garbage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GcReason {
+    /// The heap grew past its threshold, see [`VmOptions::gc_threshold`](super::VmOptions::gc_threshold)
+    Threshold,
+    /// The program called the `gc` native
+    Requested,
+    /// The host called [`VM::collect_garbage`]
+    Host,
+}
+
+impl fmt::Display for GcReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GcReason::Threshold => write!(f, "threshold"),
+            GcReason::Requested => write!(f, "requested"),
+            GcReason::Host => write!(f, "host"),
+        }
+    }
+}
+
+/// What the garbage collector has done since the VM was created, returned by
+/// [`VM::gc_stats`]. Pauses are in seconds, timed with the VM's
+/// [`HostClock`](super::HostClock).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GcStats {
+    /// How many times garbage was collected
+    pub collections: usize,
+    /// How many objects were freed, over all collections
+    pub freed: usize,
+    /// Time spent collecting, over all collections
+    pub total_pause: f64,
+    /// The longest single collection
+    pub max_pause: f64,
+}
+
+impl VM<'_> {
+    /// Frees the objects nothing in the VM refers to anymore, returning how many
+    /// were freed. Values the host keeps between programs must be pinned with
+    /// [`Heap::pin`](super::Heap::pin) to survive this.
+    pub fn collect_garbage(&mut self) -> usize {
+        let freed = self.collect(GcReason::Host);
+        self.heap.finish_sweep();
+        freed
+    }
+
+    /// Returns what the garbage collector has done so far.
+    pub fn gc_stats(&self) -> GcStats {
+        self.gc_stats
+    }
+
+    /// Collects garbage if the heap has grown past its threshold or the program
+    /// asked for it. Called between instructions, where every live value is
+    /// reachable from the VM.
+    #[inline]
+    pub(crate) fn maybe_collect(&mut self) {
+        if let Some(reason) = self.heap.gc_reason() {
+            self.collect(reason);
+        }
+    }
+
+    fn collect(&mut self, reason: GcReason) -> usize {
+        let start = self.options.clock.monotonic();
+        let before = self.heap.len();
+
+        let (marked, live, upvalues) = self.mark();
+        let freed = self.heap.sweep(marked, live);
+        self.upvalues.retain(|index, _| upvalues[index]);
+
+        let after = self.heap.len();
+        self.heap.set_next_gc(
+            self.options
+                .gc_threshold
+                .max(after.saturating_mul(GC_HEAP_GROW_FACTOR)),
+        );
+
+        let pause = self.options.clock.monotonic() - start;
+        self.gc_stats.collections += 1;
+        self.gc_stats.freed += freed;
+        self.gc_stats.total_pause += pause;
+        self.gc_stats.max_pause = self.gc_stats.max_pause.max(pause);
+
+        if self.options.gc_log {
+            let _ = writeln!(
+                self.error_writer,
+                "GC        {reason}: {before} -> {after} objects, {:.3}ms",
+                pause * 1000.0
+            );
+        }
+        freed
+    }
+
+    /// Marks every object and upvalue slot reachable from the VM's roots: the
+    /// stack, the globals, the pinned values, and the frames being run.
+    fn mark(&self) -> (Vec<bool>, usize, Vec<bool>) {
+        let mut marker = Marker {
+            vm: self,
+            objects: vec![false; self.heap.capacity()],
+            live: 0,
+            upvalues: vec![false; self.upvalues.capacity()],
+            gray: Vec::new(),
+        };
+
+        marker.gray.extend(self.stack.iter().copied());
+        marker.gray.extend(self.globals.iter().flatten().copied());
+        marker.gray.extend(self.heap.pinned_values());
+        marker
+            .gray
+            .extend(self.coroutines.iter().map(|&(_, coroutine)| coroutine));
+        marker.mark_frames(&self.frame);
+
+        while let Some(value) = marker.gray.pop() {
+            marker.mark_value(value);
+        }
+        (marker.objects, marker.live, marker.upvalues)
+    }
+}
+
+/// Walks the object graph, with the values still to be visited in `gray`.
+struct Marker<'v, 'a> {
+    vm: &'v VM<'a>,
+    objects: Vec<bool>,
+    /// How many objects are marked
+    live: usize,
+    upvalues: Vec<bool>,
+    gray: Vec<Value>,
+}
+
+impl Marker<'_, '_> {
+    fn mark_value(&mut self, value: Value) {
+        let Some(object) = value.as_object() else {
+            return;
+        };
+        match self.objects.get_mut(object.index()) {
+            Some(marked) if !*marked => *marked = true,
+            _ => return,
+        }
+
+        let Some(object) = self.vm.heap.get(object) else {
+            return;
+        };
+        self.live += 1;
+        match object {
+            Object::Function(function) => self.mark_function(function),
+            Object::Closure(closure) => self.mark_closure(closure),
+            Object::Iter(iter) => self.gray.push(iter.target),
+            Object::Coroutine(co) => {
+                self.gray.push(co.function);
+                self.gray.extend(co.stack.iter().copied());
+                for frame in &co.frames {
+                    self.mark_frames(frame);
+                }
+                for &(upvalue, _) in &co.upvalues {
+                    self.mark_upvalue(upvalue);
+                }
+            }
+            Object::String(_) | Object::Native(_) => {}
+        }
+    }
+
+    /// Marks the closures of `frame` and the frames it was called from.
+    fn mark_frames(&mut self, frame: &Frame) {
+        let mut frame = Some(frame);
+        while let Some(f) = frame {
+            self.gray.push(f.closure);
+            frame = f.caller.as_deref();
+        }
+    }
+
+    fn mark_closure(&mut self, closure: &Closure) {
+        self.gray.push(closure.function);
+        for &upvalue in &closure.upvalues {
+            self.mark_upvalue(upvalue);
+        }
+    }
+
+    fn mark_upvalue(&mut self, index: usize) {
+        let Some(marked) = self.upvalues.get_mut(index) else {
+            return;
+        };
+        *marked = true;
+        if let Some(VMUpvalue::Closed(value)) = self.vm.upvalues.get(index) {
+            self.gray.push(*value);
+        }
+    }
+
+    /// Marks the constants of `function`, including the functions declared in it.
+    fn mark_function(&mut self, function: &Function) {
+        self.gray.extend(function.chunk.values().iter().copied());
+    }
+}
@@ -1,15 +1,73 @@
-use std::rc::Rc;
+use std::fmt;
 
 use rustc_hash::FxHashMap;
 use slab::Slab;
 
-use crate::{core::Value, object::Object};
+use crate::{
+    core::{format_number, shared::Shared, HeapRef, Value},
+    object::{Function, Object},
+};
 
-use super::VM;
+use super::{GcReason, Symbols, VM};
 
+/// How many characters of an object a [`HeapEntry`] previews
+const PREVIEW_MAX: usize = 40;
+/// How many slots each allocation sweeps while a sweep is under way
+const SWEEP_STEP: usize = 16;
+
+/// One object in a [`Heap::dump`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeapEntry {
+    /// The object's index, as in `<object:N>`
+    pub index: usize,
+    /// See [`Object::kind`]
+    pub kind: &'static str,
+    /// The object as `print` would write it, shortened if long
+    pub preview: String,
+    /// How many references share the object, counting the heap's own, see
+    /// [`Object::ref_count`]
+    pub ref_count: Option<usize>,
+}
+
+impl fmt::Display for HeapEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ref_count = match self.ref_count {
+            Some(count) => count.to_string(),
+            None => "-".to_string(),
+        };
+        write!(
+            f,
+            "{:>6} {:<10} rc={:<4} {}",
+            self.index, self.kind, ref_count, self.preview
+        )
+    }
+}
+
+/// A value pinned with [`Heap::pin`], which stays alive until it is given back
+/// to [`Heap::unpin`].
+#[derive(Debug, PartialEq, Eq, Hash)]
+#[must_use = "a pinned value stays alive until its handle is unpinned"]
+pub struct PinHandle(usize);
+
+#[derive(Clone)]
 pub struct Heap {
     objects: Slab<Object>,
-    intern_table: FxHashMap<Rc<str>, usize>,
+    intern_table: FxHashMap<Shared<str>, usize>,
+    /// Values held outside the VM, e.g. by natives, which the heap must keep
+    pinned: Slab<Value>,
+    /// How many objects the heap may hold before the VM collects garbage
+    next_gc: usize,
+    /// Whether the `gc` native asked for a collection
+    gc_requested: bool,
+    /// The objects the last collection found alive, by index, while the slots
+    /// from `sweep_cursor` on have yet to be swept. Empty once the sweep is done.
+    /// Objects past its end were allocated since, and are alive.
+    marked: Vec<bool>,
+    sweep_cursor: usize,
+    /// How many objects are waiting to be swept
+    garbage: usize,
+    /// How many objects have ever been pushed
+    allocations: usize,
 }
 
 impl Heap {
@@ -17,69 +75,309 @@ impl Heap {
         Self {
             objects: Slab::new(),
             intern_table: FxHashMap::default(),
+            pinned: Slab::new(),
+            next_gc: usize::MAX,
+            gc_requested: false,
+            marked: Vec::new(),
+            sweep_cursor: 0,
+            garbage: 0,
+            allocations: 0,
+        }
+    }
+
+    /// Returns how many objects are in the heap, not counting garbage that is
+    /// still waiting to be swept.
+    pub fn len(&self) -> usize {
+        self.objects.len() - self.garbage
+    }
+
+    /// Returns how many objects the heap can hold without reallocating. Every
+    /// object's index is below this.
+    pub fn capacity(&self) -> usize {
+        self.objects.capacity()
+    }
+
+    /// Returns whether the heap has no objects.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns how many objects have been pushed into the heap since it was
+    /// created, including those freed since.
+    pub fn allocations(&self) -> usize {
+        self.allocations
+    }
+
+    /// Asks the VM to collect garbage before running its next instruction.
+    pub fn request_gc(&mut self) {
+        self.gc_requested = true;
+    }
+
+    /// Returns why the VM should collect garbage now, if it should.
+    #[inline]
+    pub(crate) fn gc_reason(&self) -> Option<GcReason> {
+        if self.gc_requested {
+            Some(GcReason::Requested)
+        } else if self.len() >= self.next_gc {
+            Some(GcReason::Threshold)
+        } else {
+            None
+        }
+    }
+
+    /// Makes the VM collect garbage once the heap holds `next_gc` objects.
+    pub(crate) fn set_next_gc(&mut self, next_gc: usize) {
+        self.next_gc = next_gc;
+    }
+
+    /// Starts sweeping away every object whose index is not set in `marked`,
+    /// given that `live` objects are, returning how many will be freed. Rather
+    /// than freeing them all at once, each allocation frees a few, so a
+    /// collection only pauses to mark.
+    pub(crate) fn sweep(&mut self, marked: Vec<bool>, live: usize) -> usize {
+        self.finish_sweep();
+        self.garbage = self.objects.len() - live;
+        self.marked = marked;
+        self.sweep_cursor = 0;
+        self.gc_requested = false;
+        self.garbage
+    }
+
+    /// Frees the garbage the last collection left to be swept.
+    pub(crate) fn finish_sweep(&mut self) {
+        while !self.marked.is_empty() {
+            self.sweep_step(usize::MAX);
+        }
+    }
+
+    /// Sweeps up to `slots` more slots, freeing the garbage among them.
+    fn sweep_step(&mut self, slots: usize) {
+        let end = self
+            .marked
+            .len()
+            .min(self.sweep_cursor.saturating_add(slots));
+        for index in self.sweep_cursor..end {
+            if !self.marked[index]
+                && let Some(object) = self.objects.try_remove(index)
+            {
+                if let Object::String(s) = object {
+                    self.intern_table.remove(&s);
+                }
+                self.garbage -= 1;
+            }
+        }
+        self.sweep_cursor = end;
+
+        if end == self.marked.len() {
+            self.marked = Vec::new();
+            self.sweep_cursor = 0;
+        }
+    }
+
+    /// Returns whether the object at `index` is waiting to be swept.
+    fn is_garbage(&self, index: usize) -> bool {
+        index >= self.sweep_cursor
+            && !self.marked.get(index).copied().unwrap_or(true)
+            && self.objects.contains(index)
+    }
+
+    /// Takes the object at `index` back out of the sweep under way.
+    fn keep(&mut self, index: usize) {
+        if self.is_garbage(index) {
+            self.garbage -= 1;
+        }
+        if let Some(marked) = self.marked.get_mut(index) {
+            *marked = true;
         }
     }
 
-    /// Pushes an object into the heap and return its index as a Value.
+    /// Pushes an object into the heap and returns a reference to it.
     /// Strings should use [`Heap::push_str`]
-    pub fn push(&mut self, obj: Object) -> Value {
+    pub fn push(&mut self, obj: Object) -> HeapRef {
+        self.sweep_step(SWEEP_STEP);
+        self.allocations += 1;
         let index = self.objects.insert(obj);
-        Value::object(index)
+        if let Some(marked) = self.marked.get_mut(index) {
+            *marked = true;
+        }
+        HeapRef::new(index)
     }
 
-    pub fn push_str(&mut self, s: String) -> Value {
-        let string: Rc<str> = Rc::from(s);
-        if let Some(index) = self.intern_table.get(&string) {
-            Value::object(*index)
+    pub fn push_str(&mut self, s: String) -> HeapRef {
+        let string: Shared<str> = Shared::from(s);
+        if let Some(&index) = self.intern_table.get(&string) {
+            // A string waiting to be swept can be handed out again, since
+            // strings refer to no other objects
+            self.keep(index);
+            HeapRef::new(index)
         } else {
-            let index = self.objects.insert(Object::String(string.clone()));
-            self.intern_table.insert(string, index);
-            Value::object(index)
+            let object = self.push(Object::String(string.clone()));
+            self.intern_table.insert(string, object.index());
+            object
         }
     }
 
-    pub fn get(&self, value: &Value) -> Option<&Object> {
-        if !value.is_object() {
-            return None;
+    /// Keeps `value` alive, and the object it refers to in place, until the
+    /// returned handle is unpinned. Natives that store a `Value` in their own
+    /// state between calls must pin it, as nothing in the VM refers to it.
+    pub fn pin(&mut self, value: Value) -> PinHandle {
+        PinHandle(self.pinned.insert(value))
+    }
+
+    /// Returns the value pinned under `handle`.
+    pub fn pinned(&self, handle: &PinHandle) -> Value {
+        self.pinned[handle.0]
+    }
+
+    /// Releases the value pinned under `handle`, returning it. The value must
+    /// not be used after this unless something else keeps it alive.
+    pub fn unpin(&mut self, handle: PinHandle) -> Value {
+        self.pinned.remove(handle.0)
+    }
+
+    /// Returns the values currently pinned, which count as roots of the heap.
+    pub fn pinned_values(&self) -> impl Iterator<Item = Value> + '_ {
+        self.pinned.iter().map(|(_, value)| *value)
+    }
+
+    /// Returns a reference to the object at `index`, as in `<object:N>` or a
+    /// `Closure` operand, if there is one.
+    pub fn ref_at(&self, index: usize) -> Option<HeapRef> {
+        self.objects.contains(index).then_some(HeapRef::new(index))
+    }
+
+    pub fn get(&self, object: HeapRef) -> Option<&Object> {
+        self.objects.get(object.index())
+    }
+
+    pub fn get_mut(&mut self, object: HeapRef) -> Option<&mut Object> {
+        self.objects.get_mut(object.index())
+    }
+
+    /// Returns the object `value` refers to, if it is an object.
+    pub fn get_value(&self, value: &Value) -> Option<&Object> {
+        self.get(value.as_object()?)
+    }
+
+    /// Returns the object `value` refers to mutably, if it is an object.
+    pub fn get_value_mut(&mut self, value: &Value) -> Option<&mut Object> {
+        self.get_mut(value.as_object()?)
+    }
+
+    /// Returns the function `value` refers to, directly or through a closure.
+    pub fn function(&self, value: &Value) -> Option<&Shared<Function>> {
+        match self.get_value(value)? {
+            Object::Function(function) => Some(function),
+            Object::Closure(closure) => match self.get_value(&closure.function)? {
+                Object::Function(function) => Some(function),
+                _ => None,
+            },
+            _ => None,
         }
+    }
 
-        self.objects.get(value.as_object())
+    /// Describes every object in the heap, in index order.
+    pub fn entries(&self) -> Vec<HeapEntry> {
+        self.objects
+            .iter()
+            .filter(|(index, _)| !self.is_garbage(*index))
+            .map(|(index, object)| {
+                let mut preview = self.format_value(object);
+                if preview.chars().count() > PREVIEW_MAX {
+                    preview = preview.chars().take(PREVIEW_MAX - 3).collect();
+                    preview.push_str("...");
+                }
+                HeapEntry {
+                    index,
+                    kind: object.kind(),
+                    preview,
+                    ref_count: object.ref_count(),
+                }
+            })
+            .collect()
     }
 
-    pub(crate) fn set(&mut self, index: usize, value: Value) {
-        self.objects[index] = Object::UpValue(value);
+    /// Formats the objects in the heap, one per line, with the index that
+    /// `Value`'s Debug output shows as `<object:N>`.
+    pub fn dump(&self) -> String {
+        let mut dump = format!("HEAP ({} objects)\n", self.len());
+        for entry in self.entries() {
+            dump.push_str(&format!("{entry}\n"));
+        }
+        dump
     }
 
-    pub fn dump(&self) {
-        eprint!("HEAP     ");
-        for (_, value) in &self.objects {
-            eprint!(" [ {} ]", self.format_value(value))
+    /// Formats `value` as `print` would, looking up objects in the heap.
+    pub fn format(&self, value: &Value) -> String {
+        if let Some(object) = value.as_object() {
+            match self.get(object) {
+                Some(object) => self.format_value(object),
+                None => "nil".to_string(),
+            }
+        } else if value.is_number() {
+            format_number(value.as_number())
+        } else if value.is_boolean() {
+            format!("{}", value.as_boolean())
+        } else if value.is_nil() {
+            "nil".to_string()
+        } else {
+            format!("{value:?}")
         }
-        eprintln!();
     }
 
     pub fn format_value(&self, value: &Object) -> String {
         match value {
             Object::String(s) => s.to_string(),
-            Object::Function(f) => format!("<fn {}>", f.name),
-            Object::Native(f) => format!("<fn {}>", f.name()),
-            Object::Closure(f) => format!("<closure {}>", f.function.name),
-            Object::UpValue(v) => match v {
-                o if o.is_object() => self.format_value(self.get(&o).unwrap()),
-                a => format!("{:?}", a),
+            Object::Function(f) => format!("<fn {}>", f.display_name()),
+            // Natives and closures print as in the reference Lox implementations
+            Object::Native(_) => "<native fn>".to_string(),
+            Object::Closure(c) => match self.function(&c.function) {
+                Some(f) => format!("<fn {}>", f.display_name()),
+                None => "<fn>".to_string(),
+            },
+            Object::Coroutine(c) => match self.get_value(&c.function) {
+                Some(Object::Closure(f)) => match self.function(&f.function) {
+                    Some(f) => format!("<coroutine {}>", f.name),
+                    None => "<coroutine>".to_string(),
+                },
+                _ => "<coroutine>".to_string(),
             },
+            Object::Iter(_) => "<iterator>".to_string(),
         }
     }
 }
 
 impl VM<'_> {
+    /// Returns a reference to the VM's heap
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
     /// Returns a mutable reference to the VM's heap
     pub fn heap_mut(&mut self) -> &mut Heap {
         &mut self.heap
     }
 
+    /// Returns the ids of global names known to the VM, and of the functions
+    /// compiled for it
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    /// Returns the symbols to compile a program for the VM with, see
+    /// [`Compiler::new`](crate::bytecode::Compiler::new).
+    pub fn symbols_mut(&mut self) -> &mut Symbols {
+        &mut self.symbols
+    }
+
     /// Gets an object on the heap based on the index `value`
     pub(crate) fn heap_get(&self, value: &Value) -> Option<&Object> {
-        self.heap.get(value)
+        self.heap.get_value(value)
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
     }
 }
@@ -1,29 +1,97 @@
+//! The last stage of the pipeline: [`execute`] runs a compiled [`Function`] on
+//! a [`VM`].
+
+mod clock;
 mod frame;
+#[cfg(feature = "tokio")]
+mod future;
+mod gc;
 mod heap;
+mod options;
+mod random;
+mod schedule;
+mod snapshot;
 mod stack;
+mod stdlib;
+mod symbols;
+#[cfg(feature = "threads")]
+mod threads;
+mod trace;
 mod upvalue;
 mod vm;
+mod watch;
 
+pub use clock::{HostClock, SystemClock};
 pub use frame::Frame;
-pub use heap::Heap;
-use rustc_hash::FxHashMap;
+pub use gc::{GcReason, GcStats};
+pub use heap::{Heap, HeapEntry, PinHandle};
+pub use options::{ProcessCapability, VmOptions};
+pub use random::Rng;
+pub use schedule::RunState;
 use slab::Slab;
+pub use snapshot::VmState;
+pub(crate) use stdlib::MODULES;
+pub use symbols::Symbols;
+pub use trace::TraceFlags;
 use upvalue::VMUpvalue;
+pub use watch::{WatchAction, WatchHandler, WatchHit};
 
-use crate::core::{errors::InterpretError, Value};
-use std::io::Write;
+use crate::{
+    core::{errors::InterpretError, shared::Writer, Value},
+    object::Function,
+};
 
 type Return = Result<(), InterpretError>;
 
 pub const FRAME_MAX: usize = 64;
+/// How many stack values the default `max_stack` allows for each of the
+/// `FRAME_MAX` frames.
 pub const STACK_MAX: usize = 256;
+/// How many objects the heap holds before the first garbage collection, by
+/// default.
+pub const GC_THRESHOLD: usize = 10_000;
+
+/// Runs `function`, as produced by [`crate::bytecode::compile`] with `vm`'s
+/// symbols, as the top-level code of a script. Returns the value of the
+/// script's final expression statement, if it ends with one.
+pub fn execute(function: Function, vm: &mut VM) -> Result<Option<Value>, InterpretError> {
+    let frame = vm.load(function);
+    vm.run(frame)
+}
 
 pub struct VM<'a> {
     frame: Frame,
     frame_count: usize,
     stack: Vec<Value>,
     heap: Heap,
-    globals: FxHashMap<u64, Value>,
+    /// Names of the globals, and the ids of functions compiled for the VM
+    symbols: Symbols,
+    /// Values of the globals, indexed by their id in `symbols`
+    globals: Vec<Option<Value>>,
     upvalues: Slab<VMUpvalue>,
-    writer: Box<dyn Write + 'a>,
+    writer: Writer<'a>,
+    /// Where compile errors, runtime errors, and warnings are reported
+    error_writer: Writer<'a>,
+    options: VmOptions,
+    /// Coroutines that are currently running, as (stack base, coroutine), innermost last
+    coroutines: Vec<(usize, Value)>,
+    trace: TraceFlags,
+    /// Symbol ids of the globals whose writes pause the program
+    watched: Vec<u32>,
+    watch_handler: Option<WatchHandler<'a>>,
+    gc_stats: GcStats,
+    /// How many instructions have been run, over all programs
+    instructions: u64,
+    /// Whether a script started by [`VM::run_for`] has instructions left to run
+    paused: bool,
+    /// Whether the script is being run by [`VM::run_async`], so async natives
+    /// can be called
+    #[cfg(feature = "tokio")]
+    in_async: bool,
+    /// The future of the async native called last, which the script waits on
+    #[cfg(feature = "tokio")]
+    pending: Option<crate::core::shared::NativeFuture>,
+    /// The channels and threads shared with the VMs spawned from this one
+    #[cfg(feature = "threads")]
+    threads: crate::core::shared::Shared<threads::Threads>,
 }
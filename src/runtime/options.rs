@@ -0,0 +1,112 @@
+use crate::{core::shared::Shared, frontend::MAX_NESTING};
+
+use super::{HostClock, Rng, SystemClock, FRAME_MAX, GC_THRESHOLD, STACK_MAX};
+
+/// Which process natives a [`VM`](super::VM) defines, see [`VmOptions::process`].
+/// Each level allows everything the ones before it do.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ProcessCapability {
+    /// No process natives
+    #[default]
+    None,
+    /// `cwd()` and `chdir(path)`
+    CurrentDir,
+    /// `exec(cmd)`, `execOutput()`, and `execErrors()` as well
+    Full,
+}
+
+/// Configuration for a [`VM`](super::VM), used to bound and customize execution.
+#[derive(Debug, Clone)]
+pub struct VmOptions {
+    /// The maximum number of nested call frames before a stack overflow is reported.
+    pub max_frames: usize,
+    /// The maximum number of values on the stack before a stack overflow is
+    /// reported. Calls are checked against it up front, using the deepest the
+    /// callee's frame can grow.
+    pub max_stack: usize,
+    /// How deeply statements and expressions in a script may be nested before
+    /// it is rejected with a syntax error, see [`Parser::with_max_nesting`].
+    /// Defaults to [`MAX_NESTING`]. An `else if` does not nest, so chains of
+    /// them are allowed whatever the limit.
+    ///
+    /// [`Parser::with_max_nesting`]: crate::frontend::Parser::with_max_nesting
+    pub max_nesting: usize,
+    /// The time source for the `clock` and `clockMono` natives.
+    pub clock: Shared<dyn HostClock>,
+    /// The seed for the `random` and `randomInt` natives, so that runs can be
+    /// repeated. `None` seeds them from the clock's wall time.
+    pub random_seed: Option<u64>,
+    /// Whether to define the Lox standard library (`abs`, `max`, `reverse`, ...)
+    /// as globals when the VM is created. Off by default, so that embedders do
+    /// not compile it, or lose its names as globals, unless they ask for it; the
+    /// `lox-bytecode-vm` binary turns it on.
+    pub load_stdlib: bool,
+    /// Whether to reject, at compile time, scripts that use a global which is
+    /// neither defined by the script nor already defined in the VM.
+    pub strict_globals: bool,
+    /// Whether `+` converts a number to a string when the other operand is a
+    /// string, as in `"x = " + 2`. Otherwise adding a string and a number is an
+    /// error that suggests `str()`.
+    pub string_coercion: bool,
+    /// Whether dividing by zero is an error. Otherwise it gives infinity, or
+    /// NaN for `0 / 0`, as floating point division does.
+    pub strict_math: bool,
+    /// The arguments passed to the script after its path, read with the
+    /// `argCount` and `arg` natives.
+    pub args: Vec<String>,
+    /// Whether the `env` native may read the host's environment variables.
+    /// Otherwise it always returns nil.
+    pub env_access: bool,
+    /// Which natives for running commands and changing the working directory
+    /// are defined. Embedded VMs get none by default; the command line binary
+    /// allows all of them.
+    pub process: ProcessCapability,
+    /// Whether to define natives for inspecting the VM itself, such as
+    /// `heapDump`.
+    pub debug_natives: bool,
+    /// Whether to compile scripts without their `assert(...)` calls and the
+    /// statements marked with a `//@debug` comment.
+    pub strip: bool,
+    /// How many objects the heap holds before the first garbage collection.
+    /// After each collection, the heap may grow to twice the objects that
+    /// survived, but never collects below this. `usize::MAX` turns collection
+    /// off.
+    pub gc_threshold: usize,
+    /// Whether to report each garbage collection to the error writer, with the
+    /// objects before and after, how long it took, and what triggered it.
+    pub gc_log: bool,
+}
+
+impl Default for VmOptions {
+    fn default() -> Self {
+        Self {
+            max_frames: FRAME_MAX,
+            max_stack: FRAME_MAX * STACK_MAX,
+            max_nesting: MAX_NESTING,
+            clock: Shared::new(SystemClock::new()),
+            random_seed: None,
+            load_stdlib: false,
+            strict_globals: false,
+            string_coercion: false,
+            strict_math: false,
+            args: Vec::new(),
+            env_access: false,
+            process: ProcessCapability::None,
+            debug_natives: false,
+            strip: false,
+            gc_threshold: GC_THRESHOLD,
+            gc_log: false,
+        }
+    }
+}
+
+impl VmOptions {
+    /// Returns the generator for the random natives, seeded with `random_seed`
+    /// or the clock's wall time.
+    pub(crate) fn rng(&self) -> Rng {
+        Rng::new(
+            self.random_seed
+                .unwrap_or_else(|| self.clock.wall().to_bits()),
+        )
+    }
+}
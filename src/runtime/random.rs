@@ -0,0 +1,47 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The generator behind the `random`, `randomInt`, and `seed` natives, a
+/// splitmix64 whose state belongs to one VM, so a script run with the same
+/// [`VmOptions::random_seed`](super::VmOptions::random_seed) always sees the
+/// same numbers.
+#[derive(Debug)]
+pub struct Rng {
+    state: AtomicU64,
+}
+
+impl Rng {
+    /// Added to the state for each number generated, the golden ratio in 64 bits.
+    const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: AtomicU64::new(seed),
+        }
+    }
+
+    /// Starts the sequence over from `seed`.
+    pub fn seed(&self, seed: u64) {
+        self.state.store(seed, Ordering::Relaxed);
+    }
+
+    /// Returns the next 64 random bits.
+    pub fn next_u64(&self) -> u64 {
+        let mut z = self
+            .state
+            .fetch_add(Self::GAMMA, Ordering::Relaxed)
+            .wrapping_add(Self::GAMMA);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in `0..1`.
+    pub fn next_f64(&self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns an integer in `0..bound`, which must not be zero.
+    pub fn below(&self, bound: u64) -> u64 {
+        ((self.next_u64() as u128 * bound as u128) >> 64) as u64
+    }
+}
@@ -0,0 +1,52 @@
+use crate::core::{errors::InterpretError, Value};
+
+use super::{Frame, VM};
+
+/// How far a script run with [`VM::run_for`] got.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunState {
+    /// The script finished, returning the value of its final expression
+    /// statement, if it ends with one
+    Done(Option<Value>),
+    /// The script ran out of instructions, and carries on from where it
+    /// stopped with [`VM::resume_for`]
+    Paused,
+}
+
+impl VM<'_> {
+    /// Runs `frame` as the top-level code of a script, like [`VM::run`], but
+    /// pauses once `instructions` have run, so that a host can interleave the
+    /// script with its own work without threads.
+    pub fn run_for(&mut self, frame: Frame, instructions: u64) -> Result<RunState, InterpretError> {
+        self.start(frame)?;
+        self.run_slice(instructions)
+    }
+
+    /// Carries on running the script paused by [`VM::run_for`] or an earlier
+    /// call to this, for at most `instructions` more instructions. Returns
+    /// `Done(None)` straight away if no script is paused.
+    pub fn resume_for(&mut self, instructions: u64) -> Result<RunState, InterpretError> {
+        if !self.paused {
+            return Ok(RunState::Done(None));
+        }
+        self.run_slice(instructions)
+    }
+
+    /// Whether a script started by [`VM::run_for`] is paused with instructions
+    /// left to run.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Runs at most `instructions` more instructions of the script that has
+    /// been started.
+    fn run_slice(&mut self, instructions: u64) -> Result<RunState, InterpretError> {
+        let limit = self.instructions.saturating_add(instructions);
+        self.paused = false;
+        if !self.execute_until(limit)? {
+            self.paused = true;
+            return Ok(RunState::Paused);
+        }
+        Ok(RunState::Done(self.finish()))
+    }
+}
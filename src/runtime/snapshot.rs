@@ -0,0 +1,43 @@
+use slab::Slab;
+
+use crate::core::Value;
+
+use super::{heap::Heap, upvalue::VMUpvalue, Symbols, VM};
+
+/// A copy of everything a program can change in a [`VM`], taken between
+/// programs by [`VM::snapshot`] and put back by [`VM::restore`].
+#[derive(Clone)]
+pub struct VmState {
+    stack: Vec<Value>,
+    heap: Heap,
+    symbols: Symbols,
+    globals: Vec<Option<Value>>,
+    upvalues: Slab<VMUpvalue>,
+}
+
+impl VM<'_> {
+    /// Copies the VM's state, such as its globals and the objects they refer to.
+    /// This should be called between programs, not from a native function.
+    ///
+    /// Functions and strings are shared with the copy rather than duplicated,
+    /// since they never change once created.
+    pub fn snapshot(&self) -> VmState {
+        VmState {
+            stack: self.stack.clone(),
+            heap: self.heap.clone(),
+            symbols: self.symbols.clone(),
+            globals: self.globals.clone(),
+            upvalues: self.upvalues.clone(),
+        }
+    }
+
+    /// Puts back the state taken by [`VM::snapshot`], undoing everything programs
+    /// have done since. Natives defined since are undone too.
+    pub fn restore(&mut self, state: VmState) {
+        self.stack = state.stack;
+        self.heap = state.heap;
+        self.symbols = state.symbols;
+        self.globals = state.globals;
+        self.upvalues = state.upvalues;
+    }
+}
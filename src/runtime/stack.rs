@@ -1,12 +1,19 @@
 use crate::core::Value;
 
-use super::VM;
+use super::{Return, VM};
 
 impl VM<'_> {
     /// Pushes a new value at the top of the stack
     #[inline]
     pub(crate) fn stack_push(&mut self, value: Value) {
         self.stack.push(value);
+        // The compiler's count of the stack a function needs must cover its pushes
+        let max_stack = self.frame.function.max_stack;
+        debug_assert!(
+            max_stack == 0 || self.stack.len() <= self.frame.fp + max_stack,
+            "{:?} pushed past its max stack of {max_stack}",
+            self.frame.function
+        );
     }
 
     /// Removes and returns the elemtn at the top of the stack
@@ -18,8 +25,10 @@ impl VM<'_> {
     /// Returns the `i`'th element from the top of the stack
     #[inline]
     pub(crate) fn stack_peek(&self, i: usize) -> Value {
-        let last = self.stack.len() - 1;
-        *self.stack.get(last - i).unwrap_or(&Value::nil())
+        match self.stack.len().checked_sub(i + 1) {
+            Some(index) => self.stack[index],
+            None => Value::nil(),
+        }
     }
 
     /// Returns the `i`th element from the bottom of the stack
@@ -29,18 +38,47 @@ impl VM<'_> {
         *self.stack.get(fp + i).unwrap_or(&Value::nil())
     }
 
+    /// Sets the `i`th element from the bottom of the stack
     #[inline]
-    pub(crate) fn stack_set(&mut self, i: usize, value: Value) {
+    pub(crate) fn stack_set(&mut self, i: usize, value: Value) -> Return {
         let fp = self.frame.fp;
-        self.stack[fp + i] = value;
+        match self.stack.get_mut(fp + i) {
+            Some(slot) => {
+                *slot = value;
+                Ok(())
+            }
+            None => Err(self.malformed("local slot out of range")),
+        }
+    }
+
+    /// Returns the values in the slots of the running frame, from its own slot
+    /// to the top of the stack. Each comes with the name of the local it holds,
+    /// if the function was compiled with debug symbols and a local is in scope
+    /// there, rather than a temporary value.
+    pub fn frame_locals(&self) -> Vec<(Option<&str>, Value)> {
+        let debug = self.frame.function.chunk.debug.as_ref();
+        let slots = self.stack.get(self.frame.fp..).unwrap_or_default();
+        slots
+            .iter()
+            .enumerate()
+            .map(|(slot, &value)| {
+                let name = debug.and_then(|debug| debug.local_name(slot, self.frame.ip));
+                (name, value)
+            })
+            .collect()
     }
 
-    /// Prints a dump of the stack
-    pub(crate) fn stack_dump(&self) {
-        eprint!("STACK     ");
-        for value in &self.stack {
-            eprint!("[ {} ]", self.format_value(value))
+    /// Formats the values in the running frame's slots, bottom first, on one
+    /// line, naming the locals where known
+    pub(crate) fn stack_dump(&self) -> String {
+        let mut dump = "STACK     ".to_string();
+        for (name, value) in self.frame_locals() {
+            let value = self.format_value(&value);
+            match name {
+                Some(name) => dump.push_str(&format!("[ {name} = {value} ]")),
+                None => dump.push_str(&format!("[ {value} ]")),
+            }
         }
-        eprintln!();
+        dump
     }
 }
@@ -0,0 +1,37 @@
+use crate::{
+    bytecode::Compiler,
+    frontend::{Parser, Scanner},
+};
+
+use super::VM;
+
+/// The standard library, as (module name, Lox source) pairs, loaded in order.
+pub(crate) const MODULES: &[(&str, &str)] = &[
+    ("math", include_str!("../../stdlib/math.lox")),
+    ("string", include_str!("../../stdlib/string.lox")),
+];
+
+impl VM<'_> {
+    /// Compiles and runs every standard library module, defining its functions
+    /// as globals.
+    pub(crate) fn load_stdlib(&mut self) {
+        for (name, source) in MODULES {
+            let parser = Parser::new(source, Scanner::new(source));
+            let mut diagnostics = vec![];
+            let main = Compiler::new(self.symbols_mut())
+                .compile(source, parser, &mut diagnostics)
+                .unwrap_or_else(|errs| {
+                    panic!("stdlib module '{name}' failed to compile: {errs:?}")
+                });
+            debug_assert!(
+                diagnostics.is_empty(),
+                "stdlib module '{name}' has warnings: {diagnostics:?}"
+            );
+
+            let frame = self.load(main);
+            if let Err(e) = self.run(frame) {
+                panic!("stdlib module '{name}' failed to run: {e}");
+            }
+        }
+    }
+}
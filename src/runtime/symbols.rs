@@ -0,0 +1,61 @@
+use rustc_hash::FxHashMap;
+
+use crate::core::shared::Shared;
+
+/// Names of global variables, each assigned a dense id when first seen. The
+/// compiler encodes globals by id, so the VM can keep them in a vector indexed
+/// by id rather than hashing names at runtime. The compiler also numbers the
+/// functions it compiles here, so ids stay unique across the programs a VM runs.
+#[derive(Default, Clone)]
+pub struct Symbols {
+    ids: FxHashMap<Shared<str>, u32>,
+    names: Vec<Shared<str>>,
+    function_count: u32,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the id of `name`, assigning it the next free id if it has none.
+    pub fn intern(&mut self, name: &str) -> u32 {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+
+        let id = self.names.len() as u32;
+        let name: Shared<str> = Shared::from(name);
+        self.ids.insert(name.clone(), id);
+        self.names.push(name);
+        id
+    }
+
+    /// Returns the id of `name`, if it has one.
+    pub fn lookup(&self, name: &str) -> Option<u32> {
+        self.ids.get(name).copied()
+    }
+
+    /// Returns the name with the id `id`.
+    pub fn name(&self, id: u32) -> Option<&str> {
+        self.names.get(id as usize).map(|name| &**name)
+    }
+
+    /// Returns the number of names with an id.
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Returns the id of a newly compiled function, see [`Function::id`].
+    ///
+    /// [`Function::id`]: crate::object::Function::id
+    pub fn next_function_id(&mut self) -> u32 {
+        let id = self.function_count;
+        self.function_count += 1;
+        id
+    }
+}
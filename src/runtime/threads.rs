@@ -0,0 +1,329 @@
+//! Natives for running Lox functions on other threads. Each spawned function
+//! runs in a VM of its own, sharing nothing with the VM that spawned it, and
+//! the threads talk through channels that carry copies of plain values.
+
+use std::{
+    collections::VecDeque,
+    io,
+    sync::{Condvar, Mutex},
+    thread::JoinHandle,
+};
+
+use crate::{
+    core::{
+        errors::{InterpretError, RuntimeError},
+        shared::Shared,
+        Value,
+    },
+    object::{native::Native, Object},
+};
+
+use super::{upvalue::VMUpvalue, Heap, VM};
+
+/// A value copied out of one heap to be put in another, see
+/// [`Message::from_value`].
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Message {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(String),
+}
+
+impl Message {
+    /// Copies `value` out of `heap`, if it is nil, a boolean, a number, or a
+    /// string. Other objects refer to the VM they were made in.
+    pub(crate) fn from_value(value: Value, heap: &Heap) -> Option<Message> {
+        if value.is_nil() {
+            Some(Message::Nil)
+        } else if value.is_boolean() {
+            Some(Message::Bool(value.as_boolean()))
+        } else if value.is_number() {
+            Some(Message::Number(value.as_number()))
+        } else {
+            match heap.get_value(&value)? {
+                Object::String(s) => Some(Message::String(s.to_string())),
+                _ => None,
+            }
+        }
+    }
+
+    /// Puts the copied value in `heap`.
+    pub(crate) fn into_value(self, heap: &mut Heap) -> Value {
+        match self {
+            Message::Nil => Value::NIL,
+            Message::Bool(b) => Value::boolean(b),
+            Message::Number(n) => Value::whole_number(n),
+            Message::String(s) => heap.push_str(s).into(),
+        }
+    }
+
+    /// Copies `value` out of `heap`, or reports that it cannot be sent.
+    fn copy(value: Value, heap: &Heap, line: u32) -> Result<Message, RuntimeError> {
+        Message::from_value(value, heap)
+            .ok_or_else(|| RuntimeError::NotSendable(line, heap.format(&value)))
+    }
+}
+
+/// A queue of messages that any thread can send to or receive from.
+#[derive(Default)]
+struct Channel {
+    messages: Mutex<VecDeque<Message>>,
+    ready: Condvar,
+}
+
+/// A spawned thread, which finishes with what its function returned or the
+/// error that stopped it.
+type Thread = JoinHandle<Result<Message, String>>;
+
+/// The channels and threads of a VM and every VM spawned from it, numbered in
+/// the order they were made.
+#[derive(Default)]
+pub(crate) struct Threads {
+    channels: Mutex<Vec<Shared<Channel>>>,
+    /// The running threads, or `None` once joined
+    handles: Mutex<Vec<Option<Thread>>>,
+    /// The error that stopped the first spawned thread to fail
+    failure: Mutex<Option<String>>,
+}
+
+impl Threads {
+    fn channel(&self, id: Value) -> Result<Shared<Channel>, RuntimeError> {
+        let channels = self.channels.lock().unwrap();
+        index(id)
+            .and_then(|i| channels.get(i).cloned())
+            .ok_or_else(|| RuntimeError::OperandMismatch(0, "a channel".to_string()))
+    }
+
+    /// Records that a spawned thread stopped with `error`, and wakes every
+    /// `recv` waiting on a channel, since the thread may have been the one
+    /// that would have sent to it.
+    fn fail(&self, error: &str) {
+        self.failure
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| error.to_string());
+        let channels = self.channels.lock().unwrap().clone();
+        for channel in channels {
+            // Taking the lock makes sure a `recv` is either waiting or yet to
+            // see the failure
+            let _messages = channel.messages.lock().unwrap();
+            channel.ready.notify_all();
+        }
+    }
+}
+
+/// Reads `value` as an index into a list of channels or threads.
+fn index(value: Value) -> Option<usize> {
+    (value.is_number() && value.as_number().fract() == 0.0 && value.as_number() >= 0.0)
+        .then(|| value.as_number() as usize)
+}
+
+impl VM<'_> {
+    /// Defines the natives that share the VM's channels and threads.
+    pub(crate) fn define_thread_natives(&mut self) {
+        let threads = self.threads.clone();
+        self.define_native(NewChannel(threads.clone()));
+        self.define_native(ChannelSend(threads.clone()));
+        self.define_native(ChannelRecv(threads.clone()));
+        self.define_native(Join(threads));
+    }
+
+    /// Starts the function on top of the stack on another thread. The function
+    /// runs as the script of a new VM with the same options, writing to stdout,
+    /// and gets copies of the values it captured.
+    pub(crate) fn run_spawn(&mut self) -> Result<(), InterpretError> {
+        self.increment_ip(1);
+        let callee = self.stack_pop();
+
+        // Errors are reported at the call to `spawn`, not inside of it
+        let line = match &self.frame.caller {
+            Some(caller) => caller.function.chunk.get_line(caller.ip.saturating_sub(1)),
+            None => self.get_current_line(),
+        };
+
+        let closure = match self.heap.get_value(&callee) {
+            Some(Object::Closure(closure)) => closure,
+            _ => {
+                return Err(InterpretError::Runtime(RuntimeError::OperandMismatch(
+                    line,
+                    "a function".to_string(),
+                )));
+            }
+        };
+        let function = match self.heap.function(&closure.function) {
+            Some(function) if function.arity == 0 => function,
+            _ => {
+                return Err(InterpretError::Runtime(RuntimeError::OperandMismatch(
+                    line,
+                    "a function without parameters".to_string(),
+                )));
+            }
+        };
+
+        let mut captured = Vec::with_capacity(closure.upvalues.len());
+        for &upvalue in &closure.upvalues {
+            let value = match self.upvalues[upvalue] {
+                VMUpvalue::Open(slot) => self.stack[slot],
+                VMUpvalue::Closed(value) => value,
+            };
+            captured.push(Message::copy(value, &self.heap, line).map_err(InterpretError::Runtime)?);
+        }
+
+        // The spawned VM runs the function as its script, so it shares the
+        // symbol ids the function was compiled with
+        let mut function = function.unlinked();
+        function.returns_value = true;
+        let mut vm = VM::with_symbols(
+            Box::new(io::stdout()),
+            self.options.clone(),
+            self.symbols.clone(),
+        );
+        vm.threads = self.threads.clone();
+        vm.define_thread_natives();
+
+        let frame = vm.load(function);
+        for message in captured {
+            let value = message.into_value(&mut vm.heap);
+            let upvalue = vm.upvalues.insert(VMUpvalue::Closed(value));
+            if let Some(Object::Closure(closure)) = vm.heap.get_value_mut(&frame.closure) {
+                closure.upvalues.push(upvalue);
+            }
+        }
+
+        let threads = self.threads.clone();
+        let handle = std::thread::spawn(move || {
+            let result = match vm.run(frame) {
+                Ok(value) => {
+                    let value = value.unwrap_or(Value::NIL);
+                    Message::copy(value, &vm.heap, 0).map_err(|e| e.to_string())
+                }
+                Err(e) => Err(e.to_string()),
+            };
+            if let Err(error) = &result {
+                threads.fail(error);
+            }
+            result
+        });
+
+        let mut handles = self.threads.handles.lock().unwrap();
+        handles.push(Some(handle));
+        let id = Value::from_i64(handles.len() as i64 - 1);
+        drop(handles);
+        self.stack_push(id);
+        Ok(())
+    }
+}
+
+/// `channel()` makes a channel that threads pass values through with `send`
+/// and `recv`.
+pub struct NewChannel(Shared<Threads>);
+impl Native for NewChannel {
+    fn name(&self) -> &str {
+        "channel"
+    }
+
+    fn doc(&self) -> &str {
+        "Makes a channel for passing values between threads."
+    }
+
+    fn arity(&self) -> u8 {
+        0
+    }
+
+    fn call(&self, _heap: &mut Heap, _args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let mut channels = self.0.channels.lock().unwrap();
+        channels.push(Shared::new(Channel::default()));
+        Ok(Value::from_i64(channels.len() as i64 - 1))
+    }
+}
+
+/// `send(ch, value)` puts a copy of `value`, which must be nil, a boolean, a
+/// number, or a string, on the channel `ch`.
+pub struct ChannelSend(Shared<Threads>);
+impl Native for ChannelSend {
+    fn name(&self) -> &str {
+        "send"
+    }
+
+    fn doc(&self) -> &str {
+        "Sends a copy of a number, string, boolean, or nil over a channel."
+    }
+
+    fn arity(&self) -> u8 {
+        2
+    }
+
+    fn call(&self, heap: &mut Heap, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let channel = self.0.channel(args[0])?;
+        let message = Message::copy(args[1], heap, 0)?;
+        channel.messages.lock().unwrap().push_back(message);
+        channel.ready.notify_one();
+        Ok(Value::NIL)
+    }
+}
+
+/// `recv(ch)` takes the oldest value sent on the channel `ch`, waiting for
+/// one if there is none. Once a spawned thread has failed, waiting reports its
+/// error instead, as nothing may ever send the value.
+pub struct ChannelRecv(Shared<Threads>);
+impl Native for ChannelRecv {
+    fn name(&self) -> &str {
+        "recv"
+    }
+
+    fn doc(&self) -> &str {
+        "Takes the oldest value sent over a channel, waiting for one if needed."
+    }
+
+    fn arity(&self) -> u8 {
+        1
+    }
+
+    fn call(&self, heap: &mut Heap, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let channel = self.0.channel(args[0])?;
+        let mut messages = channel.messages.lock().unwrap();
+        loop {
+            if let Some(message) = messages.pop_front() {
+                return Ok(message.into_value(heap));
+            }
+            if let Some(error) = self.0.failure.lock().unwrap().clone() {
+                return Err(RuntimeError::ThreadFailed(0, error));
+            }
+            messages = channel.ready.wait(messages).unwrap();
+        }
+    }
+}
+
+/// `join(thread)` waits for a thread started by `spawn` to finish and returns
+/// a copy of what its function returned.
+pub struct Join(Shared<Threads>);
+impl Native for Join {
+    fn name(&self) -> &str {
+        "join"
+    }
+
+    fn doc(&self) -> &str {
+        "Waits for a spawned thread and returns what its function returned."
+    }
+
+    fn arity(&self) -> u8 {
+        1
+    }
+
+    fn call(&self, heap: &mut Heap, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let handle = index(args[0])
+            .and_then(|i| self.0.handles.lock().unwrap().get_mut(i)?.take())
+            .ok_or_else(|| {
+                RuntimeError::OperandMismatch(0, "a thread that has not been joined".to_string())
+            })?;
+        match handle.join() {
+            Ok(Ok(message)) => Ok(message.into_value(heap)),
+            Ok(Err(error)) => Err(RuntimeError::ThreadFailed(0, error)),
+            Err(_) => Err(RuntimeError::ThreadFailed(
+                0,
+                "the thread panicked".to_string(),
+            )),
+        }
+    }
+}
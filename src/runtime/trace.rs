@@ -0,0 +1,87 @@
+use std::io::Write;
+
+use crate::core::Value;
+
+use super::VM;
+
+/// What a [`VM`] writes to its error writer while running, set with
+/// [`VM::set_trace`]. Nothing is traced by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TraceFlags {
+    /// Disassemble each instruction before it runs
+    pub instructions: bool,
+    /// Dump the running frame's slots before each instruction
+    pub stack: bool,
+    /// Dump the heap before each instruction
+    pub heap: bool,
+    /// Dump the globals, sorted by name, before each instruction
+    pub globals: bool,
+    /// Report each function call and return
+    pub calls: bool,
+}
+
+impl TraceFlags {
+    /// Traces everything.
+    pub const ALL: Self = Self {
+        instructions: true,
+        stack: true,
+        heap: true,
+        globals: true,
+        calls: true,
+    };
+
+    /// Whether anything is traced.
+    pub fn any(&self) -> bool {
+        self.instructions || self.stack || self.heap || self.globals || self.calls
+    }
+}
+
+impl VM<'_> {
+    /// Sets what the VM traces from now on.
+    pub fn set_trace(&mut self, trace: TraceFlags) {
+        self.trace = trace;
+    }
+
+    /// Returns what the VM traces.
+    pub fn trace(&self) -> TraceFlags {
+        self.trace
+    }
+
+    /// Writes the traces taken before running the instruction at `ip`.
+    pub(crate) fn trace_instruction(&mut self, ip: usize) {
+        let mut out = String::new();
+        if self.trace.stack {
+            out.push_str(&self.stack_dump());
+            out.push('\n');
+        }
+        if self.trace.heap {
+            out.push_str(&self.heap.dump());
+        }
+        if self.trace.globals {
+            out.push_str(&self.dump_globals());
+        }
+        if self.trace.instructions {
+            let chunk = &self.frame.function.chunk;
+            chunk.disassemble_instruction(ip, self, &mut out);
+        }
+        // Tracing is best effort, a failed write should not stop the program
+        let _ = self.error_writer.write_all(out.as_bytes());
+    }
+
+    /// Writes the trace of a call to `callee` with `argc` arguments.
+    pub(crate) fn trace_call(&mut self, callee: &Value, argc: usize) {
+        if self.trace.calls {
+            let callee = self.format_value(callee);
+            let _ = writeln!(self.error_writer, "CALL      {callee} ({argc} args)");
+        }
+    }
+
+    /// Writes the trace of the current function returning `value`.
+    pub(crate) fn trace_return(&mut self, value: &Value) {
+        if self.trace.calls {
+            let name = self.frame.function.display_name().into_owned();
+            let value = self.format_value(value);
+            let _ = writeln!(self.error_writer, "RETURN    {name} -> {value}");
+        }
+    }
+}
@@ -1,18 +1,36 @@
-use crate::core::Value;
+use crate::{core::Value, object::Object};
 
 use super::VM;
 
+/// A variable captured by closures: on the stack while its scope is running,
+/// then holding its own value once the scope ends.
 #[derive(Debug, Clone, Copy)]
 pub enum VMUpvalue {
-    Open(usize),   // Index into stack
-    Closed(usize), // Index into heap
+    Open(usize), // Index into stack
+    Closed(Value),
 }
 
 impl VM<'_> {
+    /// Returns where in the VM's upvalues the upvalue `index` of the running
+    /// closure is.
+    pub(crate) fn closure_upvalue(&self, index: usize) -> Option<usize> {
+        match self.heap.get_value(&self.frame.closure)? {
+            Object::Closure(closure) => closure.upvalues.get(index).copied(),
+            _ => None,
+        }
+    }
+
+    /// Returns the upvalue `index` of the running closure.
+    pub(crate) fn upvalue_slot(&self, index: usize) -> Option<VMUpvalue> {
+        let slot = self.closure_upvalue(index)?;
+        self.upvalues.get(slot).copied()
+    }
+
     pub fn upvalue_get(&self, index: u8) -> Value {
-        match self.upvalues[self.frame.closure.upvalues[index as usize]] {
-            VMUpvalue::Open(index) => self.stack[index],
-            VMUpvalue::Closed(index) => Value::object(index),
+        match self.upvalue_slot(index as usize) {
+            Some(VMUpvalue::Open(index)) => self.stack.get(index).copied().unwrap_or(Value::NIL),
+            Some(VMUpvalue::Closed(value)) => value,
+            None => Value::NIL,
         }
     }
 }
@@ -1,24 +1,44 @@
-use std::{io::Write, rc::Rc};
+use std::{
+    fmt::Display,
+    io::{self, Write},
+};
 
-use rustc_hash::FxHashMap;
 use slab::Slab;
 
-use super::{frame::Frame, heap::Heap, upvalue::VMUpvalue, Return, FRAME_MAX, STACK_MAX, VM};
+use super::{
+    frame::Frame, heap::Heap, upvalue::VMUpvalue, GcStats, ProcessCapability, Return, Symbols,
+    TraceFlags, VmOptions, STACK_MAX, VM,
+};
+#[cfg(feature = "threads")]
+use crate::object::spawn_function;
 use crate::{
     bytecode::Chunk,
     core::{
         errors::{CompileError, InterpretError, PanicError, RuntimeError},
+        shared::{Shared, Writer},
         OpCode, Value,
     },
     object::{
-        native::{Clock, Sqrt},
-        Closure, Function, Object,
+        exit_function,
+        native::{
+            Arg, ArgCount, Assert, Chdir, Clock, ClockMono, CloneValue, Create, Cwd, EnvVar, Exec,
+            ExecErrors, ExecOutput, ExecResult, Gc, HasFeature, HeapDump, Native, NativeMeta, Pow,
+            Random, RandomInt, Seed, Sqrt, Status, Str, Type, Version, MATH, MATH_CONSTANTS,
+        },
+        resume_function, Closure, CoroutineStatus, Function, Iter, Object,
     },
 };
 
-/// Compares if
+#[cfg(feature = "threads")]
+use super::threads::Threads;
+
+/// Applies an arithmetic operator to the top two values. Given `$int`, which
+/// returns `None` when the result does not fit, two integers give an integer.
 macro_rules! binary_op {
     ($self:expr_2021, $op:tt) => {
+        binary_op!($self, $op, |_, _| None)
+    };
+    ($self:expr_2021, $op:tt, $int:expr_2021) => {
         {
             let right = $self.stack_pop();
             let left = $self.stack_pop();
@@ -30,7 +50,14 @@ macro_rules! binary_op {
                 )));
             }
 
-            let result = Value::number(left.as_number() $op right.as_number());
+            let int: fn(i32, i32) -> Option<i32> = $int;
+            let result = match (left.is_int() && right.is_int())
+                .then(|| int(left.as_int(), right.as_int()))
+                .flatten()
+            {
+                Some(n) => Value::int(n),
+                None => Value::number(left.as_number() $op right.as_number()),
+            };
             $self.stack_push(result);
             $self.increment_ip(1);
             Ok(())
@@ -38,22 +65,36 @@ macro_rules! binary_op {
     };
 }
 
-// For comparison operators that return boolean
+/// Multiplies two integers, unless the result is a negative zero, which only a
+/// float can hold.
+fn int_mul(a: i32, b: i32) -> Option<i32> {
+    a.checked_mul(b).filter(|&n| n != 0 || (a >= 0 && b >= 0))
+}
+
+// For comparison operators that return boolean. Strings compare lexicographically.
 macro_rules! compare_op {
     ($self:expr_2021, $op:tt) => {
         {
             let right = $self.stack_pop();
             let left = $self.stack_pop();
 
-            if !left.is_number() || !right.is_number() {
-                return Err(InterpretError::Runtime(RuntimeError::OperandMismatch(
-                    $self.get_current_line(),
-                    "numbers".to_string(),
-                )));
-            }
+            let result = if left.is_int() && right.is_int() {
+                left.as_int() $op right.as_int()
+            } else if left.is_number() && right.is_number() {
+                left.as_number() $op right.as_number()
+            } else {
+                match ($self.heap_get(&left), $self.heap_get(&right)) {
+                    (Some(Object::String(s1)), Some(Object::String(s2))) => &**s1 $op &**s2,
+                    _ => {
+                        return Err(InterpretError::Runtime(RuntimeError::OperandMismatch(
+                            $self.get_current_line(),
+                            "numbers or strings".to_string(),
+                        )));
+                    }
+                }
+            };
 
-            let result = Value::boolean(left.as_number() $op right.as_number());
-            $self.stack_push(result);
+            $self.stack_push(Value::boolean(result));
             $self.increment_ip(1);
             Ok(())
         }
@@ -61,30 +102,189 @@ macro_rules! compare_op {
 }
 
 impl<'a> VM<'a> {
-    pub fn new(writer: Box<dyn Write + 'a>) -> Self {
+    pub fn new(writer: Writer<'a>) -> Self {
+        Self::with_options(writer, VmOptions::default())
+    }
+
+    /// Creates a VM that writes program output to `writer`, configured by `options`.
+    pub fn with_options(writer: Writer<'a>, options: VmOptions) -> Self {
+        Self::with_symbols(writer, options, Symbols::new())
+    }
+
+    /// Creates a VM like [`VM::with_options`], with the globals and functions
+    /// of `symbols` numbered as they are there.
+    pub(crate) fn with_symbols(writer: Writer<'a>, options: VmOptions, symbols: Symbols) -> Self {
         let mut vm = Self {
-            frame: Frame::new(
-                Rc::new(Closure::new(Rc::new(Function::new("".to_string(), 0)), 0)),
-                0,
-            ),
+            frame: Frame::empty(0),
             frame_count: 1,
             stack: Vec::with_capacity(STACK_MAX),
             heap: Heap::new(),
-            globals: FxHashMap::default(),
+            symbols,
+            globals: Vec::new(),
             upvalues: Slab::new(),
             writer,
+            error_writer: Box::new(io::stderr()),
+            options,
+            coroutines: Vec::new(),
+            trace: TraceFlags::default(),
+            watched: Vec::new(),
+            watch_handler: None,
+            gc_stats: GcStats::default(),
+            instructions: 0,
+            paused: false,
+            #[cfg(feature = "tokio")]
+            in_async: false,
+            #[cfg(feature = "tokio")]
+            pending: None,
+            #[cfg(feature = "threads")]
+            threads: Shared::new(Threads::default()),
         };
+        vm.heap.set_next_gc(vm.options.gc_threshold);
 
         // Push native functions
-        vm.insert_native_fn("clock".to_string(), Object::Native(Rc::new(Clock)));
-        vm.insert_native_fn("sqrt".to_string(), Object::Native(Rc::new(Sqrt)));
+        let clock = vm.options.clock.clone();
+        vm.define_native(Clock(clock.clone()));
+        vm.define_native(ClockMono(clock));
+        let rng = Shared::new(vm.options.rng());
+        vm.define_native(Random(rng.clone()));
+        vm.define_native(RandomInt(rng.clone()));
+        vm.define_native(Seed(rng));
+        vm.define_native(Sqrt);
+        vm.define_native(Pow);
+        for &math in MATH {
+            vm.define_native(math);
+        }
+        for &(name, value) in MATH_CONSTANTS {
+            vm.define_global(name, Value::number(value));
+        }
+        vm.define_native(Assert);
+        vm.define_native(Type);
+        vm.define_native(Str);
+        vm.define_native(CloneValue);
+        vm.define_native(Create);
+        vm.define_native(Status);
+        vm.define_native(Version);
+        vm.define_native(HasFeature);
+        vm.define_native(Gc);
+
+        let args: Shared<[String]> = vm.options.args.clone().into();
+        vm.define_native(ArgCount(args.clone()));
+        vm.define_native(Arg(args));
+        vm.define_native(EnvVar(vm.options.env_access));
+        if vm.options.process >= ProcessCapability::CurrentDir {
+            vm.define_native(Cwd);
+            vm.define_native(Chdir);
+        }
+        if vm.options.process >= ProcessCapability::Full {
+            let result = ExecResult::default();
+            vm.define_native(Exec(result.clone()));
+            vm.define_native(ExecOutput(result.clone()));
+            vm.define_native(ExecErrors(result));
+        }
+        if vm.options.debug_natives {
+            vm.define_native(HeapDump);
+        }
+
+        let (resume, _) = vm.push_closure(resume_function());
+        vm.define_global("resume", resume);
+
+        let (exit, _) = vm.push_closure(exit_function());
+        vm.define_global("exit", exit);
+
+        #[cfg(feature = "threads")]
+        {
+            let (spawn, _) = vm.push_closure(spawn_function());
+            vm.define_global("spawn", spawn);
+            vm.define_thread_natives();
+        }
+
+        if vm.options.load_stdlib {
+            vm.load_stdlib();
+        }
         vm
     }
 
-    fn insert_native_fn(&mut self, name: String, native: Object) {
-        let name_idx = self.heap.push_str(name);
-        let native_idx = self.heap.push(native);
-        self.globals.insert(name_idx.bits, native_idx);
+    /// Registers `native` as a global function, callable from Lox code under
+    /// [`Native::name`]. Redefining an existing global replaces it.
+    pub fn define_native(&mut self, native: impl Native + 'static) {
+        let name = native.name().to_string();
+        let native = self.heap.push(Object::Native(Shared::new(native)));
+        self.define_global(&name, native.into());
+    }
+
+    /// Puts `function` in the heap along with a closure over it without
+    /// upvalues, returning the closure and the function.
+    fn push_closure(&mut self, function: Function) -> (Value, Shared<Function>) {
+        let function = Shared::new(function);
+        let function_idx = self.heap.push(Object::Function(function.clone()));
+        let closure = self
+            .heap
+            .push(Object::Closure(Closure::new(function_idx.into(), 0)));
+        (closure.into(), function)
+    }
+
+    /// Links `main`, the top-level function of a script compiled with the VM's
+    /// [symbols](Self::symbols_mut), into the heap, returning a frame that runs
+    /// it. See [`Chunk::link`](crate::bytecode::Chunk::link).
+    pub fn load(&mut self, mut main: Function) -> Frame {
+        main.chunk.link(&mut self.heap);
+        let (closure, function) = self.push_closure(main);
+        Frame::new(closure, function, 0)
+    }
+
+    fn define_global(&mut self, name: &str, value: Value) {
+        let symbol = self.symbols.intern(name);
+        self.global_set(symbol as usize, value);
+    }
+
+    /// Sets the global with the symbol id `symbol`, defining it if needed.
+    fn global_set(&mut self, symbol: usize, value: Value) {
+        if symbol >= self.globals.len() {
+            self.globals.resize(symbol + 1, None);
+        }
+        self.globals[symbol] = Some(value);
+    }
+
+    /// Returns the value of the global with the symbol id `symbol`, if it is defined.
+    fn global_get(&self, symbol: usize) -> Option<Value> {
+        self.globals.get(symbol).copied().flatten()
+    }
+
+    /// Calls the global function `name` with `args` and returns its result. This is
+    /// intended to be used after [`crate::interpret`] has defined the function.
+    pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Value, InterpretError> {
+        let symbol = self.symbols.lookup(name);
+        let callee = match symbol.and_then(|symbol| self.global_get(symbol as usize)) {
+            Some(v) => v,
+            None => {
+                return Err(InterpretError::Runtime(RuntimeError::NameError(
+                    0,
+                    name.to_string(),
+                )))
+            }
+        };
+
+        // Run the callee on top of an empty frame, so execution stops as soon as
+        // the callee returns to it.
+        let base = self.stack.len();
+        let frame_count = self.frame_count;
+        let host = std::mem::replace(&mut self.frame, Frame::empty(base));
+
+        self.stack_push(callee);
+        for arg in args {
+            self.stack_push(*arg);
+        }
+
+        let result = self
+            .call_value(callee, args.len())
+            .and_then(|_| self.execute());
+        let return_val = self.stack_pop();
+
+        self.frame = host;
+        self.frame_count = frame_count;
+        self.stack.truncate(base);
+
+        result.map(|_| return_val)
     }
 
     #[inline]
@@ -93,7 +293,7 @@ impl<'a> VM<'a> {
     }
 
     #[inline]
-    fn increment_ip(&mut self, offset: usize) {
+    pub(crate) fn increment_ip(&mut self, offset: usize) {
         self.frame.ip += offset;
     }
 
@@ -104,66 +304,177 @@ impl<'a> VM<'a> {
 
     #[inline]
     fn get_chunk(&self) -> &Chunk {
-        &self.frame.closure.function.chunk
+        &self.frame.function.chunk
     }
 
     #[inline]
     fn get_code_length(&self) -> usize {
-        self.frame.closure.function.chunk.code.len()
+        self.frame.function.chunk.code.len()
     }
 
     #[inline]
-    fn get_current_line(&self) -> u32 {
+    pub(crate) fn get_current_line(&self) -> u32 {
         let ip = self.get_ip();
         self.get_chunk().get_line(ip)
     }
 
-    pub(crate) fn format_value(&self, value: &Value) -> String {
-        if value.is_object() {
-            match self.heap_get(value) {
-                Some(object) => self.heap.format_value(object),
-                None => "nil".to_string(),
-            }
-        } else if value.is_number() {
-            format!("{}", value.as_number())
-        } else if value.is_boolean() {
-            format!("{}", value.as_boolean())
-        } else if value.is_nil() {
-            "nil".to_string()
-        } else {
-            panic!("Inavlid bit sequence for value");
+    /// Makes program output go to `writer` from now on, returning the previous writer.
+    pub fn set_writer(&mut self, writer: Writer<'a>) -> Writer<'a> {
+        std::mem::replace(&mut self.writer, writer)
+    }
+
+    /// Makes errors and warnings reported by [`crate::interpret`] go to `writer`
+    /// from now on, returning the previous writer. Defaults to stderr.
+    pub fn set_error_writer(&mut self, writer: Writer<'a>) -> Writer<'a> {
+        std::mem::replace(&mut self.error_writer, writer)
+    }
+
+    /// Writes an error or warning to the error writer.
+    pub(crate) fn report(&mut self, message: impl Display) {
+        // There is nowhere left to report a failure to write an error
+        let _ = writeln!(self.error_writer, "{message}");
+    }
+
+    /// Returns the options the VM was created with.
+    pub fn options(&self) -> &VmOptions {
+        &self.options
+    }
+
+    /// Describes the native functions currently defined as globals, sorted by name.
+    pub fn natives(&self) -> Vec<NativeMeta<'_>> {
+        let mut natives: Vec<_> = self
+            .globals
+            .iter()
+            .flatten()
+            .filter_map(|value| match self.heap_get(value) {
+                Some(Object::Native(native)) => Some(native.meta()),
+                _ => None,
+            })
+            .collect();
+        natives.sort_by_key(|meta| meta.name);
+        natives
+    }
+
+    /// Returns how many instructions the VM has run since it was created.
+    pub fn instructions_run(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Returns the globals currently defined in the VM with their values, sorted
+    /// by name, so listings of them do not depend on the order they were
+    /// defined in.
+    pub fn globals(&self) -> Vec<(&str, Value)> {
+        let mut globals: Vec<_> = (0..self.globals.len())
+            .filter_map(|symbol| {
+                let value = self.global_get(symbol)?;
+                Some((self.symbols.name(symbol as u32)?, value))
+            })
+            .collect();
+        globals.sort_by_key(|&(name, _)| name);
+        globals
+    }
+
+    /// Formats the globals, one per line as `name = value`, sorted by name.
+    pub fn dump_globals(&self) -> String {
+        let globals = self.globals();
+        let mut dump = format!("GLOBALS ({} defined)\n", globals.len());
+        for (name, value) in globals {
+            dump.push_str(&format!("{name:>14} = {}\n", self.format_value(&value)));
         }
+        dump
+    }
+
+    /// Returns the names of all globals currently defined in the VM, natives
+    /// included, in the order they were first defined.
+    pub fn global_names(&self) -> Vec<String> {
+        (0..self.globals.len())
+            .filter(|&symbol| self.global_get(symbol).is_some())
+            .filter_map(|symbol| self.symbols.name(symbol as u32))
+            .map(str::to_string)
+            .collect()
+    }
+
+    pub(crate) fn format_value(&self, value: &Value) -> String {
+        self.heap.format(value)
     }
 }
 
 // bytecode execution functions
 impl VM<'_> {
-    pub fn run(&mut self, frame: Frame) -> Return {
+    /// Runs `frame` as the top-level code of a script, returning the value of
+    /// its final expression statement, if it ends with one.
+    pub fn run(&mut self, frame: Frame) -> Result<Option<Value>, InterpretError> {
+        self.start(frame)?;
+        self.execute()?;
+        Ok(self.finish())
+    }
+
+    /// Sets the VM up to run `frame` as the top-level code of a script, on an
+    /// empty stack.
+    pub(crate) fn start(&mut self, frame: Frame) -> Return {
+        self.paused = false;
         self.frame = frame;
+        self.frame_count = 1;
+        self.stack.clear();
+        self.coroutines.clear();
         self.stack_push(Value::number(0.0));
+        self.reserve_stack(self.frame.function.max_stack)
+    }
+
+    /// Takes the return value of the script that has just finished running,
+    /// which is the value of its final expression statement, if it ends with one.
+    pub(crate) fn finish(&mut self) -> Option<Value> {
+        let value = self.stack_pop(); // pops the return value of the main function
+        self.frame.function.returns_value.then_some(value)
+    }
 
+    /// Executes instructions until the frame at the bottom of the call stack returns,
+    /// leaving its return value on the top of the stack.
+    fn execute(&mut self) -> Return {
+        self.execute_until(u64::MAX).map(|_| ())
+    }
+
+    /// Like [`VM::execute`], but stops before running the instruction that would
+    /// take [`VM::instructions_run`] past `limit`. Returns whether the bottom
+    /// frame finished.
+    pub(crate) fn execute_until(&mut self, limit: u64) -> Result<bool, InterpretError> {
         while self.get_ip() < self.get_code_length() {
+            if self.instructions >= limit {
+                return Ok(false);
+            }
+            #[cfg(feature = "tokio")]
+            if self.pending.is_some() {
+                return Ok(false);
+            }
+            self.maybe_collect();
+            self.instructions += 1;
             let ip = self.get_ip();
             let op = self.get_chunk().code[ip];
 
-            #[cfg(debug_assertions)]
-            {
-                eprint!("\n\x1b[38;5;248m");
-                self.stack_dump();
-                self.heap.dump();
-                self.get_chunk().disassemble_instruction(ip, self);
-                eprint!("\x1b[0m");
+            if self.trace.any() {
+                self.trace_instruction(ip);
             }
 
             match OpCode::try_from(op) {
-                Ok(OpCode::LoadConstant) => self.run_constant(1)?,
-                Ok(OpCode::LoadConstantLong) => self.run_constant(3)?,
+                Ok(op @ (OpCode::LoadConstant | OpCode::LoadConstantLong)) => {
+                    self.run_constant(op.operand_width())?
+                }
+                Ok(OpCode::Nil) => self.run_literal(Value::NIL)?,
+                Ok(OpCode::True) => self.run_literal(Value::TRUE)?,
+                Ok(OpCode::False) => self.run_literal(Value::FALSE)?,
+                Ok(op @ OpCode::LoadSmallInt) => self.run_small_int(op.operand_width())?,
                 Ok(OpCode::Negate) => self.run_negate()?,
                 Ok(OpCode::Not) => self.run_not()?,
+                Ok(OpCode::BitNot) => self.run_bit_not()?,
                 Ok(OpCode::Add) => self.run_add()?,
-                Ok(OpCode::Subtract) => binary_op!(self, -)?,
-                Ok(OpCode::Multiply) => binary_op!(self, *)?,
-                Ok(OpCode::Divide) => binary_op!(self, /)?,
+                Ok(OpCode::Subtract) => binary_op!(self, -, i32::checked_sub)?,
+                Ok(OpCode::Multiply) => binary_op!(self, *, int_mul)?,
+                Ok(OpCode::Divide) => self.run_divide()?,
+                Ok(OpCode::BitAnd) => self.run_bitwise(|a, b| a & b)?,
+                Ok(OpCode::BitOr) => self.run_bitwise(|a, b| a | b)?,
+                Ok(OpCode::BitXor) => self.run_bitwise(|a, b| a ^ b)?,
+                Ok(OpCode::ShiftLeft) => self.run_bitwise(|a, b| a.wrapping_shl(b as u32))?,
+                Ok(OpCode::ShiftRight) => self.run_bitwise(|a, b| a.wrapping_shr(b as u32))?,
                 Ok(OpCode::Equal) => self.run_equals(true)?,
                 Ok(OpCode::NotEqual) => self.run_equals(false)?,
                 Ok(OpCode::LessEqual) => compare_op!(self, <=)?,
@@ -172,57 +483,51 @@ impl VM<'_> {
                 Ok(OpCode::GreaterEqual) => compare_op!(self, >=)?,
                 Ok(OpCode::Print) => self.run_print()?,
                 Ok(OpCode::Pop) => self.run_pop()?,
-                Ok(OpCode::DefineGlobal) => self.run_define_global(1)?,
-                Ok(OpCode::DefineGlobalLong) => self.run_define_global(3)?,
-                Ok(OpCode::GetGlobal) => self.run_get_global(1)?,
-                Ok(OpCode::GetGlobalLong) => self.run_get_global(3)?,
-                Ok(OpCode::SetGlobal) => self.run_set_global(1)?,
-                Ok(OpCode::SetGlobalLong) => self.run_set_global(3)?,
-                Ok(OpCode::GetLocal) => self.run_get_local(1)?,
-                Ok(OpCode::GetLocalLong) => self.run_get_local(3)?,
-                Ok(OpCode::SetLocal) => self.run_set_local(1)?,
-                Ok(OpCode::SetLocalLong) => self.run_set_local(3)?,
-                Ok(OpCode::GetUpvalue) => {
-                    self.increment_ip(1);
-                    let index = self.read_operand(1);
-                    match self.upvalues[self.frame.closure.upvalues[index]] {
-                        VMUpvalue::Open(index) => {
-                            self.stack.push(self.stack[index]);
-                        }
-                        VMUpvalue::Closed(index) => {
-                            let actual_value = self.heap.get(&Value::object(index));
-                            match actual_value {
-                                Some(Object::UpValue(value)) => self.stack.push(*value),
-                                _ => {
-                                    panic!("PANIC!: value is not uvpalue")
-                                }
-                            }
-                        }
-                    }
+                Ok(op @ OpCode::PopN) => self.run_pop_n(op.operand_width())?,
+                Ok(op @ (OpCode::DefineGlobal | OpCode::DefineGlobalLong)) => {
+                    self.run_define_global(op.operand_width())?
                 }
-                Ok(OpCode::SetUpvalue) => {
-                    let value = self.stack_peek(0);
-                    self.increment_ip(1);
-                    let index = self.read_operand(1);
-                    match self.upvalues[self.frame.closure.upvalues[index]] {
-                        VMUpvalue::Open(index) => {
-                            self.stack[index] = value;
-                        }
-                        VMUpvalue::Closed(index) => {
-                            self.heap.set(index, value);
-                        }
-                    }
+                Ok(op @ (OpCode::GetGlobal | OpCode::GetGlobalLong)) => {
+                    self.run_get_global(op.operand_width())?
+                }
+                Ok(op @ (OpCode::SetGlobal | OpCode::SetGlobalLong)) => {
+                    self.run_set_global(op.operand_width())?
+                }
+                Ok(op @ (OpCode::GetLocal | OpCode::GetLocalLong)) => {
+                    self.run_get_local(op.operand_width())?
                 }
-                Ok(OpCode::JumpIfFalse) => self.run_jump_if()?,
-                Ok(OpCode::Jump) => self.run_jump()?,
-                Ok(OpCode::Loop) => self.run_loop()?,
-                Ok(OpCode::Call) => self.run_call()?,
-                Ok(OpCode::Closure) => self.run_closure(1)?,
-                Ok(OpCode::ClosureLong) => self.run_closure(3)?,
-                Ok(OpCode::CloseUpvalue) => self.run_upvalue()?,
+                Ok(op @ (OpCode::SetLocal | OpCode::SetLocalLong)) => {
+                    self.run_set_local(op.operand_width())?
+                }
+                Ok(op @ (OpCode::PopLocal | OpCode::PopLocalLong)) => {
+                    self.run_pop_local(op.operand_width())?
+                }
+                Ok(op @ OpCode::GetUpvalue) => self.run_get_upvalue(op.operand_width())?,
+                Ok(op @ OpCode::SetUpvalue) => self.run_set_upvalue(op.operand_width())?,
+                Ok(op @ (OpCode::JumpIfFalse | OpCode::JumpIfFalseLong)) => {
+                    self.run_jump_if(op.operand_width(), false)?
+                }
+                Ok(op @ (OpCode::JumpIfTrue | OpCode::JumpIfTrueLong)) => {
+                    self.run_jump_if(op.operand_width(), true)?
+                }
+                Ok(op @ (OpCode::Jump | OpCode::JumpLong)) => self.run_jump(op.operand_width())?,
+                Ok(op @ (OpCode::Loop | OpCode::LoopLong)) => self.run_loop(op.operand_width())?,
+                Ok(OpCode::IterNew) => self.run_iter_new()?,
+                Ok(op @ (OpCode::IterNext | OpCode::IterNextLong)) => {
+                    self.run_iter_next(op.operand_width())?
+                }
+                Ok(op @ OpCode::Call) => self.run_call(op.operand_width())?,
+                Ok(op @ (OpCode::Closure | OpCode::ClosureLong)) => {
+                    self.run_closure(op.operand_width())?
+                }
+                Ok(op @ OpCode::CloseUpvalue) => self.run_close_upvalue(op.operand_width())?,
+                Ok(OpCode::Yield) => self.run_yield()?,
+                Ok(OpCode::Resume) => self.run_resume()?,
+                Ok(OpCode::Exit) => self.run_exit()?,
+                Ok(OpCode::Spawn) => self.run_spawn()?,
                 Ok(OpCode::Return) => {
                     if self.run_return()? {
-                        return Ok(());
+                        return Ok(true);
                     }
                 }
                 Ok(OpCode::Nop) => self.increment_ip(1),
@@ -235,48 +540,74 @@ impl VM<'_> {
                 }
             }
         }
-        Ok(())
+        Ok(true)
     }
 
     /// Reads the operand at the current position of the internal `ip` counter.
-    /// If `long` is set to true, retrieves the next 3 bytes to form the operand, otherwise
-    /// only consumes the current byte. Advances the interal `ip` counter pass all the
+    /// Retrieves the next `operands` bytes, lowest byte first, to form the operand. Advances the interal `ip` counter pass all the
     /// bytes read.
-    fn read_operand(&mut self, operands: u8) -> usize {
+    fn read_operand(&mut self, operands: u8) -> Result<usize, InterpretError> {
         let ip = self.get_ip();
-        let code = &self.get_chunk().code;
-
-        if operands == 3 {
-            let low_byte = code[ip] as usize;
-            let mid_byte = code[ip + 1] as usize;
-            let high_byte = code[ip + 2] as usize;
-            self.increment_ip(3);
-            (high_byte << 16) | (mid_byte << 8) | low_byte
-        } else if operands == 2 {
-            let low_byte = code[ip] as usize;
-            let high_byte = code[ip + 1] as usize;
-            self.increment_ip(2);
-            (high_byte << 8) | low_byte
-        } else if operands == 1 {
-            let byte = code[ip] as usize;
-            self.increment_ip(1);
-            byte
-        } else {
-            panic!("<read_operand> only acepts 1, 2, or 3")
+        let width = operands as usize;
+
+        let Some(bytes) = self.get_chunk().code.get(ip..ip + width) else {
+            return Err(self.malformed("operand runs past the end of the chunk"));
+        };
+        let operand = bytes
+            .iter()
+            .rev()
+            .fold(0, |operand, &byte| (operand << 8) | byte as usize);
+
+        self.increment_ip(width);
+        Ok(operand)
+    }
+
+    /// Returns the constant at `index` in the current chunk's constant pool.
+    fn get_constant(&self, index: usize) -> Result<Value, InterpretError> {
+        match self.get_chunk().values().get(index) {
+            Some(constant) => Ok(*constant),
+            None => Err(self.malformed("constant index out of range")),
         }
     }
 
+    /// Returns the error for bytecode that the compiler would never produce.
+    pub(crate) fn malformed(&self, message: &str) -> InterpretError {
+        InterpretError::Panic(PanicError::MalformedBytecode(
+            self.get_current_line(),
+            message.to_string(),
+        ))
+    }
+
     fn run_constant(&mut self, operands: u8) -> Return {
         self.increment_ip(1);
-        let index = self.read_operand(operands);
-        let constant = self.get_chunk().constants[index];
+        let index = self.read_operand(operands)?;
+        let constant = self.get_constant(index)?;
         self.stack_push(constant);
         Ok(())
     }
 
+    #[inline]
+    fn run_literal(&mut self, value: Value) -> Return {
+        self.stack_push(value);
+        self.increment_ip(1);
+        Ok(())
+    }
+
+    fn run_small_int(&mut self, operands: u8) -> Return {
+        self.increment_ip(1);
+        let n = self.read_operand(operands)?;
+        self.stack_push(Value::small_int(n as u8));
+        Ok(())
+    }
+
     fn run_negate(&mut self) -> Return {
         let constant = self.stack_pop();
         match constant {
+            // -0 is a float
+            n if n.is_int() && n.as_int() != 0 => match n.as_int().checked_neg() {
+                Some(n) => self.stack_push(Value::int(n)),
+                None => self.stack_push(Value::number(-n.as_number())),
+            },
             n if n.is_number() => {
                 self.stack_push(Value::number(-n.as_number()));
             }
@@ -301,13 +632,82 @@ impl VM<'_> {
         Ok(())
     }
 
+    fn run_bit_not(&mut self) -> Return {
+        let value = self.stack_pop();
+        if !value.is_number() {
+            return Err(InterpretError::Runtime(RuntimeError::OperandMismatch(
+                self.get_current_line(),
+                "numbers".to_string(),
+            )));
+        }
+
+        self.stack_push(Value::from_i64(!(value.as_number() as i64)));
+        self.increment_ip(1);
+        Ok(())
+    }
+
+    /// Applies `op` to the integer parts of the top two values.
+    fn run_bitwise(&mut self, op: impl Fn(i64, i64) -> i64) -> Return {
+        let right = self.stack_pop();
+        let left = self.stack_pop();
+        if !left.is_number() || !right.is_number() {
+            return Err(InterpretError::Runtime(RuntimeError::OperandMismatch(
+                self.get_current_line(),
+                "numbers".to_string(),
+            )));
+        }
+
+        let result = op(left.as_number() as i64, right.as_number() as i64);
+        self.stack_push(Value::from_i64(result));
+        self.increment_ip(1);
+        Ok(())
+    }
+
+    /// Divides the top two values, which with `strict_math` fails if the
+    /// divisor is zero rather than giving infinity or NaN.
+    fn run_divide(&mut self) -> Return {
+        let (left, right) = (self.stack_peek(1), self.stack_peek(0));
+        if self.options.strict_math
+            && left.is_number()
+            && right.is_number()
+            && right.as_number() == 0.0
+        {
+            return Err(InterpretError::Runtime(RuntimeError::DivisionByZero(
+                self.get_current_line(),
+            )));
+        }
+        binary_op!(self, /)
+    }
+
     fn run_add(&mut self) -> Return {
         let right = self.stack_pop();
         let left = self.stack_pop();
         match (left, right) {
+            (n1, n2) if n1.is_int() && n2.is_int() => match n1.as_int().checked_add(n2.as_int()) {
+                Some(n) => self.stack_push(Value::int(n)),
+                None => self.stack_push(Value::number(n1.as_number() + n2.as_number())),
+            },
             (n1, n2) if n1.is_number() && n2.is_number() => {
                 self.stack_push(Value::number(n1.as_number() + n2.as_number()))
             }
+            (s1, s2) if s1.is_number() || s2.is_number() => {
+                let string = if s1.is_number() { s2 } else { s1 };
+                if !matches!(self.heap_get(&string), Some(Object::String(_))) {
+                    return Err(InterpretError::Runtime(RuntimeError::OperandMismatch(
+                        self.get_current_line(),
+                        "numbers or strings".to_string(),
+                    )));
+                }
+                if !self.options.string_coercion {
+                    return Err(InterpretError::Runtime(RuntimeError::StringNumberAdd(
+                        self.get_current_line(),
+                    )));
+                }
+
+                let s = format!("{}{}", self.format_value(&s1), self.format_value(&s2));
+                let value = self.heap.push_str(s);
+                self.stack_push(value.into());
+            }
             (s1, s2) if s1.is_object() && s2.is_object() => {
                 let s1 = self.heap_get(&s1);
                 let s2 = self.heap_get(&s2);
@@ -316,7 +716,7 @@ impl VM<'_> {
                     (Some(Object::String(s1)), Some(Object::String(s2))) => {
                         let s = format!("{s1}{s2}");
                         let value = self.heap.push_str(s);
-                        self.stack_push(value);
+                        self.stack_push(value.into());
                     }
                     _ => {
                         return Err(InterpretError::Runtime(RuntimeError::OperandMismatch(
@@ -349,24 +749,26 @@ impl VM<'_> {
         Ok(())
     }
 
-    fn get_variable_name(&mut self, name: &Value, ip: usize) -> Result<String, InterpretError> {
-        if name.is_object() {
-            match self.heap_get(name) {
-                Some(Object::String(s)) => Ok(s.to_string()),
-                _ => Err(InterpretError::Panic(PanicError::DeallocatedObject(
-                    self.get_chunk().get_line(ip),
-                ))),
-            }
-        } else {
-            Err(InterpretError::Panic(PanicError::NonObjectVariable(
-                self.get_chunk().get_line(ip),
-            )))
+    /// Returns the error for using the global with the symbol id `symbol`
+    /// before it is defined.
+    fn undefined_global(&self, symbol: usize) -> InterpretError {
+        match self.symbols.name(symbol as u32) {
+            Some(name) => InterpretError::Runtime(RuntimeError::NameError(
+                self.get_current_line(),
+                name.to_string(),
+            )),
+            None => self.malformed("global symbol id out of range"),
         }
     }
 
     fn run_print(&mut self) -> Return {
         let constant = self.stack_pop();
-        writeln!(self.writer, "{}", self.format_value(&constant)).unwrap();
+        writeln!(self.writer, "{}", self.format_value(&constant)).map_err(|e| {
+            InterpretError::Panic(PanicError::WriteFailed(
+                self.get_current_line(),
+                e.to_string(),
+            ))
+        })?;
         self.increment_ip(1);
         Ok(())
     }
@@ -377,138 +779,253 @@ impl VM<'_> {
         Ok(())
     }
 
+    fn run_pop_n(&mut self, operands: u8) -> Return {
+        self.increment_ip(1);
+        let count = self.read_operand(operands)?;
+        let len = self.stack.len().saturating_sub(count);
+        self.stack.truncate(len);
+        Ok(())
+    }
+
     fn run_define_global(&mut self, operands: u8) -> Return {
         let value = self.stack_pop();
 
         self.increment_ip(1);
-        let index = self.read_operand(operands);
-
-        let name_value = self.get_chunk().constants[index];
-        // let name = self.get_variable_name(&name_value, ip)?;
-
-        self.globals.insert(name_value.bits, value);
+        let symbol = self.read_operand(operands)?;
 
+        self.global_set(symbol, value);
         Ok(())
     }
 
     fn run_get_global(&mut self, operands: u8) -> Return {
-        let ip = self.get_ip();
         self.increment_ip(1);
-        let index = self.read_operand(operands);
-
-        let name_value = self.get_chunk().constants[index];
-
-        let value = self.globals.get(&name_value.bits);
-        match value {
-            Some(v) => {
-                self.stack_push(*v);
-            }
-            None => {
-                return Err(InterpretError::Runtime(RuntimeError::NameError(
-                    self.get_current_line(),
-                    self.get_variable_name(&name_value, ip)?,
-                )))
+        let symbol = self.read_operand(operands)?;
+
+        // The operand is already the global's slot, resolved by the compiler, so
+        // there is no lookup worth caching per instruction
+        match self.global_get(symbol) {
+            Some(value) => {
+                self.stack_push(value);
+                Ok(())
             }
+            None => Err(self.undefined_global(symbol)),
         }
-
-        Ok(())
     }
 
     fn run_set_global(&mut self, operands: u8) -> Return {
         let value = self.stack_peek(0);
 
-        let ip = self.get_ip();
         self.increment_ip(1);
-        let index = self.read_operand(operands);
-
-        let name_value = self.get_chunk().constants[index];
-
-        match self.globals.contains_key(&name_value.bits) {
-            true => {
-                self.globals.insert(name_value.bits, value);
-            }
-            false => {
-                return Err(InterpretError::Runtime(RuntimeError::NameError(
-                    self.get_current_line(),
-                    self.get_variable_name(&name_value, ip)?,
-                )));
-            }
-        }
+        let symbol = self.read_operand(operands)?;
 
-        Ok(())
+        let old = match self.globals.get_mut(symbol) {
+            Some(Some(global)) => std::mem::replace(global, value),
+            _ => return Err(self.undefined_global(symbol)),
+        };
+        self.check_watch(symbol, old, value)
     }
 
     fn run_get_local(&mut self, operands: u8) -> Return {
         self.increment_ip(1);
-        let index = self.read_operand(operands);
+        let index = self.read_operand(operands)?;
         self.stack_push(self.stack_get(index));
         Ok(())
     }
 
     fn run_set_local(&mut self, operands: u8) -> Return {
         self.increment_ip(1);
-        let index = self.read_operand(operands);
-        self.stack_set(index, self.stack_peek(0));
+        let index = self.read_operand(operands)?;
+        self.stack_set(index, self.stack_peek(0))?;
 
         Ok(())
     }
 
-    fn run_jump_if(&mut self) -> Return {
+    fn run_pop_local(&mut self, operands: u8) -> Return {
+        self.increment_ip(1);
+        let index = self.read_operand(operands)?;
+        let value = self.stack_pop();
+        self.stack_set(index, value)?;
+
+        Ok(())
+    }
+
+    fn run_get_upvalue(&mut self, operands: u8) -> Return {
+        self.increment_ip(1);
+        let index = self.read_operand(operands)?;
+
+        let value = match self.upvalue_slot(index) {
+            Some(VMUpvalue::Open(index)) => self.stack.get(index).copied(),
+            Some(VMUpvalue::Closed(value)) => Some(value),
+            None => None,
+        };
+
+        match value {
+            Some(value) => {
+                self.stack_push(value);
+                Ok(())
+            }
+            None => Err(self.malformed("upvalue does not refer to a captured variable")),
+        }
+    }
+
+    fn run_set_upvalue(&mut self, operands: u8) -> Return {
+        let value = self.stack_peek(0);
         self.increment_ip(1);
-        let jump_distance = self.read_operand(2);
+        let index = self.read_operand(operands)?;
+
+        let upvalue = self
+            .closure_upvalue(index)
+            .and_then(|slot| self.upvalues.get_mut(slot));
+        let slot = match upvalue {
+            Some(VMUpvalue::Open(index)) => self.stack.get_mut(*index),
+            Some(VMUpvalue::Closed(value)) => Some(value),
+            None => None,
+        };
+
+        match slot {
+            Some(slot) => {
+                *slot = value;
+                Ok(())
+            }
+            None => Err(self.malformed("upvalue does not refer to a captured variable")),
+        }
+    }
+
+    /// Jumps if the truthiness of the value on top of the stack is `when`.
+    fn run_jump_if(&mut self, operands: u8, when: bool) -> Return {
+        self.increment_ip(1);
+        let jump_distance = self.read_operand(operands)?;
         let condition = self.stack_peek(0);
 
-        if !condition.is_truthy() {
+        if condition.is_truthy() == when {
             self.increment_ip(jump_distance);
         }
 
         Ok(())
     }
 
-    fn run_jump(&mut self) -> Return {
+    fn run_jump(&mut self, operands: u8) -> Return {
         self.increment_ip(1);
-        let jump_distance = self.read_operand(2);
+        let jump_distance = self.read_operand(operands)?;
         self.increment_ip(jump_distance);
 
         Ok(())
     }
 
-    fn run_loop(&mut self) -> Return {
+    fn run_loop(&mut self, operands: u8) -> Return {
         self.increment_ip(1);
-        let jump_distance = self.read_operand(2);
+        let jump_distance = self.read_operand(operands)?;
+        if jump_distance > self.get_ip() {
+            return Err(self.malformed("loop jumps before the start of the chunk"));
+        }
         self.decrement_ip(jump_distance);
         Ok(())
     }
 
-    fn run_call(&mut self) -> Return {
+    fn run_iter_new(&mut self) -> Return {
+        let collection = self.stack_pop();
+
+        match self.heap_get(&collection) {
+            Some(Object::String(_)) => {
+                let iter = self.heap.push(Object::Iter(Iter::new(collection)));
+                self.stack_push(iter.into());
+            }
+            _ => {
+                return Err(InterpretError::Runtime(RuntimeError::NotIterable(
+                    self.get_current_line(),
+                    self.format_value(&collection),
+                )));
+            }
+        }
+
+        self.increment_ip(1);
+        Ok(())
+    }
+
+    fn run_iter_next(&mut self, operands: u8) -> Return {
         self.increment_ip(1);
-        let argc = self.read_operand(1);
+        let jump_distance = self.read_operand(operands)?;
+        let iter = self.stack_peek(0);
 
-        if self.frame_count >= FRAME_MAX {
+        let (target, position) = match self.heap_get(&iter) {
+            Some(Object::Iter(it)) => (it.target, it.position),
+            _ => {
+                return Err(InterpretError::Panic(PanicError::General(
+                    self.get_current_line(),
+                    "IterNext on non-iterator value.".to_string(),
+                )));
+            }
+        };
+
+        let next = match self.heap_get(&target) {
+            Some(Object::String(s)) => s.get(position..).and_then(|s| s.chars().next()),
+            _ => None,
+        };
+
+        match next {
+            Some(ch) => {
+                if let Some(Object::Iter(it)) = self.heap.get_value_mut(&iter) {
+                    it.position += ch.len_utf8();
+                }
+                let element = self.heap.push_str(ch.to_string());
+                self.stack_push(element.into());
+            }
+            None => self.increment_ip(jump_distance),
+        }
+
+        Ok(())
+    }
+
+    fn run_call(&mut self, operands: u8) -> Return {
+        self.increment_ip(1);
+        let argc = self.read_operand(operands)?;
+        let callee = self.stack_peek(argc);
+
+        self.call_value(callee, argc)
+    }
+
+    /// Makes room for the stack to grow to `needed` values, reporting a stack
+    /// overflow instead if that is more than `max_stack`.
+    fn reserve_stack(&mut self, needed: usize) -> Return {
+        if needed > self.options.max_stack {
             return Err(InterpretError::Runtime(RuntimeError::StackOverflow(
                 self.get_current_line(),
             )));
         }
+        self.stack.reserve(needed.saturating_sub(self.stack.len()));
+        Ok(())
+    }
 
-        let callee = self.stack_peek(argc);
+    /// Calls `callee` with the `argc` values on top of the stack as its arguments.
+    /// Closures get a new frame, while natives are run to completion immediately.
+    fn call_value(&mut self, callee: Value, argc: usize) -> Return {
+        if self.frame_count >= self.options.max_frames {
+            return Err(InterpretError::Runtime(RuntimeError::StackOverflow(
+                self.get_current_line(),
+            )));
+        }
+
+        self.trace_call(&callee, argc);
         if callee.is_object() {
             match &self.heap_get(&callee) {
                 Some(Object::Closure(c)) => {
-                    let closure = c.clone();
-                    if argc != closure.function.arity as usize {
+                    let Some(function) = self.heap.function(&c.function).cloned() else {
+                        return Err(self.malformed("closure does not refer to a function"));
+                    };
+                    if argc != function.arity as usize {
                         return Err(InterpretError::Runtime(
                             RuntimeError::FunctionCallArityMismatch(
                                 self.get_current_line(),
-                                closure.function.arity as usize,
+                                function.arity as usize,
                                 argc,
                             ),
                         ));
                     }
 
-                    let caller = std::mem::replace(
-                        &mut self.frame,
-                        Frame::new(closure, self.stack.len() - argc - 1),
-                    );
+                    let fp = self.stack.len() - argc - 1;
+                    self.reserve_stack(fp + function.max_stack)?;
+                    let caller =
+                        std::mem::replace(&mut self.frame, Frame::new(callee, function, fp));
 
                     self.frame.caller = Some(Box::new(caller));
                     self.frame_count += 1;
@@ -528,7 +1045,21 @@ impl VM<'_> {
 
                     let args = self.stack.split_off(self.stack.len() - argc);
                     self.stack_pop(); // pop function object
-                    let result = native.call(args).map_err(InterpretError::Runtime)?;
+                    #[cfg(feature = "tokio")]
+                    if let Some(native) = native.as_async() {
+                        if !self.in_async {
+                            return Err(InterpretError::Runtime(RuntimeError::AsyncNative(
+                                self.get_current_line(),
+                                native.name().to_string(),
+                            )));
+                        }
+                        // The result is pushed once `run_async` has awaited it
+                        self.pending = Some(native.call(&mut self.heap, args));
+                        return Ok(());
+                    }
+                    let result = native
+                        .call(&mut self.heap, args)
+                        .map_err(InterpretError::Runtime)?;
                     self.stack_push(result);
                 }
                 Some(_) => {
@@ -556,34 +1087,20 @@ impl VM<'_> {
     fn run_return(&mut self) -> Result<bool, InterpretError> {
         self.increment_ip(1);
         let return_val = self.stack_pop();
+        self.trace_return(&return_val);
 
         let new_stack_top = self.frame.fp;
         let caller = self.frame.caller.take();
 
-        let pred = |up: &VMUpvalue| {
-            if let VMUpvalue::Open(i) = up {
-                *i >= new_stack_top
-            } else {
-                false
-            }
-        };
-
-        let stack_indices_to_pop: Vec<usize> = self
-            .upvalues
-            .iter()
-            .filter_map(|(i, x)| if pred(x) { Some(i) } else { None })
-            .collect();
+        self.close_upvalues(new_stack_top);
 
-        for i in stack_indices_to_pop {
-            let up = self.upvalues[i];
-            if let VMUpvalue::Open(stack_index) = up {
-                if stack_index < self.stack.len() {
-                    let value_on_stack = self.stack[stack_index];
-                    let index = self.heap.push(Object::UpValue(value_on_stack));
-                    self.upvalues[i] = VMUpvalue::Closed(index.as_object());
-                }
-            } else {
-                panic!("THIS NOT SUPOSED TO HAPPEN")
+        // The entry frame of a coroutine is the only frame starting at its stack base
+        if let Some(&(base, coroutine)) = self.coroutines.last()
+            && base == new_stack_top
+        {
+            self.coroutines.pop();
+            if let Some(Object::Coroutine(co)) = self.heap.get_value_mut(&coroutine) {
+                co.status = CoroutineStatus::Dead;
             }
         }
 
@@ -593,7 +1110,8 @@ impl VM<'_> {
                 self.frame = *caller;
             }
             None => {
-                self.stack_pop(); // pops the function pointer
+                self.stack.truncate(new_stack_top);
+                self.stack_push(return_val);
                 return Ok(true);
             }
         }
@@ -605,19 +1123,17 @@ impl VM<'_> {
 
     fn run_closure(&mut self, operands: u8) -> Return {
         self.increment_ip(1);
-        let function_idx = self.read_operand(operands);
+        let function_idx = self.read_operand(operands)?;
 
-        let mut closure =
-            if let Some(Object::Function(function)) = self.heap_get(&Value::object(function_idx)) {
-                // compiler already checked that upvalue_count <= 256
-                Closure::new(function.clone(), function.upvalue_count as u8)
-            } else {
-                panic!("Attemping to create closure on non-function object.")
-            };
+        let function = self.get_constant(function_idx)?;
+        let mut closure = match self.heap_get(&function) {
+            Some(Object::Function(f)) => Closure::new(function, f.upvalue_count),
+            _ => return Err(self.malformed("closure operand is not a function")),
+        };
 
         for _ in 0..closure.upvalue_count {
-            let is_local = self.read_operand(1) != 0;
-            let rel_stack_index = self.read_operand(1);
+            let is_local = self.read_operand(1)? != 0;
+            let rel_stack_index = self.read_operand(1)?;
             let stack_index = rel_stack_index + self.frame.fp;
 
             if is_local {
@@ -637,40 +1153,216 @@ impl VM<'_> {
                     }
                 }
             } else {
-                closure
-                    .upvalues
-                    .push(self.frame.closure.upvalues[rel_stack_index])
+                match self.closure_upvalue(rel_stack_index) {
+                    Some(index) => closure.upvalues.push(index),
+                    None => return Err(self.malformed("upvalue index out of range")),
+                }
             }
         }
 
-        let closure_idx = self.heap.push(Object::Closure(Rc::new(closure)));
-        self.stack_push(closure_idx);
+        let closure_idx = self.heap.push(Object::Closure(closure));
+        self.stack_push(closure_idx.into());
+
+        Ok(())
+    }
+
+    fn run_close_upvalue(&mut self, operands: u8) -> Return {
+        self.increment_ip(1);
+        let slot = self.frame.fp + self.read_operand(operands)?;
+        if slot >= self.stack.len() {
+            return Err(self.malformed("no value to close over"));
+        }
 
+        self.close_upvalues(slot);
+        self.stack_pop();
         Ok(())
     }
 
-    fn run_upvalue(&mut self) -> Return {
+    /// Closes every open upvalue for a stack slot at or above `slot`, copying
+    /// the value out of the stack into the upvalue.
+    fn close_upvalues(&mut self, slot: usize) {
+        for (_, upvalue) in self.upvalues.iter_mut() {
+            if let VMUpvalue::Open(index) = *upvalue
+                && index >= slot
+                && let Some(&value) = self.stack.get(index)
+            {
+                *upvalue = VMUpvalue::Closed(value);
+            }
+        }
+    }
+
+    fn run_yield(&mut self) -> Return {
         self.increment_ip(1);
-        let stack_idx = self.stack.len() - 1;
-        let open_upvalue = self.stack_pop();
+        let value = self.stack_pop();
 
-        // Find the upvalue index
-        let mut upvalue_idx = None;
-        for (idx, upvalue) in self.upvalues.iter() {
-            if let VMUpvalue::Open(i) = *upvalue {
-                if i == stack_idx {
-                    upvalue_idx = Some(idx);
+        let Some((base, coroutine)) = self.coroutines.pop() else {
+            return Err(InterpretError::Runtime(
+                RuntimeError::YieldOutsideCoroutine(self.get_current_line()),
+            ));
+        };
+
+        // Detach the coroutine's frames, down to its entry frame, from the call stack
+        let mut frames = Vec::new();
+        let placeholder = Frame::new(self.frame.closure, self.frame.function.clone(), 0);
+        let mut frame = std::mem::replace(&mut self.frame, placeholder);
+        loop {
+            let caller = frame.caller.take();
+            let is_entry = frame.fp == base;
+            frame.fp -= base;
+            frames.push(frame);
+
+            match caller {
+                Some(caller) if is_entry => {
+                    self.frame = *caller;
                     break;
                 }
+                Some(caller) => frame = *caller,
+                None => {
+                    return Err(InterpretError::Panic(PanicError::General(
+                        self.get_current_line(),
+                        "Coroutine entry frame not found.".to_string(),
+                    )));
+                }
+            }
+        }
+        self.frame_count -= frames.len();
+
+        // Close over captured variables, since their stack slots are about to be moved
+        // out. They are reopened when the coroutine is resumed.
+        let mut upvalues = Vec::new();
+        for (index, upvalue) in self.upvalues.iter_mut() {
+            if let VMUpvalue::Open(stack_index) = *upvalue
+                && stack_index >= base
+            {
+                let value = self.stack.get(stack_index).copied().unwrap_or(Value::NIL);
+                *upvalue = VMUpvalue::Closed(value);
+                upvalues.push((index, stack_index - base));
+            }
+        }
+        let stack = self.stack.split_off(base);
+
+        if let Some(Object::Coroutine(co)) = self.heap.get_value_mut(&coroutine) {
+            co.status = CoroutineStatus::Suspended;
+            co.frames = frames;
+            co.stack = stack;
+            co.upvalues = upvalues;
+        }
+
+        self.stack_push(value);
+        Ok(())
+    }
+
+    fn run_exit(&mut self) -> Return {
+        self.increment_ip(1);
+        let code = self.stack_pop();
+
+        if !code.is_number() || code.as_number() != code.as_number() as i32 as f64 {
+            // Errors are reported at the call to `exit`, not inside of it
+            let line = match &self.frame.caller {
+                Some(caller) => caller.function.chunk.get_line(caller.ip.saturating_sub(1)),
+                None => self.get_current_line(),
+            };
+            return Err(InterpretError::Runtime(RuntimeError::OperandMismatch(
+                line,
+                "an integer".to_string(),
+            )));
+        }
+
+        Err(InterpretError::Exit(code.as_number() as i32))
+    }
+
+    /// Without the `threads` feature there is no `spawn` function to run
+    /// `Spawn`, so it only turns up in hand-written bytecode.
+    #[cfg(not(feature = "threads"))]
+    fn run_spawn(&mut self) -> Return {
+        self.increment_ip(1);
+        Err(InterpretError::Compile(CompileError::InvalidOpCode(
+            self.get_current_line(),
+            OpCode::Spawn as u8,
+        )))
+    }
+
+    fn run_resume(&mut self) -> Return {
+        self.increment_ip(1);
+        let value = self.stack_pop();
+        let coroutine = self.stack_pop();
+
+        // Errors are reported at the call to `resume`, not inside of it
+        let line = match &self.frame.caller {
+            Some(caller) => caller.function.chunk.get_line(caller.ip.saturating_sub(1)),
+            None => self.get_current_line(),
+        };
+
+        let co = match self.heap.get_value_mut(&coroutine) {
+            Some(Object::Coroutine(co)) => co,
+            _ => {
+                return Err(InterpretError::Runtime(RuntimeError::OperandMismatch(
+                    line,
+                    "a coroutine".to_string(),
+                )));
+            }
+        };
+
+        if co.status != CoroutineStatus::Suspended {
+            return Err(InterpretError::Runtime(
+                RuntimeError::CoroutineNotResumable(line, co.status.name().to_string()),
+            ));
+        }
+
+        if self.frame_count + co.frames.len() >= self.options.max_frames
+            || self.stack.len() + co.stack.len() > self.options.max_stack
+        {
+            return Err(InterpretError::Runtime(RuntimeError::StackOverflow(line)));
+        }
+
+        co.status = CoroutineStatus::Running;
+        let base = self.stack.len();
+
+        if !co.started {
+            co.started = true;
+            let callee = co.function;
+            let arity = match self.heap.function(&callee) {
+                Some(function) => function.arity as usize,
+                None => 0,
+            };
+
+            self.coroutines.push((base, coroutine));
+            self.stack_push(callee);
+            if arity == 1 {
+                self.stack_push(value);
+            }
+            return self.call_value(callee, arity);
+        }
+
+        let frames = std::mem::take(&mut co.frames);
+        let stack = std::mem::take(&mut co.stack);
+        let upvalues = std::mem::take(&mut co.upvalues);
+
+        self.coroutines.push((base, coroutine));
+        self.stack.extend(stack);
+
+        for (index, rel_stack_index) in upvalues {
+            let Some(upvalue) = self.upvalues.get_mut(index) else {
+                continue;
+            };
+            if let VMUpvalue::Closed(value) = *upvalue
+                && let Some(slot) = self.stack.get_mut(base + rel_stack_index)
+            {
+                *slot = value;
             }
+            *upvalue = VMUpvalue::Open(base + rel_stack_index);
         }
 
-        // If we found a matching upvalue, close it
-        if let Some(idx) = upvalue_idx {
-            let heap_idx = self.heap.push(Object::UpValue(open_upvalue));
-            self.upvalues[idx] = VMUpvalue::Closed(heap_idx.as_object());
+        // Frames are stored innermost first, so the entry frame is linked in first
+        self.frame_count += frames.len();
+        for mut frame in frames.into_iter().rev() {
+            frame.fp += base;
+            let caller = std::mem::replace(&mut self.frame, frame);
+            self.frame.caller = Some(Box::new(caller));
         }
 
+        // The resumed value becomes the result of the suspended `yield` expression
+        self.stack_push(value);
         Ok(())
     }
 }
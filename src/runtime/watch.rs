@@ -0,0 +1,122 @@
+use std::fmt::Display;
+
+use crate::core::{
+    errors::{InterpretError, RuntimeError},
+    Value,
+};
+
+use super::VM;
+
+/// A write to a watched global, passed to the [`WatchHandler`] while the VM is
+/// paused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchHit {
+    /// Name of the global
+    pub name: String,
+    /// Line of the assignment
+    pub line: u32,
+    /// The global's value before the assignment, formatted as `print` would
+    pub old: String,
+    /// The value assigned
+    pub new: String,
+    /// The values in the slots of the frame that made the assignment, with
+    /// the names of the locals they hold where known, see [`VM::frame_locals`]
+    pub locals: Vec<(Option<String>, String)>,
+}
+
+impl Display for WatchHit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[line {}]: '{}' changed from {} to {}",
+            self.line, self.name, self.old, self.new
+        )
+    }
+}
+
+/// What a paused VM does once its [`WatchHandler`] returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchAction {
+    /// Carry on running the program
+    Continue,
+    /// Stop the program with a runtime error
+    Stop,
+}
+
+/// Called on every write to a watched global, set with [`VM::set_watch_handler`].
+#[cfg(not(feature = "send"))]
+pub type WatchHandler<'a> = Box<dyn FnMut(&WatchHit) -> WatchAction + 'a>;
+/// Called on every write to a watched global, set with [`VM::set_watch_handler`].
+#[cfg(feature = "send")]
+pub type WatchHandler<'a> = Box<dyn FnMut(&WatchHit) -> WatchAction + Send + 'a>;
+
+impl<'a> VM<'a> {
+    /// Pauses the program whenever it assigns to the global `name`, which need
+    /// not be defined yet.
+    pub fn watch(&mut self, name: &str) {
+        let symbol = self.symbols.intern(name);
+        if !self.watched.contains(&symbol) {
+            self.watched.push(symbol);
+        }
+    }
+
+    /// Stops watching the global `name`, returning whether it was watched.
+    pub fn unwatch(&mut self, name: &str) -> bool {
+        let Some(symbol) = self.symbols.lookup(name) else {
+            return false;
+        };
+        let watched = self.watched.len();
+        self.watched.retain(|&s| s != symbol);
+        self.watched.len() != watched
+    }
+
+    /// Makes `handler` decide what happens when a watched global is written,
+    /// returning the previous handler. Without one, writes are reported to the
+    /// error writer and the program carries on.
+    pub fn set_watch_handler(&mut self, handler: WatchHandler<'a>) -> Option<WatchHandler<'a>> {
+        self.watch_handler.replace(handler)
+    }
+
+    /// Pauses for a write of `new` over `old` to the global with the symbol id
+    /// `symbol`, if it is watched.
+    pub(crate) fn check_watch(
+        &mut self,
+        symbol: usize,
+        old: Value,
+        new: Value,
+    ) -> Result<(), InterpretError> {
+        if !self.watched.contains(&(symbol as u32)) {
+            return Ok(());
+        }
+
+        let hit = WatchHit {
+            name: self
+                .symbols
+                .name(symbol as u32)
+                .unwrap_or_default()
+                .to_string(),
+            line: self.get_current_line(),
+            old: self.format_value(&old),
+            new: self.format_value(&new),
+            locals: self
+                .frame_locals()
+                .into_iter()
+                .map(|(name, value)| (name.map(str::to_string), self.format_value(&value)))
+                .collect(),
+        };
+        let action = match self.watch_handler.as_mut() {
+            Some(handler) => handler(&hit),
+            None => {
+                self.report(&hit);
+                WatchAction::Continue
+            }
+        };
+
+        match action {
+            WatchAction::Continue => Ok(()),
+            WatchAction::Stop => Err(InterpretError::Runtime(RuntimeError::Stopped(
+                hit.line, hit.name,
+            ))),
+        }
+    }
+}
@@ -0,0 +1,550 @@
+use std::{cmp::Ordering, io::Write, rc::Rc};
+
+use crate::{
+    ast::{
+        expr::{Expr, ExprVisitor},
+        stmt::{MethodKind, Stmt, StmtVisitor},
+    },
+    core::{
+        errors::{InterpretError, PanicError, RuntimeError},
+        token::{Token, TokenType},
+    },
+    frontend::parse_number,
+};
+
+use super::{
+    value::{Env, TreeFunction, TreeValue},
+    TreeWalker,
+};
+
+/// Why execution of a statement stopped early.
+pub enum Unwind {
+    /// A `return` statement is leaving the current function
+    Return(TreeValue),
+    Error(InterpretError),
+}
+
+impl From<InterpretError> for Unwind {
+    fn from(error: InterpretError) -> Self {
+        Unwind::Error(error)
+    }
+}
+
+type Exec = Result<(), Unwind>;
+type Eval = Result<TreeValue, Unwind>;
+
+fn runtime_error(error: RuntimeError) -> Unwind {
+    Unwind::Error(InterpretError::Runtime(error))
+}
+
+impl TreeWalker<'_> {
+    /// Returns the text of `token` in the source code being run.
+    fn lexeme(&self, token: &Token) -> &str {
+        token.lexeme(&self.source)
+    }
+
+    /// Runs `statements` in `env`, restoring the current scope afterwards.
+    fn execute_in(&mut self, env: Rc<Env>, statements: Vec<Stmt>) -> Exec {
+        let previous = self.scope.replace(env);
+        let result = statements
+            .into_iter()
+            .try_for_each(|stmt| stmt.accept(self));
+        self.scope = previous;
+        result
+    }
+
+    /// Creates a scope nested in the current one.
+    fn nested_env(&self) -> Rc<Env> {
+        Env::new(self.scope.as_ref().map(Env::scope))
+    }
+
+    /// Declares every function declared directly in `statements`, parsed from
+    /// `source`, as `nil` in `env`, so that functions can refer to ones declared
+    /// later in the scope.
+    fn hoist_functions(env: &Env, source: &str, statements: &[Stmt]) {
+        for stmt in statements {
+            if let Stmt::DeclareFunc(id, _, _) = stmt {
+                env.declare(id.lexeme(source).to_string(), TreeValue::Nil);
+            }
+        }
+    }
+
+    fn call_value(&mut self, callee: TreeValue, args: Vec<TreeValue>, line: u32) -> Eval {
+        if self.frame_count >= self.options.max_frames {
+            return Err(runtime_error(RuntimeError::StackOverflow(line)));
+        }
+
+        match &callee {
+            TreeValue::Function(function) => {
+                let function = function.clone();
+                if args.len() != function.params.len() {
+                    return Err(runtime_error(RuntimeError::FunctionCallArityMismatch(
+                        line,
+                        function.params.len(),
+                        args.len(),
+                    )));
+                }
+
+                // [ <fn> ] [ arg1 ] [ arg2 ], as in a VM frame
+                let source = &function.source;
+                let env = Env::new(function.closure.clone());
+                env.declare(function.name.lexeme(source).to_string(), callee.clone());
+                for (param, arg) in function.params.iter().zip(args) {
+                    env.declare(param.lexeme(source).to_string(), arg);
+                }
+                Self::hoist_functions(&env, source, &function.body);
+
+                // The body may come from an earlier script than the caller
+                let caller_source = std::mem::replace(&mut self.source, source.clone());
+                self.frame_count += 1;
+                let result = self.execute_in(env, function.body.clone());
+                self.frame_count -= 1;
+                self.source = caller_source;
+
+                match result {
+                    Ok(()) => Ok(TreeValue::Nil),
+                    Err(Unwind::Return(value)) => Ok(value),
+                    Err(e) => Err(e),
+                }
+            }
+            TreeValue::Native(native) => {
+                if args.len() != native.arity() as usize {
+                    return Err(runtime_error(RuntimeError::FunctionCallArityMismatch(
+                        line,
+                        native.arity() as usize,
+                        args.len(),
+                    )));
+                }
+
+                let args = args.iter().map(|arg| self.export_value(arg)).collect();
+                let result = native.call(&mut self.heap, args).map_err(runtime_error)?;
+                Ok(self.import_value(result))
+            }
+            _ => Err(runtime_error(RuntimeError::InvalidCall(
+                line,
+                callee.to_string(),
+            ))),
+        }
+    }
+
+    fn number_operands(
+        left: &TreeValue,
+        right: &TreeValue,
+        line: u32,
+    ) -> Result<(f64, f64), Unwind> {
+        match (left, right) {
+            (TreeValue::Number(a), TreeValue::Number(b)) => Ok((*a, *b)),
+            _ => Err(runtime_error(RuntimeError::OperandMismatch(
+                line,
+                "numbers".to_string(),
+            ))),
+        }
+    }
+
+    /// Orders two numbers, or two strings lexicographically. `None` if either
+    /// number is NaN.
+    fn compare_operands(
+        left: &TreeValue,
+        right: &TreeValue,
+        line: u32,
+    ) -> Result<Option<Ordering>, Unwind> {
+        match (left, right) {
+            (TreeValue::Number(a), TreeValue::Number(b)) => Ok(a.partial_cmp(b)),
+            (TreeValue::String(a), TreeValue::String(b)) => Ok(Some(a.cmp(b))),
+            _ => Err(runtime_error(RuntimeError::OperandMismatch(
+                line,
+                "numbers or strings".to_string(),
+            ))),
+        }
+    }
+}
+
+impl StmtVisitor<Exec> for TreeWalker<'_> {
+    fn visit_print(&mut self, _token: Token, expr: Expr) -> Exec {
+        let value = expr.accept(self)?;
+        writeln!(self.writer, "{value}").unwrap();
+        Ok(())
+    }
+
+    fn visit_expr(&mut self, _token: Token, expr: Expr) -> Exec {
+        if self.options.strip && expr.is_assert_call(&self.source) {
+            return Ok(());
+        }
+        expr.accept(self)?;
+        Ok(())
+    }
+
+    fn visit_declare_var(&mut self, id: Token, expr: Option<Expr>) -> Exec {
+        let value = match expr {
+            Some(expr) => expr.accept(self)?,
+            None => TreeValue::Nil,
+        };
+
+        let name = self.lexeme(&id).to_string();
+        match &self.scope {
+            Some(env) => env.declare(name, value),
+            None => {
+                self.globals.insert(name, value);
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_block(&mut self, statements: Vec<Stmt>) -> Exec {
+        let env = self.nested_env();
+        Self::hoist_functions(&env, &self.source, &statements);
+        self.execute_in(env, statements)
+    }
+
+    fn visit_if(
+        &mut self,
+        _token: Token,
+        condition: Expr,
+        if_block: Stmt,
+        else_block: Option<Box<Stmt>>,
+    ) -> Exec {
+        // The `else if`s of a chain are tested in turn rather than nested
+        let (mut condition, mut if_block, mut else_block) = (condition, if_block, else_block);
+        loop {
+            if condition.accept(self)?.is_truthy() {
+                return if_block.accept(self);
+            }
+            match else_block.map(|else_block| *else_block) {
+                Some(Stmt::If(_, next_condition, next_block, next_else)) => {
+                    (condition, if_block, else_block) = (next_condition, *next_block, next_else);
+                }
+                Some(else_block) => return else_block.accept(self),
+                None => return Ok(()),
+            }
+        }
+    }
+
+    fn visit_while(&mut self, _token: Token, condition: Expr, while_block: Stmt) -> Exec {
+        while condition.clone().accept(self)?.is_truthy() {
+            while_block.clone().accept(self)?;
+        }
+        Ok(())
+    }
+
+    fn visit_do_while(&mut self, _token: Token, body: Stmt, condition: Expr) -> Exec {
+        loop {
+            body.clone().accept(self)?;
+            if !condition.clone().accept(self)?.is_truthy() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn visit_foreach(&mut self, token: Token, id: Token, collection: Expr, body: Stmt) -> Exec {
+        let collection = collection.accept(self)?;
+        let TreeValue::String(s) = &collection else {
+            return Err(runtime_error(RuntimeError::NotIterable(
+                token.line,
+                collection.to_string(),
+            )));
+        };
+
+        // Each iteration gets a fresh variable, so closures capture the current element
+        for ch in s.chars() {
+            let env = self.nested_env();
+            env.declare(
+                self.lexeme(&id).to_string(),
+                TreeValue::String(Rc::from(ch.to_string())),
+            );
+            self.execute_in(env, vec![body.clone()])?;
+        }
+        Ok(())
+    }
+
+    fn visit_declare_func(&mut self, id: Token, params: Vec<Token>, body: Vec<Stmt>) -> Exec {
+        let function = TreeValue::Function(Rc::new(TreeFunction {
+            name: id.clone(),
+            params,
+            body,
+            source: self.source.clone(),
+            closure: self.scope.as_ref().map(Env::scope),
+        }));
+
+        let name = self.lexeme(&id).to_string();
+        match &self.scope {
+            // Fill in the variable declared when the scope was entered
+            Some(env) => match env.lookup(&name) {
+                Some(slot) => *slot.borrow_mut() = function,
+                None => env.declare(name, function),
+            },
+            None => {
+                self.globals.insert(name, function);
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_return(&mut self, _token: Token, expr: Expr) -> Exec {
+        Err(Unwind::Return(expr.accept(self)?))
+    }
+
+    fn visit_declare_class(
+        &mut self,
+        _id: Token,
+        _parent: Option<Token>,
+        _methods: Vec<(MethodKind, Token, Vec<Token>, Vec<Stmt>)>,
+    ) -> Exec {
+        Err(Unwind::Error(InterpretError::UnImplemented))
+    }
+
+    fn visit_debug(&mut self, _pragma: Token, stmt: Stmt) -> Exec {
+        if self.options.strip {
+            return Ok(());
+        }
+        stmt.accept(self)
+    }
+
+    fn visit_error(&mut self, _token: Token) -> Exec {
+        // The parser has already reported the error
+        Ok(())
+    }
+}
+
+impl ExprVisitor<Eval> for TreeWalker<'_> {
+    fn visit_literal(&mut self, token: Token) -> Eval {
+        match token.token {
+            TokenType::Number => Ok(TreeValue::Number(
+                parse_number(self.lexeme(&token)).unwrap(),
+            )),
+            TokenType::String => Ok(TreeValue::String(Rc::from(
+                self.lexeme(&token).replace("\"", ""),
+            ))),
+            TokenType::True => Ok(TreeValue::Boolean(true)),
+            TokenType::False => Ok(TreeValue::Boolean(false)),
+            TokenType::Nil => Ok(TreeValue::Nil),
+            _ => Err(Unwind::Error(InterpretError::Panic(
+                PanicError::InvalidToken(
+                    token.line,
+                    token.token,
+                    "<treewalk.visit_literal>".to_string(),
+                ),
+            ))),
+        }
+    }
+
+    fn visit_unary(&mut self, operator: Token, expr: Expr) -> Eval {
+        let value = expr.accept(self)?;
+        match operator.token {
+            TokenType::Minus => match value {
+                TreeValue::Number(n) => Ok(TreeValue::Number(-n)),
+                _ => Err(runtime_error(RuntimeError::OperandMismatch(
+                    operator.line,
+                    "numbers".to_string(),
+                ))),
+            },
+            TokenType::Bang => Ok(TreeValue::Boolean(!value.is_truthy())),
+            TokenType::Tilde => match value {
+                TreeValue::Number(n) => Ok(TreeValue::Number(!(n as i64) as f64)),
+                _ => Err(runtime_error(RuntimeError::OperandMismatch(
+                    operator.line,
+                    "numbers".to_string(),
+                ))),
+            },
+            _ => Err(Unwind::Error(InterpretError::Panic(
+                PanicError::InvalidToken(
+                    operator.line,
+                    operator.token,
+                    "<treewalk.visit_unary>".to_string(),
+                ),
+            ))),
+        }
+    }
+
+    fn visit_binary(&mut self, operator: Token, left: Expr, right: Expr) -> Eval {
+        let left = left.accept(self)?;
+        let right = right.accept(self)?;
+        let line = operator.line;
+
+        let value = match operator.token {
+            TokenType::Plus => match (&left, &right) {
+                (TreeValue::Number(a), TreeValue::Number(b)) => TreeValue::Number(a + b),
+                (TreeValue::String(a), TreeValue::String(b)) => {
+                    TreeValue::String(Rc::from(format!("{a}{b}")))
+                }
+                (TreeValue::String(_), TreeValue::Number(_))
+                | (TreeValue::Number(_), TreeValue::String(_)) => {
+                    if !self.options.string_coercion {
+                        return Err(runtime_error(RuntimeError::StringNumberAdd(line)));
+                    }
+                    TreeValue::String(Rc::from(format!("{left}{right}")))
+                }
+                _ => {
+                    return Err(runtime_error(RuntimeError::OperandMismatch(
+                        line,
+                        "numbers or strings".to_string(),
+                    )))
+                }
+            },
+            TokenType::Minus => {
+                let (a, b) = Self::number_operands(&left, &right, line)?;
+                TreeValue::Number(a - b)
+            }
+            TokenType::Star => {
+                let (a, b) = Self::number_operands(&left, &right, line)?;
+                TreeValue::Number(a * b)
+            }
+            TokenType::Slash => {
+                let (a, b) = Self::number_operands(&left, &right, line)?;
+                if self.options.strict_math && b == 0.0 {
+                    return Err(runtime_error(RuntimeError::DivisionByZero(line)));
+                }
+                TreeValue::Number(a / b)
+            }
+            TokenType::Ampersand
+            | TokenType::Pipe
+            | TokenType::Caret
+            | TokenType::LessLess
+            | TokenType::GreaterGreater => {
+                let (a, b) = Self::number_operands(&left, &right, line)?;
+                let (a, b) = (a as i64, b as i64);
+                let result = match operator.token {
+                    TokenType::Ampersand => a & b,
+                    TokenType::Pipe => a | b,
+                    TokenType::Caret => a ^ b,
+                    TokenType::LessLess => a.wrapping_shl(b as u32),
+                    _ => a.wrapping_shr(b as u32),
+                };
+                TreeValue::Number(result as f64)
+            }
+            TokenType::EqualEqual => TreeValue::Boolean(left == right),
+            TokenType::BangEqual => TreeValue::Boolean(left != right),
+            TokenType::LessThan => {
+                let ordering = Self::compare_operands(&left, &right, line)?;
+                TreeValue::Boolean(matches!(ordering, Some(Ordering::Less)))
+            }
+            TokenType::LessEqual => {
+                let ordering = Self::compare_operands(&left, &right, line)?;
+                TreeValue::Boolean(matches!(ordering, Some(Ordering::Less | Ordering::Equal)))
+            }
+            TokenType::GreaterThan => {
+                let ordering = Self::compare_operands(&left, &right, line)?;
+                TreeValue::Boolean(matches!(ordering, Some(Ordering::Greater)))
+            }
+            TokenType::GreaterEqual => {
+                let ordering = Self::compare_operands(&left, &right, line)?;
+                TreeValue::Boolean(matches!(
+                    ordering,
+                    Some(Ordering::Greater | Ordering::Equal)
+                ))
+            }
+            _ => {
+                return Err(Unwind::Error(InterpretError::Panic(
+                    PanicError::InvalidToken(
+                        line,
+                        operator.token,
+                        "<treewalk.visit_binary>".to_string(),
+                    ),
+                )))
+            }
+        };
+
+        Ok(value)
+    }
+
+    fn visit_grouping(&mut self, expr: Expr) -> Eval {
+        expr.accept(self)
+    }
+
+    fn visit_variable(&mut self, id: Token) -> Eval {
+        let name = self.lexeme(&id);
+        if let Some(slot) = self.scope.as_ref().and_then(|env| env.lookup(name)) {
+            return Ok(slot.borrow().clone());
+        }
+
+        match self.globals.get(name) {
+            Some(value) => Ok(value.clone()),
+            None => Err(runtime_error(RuntimeError::NameError(
+                id.line,
+                name.to_string(),
+            ))),
+        }
+    }
+
+    fn visit_assignment(&mut self, id: Token, assignment: Expr) -> Eval {
+        let value = assignment.accept(self)?;
+
+        let name = id.lexeme(&self.source);
+        if let Some(slot) = self.scope.as_ref().and_then(|env| env.lookup(name)) {
+            *slot.borrow_mut() = value.clone();
+            return Ok(value);
+        }
+
+        match self.globals.get_mut(name) {
+            Some(global) => {
+                *global = value.clone();
+                Ok(value)
+            }
+            None => Err(runtime_error(RuntimeError::NameError(
+                id.line,
+                name.to_string(),
+            ))),
+        }
+    }
+
+    // Returns first false, or last value
+    fn visit_and(&mut self, _token: Token, left: Expr, right: Expr) -> Eval {
+        let left = left.accept(self)?;
+        if !left.is_truthy() {
+            return Ok(left);
+        }
+        right.accept(self)
+    }
+
+    // Returns first true, or last value
+    fn visit_or(&mut self, _token: Token, left: Expr, right: Expr) -> Eval {
+        let left = left.accept(self)?;
+        if left.is_truthy() {
+            return Ok(left);
+        }
+        right.accept(self)
+    }
+
+    fn visit_call(&mut self, callee: Expr, arguments: Vec<Expr>, closing: Token) -> Eval {
+        let callee = callee.accept(self)?;
+        let args = arguments
+            .into_iter()
+            .map(|arg| arg.accept(self))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.call_value(callee, args, closing.line)
+    }
+
+    fn visit_get(&mut self, _obj: Expr, _prop: Token) -> Eval {
+        Err(Unwind::Error(InterpretError::UnImplemented))
+    }
+
+    fn visit_set(&mut self, _obj: Expr, _prop: Token, _value: Expr) -> Eval {
+        Err(Unwind::Error(InterpretError::UnImplemented))
+    }
+
+    fn visit_this(&mut self, _token: Token) -> Eval {
+        Err(Unwind::Error(InterpretError::UnImplemented))
+    }
+
+    fn visit_super(&mut self, _super_token: Token, _prop: Token) -> Eval {
+        Err(Unwind::Error(InterpretError::UnImplemented))
+    }
+
+    fn visit_lambda(&mut self, token: Token, params: Vec<Token>, body: Vec<Stmt>) -> Eval {
+        Ok(TreeValue::Function(Rc::new(TreeFunction {
+            name: Token::synthetic(token.token, token.line, token.column),
+            params,
+            body,
+            source: self.source.clone(),
+            closure: self.scope.as_ref().map(Env::scope),
+        })))
+    }
+
+    // There are no coroutines to yield from
+    fn visit_yield(&mut self, token: Token, value: Expr) -> Eval {
+        value.accept(self)?;
+        Err(runtime_error(RuntimeError::YieldOutsideCoroutine(
+            token.line,
+        )))
+    }
+}
@@ -0,0 +1,237 @@
+//! A tree-walk interpreter that runs the syntax tree directly, as a reference
+//! for the bytecode compiler and VM. It rejects the same programs and follows
+//! the same scoping rules, but does not report warnings and has no coroutines.
+
+mod exec;
+mod resolver;
+mod value;
+
+use std::{
+    io::{self, Write},
+    rc::Rc,
+};
+
+use rustc_hash::FxHashMap;
+
+use crate::{
+    core::{
+        errors::{InterpretError, InterpretOutcome},
+        shared::{Shared, Writer},
+        Value,
+    },
+    frontend::{Parser, Scanner},
+    object::{
+        native::{
+            Arg, ArgCount, Assert, Chdir, Clock, ClockMono, CloneValue, Cwd, EnvVar, Exec,
+            ExecErrors, ExecOutput, ExecResult, Gc, HasFeature, Native, Pow, Random, RandomInt,
+            Seed, Sqrt, Str, Type, Version, MATH, MATH_CONSTANTS,
+        },
+        Closure, Function, Object,
+    },
+    runtime::{Heap, ProcessCapability, VmOptions, MODULES},
+};
+use exec::Unwind;
+use resolver::Resolver;
+use value::{Env, TreeValue};
+
+/// Runs Lox source code by walking its syntax tree.
+pub struct TreeWalker<'a> {
+    globals: FxHashMap<String, TreeValue>,
+    /// The innermost scope being executed, or `None` at the top level
+    scope: Option<Rc<Env>>,
+    /// The number of calls in progress, counting the top-level code
+    frame_count: usize,
+    /// Holds the arguments and results of native calls
+    heap: Heap,
+    /// The source code of the statements being run, which their tokens point into
+    source: Rc<str>,
+    writer: Writer<'a>,
+    error_writer: Writer<'a>,
+    options: VmOptions,
+}
+
+impl<'a> TreeWalker<'a> {
+    pub fn new(writer: Writer<'a>) -> Self {
+        Self::with_options(writer, VmOptions::default())
+    }
+
+    /// Creates an interpreter that writes program output to `writer`, configured
+    /// like a [`crate::VM`] by `options`.
+    pub fn with_options(writer: Writer<'a>, options: VmOptions) -> Self {
+        let mut walker = Self {
+            globals: FxHashMap::default(),
+            scope: None,
+            frame_count: 1,
+            heap: Heap::new(),
+            source: Rc::from(""),
+            writer,
+            error_writer: Box::new(io::stderr()),
+            options,
+        };
+
+        let clock = walker.options.clock.clone();
+        walker.define_native(Clock(clock.clone()));
+        walker.define_native(ClockMono(clock));
+        let rng = Shared::new(walker.options.rng());
+        walker.define_native(Random(rng.clone()));
+        walker.define_native(RandomInt(rng.clone()));
+        walker.define_native(Seed(rng));
+        walker.define_native(Sqrt);
+        walker.define_native(Pow);
+        for &math in MATH {
+            walker.define_native(math);
+        }
+        for &(name, value) in MATH_CONSTANTS {
+            walker
+                .globals
+                .insert(name.to_string(), TreeValue::Number(value));
+        }
+        walker.define_native(Assert);
+        walker.define_native(Type);
+        walker.define_native(Str);
+        walker.define_native(CloneValue);
+        walker.define_native(Version);
+        walker.define_native(HasFeature);
+        // Values are reference counted, so the collection `gc()` requests never
+        // happens, but scripts calling it run the same on both engines
+        walker.define_native(Gc);
+
+        let args: Shared<[String]> = walker.options.args.clone().into();
+        walker.define_native(ArgCount(args.clone()));
+        walker.define_native(Arg(args));
+        walker.define_native(EnvVar(walker.options.env_access));
+        if walker.options.process >= ProcessCapability::CurrentDir {
+            walker.define_native(Cwd);
+            walker.define_native(Chdir);
+        }
+        if walker.options.process >= ProcessCapability::Full {
+            let result = ExecResult::default();
+            walker.define_native(Exec(result.clone()));
+            walker.define_native(ExecOutput(result.clone()));
+            walker.define_native(ExecErrors(result));
+        }
+
+        if walker.options.load_stdlib {
+            for (name, source) in MODULES {
+                if let Err(e) = walker.interpret(source) {
+                    panic!("stdlib module '{name}' failed: {:?}", e.errors());
+                }
+            }
+        }
+        walker
+    }
+
+    /// Registers `native` as a global function, like [`crate::VM::define_native`].
+    pub fn define_native(&mut self, native: impl Native + 'static) {
+        self.globals.insert(
+            native.name().to_string(),
+            TreeValue::Native(Shared::new(native)),
+        );
+    }
+
+    /// Makes program output go to `writer` from now on, returning the previous writer.
+    pub fn set_writer(&mut self, writer: Writer<'a>) -> Writer<'a> {
+        std::mem::replace(&mut self.writer, writer)
+    }
+
+    /// Makes errors go to `writer` from now on, returning the previous writer.
+    /// Defaults to stderr.
+    pub fn set_error_writer(&mut self, writer: Writer<'a>) -> Writer<'a> {
+        std::mem::replace(&mut self.error_writer, writer)
+    }
+
+    /// Returns the options the interpreter was created with.
+    pub fn options(&self) -> &VmOptions {
+        &self.options
+    }
+
+    /// Parses and runs `source`, returning the errors that stopped it, if any.
+    pub fn interpret(&mut self, source: &str) -> Result<(), InterpretOutcome> {
+        let known_globals = self
+            .options
+            .strict_globals
+            .then(|| self.globals.keys().cloned().collect());
+        let mut resolver = Resolver::new(source, known_globals, self.options.strip);
+
+        let mut statements = vec![];
+        let mut errors = vec![];
+        let parser =
+            Parser::new(source, Scanner::new(source)).with_max_nesting(self.options.max_nesting);
+        for stmt in parser {
+            match stmt {
+                Ok(stmt) => {
+                    if let Err(e) = resolver.resolve(stmt.clone()) {
+                        errors.push(e);
+                    }
+                    statements.push(stmt);
+                }
+                Err(e) => {
+                    if matches!(e, InterpretError::Syntax(_)) {
+                        resolver.syntax_error();
+                    }
+                    errors.push(e);
+                }
+            }
+        }
+        errors.extend(resolver.check_globals());
+
+        if !errors.is_empty() {
+            return Err(InterpretOutcome::CompileError(errors));
+        }
+
+        self.scope = None;
+        self.frame_count = 1;
+        self.source = Rc::from(source);
+        for stmt in statements {
+            match stmt.accept(self) {
+                Ok(()) => {}
+                Err(Unwind::Error(e)) => return Err(InterpretOutcome::RuntimeError(e)),
+                // The resolver rejects top-level returns
+                Err(Unwind::Return(_)) => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes an error to the error writer.
+    pub(crate) fn report(&mut self, error: &InterpretError) {
+        writeln!(self.error_writer, "{error}").unwrap();
+    }
+
+    /// Converts a value to one natives understand, allocating strings in the heap.
+    /// Lox functions are passed as bodiless stand-ins, which natives can inspect
+    /// but not call.
+    fn export_value(&mut self, value: &TreeValue) -> Value {
+        match value {
+            TreeValue::Nil => Value::NIL,
+            TreeValue::Boolean(b) => Value::boolean(*b),
+            TreeValue::Number(n) => Value::number(*n),
+            TreeValue::String(s) => self.heap.push_str(s.to_string()).into(),
+            TreeValue::Function(f) => {
+                let name = f.name.lexeme(&f.source).to_string();
+                let mut function = Function::new(name, f.params.len() as u8);
+                function.line = f.name.line;
+                let function = self.heap.push(Object::Function(Shared::new(function)));
+                self.heap
+                    .push(Object::Closure(Closure::new(function.into(), 0)))
+                    .into()
+            }
+            TreeValue::Native(n) => self.heap.push(Object::Native(n.clone())).into(),
+        }
+    }
+
+    /// Converts the result of a native call back to a tree-walk value.
+    fn import_value(&self, value: Value) -> TreeValue {
+        if value.is_number() {
+            TreeValue::Number(value.as_number())
+        } else if value.is_boolean() {
+            TreeValue::Boolean(value.as_boolean())
+        } else {
+            match self.heap.get_value(&value) {
+                Some(Object::String(s)) => TreeValue::String(Rc::from(&**s)),
+                Some(Object::Native(n)) => TreeValue::Native(n.clone()),
+                _ => TreeValue::Nil,
+            }
+        }
+    }
+}
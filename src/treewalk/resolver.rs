@@ -0,0 +1,423 @@
+use crate::{
+    ast::{
+        expr::{Expr, ExprVisitor},
+        stmt::{MethodKind, Stmt, StmtVisitor},
+    },
+    core::{
+        errors::{CompileError, InterpretError},
+        token::Token,
+    },
+};
+
+type Return = Result<(), InterpretError>;
+
+/// The locals of a function being resolved, as (name, scope depth, initialized).
+struct FunctionScope {
+    is_main: bool,
+    scope_depth: usize,
+    locals: Vec<(String, usize, bool)>,
+}
+
+/// Finds the errors the bytecode compiler reports before a program runs, so the
+/// tree-walk interpreter rejects the same programs. Scoping mirrors the compiler:
+/// a name that is not a local when it is resolved is a global.
+pub struct Resolver<'a> {
+    /// The source code of the statements being resolved
+    source: &'a str,
+    /// Innermost last, starting with the top-level code
+    functions: Vec<FunctionScope>,
+    global_refs: Vec<(String, u32)>,
+    global_defs: Vec<String>,
+    known_globals: Option<Vec<String>>,
+    /// Whether `assert(...)` calls and `//@debug` statements are left out
+    strip: bool,
+    /// Whether the parser has reported a syntax error in the program
+    syntax_errors: bool,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(source: &'a str, known_globals: Option<Vec<String>>, strip: bool) -> Self {
+        Self {
+            source,
+            functions: vec![FunctionScope {
+                is_main: true,
+                scope_depth: 0,
+                locals: Vec::new(),
+            }],
+            global_refs: Vec::new(),
+            global_defs: Vec::new(),
+            known_globals,
+            strip,
+            syntax_errors: false,
+        }
+    }
+
+    /// Notes that the program has a syntax error, like the compiler does when
+    /// the parser reports one.
+    pub fn syntax_error(&mut self) {
+        self.syntax_errors = true;
+    }
+
+    /// Returns the text of `token` in the source code being resolved.
+    fn lexeme(&self, token: &Token) -> &'a str {
+        token.lexeme(self.source)
+    }
+
+    /// Resolves a top-level statement.
+    pub fn resolve(&mut self, statement: Stmt) -> Return {
+        let result = statement.accept(self);
+        // An error leaves scopes open, so start the next statement from the top level
+        self.functions.truncate(1);
+        self.functions[0].scope_depth = 0;
+        self.functions[0].locals.clear();
+        result
+    }
+
+    /// Reports the first use of every global that is never defined, if the
+    /// resolver was created with known globals.
+    pub fn check_globals(&self) -> Vec<InterpretError> {
+        let Some(known) = &self.known_globals else {
+            return vec![];
+        };
+
+        let mut refs = self.global_refs.clone();
+        refs.sort_by_key(|(_, line)| *line);
+
+        let mut reported: Vec<&str> = vec![];
+        let mut errors = vec![];
+        for (name, line) in &refs {
+            let defined = known.contains(name) || self.global_defs.contains(name);
+            if defined || reported.contains(&name.as_str()) {
+                continue;
+            }
+            reported.push(name);
+            errors.push(InterpretError::Compile(CompileError::UndefinedGlobal(
+                *line,
+                name.clone(),
+            )));
+        }
+        errors
+    }
+
+    fn current(&mut self) -> &mut FunctionScope {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let function = self.current();
+        function.scope_depth -= 1;
+        let depth = function.scope_depth;
+        function.locals.retain(|(_, d, _)| *d <= depth);
+    }
+
+    fn declare(&mut self, name: &str, line: u32) -> Return {
+        let function = self.current();
+        let depth = function.scope_depth;
+        if depth == 0 {
+            return Ok(());
+        }
+
+        if function
+            .locals
+            .iter()
+            .any(|(n, d, _)| *d == depth && n == name)
+        {
+            return Err(InterpretError::Compile(CompileError::AlreadyDeclared(
+                line,
+                name.to_string(),
+            )));
+        }
+
+        function.locals.push((name.to_string(), depth, false));
+        Ok(())
+    }
+
+    fn define(&mut self) {
+        let function = self.current();
+        if function.scope_depth == 0 {
+            return;
+        }
+        if let Some(local) = function.locals.last_mut() {
+            local.2 = true;
+        }
+    }
+
+    /// Declares every function declared directly in `statements`, like the
+    /// compiler's hoisting.
+    fn hoist_functions(&mut self, statements: &[Stmt]) -> Return {
+        if self.current().scope_depth == 0 {
+            return Ok(());
+        }
+
+        for stmt in statements {
+            if let Stmt::DeclareFunc(id, _, _) = stmt {
+                self.declare(self.lexeme(id), id.line)?;
+                self.define();
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves a use of `name`, recording it as a global if no function sees
+    /// a local with that name.
+    fn resolve_name(&mut self, name: &str, line: u32) -> Return {
+        for function in self.functions.iter().rev() {
+            if let Some((_, _, init)) = function.locals.iter().rfind(|(n, _, _)| n == name) {
+                if !init {
+                    return Err(InterpretError::Compile(CompileError::SelfInitialization(
+                        line,
+                    )));
+                }
+                return Ok(());
+            }
+        }
+
+        self.global_refs.push((name.to_string(), line));
+        Ok(())
+    }
+}
+
+impl StmtVisitor<Return> for Resolver<'_> {
+    fn visit_print(&mut self, _token: Token, expr: Expr) -> Return {
+        expr.accept(self)
+    }
+
+    fn visit_expr(&mut self, _token: Token, expr: Expr) -> Return {
+        if self.strip && expr.is_assert_call(self.source) {
+            return Ok(());
+        }
+        expr.accept(self)
+    }
+
+    fn visit_declare_var(&mut self, id: Token, expr: Option<Expr>) -> Return {
+        self.declare(self.lexeme(&id), id.line)?;
+        if let Some(expr) = expr {
+            expr.accept(self)?;
+        }
+
+        if self.current().scope_depth == 0 {
+            self.global_defs.push(self.lexeme(&id).to_string());
+        }
+        self.define();
+        Ok(())
+    }
+
+    fn visit_block(&mut self, statements: Vec<Stmt>) -> Return {
+        self.begin_scope();
+        self.hoist_functions(&statements)?;
+        for stmt in statements {
+            stmt.accept(self)?;
+        }
+        self.end_scope();
+        Ok(())
+    }
+
+    fn visit_if(
+        &mut self,
+        _token: Token,
+        condition: Expr,
+        if_block: Stmt,
+        else_block: Option<Box<Stmt>>,
+    ) -> Return {
+        // The `else if`s of a chain are resolved in turn rather than nested
+        let (mut condition, mut if_block, mut else_block) = (condition, if_block, else_block);
+        loop {
+            condition.accept(self)?;
+            if_block.accept(self)?;
+            match else_block.map(|else_block| *else_block) {
+                Some(Stmt::If(_, next_condition, next_block, next_else)) => {
+                    (condition, if_block, else_block) = (next_condition, *next_block, next_else);
+                }
+                Some(else_block) => return else_block.accept(self),
+                None => return Ok(()),
+            }
+        }
+    }
+
+    fn visit_while(&mut self, _token: Token, condition: Expr, while_block: Stmt) -> Return {
+        condition.accept(self)?;
+        while_block.accept(self)
+    }
+
+    fn visit_do_while(&mut self, _token: Token, body: Stmt, condition: Expr) -> Return {
+        body.accept(self)?;
+        condition.accept(self)
+    }
+
+    fn visit_foreach(&mut self, token: Token, id: Token, collection: Expr, body: Stmt) -> Return {
+        self.begin_scope();
+        collection.accept(self)?;
+        self.declare("for in", token.line)?;
+        self.define();
+
+        self.begin_scope();
+        self.declare(self.lexeme(&id), id.line)?;
+        self.define();
+        body.accept(self)?;
+        self.end_scope();
+
+        self.end_scope();
+        Ok(())
+    }
+
+    fn visit_declare_func(&mut self, id: Token, params: Vec<Token>, body: Vec<Stmt>) -> Return {
+        let name = self.lexeme(&id);
+        let function = self.current();
+        let depth = function.scope_depth;
+        let hoisted = depth > 0
+            && function
+                .locals
+                .iter()
+                .any(|(n, d, _)| *d == depth && n == name);
+        if !hoisted {
+            self.declare(name, id.line)?;
+        }
+
+        self.functions.push(FunctionScope {
+            is_main: false,
+            scope_depth: 1,
+            locals: vec![(name.to_string(), 1, true)],
+        });
+        for param in params {
+            self.declare(self.lexeme(&param), param.line)?;
+            self.define();
+        }
+        self.hoist_functions(&body)?;
+        for stmt in body {
+            stmt.accept(self)?;
+        }
+        self.functions.pop();
+
+        if self.current().scope_depth == 0 {
+            self.global_defs.push(name.to_string());
+        }
+        if !hoisted {
+            self.define();
+        }
+        Ok(())
+    }
+
+    fn visit_return(&mut self, token: Token, expr: Expr) -> Return {
+        if self.current().is_main {
+            return Err(InterpretError::Compile(CompileError::TopReturn(token.line)));
+        }
+        expr.accept(self)
+    }
+
+    fn visit_declare_class(
+        &mut self,
+        _id: Token,
+        _parent: Option<Token>,
+        _methods: Vec<(MethodKind, Token, Vec<Token>, Vec<Stmt>)>,
+    ) -> Return {
+        // Like the compiler, only report that classes are not implemented for
+        // programs without syntax errors
+        if self.syntax_errors {
+            return Ok(());
+        }
+        Err(InterpretError::UnImplemented)
+    }
+
+    fn visit_debug(&mut self, _pragma: Token, stmt: Stmt) -> Return {
+        if self.strip {
+            return Ok(());
+        }
+        stmt.accept(self)
+    }
+
+    fn visit_error(&mut self, _token: Token) -> Return {
+        // The parser has already reported the error
+        Ok(())
+    }
+}
+
+impl ExprVisitor<Return> for Resolver<'_> {
+    fn visit_literal(&mut self, _token: Token) -> Return {
+        Ok(())
+    }
+
+    fn visit_unary(&mut self, _operator: Token, expr: Expr) -> Return {
+        expr.accept(self)
+    }
+
+    fn visit_binary(&mut self, _operator: Token, left: Expr, right: Expr) -> Return {
+        left.accept(self)?;
+        right.accept(self)
+    }
+
+    fn visit_grouping(&mut self, expr: Expr) -> Return {
+        expr.accept(self)
+    }
+
+    fn visit_variable(&mut self, id: Token) -> Return {
+        self.resolve_name(self.lexeme(&id), id.line)
+    }
+
+    fn visit_assignment(&mut self, id: Token, assignment: Expr) -> Return {
+        assignment.accept(self)?;
+        self.resolve_name(self.lexeme(&id), id.line)
+    }
+
+    fn visit_and(&mut self, _token: Token, left: Expr, right: Expr) -> Return {
+        left.accept(self)?;
+        right.accept(self)
+    }
+
+    fn visit_or(&mut self, _token: Token, left: Expr, right: Expr) -> Return {
+        left.accept(self)?;
+        right.accept(self)
+    }
+
+    fn visit_call(&mut self, callee: Expr, arguments: Vec<Expr>, _closing: Token) -> Return {
+        callee.accept(self)?;
+        for arg in arguments {
+            arg.accept(self)?;
+        }
+        Ok(())
+    }
+
+    fn visit_get(&mut self, _obj: Expr, _prop: Token) -> Return {
+        Err(InterpretError::UnImplemented)
+    }
+
+    fn visit_set(&mut self, _obj: Expr, _prop: Token, _value: Expr) -> Return {
+        Err(InterpretError::UnImplemented)
+    }
+
+    fn visit_this(&mut self, _token: Token) -> Return {
+        Err(InterpretError::UnImplemented)
+    }
+
+    fn visit_super(&mut self, _super_token: Token, _prop: Token) -> Return {
+        Err(InterpretError::UnImplemented)
+    }
+
+    fn visit_lambda(&mut self, _token: Token, params: Vec<Token>, body: Vec<Stmt>) -> Return {
+        self.functions.push(FunctionScope {
+            is_main: false,
+            scope_depth: 1,
+            locals: vec![(String::new(), 1, true)],
+        });
+        for param in params {
+            self.declare(self.lexeme(&param), param.line)?;
+            self.define();
+        }
+        self.hoist_functions(&body)?;
+        for stmt in body {
+            stmt.accept(self)?;
+        }
+        self.functions.pop();
+        Ok(())
+    }
+
+    fn visit_yield(&mut self, token: Token, value: Expr) -> Return {
+        if self.current().is_main {
+            return Err(InterpretError::Compile(CompileError::TopYield(token.line)));
+        }
+        value.accept(self)
+    }
+}
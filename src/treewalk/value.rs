@@ -0,0 +1,138 @@
+use std::{borrow::Cow, cell::RefCell, fmt, rc::Rc};
+
+use crate::{
+    ast::stmt::Stmt,
+    core::{format_number, shared::Shared, token::Token},
+    object::native::Native,
+};
+
+/// A value as seen by the tree-walk interpreter.
+#[derive(Clone)]
+pub enum TreeValue {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(Rc<str>),
+    Function(Rc<TreeFunction>),
+    Native(Shared<dyn Native>),
+}
+
+impl TreeValue {
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, TreeValue::Nil | TreeValue::Boolean(false))
+    }
+}
+
+impl PartialEq for TreeValue {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (TreeValue::Nil, TreeValue::Nil) => true,
+            (TreeValue::Boolean(a), TreeValue::Boolean(b)) => a == b,
+            // Compared by bits, like the VM's values, which store every NaN alike
+            (TreeValue::Number(a), TreeValue::Number(b)) => {
+                a.to_bits() == b.to_bits() || (a.is_nan() && b.is_nan())
+            }
+            (TreeValue::String(a), TreeValue::String(b)) => a == b,
+            (TreeValue::Function(a), TreeValue::Function(b)) => Rc::ptr_eq(a, b),
+            (TreeValue::Native(a), TreeValue::Native(b)) => Shared::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+/// Formats values the same way the VM's `print` does.
+impl fmt::Display for TreeValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TreeValue::Nil => write!(f, "nil"),
+            TreeValue::Boolean(b) => write!(f, "{b}"),
+            TreeValue::Number(n) => write!(f, "{}", format_number(*n)),
+            TreeValue::String(s) => write!(f, "{s}"),
+            TreeValue::Function(function) => write!(f, "<fn {}>", function.display_name()),
+            TreeValue::Native(_) => write!(f, "<native fn>"),
+        }
+    }
+}
+
+/// A function declared in Lox code, along with the scope it was declared in.
+pub struct TreeFunction {
+    /// The name bound to the function inside its body, empty for anonymous
+    /// functions
+    pub name: Token,
+    pub params: Vec<Token>,
+    pub body: Vec<Stmt>,
+    /// The source code the function was parsed from, which its tokens point into
+    pub source: Rc<str>,
+    /// The scope the function was declared in, or `None` at the top level
+    pub closure: Option<Scope>,
+}
+
+impl TreeFunction {
+    /// Returns the name the function is printed with, as
+    /// [`Function::display_name`](crate::object::Function::display_name) does.
+    pub fn display_name(&self) -> Cow<'_, str> {
+        let name = self.name.lexeme(&self.source);
+        if name.is_empty() {
+            Cow::Owned(format!("anonymous (line {})", self.name.line))
+        } else {
+            Cow::Borrowed(name)
+        }
+    }
+}
+
+/// The variables declared directly in one scope, in declaration order.
+pub struct Env {
+    vars: RefCell<Vec<(String, Rc<RefCell<TreeValue>>)>>,
+    parent: Option<Scope>,
+}
+
+/// A view of an [`Env`] that only sees the variables declared before the view
+/// was taken. Code only sees the locals declared before it in the source, so
+/// functions and nested blocks look up variables through a scope, and fall back
+/// to globals for locals declared after them.
+#[derive(Clone)]
+pub struct Scope {
+    env: Rc<Env>,
+    visible: usize,
+}
+
+impl Env {
+    pub fn new(parent: Option<Scope>) -> Rc<Self> {
+        Rc::new(Self {
+            vars: RefCell::new(Vec::new()),
+            parent,
+        })
+    }
+
+    /// Returns a scope seeing every variable declared in this environment so far.
+    pub fn scope(self: &Rc<Self>) -> Scope {
+        Scope {
+            env: self.clone(),
+            visible: self.vars.borrow().len(),
+        }
+    }
+
+    pub fn declare(&self, name: String, value: TreeValue) {
+        self.vars
+            .borrow_mut()
+            .push((name, Rc::new(RefCell::new(value))));
+    }
+
+    /// Finds the variable `name` in this environment or the scopes enclosing it.
+    pub fn lookup(&self, name: &str) -> Option<Rc<RefCell<TreeValue>>> {
+        self.lookup_visible(name, usize::MAX)
+    }
+
+    fn lookup_visible(&self, name: &str, visible: usize) -> Option<Rc<RefCell<TreeValue>>> {
+        let vars = self.vars.borrow();
+        let visible = visible.min(vars.len());
+
+        match vars[..visible].iter().rfind(|(n, _)| n == name) {
+            Some((_, value)) => Some(value.clone()),
+            None => self
+                .parent
+                .as_ref()
+                .and_then(|parent| parent.env.lookup_visible(name, parent.visible)),
+        }
+    }
+}
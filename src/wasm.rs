@@ -0,0 +1,72 @@
+//! JavaScript bindings, enabled by the `wasm` feature, for running Lox in a
+//! browser, e.g. to power a web playground. Build them with
+//! `wasm-pack build --features wasm`.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{try_interpret, VM};
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = Date, js_name = now)]
+    fn date_now() -> f64;
+
+    #[wasm_bindgen(js_namespace = performance, js_name = now)]
+    fn performance_now() -> f64;
+}
+
+/// Seconds since the Unix epoch, from `Date.now()`.
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub(crate) fn wall() -> f64 {
+    date_now() / 1000.0
+}
+
+/// Seconds since the page loaded, from `performance.now()`.
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub(crate) fn monotonic() -> f64 {
+    performance_now() / 1000.0
+}
+
+/// What running a script with [`run_source`] produced.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunResult {
+    /// Everything the script printed
+    pub output: String,
+    /// The errors and warnings reported while compiling and running the
+    /// script, one per line
+    pub errors: String,
+    /// The status the command line binary would exit with: 0 on success, 65
+    /// after a compile error, 70 after a runtime error, or the code passed to
+    /// `exit`
+    #[wasm_bindgen(js_name = exitCode)]
+    pub exit_code: i32,
+}
+
+/// Runs `source` on a fresh VM, collecting its output and errors in memory
+/// rather than writing them to stdout and stderr, which a browser lacks.
+#[wasm_bindgen(js_name = runSource)]
+pub fn run_source(source: &str) -> RunResult {
+    let mut output = Vec::new();
+    let mut errors = Vec::new();
+
+    let mut vm = VM::new(Box::new(&mut output));
+    vm.set_error_writer(Box::new(&mut errors));
+    let exit_code = match try_interpret(source, &mut vm) {
+        Ok(_) => 0,
+        Err(outcome) => {
+            for e in outcome.errors() {
+                vm.report(e);
+            }
+            outcome.exit_code()
+        }
+    };
+    drop(vm);
+
+    RunResult {
+        output: String::from_utf8_lossy(&output).into_owned(),
+        errors: String::from_utf8_lossy(&errors).into_owned(),
+        exit_code,
+    }
+}
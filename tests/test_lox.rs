@@ -1,8 +1,9 @@
-// Tests (26 suites)
+// Tests (35 suites)
 // bool
 // string
 // comments
 // print
+// number
 // operator
 // logical_operator
 // variable
@@ -16,6 +17,16 @@
 // call
 // return
 // closure
+// late_binding
+// coroutine
+// native
+// stdlib
+// warning
+// strict (run with VmOptions::strict_globals)
+// strip (run with VmOptions::strip)
+// string_coercion (run with VmOptions::string_coercion)
+// strict_math (run with VmOptions::strict_math)
+// gc (run with a VmOptions::gc_threshold of 1)
 // class
 // field
 // constructor
@@ -27,8 +38,18 @@
 // limit
 // benchmark
 
-use lox_bytecode_vm::interpret;
-use lox_bytecode_vm::VM;
+use lox_bytecode_vm::ast::expr::Expr;
+use lox_bytecode_vm::ast::stmt::{MethodKind, Stmt};
+use lox_bytecode_vm::bytecode::peephole::{self, Peephole};
+use lox_bytecode_vm::bytecode::{self, Constant, LineInfo, OpCode};
+use lox_bytecode_vm::pipeline::{compare, Engine, EngineKind};
+use lox_bytecode_vm::repl::Session;
+use lox_bytecode_vm::{
+    frontend, runtime, try_interpret, try_interpret_incremental, Function, Heap, InterpretOutcome,
+    Object, ProcessCapability, RunState, Shared, TraceFlags, Value, VmOptions, WatchAction,
+    WatchHit, VM,
+};
+use std::collections::HashSet;
 use std::fs;
 use std::io::{self};
 use std::path::{Path, PathBuf};
@@ -44,6 +65,11 @@ fn test_string() {
     run_test_suite("string");
 }
 
+#[test]
+fn test_scanning() {
+    run_test_suite("scanning");
+}
+
 #[test]
 fn test_comments() {
     run_test_suite("comments");
@@ -54,6 +80,11 @@ fn test_print() {
     run_test_suite("print");
 }
 
+#[test]
+fn test_number() {
+    run_test_suite("number");
+}
+
 #[test]
 fn test_operator() {
     run_test_suite("operator");
@@ -114,6 +145,96 @@ fn test_closure() {
     run_test_suite("closure");
 }
 
+#[test]
+fn test_late_binding() {
+    run_test_suite("late_binding");
+}
+
+#[test]
+fn test_coroutine() {
+    run_test_suite("coroutine");
+}
+
+#[test]
+fn test_native() {
+    run_test_suite("native");
+}
+
+#[test]
+fn test_stdlib() {
+    run_test_suite("stdlib");
+}
+
+#[test]
+fn test_warning() {
+    run_test_suite("warning");
+}
+
+#[test]
+fn test_string_coercion() {
+    run_test_suite_with_options(
+        "string_coercion",
+        VmOptions {
+            string_coercion: true,
+            ..VmOptions::default()
+        },
+    );
+}
+
+#[test]
+fn test_strict_math() {
+    run_test_suite_with_options(
+        "strict_math",
+        VmOptions {
+            strict_math: true,
+            ..VmOptions::default()
+        },
+    );
+}
+
+#[test]
+fn test_strip() {
+    run_test_suite_with_options(
+        "strip",
+        VmOptions {
+            strip: true,
+            ..VmOptions::default()
+        },
+    );
+}
+
+#[test]
+fn test_strict() {
+    run_test_suite_with_options(
+        "strict",
+        VmOptions {
+            strict_globals: true,
+            ..VmOptions::default()
+        },
+    );
+}
+
+// Collecting whenever the heap doubles must not free anything still in use
+#[test]
+fn test_gc() {
+    let stress = VmOptions {
+        gc_threshold: 1,
+        ..VmOptions::default()
+    };
+    for suite in [
+        "gc",
+        "function",
+        "late_binding",
+        "coroutine",
+        "for",
+        "string",
+        "native",
+        "stdlib",
+    ] {
+        run_test_suite_with_options(suite, stress.clone());
+    }
+}
+
 #[test]
 #[ignore]
 fn test_class() {
@@ -163,7 +284,6 @@ fn test_regression() {
 }
 
 #[test]
-#[ignore]
 fn test_limit() {
     run_test_suite("limit");
 }
@@ -174,19 +294,1530 @@ fn test_benchmark() {
     run_test_suite("benchmark");
 }
 
+// The tree-walk engine on the suites without warnings, classes, or coroutines
+#[test]
+fn test_tree_walk() {
+    for suite in [
+        "bool",
+        "string",
+        "comments",
+        "print",
+        "logical_operator",
+        "assignment",
+        "block",
+        "if",
+        "while",
+        "function",
+        "stdlib",
+    ] {
+        run_test_suite_on(suite, EngineKind::TreeWalk, VmOptions::default());
+    }
+
+    run_test_suite_on(
+        "strict",
+        EngineKind::TreeWalk,
+        VmOptions {
+            strict_globals: true,
+            ..VmOptions::default()
+        },
+    );
+    run_test_suite_on(
+        "strip",
+        EngineKind::TreeWalk,
+        VmOptions {
+            strip: true,
+            ..VmOptions::default()
+        },
+    );
+    run_test_suite_on(
+        "string_coercion",
+        EngineKind::TreeWalk,
+        VmOptions {
+            string_coercion: true,
+            ..VmOptions::default()
+        },
+    );
+    run_test_suite_on(
+        "strict_math",
+        EngineKind::TreeWalk,
+        VmOptions {
+            strict_math: true,
+            ..VmOptions::default()
+        },
+    );
+}
+
+// Differential test: both engines on every suite that does not use classes or coroutines
+#[test]
+fn test_engines_agree() {
+    for suite in [
+        "bool",
+        "string",
+        "comments",
+        "print",
+        "operator",
+        "logical_operator",
+        "variable",
+        "assignment",
+        "block",
+        "if",
+        "while",
+        "for",
+        "function",
+        "call",
+        "return",
+        "closure",
+        "late_binding",
+        "stdlib",
+        "warning",
+    ] {
+        for test_path in suite_files(suite) {
+            let source = fs::read_to_string(&test_path).unwrap_or_else(|e| {
+                panic!("Error reading test file {}: {}", test_path.display(), e)
+            });
+
+            let options = VmOptions {
+                load_stdlib: true,
+                ..VmOptions::default()
+            };
+            let comparison = compare(&source, options);
+            assert!(
+                !comparison.diverged(),
+                "\n=== Engines diverged on {} ===\n{}",
+                test_path.display(),
+                comparison
+            );
+        }
+    }
+}
+
+// Deeply nested input is rejected before it can overflow the stack
+#[test]
+fn test_deep_nesting() {
+    // The statement and its expression take two levels, and each parenthesis
+    // one more. Parentheses take the most stack per level, so nesting them to
+    // the default limit must still fit on a test thread's stack.
+    let parens = frontend::MAX_NESTING - 2;
+    let nested = format!("print {}1{};", "(".repeat(parens), ")".repeat(parens));
+    let past_limit = format!(
+        "print {}1{};",
+        "(".repeat(parens + 1),
+        ")".repeat(parens + 1)
+    );
+    let too_deep = format!("print {}1{};", "(".repeat(100000), ")".repeat(100000));
+
+    for kind in [EngineKind::Bytecode, EngineKind::TreeWalk] {
+        let actual = capture_output_from_interpret(&nested, kind, VmOptions::default()).unwrap();
+        assert_eq!(actual.trim(), "1");
+
+        let actual =
+            capture_output_from_interpret(&past_limit, kind, VmOptions::default()).unwrap();
+        assert_eq!(actual.trim(), "[line 1]: Error: Code is nested too deeply.");
+
+        let actual = capture_output_from_interpret(&too_deep, kind, VmOptions::default()).unwrap();
+        assert_eq!(actual.trim(), "[line 1]: Error: Code is nested too deeply.");
+    }
+}
+
+// Binary operators bind from `or`, the loosest, up to `*` and `/`, and each
+// level groups to the left
+#[test]
+fn test_precedence() {
+    let cases = [
+        // `and` binds tighter than `or`
+        ("true or false and false", "true"),
+        ("false and true or true", "true"),
+        // Equality binds tighter than `and`
+        ("1 == 1 and 2", "2"),
+        // Comparisons bind tighter than equality
+        ("false == 2 < 1", "true"),
+        // Bitwise operators bind tighter than comparisons
+        ("1 | 2 > 2", "true"),
+        ("6 & 1 == 0", "true"),
+        // `^` binds tighter than `|`, and `&` tighter than `^`
+        ("1 | 1 ^ 1", "1"),
+        ("1 ^ 1 & 0", "1"),
+        // Shifts bind tighter than `&`
+        ("1 & 1 << 1", "0"),
+        // `+` and `-` bind tighter than shifts
+        ("1 << 2 + 1", "8"),
+        ("16 >> 3 - 1", "4"),
+        // `*` and `/` bind tighter than `+` and `-`
+        ("2 + 3 * 4", "14"),
+        ("2 - 6 / 3", "0"),
+        // Each level groups to the left
+        ("1 - 2 - 3", "-4"),
+        ("8 / 4 / 2", "1"),
+        ("256 >> 2 >> 1", "32"),
+        ("1 == 2 == false", "true"),
+        ("7 & 3 & 1", "1"),
+        // Parentheses override the levels
+        ("(1 | 1) ^ 1", "0"),
+        ("(1 - 2) * 3", "-3"),
+    ];
+
+    for kind in [EngineKind::Bytecode, EngineKind::TreeWalk] {
+        for (expr, expected) in cases {
+            let source = format!("print {expr};");
+            let actual =
+                capture_output_from_interpret(&source, kind, VmOptions::default()).unwrap();
+            assert_eq!(actual.trim(), expected, "{expr} on {kind:?}");
+        }
+    }
+}
+
+// The nesting limit can be lowered, rejecting input the default allows
+#[test]
+fn test_max_nesting() {
+    let nested = format!("print {}1{};", "(".repeat(60), ")".repeat(60));
+    let options = VmOptions {
+        max_nesting: 8,
+        ..VmOptions::default()
+    };
+
+    for kind in [EngineKind::Bytecode, EngineKind::TreeWalk] {
+        let actual = capture_output_from_interpret(&nested, kind, options.clone()).unwrap();
+        assert_eq!(actual.trim(), "[line 1]: Error: Code is nested too deeply.");
+
+        let chain = format!(
+            "if (false) {{}}{} else print 1;",
+            " else if (false) {}".repeat(100)
+        );
+        let actual = capture_output_from_interpret(&chain, kind, options.clone()).unwrap();
+        assert_eq!(actual.trim(), "1");
+    }
+
+    let parser =
+        frontend::Parser::new(&nested, frontend::Scanner::new(&nested)).with_max_nesting(8);
+    let errors: Vec<_> = parser
+        .filter_map(|stmt| stmt.err())
+        .map(|e| e.to_string())
+        .collect();
+    assert_eq!(errors, ["[line 1]: Error: Code is nested too deeply."]);
+}
+
+// A syntax tree nested deeper than the compiler allows is an error, while long
+// chains of binary operators compile without recursing
+#[test]
+fn test_compile_depth() {
+    let mut symbols = runtime::Symbols::new();
+    let source = "print -1;";
+    let mut statements = frontend::parse(source, frontend::scan(source).unwrap()).unwrap();
+    let Some(Stmt::Print(_, expr)) = statements.first_mut() else {
+        panic!("expected a print statement");
+    };
+    let minus = frontend::Token::synthetic(frontend::TokenType::Minus, 1, 7);
+    for _ in 0..1000 {
+        let inner = std::mem::replace(expr, Expr::Literal(minus.clone()));
+        *expr = Expr::Unary(minus.clone(), Box::new(inner));
+    }
+    let errors = bytecode::compile(source, statements, &mut symbols, &mut vec![]).unwrap_err();
+    let errors: Vec<_> = errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(errors, ["[line 1]: Error: Code is nested too deeply."]);
+
+    let source = format!("print {};", vec!["1"; 100000].join(" + "));
+    let statements = frontend::parse(&source, frontend::scan(&source).unwrap()).unwrap();
+    assert!(bytecode::compile(&source, statements, &mut symbols, &mut vec![]).is_ok());
+}
+
+// Integers are numbers, equal to the floats with the same value
+#[test]
+fn test_integer_values() {
+    let three = Value::int(3);
+    assert!(three.is_int() && three.is_number());
+    assert_eq!(three.as_number(), 3.0);
+    assert!(three == Value::number(3.0));
+    assert!(Value::int(-1).as_int() == -1);
+
+    assert!(Value::whole_number(4.0).is_int());
+    assert!(!Value::whole_number(0.5).is_int());
+    assert!(!Value::whole_number(-0.0).is_int());
+    assert!(!Value::whole_number(1e10).is_int());
+    assert!(!Value::from_i64(1 << 40).is_int());
+}
+
+// Closing over a variable keeps its value with the upvalue, rather than in a new
+// heap object
+#[test]
+fn test_closed_upvalues() {
+    let mut output = Vec::new();
+    let mut vm = VM::new(Box::new(&mut output));
+    try_interpret(
+        "fun make(n) { fun get() { return n; } return get; }",
+        &mut vm,
+    )
+    .unwrap();
+    let before = vm.heap().allocations();
+    let source =
+        "var sum = 0;\nfor (var i = 0; i < 100; i = i + 1) sum = sum + make(i)();\nprint sum;";
+    try_interpret(source, &mut vm).unwrap();
+    // A closure for each call, and the script's function and closure
+    assert_eq!(vm.heap().allocations() - before, 100 + 2);
+    drop(vm);
+    assert_eq!(String::from_utf8_lossy(&output), "4950\n");
+}
+
+// Every NaN is stored alike, so none passes for a tagged value, and values that
+// are equal hash the same
+#[test]
+fn test_value_equality() {
+    let tagged_nan = f64::from_bits(Value::NIL.bits);
+    assert!(tagged_nan.is_nan());
+    let nan = Value::number(tagged_nan);
+    assert!(nan.is_number() && !nan.is_nil());
+    assert!(nan.as_number().is_nan());
+    assert_eq!(nan, Value::number(f64::NAN));
+    assert_ne!(Value::number(0.0), Value::number(-0.0));
+
+    let keys: HashSet<Value> = [
+        Value::int(1),
+        Value::number(1.0),
+        nan,
+        Value::number(-f64::NAN),
+        Value::NIL,
+        Value::FALSE,
+    ]
+    .into_iter()
+    .collect();
+    assert_eq!(keys.len(), 4);
+    assert!(keys.contains(&Value::number(f64::INFINITY - f64::INFINITY)));
+}
+
+// Functions are numbered in the order they are compiled, and know their line
+#[test]
+fn test_function_ids() {
+    let mut symbols = runtime::Symbols::new();
+    let source = "fun f() {}\nvar g = fun () {};";
+    let statements = frontend::parse(source, frontend::scan(source).unwrap()).unwrap();
+    let main = bytecode::compile(source, statements, &mut symbols, &mut vec![]).unwrap();
+
+    assert_eq!(main.id, 0);
+    assert_eq!(main.header(), "main #0 (line 0)");
+    assert_eq!(symbols.next_function_id(), 3);
+
+    let mut lambda = Function::new(String::new(), 0);
+    lambda.line = 2;
+    assert_eq!(lambda.display_name(), "anonymous (line 2)");
+}
+
+// Bytecode maps back to the line and column it was compiled from, and lines map
+// to their first byte of code
+#[test]
+fn test_line_info() {
+    let mut symbols = runtime::Symbols::new();
+    let source = "var a = 1;\n\nprint a + 2;";
+    let tokens = frontend::scan(source).unwrap();
+    assert_eq!((tokens[1].line, tokens[1].column), (1, 5));
+    assert_eq!((tokens[7].line, tokens[7].column), (3, 9));
+
+    let statements = frontend::parse(source, tokens).unwrap();
+    let main = bytecode::compile(source, statements, &mut symbols, &mut vec![]).unwrap();
+    let chunk = &main.chunk;
+
+    let add = chunk
+        .code
+        .iter()
+        .rposition(|&byte| byte == OpCode::Add as u8);
+    let info = chunk.line_info(add.unwrap()).unwrap();
+    assert_eq!(info, LineInfo { line: 3, column: 9 });
+
+    assert_eq!(chunk.first_offset(1), Some(0));
+    let third = chunk.first_offset(2).unwrap();
+    assert_eq!(chunk.first_offset(3), Some(third));
+    assert_eq!(chunk.line_info(third).unwrap().line, 3);
+    assert_eq!(chunk.line_info(chunk.code.len()), None);
+}
+
+// Code the compiler adds on its own, like the pops that end a block, takes the
+// line of the last statement compiled rather than line 0
+#[test]
+fn test_synthesized_line_info() {
+    let mut symbols = runtime::Symbols::new();
+    let source = "{\n  var a = 1;\n  {\n    var b = a;\n    fun f() { return b; }\n  }\n}";
+    let statements = frontend::parse(source, frontend::scan(source).unwrap()).unwrap();
+    let main = bytecode::compile(source, statements, &mut symbols, &mut vec![]).unwrap();
+    let chunk = &main.chunk;
+
+    let lines: Vec<_> = (0..chunk.code.len())
+        .map(|offset| chunk.line_info(offset).unwrap().line)
+        .collect();
+    assert!(lines.iter().all(|&line| (1..=7).contains(&line)));
+
+    let close = chunk
+        .code
+        .iter()
+        .position(|&byte| byte == OpCode::CloseUpvalue as u8);
+    assert_eq!(lines[close.unwrap()], 5);
+    let end = chunk.code.len() - 1;
+    assert_eq!(chunk.code[end], OpCode::Return as u8);
+    assert!(lines[..end].ends_with(&[5, 5, 5]));
+    assert_eq!(lines[end], 5);
+}
+
+// Tokens record where their text lies in the source rather than copying it
+#[test]
+fn test_token_spans() {
+    let source = "var café = \"au lait\";";
+    let tokens = frontend::scan(source).unwrap();
+    let lexemes: Vec<_> = tokens.iter().map(|token| token.lexeme(source)).collect();
+    assert_eq!(lexemes, ["var", "café", "=", "\"au lait\"", ";", ""]);
+    assert_eq!((tokens[1].span.start, tokens[1].span.end), (4, 9));
+
+    // Columns count characters, and identifiers may use letters of any script
+    let source = "var π = \"☃\"; print π;";
+    let tokens = frontend::scan(source).unwrap();
+    let columns: Vec<_> = tokens.iter().map(|token| token.column).collect();
+    assert_eq!(columns[..6], [1, 5, 7, 9, 12, 14]);
+    assert_eq!(tokens[6].lexeme(source), "π");
+    assert!(frontend::is_identifier_start('é') && !frontend::is_identifier_start('1'));
+
+    // A byte order mark and `#!` line are skipped, keeping offsets and lines
+    let source = "\u{feff}#!/usr/bin/env lox\nprint 1;";
+    let tokens = frontend::scan(source).unwrap();
+    assert_eq!(tokens[0].lexeme(source), "print");
+    assert_eq!((tokens[0].line, tokens[0].column), (2, 1));
+}
+
+// Statements that fail to parse are replaced by error nodes, and the rest of
+// the tree is kept
+#[test]
+fn test_parse_partial() {
+    let source = "fun f() {\n  print 1 +;\n  print 2;\n}\nvar x = ;\nprint 3;";
+    let (statements, errors) = frontend::parse_partial(source, frontend::scan(source).unwrap());
+    assert_eq!(errors.len(), 2);
+    assert_eq!(statements.len(), 3);
+
+    let Stmt::DeclareFunc(_, _, body) = &statements[0] else {
+        panic!("expected a function, got {:?}", statements[0]);
+    };
+    assert!(matches!(&body[0], Stmt::Error(token) if token.line == 2));
+    assert!(matches!(body[1], Stmt::Print(_, _)));
+    assert!(matches!(&statements[1], Stmt::Error(token) if token.lexeme(source) == "var"));
+    assert!(matches!(statements[2], Stmt::Print(_, _)));
+}
+
+// Classes may declare getters and setters, and still have methods called get
+#[test]
+fn test_parse_accessors() {
+    let source = "class Rect {\n  get area { return 1; }\n  set width(w) {}\n  get(key) {}\n}";
+    let statements = frontend::parse(source, frontend::scan(source).unwrap()).unwrap();
+    let Stmt::DeclareClass(_, _, methods) = &statements[0] else {
+        panic!("expected a class, got {:?}", statements[0]);
+    };
+    let members: Vec<_> = methods
+        .iter()
+        .map(|(kind, name, params, _)| (*kind, name.lexeme(source), params.len()))
+        .collect();
+    assert_eq!(
+        members,
+        [
+            (MethodKind::Getter, "area", 0),
+            (MethodKind::Setter, "width", 1),
+            (MethodKind::Method, "get", 1),
+        ]
+    );
+
+    let source = "class Rect {\n  set width(w, h) {}\n}";
+    let errors = frontend::parse(source, frontend::scan(source).unwrap()).unwrap_err();
+    assert_eq!(
+        errors[0].to_string(),
+        "[line 2]: Error at 'width': A setter takes exactly one parameter."
+    );
+}
+
+// Disassembling ahead of running shows locals by slot and recorded name
+#[test]
+fn test_local_names() {
+    let source = "{ var a = 1; { var b = 2; print b; } { var c = 3; print a + c; } }";
+    let mut vm = VM::new(Box::new(io::sink()));
+    let main = compile_with_debug_symbols(source, &mut vm);
+
+    // b and c share a slot, in different parts of the chunk
+    let debug = main.chunk.debug.as_ref().unwrap();
+    let names: Vec<_> = debug
+        .locals
+        .iter()
+        .map(|local| (local.slot, local.name.as_str()))
+        .collect();
+    assert_eq!(names, [(2, "b"), (2, "c"), (1, "a")]);
+
+    let lines: Vec<_> = main
+        .disassembly(&vm)
+        .lines()
+        .filter(|line| line.contains("GetLocal"))
+        .map(|line| {
+            line.split_whitespace()
+                .skip(3)
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect();
+    assert_eq!(lines, ["2 b", "1 a", "2 c"]);
+}
+
+// Debug symbols name the upvalues of a function and the span it was compiled from
+#[test]
+fn test_debug_symbols() {
+    let source = "fun outer(x) {\n  fun inner() {\n    return x;\n  }\n  return inner;\n}";
+    let mut vm = VM::new(Box::new(io::sink()));
+    let main = compile_with_debug_symbols(source, &mut vm);
+    let outer = nested_function(&main);
+    let inner = nested_function(&outer);
+
+    let debug = outer.chunk.debug.as_ref().unwrap();
+    assert_eq!(debug.span.start, LineInfo { line: 1, column: 5 });
+    assert_eq!(debug.span.end.line, 5);
+    assert!(debug.locals.iter().any(|local| local.name == "x"));
+
+    let debug = inner.chunk.debug.as_ref().unwrap();
+    assert_eq!(debug.upvalues, ["x"]);
+    let disassembly = inner.disassembly(&vm);
+    let get = disassembly.lines().find(|line| line.contains("GetUpvalue"));
+    assert!(get.unwrap().ends_with("0 x"), "{disassembly}");
+
+    // Without debug symbols, nothing is recorded
+    let statements = frontend::parse(source, frontend::scan(source).unwrap()).unwrap();
+    let main = bytecode::compile(source, statements, vm.symbols_mut(), &mut vec![]).unwrap();
+    assert!(main.chunk.debug.is_none());
+    assert!(nested_function(&main).chunk.debug.is_none());
+}
+
+// Constants keep what they are apart from the heap, and are linked into values
+#[test]
+fn test_typed_constants() {
+    let source = "print 1.5; print \"hi\";";
+    let mut vm = VM::new(Box::new(io::sink()));
+    let mut main = compile_with_debug_symbols(source, &mut vm);
+
+    assert_eq!(
+        main.chunk.constants,
+        [Constant::Number(1.5), Constant::String("hi".into())]
+    );
+    assert!(main.chunk.values().is_empty());
+    main.chunk.link(vm.heap_mut());
+    assert_eq!(main.chunk.values().len(), 2);
+    assert_eq!(vm.heap().format(&main.chunk.values()[1]), "hi");
+    let disassembly = main.disassembly(&vm);
+    assert!(disassembly.contains("'\"hi\"'"), "{disassembly}");
+
+    // Functions declared in a chunk are among its constants
+    let main = compile_with_debug_symbols("fun f() { return \"in f\"; }", &mut vm);
+    let f = nested_function(&main);
+    assert_eq!(main.chunk.constants, [Constant::Function(f.clone())]);
+    assert_eq!(f.chunk.constants, [Constant::String("in f".into())]);
+    let disassembly = main.disassembly(&vm);
+    assert!(disassembly.contains("'<fn f>'"), "{disassembly}");
+}
+
+// Conditions that are literals compile to only the code that can run, while the
+// code left out is still checked for errors
+#[test]
+fn test_constant_conditions() {
+    let mut vm = VM::new(Box::new(io::sink()));
+    let jumps = [OpCode::Jump, OpCode::JumpIfFalse].map(|op| op as u8);
+
+    let main = compile_with_debug_symbols(
+        "if (true) print 1.5; else { var a = fun () {}; print a; }\nwhile (false) print 2.5;",
+        &mut vm,
+    );
+    assert_eq!(main.chunk.constants, [Constant::Number(1.5)]);
+    assert!(!main.chunk.code.iter().any(|byte| jumps.contains(byte)));
+    assert_eq!(
+        main.chunk.lines.iter().map(|(_, n)| n).sum::<usize>(),
+        main.chunk.code.len()
+    );
+
+    let main = compile_with_debug_symbols("for (var i = 0;;) { if (nil) print i; }", &mut vm);
+    let code = &main.chunk.code;
+    assert!(!code.contains(&(OpCode::JumpIfFalse as u8)));
+    assert!(code.contains(&(OpCode::Loop as u8)));
+
+    let source = "if (false) return 1;";
+    let statements = frontend::parse(source, frontend::scan(source).unwrap()).unwrap();
+    let errors = bytecode::compile(source, statements, vm.symbols_mut(), &mut vec![]).unwrap_err();
+    assert_eq!(
+        errors[0].to_string(),
+        "[line 1]: Error: Cannot return from top level code."
+    );
+}
+
+// `or` jumps over its right operand with a single conditional jump
+#[test]
+fn test_or_jump() {
+    let mut vm = VM::new(Box::new(io::sink()));
+    let main = compile_with_debug_symbols("var a; print a or 2.5;", &mut vm);
+
+    // Operand bytes can equal an opcode, so look at the instructions as listed
+    let disassembly = main.disassembly(&vm);
+    let ops = opcodes(&disassembly);
+    assert!(ops.contains(&"JumpIfTrue"), "{disassembly}");
+    assert!(!ops.contains(&"JumpIfFalse"), "{disassembly}");
+    assert!(!ops.contains(&"Jump"), "{disassembly}");
+}
+
+// The peephole optimizer rewrites runs of instructions into cheaper ones, keeping
+// jumps and the names of locals in step
+#[test]
+fn test_peephole() {
+    let mut vm = VM::new(Box::new(io::sink()));
+    let source = "{ var a = 1; var b = 2; a = -3.5; if (!a) print b; else print a; }";
+
+    let before = compile_with_peephole(source, &mut vm, Peephole::new());
+    let disassembly = before.chunk.disassembly("main", &vm);
+    assert_eq!(
+        opcodes(&disassembly),
+        [
+            "LoadSmallInt",
+            "LoadSmallInt",
+            "LoadConstant",
+            "Negate",
+            "SetLocal",
+            "Pop",
+            "GetLocal",
+            "Not",
+            "JumpIfFalse",
+            "Pop",
+            "GetLocal",
+            "Print",
+            "Jump",
+            "Pop",
+            "GetLocal",
+            "Print",
+            "Pop",
+            "Pop",
+            "Return",
+        ]
+    );
+
+    let after = compile_with_peephole(source, &mut vm, Peephole::default());
+    let disassembly = after.chunk.disassembly("main", &vm);
+    assert_eq!(
+        opcodes(&disassembly),
+        [
+            "LoadSmallInt",
+            "LoadSmallInt",
+            "LoadConstant",
+            "PopLocal",
+            "GetLocal",
+            "JumpIfTrue",
+            "Pop",
+            "GetLocal",
+            "Print",
+            "Jump",
+            "Pop",
+            "GetLocal",
+            "Print",
+            "PopN",
+            "Return",
+        ]
+    );
+    assert!(disassembly.contains("'\"-3.5\"'"), "{disassembly}");
+    assert!(disassembly.contains("PopLocal    1 a"), "{disassembly}");
+    assert!(disassembly.contains("JumpIfTrue    7"), "{disassembly}");
+
+    // The value `!a` leaves is the result of `and`, so the jump is kept
+    let source = "var a; print !a and 2.5;";
+    let after = compile_with_peephole(source, &mut vm, Peephole::default());
+    let disassembly = after.chunk.disassembly("main", &vm);
+    assert!(opcodes(&disassembly).contains(&"Not"), "{disassembly}");
+
+    // Rules can be picked one at a time
+    let source = "{ var a = 1; var b = a; a = 2; }";
+    let only_pops = Peephole::new().with_rule(peephole::PopN);
+    let after = compile_with_peephole(source, &mut vm, only_pops);
+    let disassembly = after.chunk.disassembly("main", &vm);
+    assert_eq!(
+        opcodes(&disassembly),
+        [
+            "LoadSmallInt",
+            "GetLocal",
+            "LoadSmallInt",
+            "SetLocal",
+            "PopN",
+            "Return"
+        ]
+    );
+}
+
+fn compile_with_peephole(source: &str, vm: &mut VM, peephole: Peephole) -> Function {
+    let statements = frontend::parse(source, frontend::scan(source).unwrap()).unwrap();
+    bytecode::Compiler::new(vm.symbols_mut())
+        .with_debug_symbols()
+        .with_peephole(peephole)
+        .compile(source, statements.into_iter().map(Ok), &mut vec![])
+        .unwrap()
+}
+
+// Returns the name of each instruction in a disassembly
+fn opcodes(disassembly: &str) -> Vec<&str> {
+    disassembly
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().nth(2))
+        .collect()
+}
+
+fn compile_with_debug_symbols(source: &str, vm: &mut VM) -> Function {
+    let statements = frontend::parse(source, frontend::scan(source).unwrap()).unwrap();
+    bytecode::Compiler::new(vm.symbols_mut())
+        .with_debug_symbols()
+        .compile(source, statements.into_iter().map(Ok), &mut vec![])
+        .unwrap()
+}
+
+// Returns the first function created by a `Closure` instruction in `function`
+fn nested_function(function: &Function) -> Shared<Function> {
+    let code = &function.chunk.code;
+    let closure = code
+        .iter()
+        .position(|&byte| byte == OpCode::Closure as u8)
+        .unwrap();
+    match function.chunk.function(code[closure + 1] as usize) {
+        Some(function) => function.clone(),
+        None => panic!("Closure operand is not a function"),
+    }
+}
+
+// Restoring a snapshot undoes everything run since it was taken
+#[test]
+fn test_snapshot_restore() {
+    let mut output = Vec::new();
+    let mut vm = VM::new(Box::new(&mut output));
+    vm.set_error_writer(Box::new(io::sink()));
+
+    let setup = "var a = 1;
+    fun counter() { var n = 0; fun inc() { n = n + 1; return n; } return inc; }
+    var next = counter();";
+    try_interpret(setup, &mut vm).unwrap();
+    let state = vm.snapshot();
+
+    try_interpret("a = 2; var b = 3; next(); next();", &mut vm).unwrap();
+    vm.restore(state);
+    try_interpret("print a; print next();", &mut vm).unwrap();
+    assert!(try_interpret("print b;", &mut vm).is_err());
+
+    drop(vm);
+    assert_eq!(String::from_utf8_lossy(&output), "1\n1\n");
+}
+
+// Script arguments reach both engines, and the environment stays hidden unless allowed
+#[test]
+fn test_script_args() {
+    let source =
+        "print argCount(); print arg(0); print arg(1); print arg(2); print env(\"PATH\") == nil;";
+    let options = VmOptions {
+        args: vec!["a".to_string(), "b c".to_string()],
+        ..VmOptions::default()
+    };
+
+    let comparison = compare(source, options.clone());
+    assert!(!comparison.diverged(), "{comparison}");
+    assert_eq!(comparison.bytecode.output, "2\na\nb c\nnil\ntrue\n");
+
+    let comparison = compare(
+        "print env(\"PATH\") == nil;",
+        VmOptions {
+            env_access: true,
+            ..options
+        },
+    );
+    assert!(!comparison.diverged(), "{comparison}");
+    assert_eq!(comparison.bytecode.output, "false\n");
+}
+
+// `gc()` has nothing to collect in the tree-walker, but scripts calling it
+// still run the same on both engines
+#[test]
+fn test_gc_native() {
+    let comparison = compare("print gc(); print \"after\";", VmOptions::default());
+    assert!(!comparison.diverged(), "{comparison}");
+    assert_eq!(comparison.bytecode.output, "nil\nafter\n");
+}
+
+// Process natives are only defined when the host allows them
+#[test]
+fn test_process_natives() {
+    let comparison = compare("print cwd();", VmOptions::default());
+    assert!(!comparison.diverged(), "{comparison}");
+    assert!(comparison.bytecode.errors()[0].contains("'cwd' is not defined"));
+
+    let source = "print cwd(); print chdir(\".\"); print chdir(\"/no/such/dir\"); print exec;";
+    let options = VmOptions {
+        process: ProcessCapability::CurrentDir,
+        ..VmOptions::default()
+    };
+    let comparison = compare(source, options);
+    assert!(!comparison.diverged(), "{comparison}");
+    let cwd = std::env::current_dir().unwrap();
+    assert!(
+        comparison
+            .bytecode
+            .output
+            .starts_with(&format!("{}\ntrue\nfalse\n", cwd.display())),
+        "{comparison}"
+    );
+    assert!(comparison.bytecode.errors()[0].contains("'exec' is not defined"));
+
+    if cfg!(unix) {
+        let source = "print exec(\"echo out; echo err >&2; exit 3\");\n\
+                      print execOutput(); print execErrors();";
+        let options = VmOptions {
+            process: ProcessCapability::Full,
+            ..VmOptions::default()
+        };
+        let comparison = compare(source, options);
+        assert!(!comparison.diverged(), "{comparison}");
+        assert_eq!(comparison.bytecode.output, "3\nout\n\nerr\n\n");
+    }
+}
+
+// Random numbers repeat for the same seed, and both engines draw the same ones
+#[test]
+fn test_random_seed() {
+    let source = "print random(); print randomInt(1, 1000000);";
+    let run = |random_seed| {
+        let options = VmOptions {
+            random_seed,
+            ..VmOptions::default()
+        };
+        let comparison = compare(source, options);
+        assert!(!comparison.diverged(), "{comparison}");
+        comparison.bytecode.output
+    };
+
+    assert_eq!(run(Some(7)), run(Some(7)));
+    assert_ne!(run(Some(7)), run(Some(8)));
+    run(None);
+}
+
+// exit() stops the program with its code, without reporting an error
+#[test]
+fn test_exit() {
+    let mut output = Vec::new();
+    let mut vm = VM::new(Box::new(&mut output));
+
+    let outcome = try_interpret("print 1; fun f() { exit(3); } f(); print 2;", &mut vm);
+    match outcome {
+        Err(outcome @ InterpretOutcome::Exit(3)) => {
+            assert!(outcome.errors().is_empty());
+            assert_eq!(outcome.exit_code(), 3);
+        }
+        other => panic!("expected exit, got {other:?}"),
+    }
+
+    let outcome = try_interpret("exit(1.5);", &mut vm);
+    assert!(matches!(outcome, Err(InterpretOutcome::RuntimeError(_))));
+    assert!(try_interpret("print 4;", &mut vm).is_ok());
+
+    drop(vm);
+    assert_eq!(String::from_utf8_lossy(&output), "1\n4\n");
+}
+
+// Traces go to the error writer, and only when asked for
+#[test]
+fn test_trace() {
+    let source = "fun f(x) { return x + 1; } print f(2);";
+    let mut errors = Vec::new();
+    let mut vm = VM::new(Box::new(io::sink()));
+    vm.set_error_writer(Box::new(&mut errors));
+
+    try_interpret(source, &mut vm).unwrap();
+    vm.set_trace(TraceFlags {
+        calls: true,
+        ..TraceFlags::default()
+    });
+    try_interpret(source, &mut vm).unwrap();
+    drop(vm);
+
+    let errors = String::from_utf8_lossy(&errors);
+    let lines: Vec<_> = errors.lines().collect();
+    assert_eq!(lines.len(), 3, "{errors}");
+    assert!(lines[0].starts_with("CALL") && lines[0].contains("f"));
+    assert!(lines[1].starts_with("RETURN") && lines[1].ends_with("f -> 3"));
+    assert!(lines[2].starts_with("RETURN"));
+}
+
+// Globals are listed sorted by name, whatever order they were defined in
+#[test]
+fn test_globals_dump() {
+    let mut vm = VM::new(Box::new(io::sink()));
+    try_interpret("var zeta = 1; var alpha = \"a\";", &mut vm).unwrap();
+    let globals = vm.globals();
+    let names: Vec<_> = globals.iter().map(|&(name, _)| name).collect();
+    let mut sorted = names.clone();
+    sorted.sort();
+    assert_eq!(names, sorted);
+
+    let dump = vm.dump_globals();
+    let alpha = dump.find("alpha = a\n").unwrap();
+    let zeta = dump.find("zeta = 1\n").unwrap();
+    assert!(alpha < zeta, "{dump}");
+    assert!(dump.starts_with(&format!("GLOBALS ({} defined)\n", globals.len())));
+
+    let mut errors = Vec::new();
+    let mut vm = VM::new(Box::new(io::sink()));
+    vm.set_error_writer(Box::new(&mut errors));
+    vm.set_trace(TraceFlags {
+        globals: true,
+        ..TraceFlags::default()
+    });
+    try_interpret("var x = 1;", &mut vm).unwrap();
+    drop(vm);
+    let errors = String::from_utf8_lossy(&errors);
+    assert!(errors.starts_with("GLOBALS ("), "{errors}");
+}
+
+// Writes to a watched global pause the VM in its watch handler
+#[test]
+fn test_watch() {
+    let source = "var x = 1; var y = 1; x = 2; y = 2; x = \"three\"; print x;";
+    let mut hits = Vec::new();
+    let mut output = Vec::new();
+    let mut vm = VM::new(Box::new(&mut output));
+    vm.watch("x");
+    vm.set_watch_handler(Box::new(|hit: &WatchHit| {
+        hits.push(hit.clone());
+        WatchAction::Continue
+    }));
+    try_interpret(source, &mut vm).unwrap();
+
+    assert!(vm.unwatch("x"));
+    assert!(!vm.unwatch("x"));
+    vm.watch("y");
+    vm.set_watch_handler(Box::new(|_: &WatchHit| WatchAction::Stop));
+    vm.set_error_writer(Box::new(io::sink()));
+    let outcome = try_interpret("y = 3; print y;", &mut vm).unwrap_err();
+    drop(vm);
+
+    assert_eq!(hits.len(), 2);
+    assert_eq!(hits[0].to_string(), "[line 1]: 'x' changed from 1 to 2");
+    assert_eq!((hits[1].old.as_str(), hits[1].new.as_str()), ("2", "three"));
+    assert_eq!(
+        outcome.errors()[0].to_string(),
+        "[line 1]: Error: Stopped at watchpoint on 'y'."
+    );
+    assert_eq!(String::from_utf8_lossy(&output), "three\n");
+}
+
+// A script run a slice of instructions at a time pauses between slices, and
+// ends the same as one run in one go
+#[test]
+fn test_run_for() {
+    let source =
+        "var sum = 0;\nfor (var i = 0; i < 100; i = i + 1) sum = sum + i;\nprint sum;\nsum;";
+    let mut output = Vec::new();
+    let mut vm = VM::new(Box::new(&mut output));
+    let start = vm.instructions_run();
+    let main = compile_with_debug_symbols(source, &mut vm);
+    let frame = vm.load(main);
+    vm.run(frame).unwrap();
+    let total = vm.instructions_run() - start;
+
+    let main = compile_with_debug_symbols(source, &mut vm);
+    let frame = vm.load(main);
+    let mut state = vm.run_for(frame, 10).unwrap();
+    let mut slices = 1;
+    while state == RunState::Paused {
+        assert!(vm.is_paused());
+        state = vm.resume_for(10).unwrap();
+        slices += 1;
+    }
+    assert_eq!(state, RunState::Done(Some(Value::number(4950.0))));
+    assert_eq!(slices, total.div_ceil(10));
+    assert!(!vm.is_paused());
+    assert_eq!(vm.resume_for(10).unwrap(), RunState::Done(None));
+    drop(vm);
+    assert_eq!(String::from_utf8_lossy(&output), "4950\n4950\n");
+}
+
+// A pause shows the slots of the running frame, named where debug symbols say
+// which local each holds
+#[test]
+fn test_frame_locals() {
+    let source = "var seen; fun f(a) { var b = a + 1; seen = b; } f(1);";
+    let mut hits = Vec::new();
+    let mut vm = VM::new(Box::new(io::sink()));
+    let main = compile_with_debug_symbols(source, &mut vm);
+    vm.watch("seen");
+    vm.set_watch_handler(Box::new(|hit: &WatchHit| {
+        hits.push(hit.clone());
+        WatchAction::Continue
+    }));
+    runtime::execute(main, &mut vm).unwrap();
+    assert!(vm.frame_locals().is_empty());
+    drop(vm);
+
+    let named = |name: &str, value: &str| (Some(name.to_string()), value.to_string());
+    assert_eq!(hits.len(), 1);
+    assert_eq!(
+        hits[0].locals,
+        [
+            named("f", "<fn f>"),
+            named("a", "1"),
+            named("b", "2"),
+            (None, "2".to_string())
+        ]
+    );
+}
+
+// The wasm bindings collect output and errors in memory
+#[cfg(feature = "wasm")]
+#[test]
+fn test_wasm_run_source() {
+    use lox_bytecode_vm::wasm::run_source;
+
+    let result = run_source("print 1 + 2;");
+    assert_eq!(result.output, "3\n");
+    assert_eq!(result.errors, "");
+    assert_eq!(result.exit_code, 0);
+
+    let result = run_source("print \"before\"; print nope;");
+    assert_eq!(result.output, "before\n");
+    assert_eq!(result.errors, "[line 1]: Error: 'nope' is not defined.\n");
+    assert_eq!(result.exit_code, 70);
+
+    assert_eq!(run_source("exit(3);").exit_code, 3);
+}
+
+// Natives registered through the C API are called with their user data
+#[cfg(feature = "ffi")]
+#[test]
+fn test_ffi() {
+    use lox_bytecode_vm::ffi::*;
+    use std::ffi::{c_void, CString};
+
+    unsafe extern "C" fn scale(
+        user_data: *mut c_void,
+        args: *const LoxValue,
+        argc: u8,
+        result: *mut LoxValue,
+    ) -> bool {
+        let factor = unsafe { *(user_data as *const f64) };
+        let arg = unsafe { *args };
+        if argc != 1 || arg.tag != LoxValueTag::Number {
+            return false;
+        }
+        unsafe {
+            *result = LoxValue {
+                tag: LoxValueTag::Number,
+                number: arg.number * factor,
+            }
+        };
+        true
+    }
+
+    let mut factor = 3.0f64;
+    let source = |s: &str| CString::new(s).unwrap();
+    unsafe {
+        let vm = lox_vm_new();
+        let name = source("scale");
+        let user_data = &mut factor as *mut f64 as *mut c_void;
+        assert_eq!(
+            lox_vm_register_native(vm, name.as_ptr(), 1, scale, user_data),
+            LoxStatus::Ok
+        );
+
+        let run = |s: &str| lox_vm_interpret(vm, source(s).as_ptr());
+        assert_eq!(run("var x = scale(2); if (x != 6) exit(1);"), LoxStatus::Ok);
+        assert_eq!(run("exit(x);"), LoxStatus::Exit);
+        assert_eq!(run("scale(nil);"), LoxStatus::RuntimeError);
+        assert_eq!(run("scale(\"a\");"), LoxStatus::RuntimeError);
+        assert_eq!(run("var;"), LoxStatus::CompileError);
+        assert_eq!(
+            lox_vm_interpret(vm, std::ptr::null()),
+            LoxStatus::InvalidArgument
+        );
+        lox_vm_free(vm);
+    }
+}
+
+// Incremental compilation runs a script in batches, each before the next is parsed
+#[test]
+fn test_incremental() {
+    let source = "fun twice(n) { return 2 * n; }\nvar a = twice(1);\nprint a;\nprint twice(a);";
+    let parser = frontend::Parser::new(source, frontend::Scanner::new(source));
+    let mut vm = VM::new(Box::new(io::sink()));
+    let mut batches = bytecode::compile_incremental(source, parser).with_batch_size(3);
+    let first = batches
+        .next_function(vm.symbols_mut(), &mut vec![])
+        .unwrap();
+    let second = batches
+        .next_function(vm.symbols_mut(), &mut vec![])
+        .unwrap();
+    assert!(batches
+        .next_function(vm.symbols_mut(), &mut vec![])
+        .is_none());
+    assert_eq!(first.unwrap().chunk.line_info(0).unwrap().line, 1);
+    assert_eq!(second.unwrap().chunk.line_info(0).unwrap().line, 4);
+
+    let mut output = Vec::new();
+    let mut vm = VM::new(Box::new(&mut output));
+    try_interpret_incremental(source, &mut vm).unwrap();
+    drop(vm);
+    assert_eq!(String::from_utf8_lossy(&output), "2\n4\n");
+
+    // Batches before a compile error have already run
+    let source = format!("{}print 1 +;", "print 1;\n".repeat(100));
+    let mut output = Vec::new();
+    let mut vm = VM::new(Box::new(&mut output));
+    let outcome = try_interpret_incremental(&source, &mut vm).unwrap_err();
+    drop(vm);
+    assert!(matches!(outcome, InterpretOutcome::CompileError(_)));
+    assert_eq!(String::from_utf8_lossy(&output), "1\n".repeat(64));
+}
+
+// A script returns the value of its final expression statement, if it ends with one
+#[test]
+fn test_script_value() {
+    let mut vm = VM::new(Box::new(io::sink()));
+    let value = try_interpret("var a = 1;\na + 2;", &mut vm).unwrap();
+    assert_eq!(value, Some(Value::number(3.0)));
+    let value = try_interpret("\"lo\" + \"x\";", &mut vm).unwrap().unwrap();
+    assert_eq!(vm.heap().format(&value), "lox");
+    assert_eq!(try_interpret("nil;", &mut vm).unwrap(), Some(Value::NIL));
+
+    for source in ["", "var b = 1;", "1; print 2;", "{ 3; }", "fun f() { 4; }"] {
+        assert_eq!(try_interpret(source, &mut vm).unwrap(), None, "{source}");
+    }
+
+    let source = format!("{}5;", "print 1;\n".repeat(100));
+    let value = try_interpret_incremental(&source, &mut vm).unwrap();
+    assert_eq!(value, Some(Value::number(5.0)));
+
+    let mut session = Session::new();
+    assert_eq!(
+        session.run("a = 6;", &mut vm).unwrap(),
+        Some(Value::number(6.0))
+    );
+}
+
+// Pinned values stay in the heap's roots until they are unpinned
+#[test]
+fn test_pin() {
+    let mut heap = Heap::new();
+    let kept = Value::from(heap.push_str("kept".to_string()));
+    let first = heap.pin(kept);
+    let second = heap.pin(Value::number(2.0));
+
+    assert_eq!(heap.pinned(&first), kept);
+    assert_eq!(heap.pinned_values().count(), 2);
+    assert_eq!(heap.unpin(second), Value::number(2.0));
+    assert_eq!(heap.pinned_values().collect::<Vec<_>>(), vec![kept]);
+    assert_eq!(heap.unpin(first), kept);
+    assert_eq!(heap.pinned_values().count(), 0);
+}
+
+// Collections are logged and counted, and pinned values survive them
+#[test]
+fn test_gc_log() {
+    let mut errors = Vec::new();
+    let options = VmOptions {
+        gc_log: true,
+        ..VmOptions::default()
+    };
+    let mut vm = VM::with_options(Box::new(io::sink()), options);
+    vm.set_error_writer(Box::new(&mut errors));
+
+    try_interpret("var s = \"a\" + \"b\"; gc(); print s;", &mut vm).unwrap();
+    assert_eq!(vm.gc_stats().collections, 1);
+
+    let kept = vm.heap_mut().push_str("kept".to_string());
+    let handle = vm.heap_mut().pin(kept.into());
+    let dropped = vm.heap_mut().push_str("dropped".to_string());
+    assert!(vm.collect_garbage() > 0);
+    assert!(matches!(vm.heap().get(kept), Some(Object::String(s)) if &**s == "kept"));
+    assert!(vm.heap().get(dropped).is_none());
+    let _ = vm.heap_mut().unpin(handle);
+
+    let stats = vm.gc_stats();
+    assert_eq!(stats.collections, 2);
+    assert!(stats.max_pause <= stats.total_pause);
+    drop(vm);
+
+    let errors = String::from_utf8_lossy(&errors);
+    let lines: Vec<_> = errors.lines().collect();
+    assert_eq!(lines.len(), 2, "{errors}");
+    assert!(lines[0].starts_with("GC        requested: "), "{errors}");
+    assert!(lines[1].starts_with("GC        host: "), "{errors}");
+    assert!(lines[1].contains(" objects, "), "{errors}");
+}
+
+// Garbage is swept a little at each allocation, and strings waiting to be swept
+// can be handed out again
+#[test]
+fn test_lazy_sweep() {
+    let source = "
+        var s;
+        for (var i = 0; i < 500; i = i + 1) s = str(i);
+        gc();
+        for (var i = 0; i < 500; i = i + 1) s = str(i);
+        print s;
+    ";
+    let mut output = Vec::new();
+    let mut vm = VM::new(Box::new(&mut output));
+    try_interpret(source, &mut vm).unwrap();
+
+    let heap = vm.heap_mut();
+    assert_eq!(heap.len(), heap.entries().len());
+    vm.collect_garbage();
+    let heap = vm.heap_mut();
+    assert_eq!(heap.len(), heap.entries().len());
+    assert!(heap.len() < 100, "{}", heap.len());
+    drop(vm);
+    assert_eq!(String::from_utf8_lossy(&output), "499\n");
+}
+
+// Closures refer to their function and upvalues by heap index, so one that
+// captures a variable holding itself is freed like any other garbage
+#[test]
+fn test_closure_cycle() {
+    let source = "
+        fun cycle() {
+          var self;
+          self = fun () { return self; };
+        }
+        for (var i = 0; i < 100; i = i + 1) cycle();
+    ";
+    let mut vm = VM::new(Box::new(io::sink()));
+    try_interpret("", &mut vm).unwrap();
+    vm.collect_garbage();
+    let before = vm.heap_mut().len();
+
+    try_interpret(source, &mut vm).unwrap();
+    vm.collect_garbage();
+    // Besides the script itself, only `cycle` and its functions are left
+    assert!(vm.heap_mut().len() <= before + 4);
+    let closures = vm
+        .heap_mut()
+        .entries()
+        .into_iter()
+        .filter(|e| e.kind == "closure" && e.preview.contains("anonymous"))
+        .count();
+    assert_eq!(closures, 0);
+}
+
+// The heap dump lists objects by the index Value's Debug output shows
+#[test]
+fn test_heap_dump() {
+    let mut vm = VM::new(Box::new(io::sink()));
+    vm.set_error_writer(Box::new(io::sink()));
+    assert!(try_interpret("heapDump();", &mut vm).is_err());
+
+    let mut output = Vec::new();
+    let options = VmOptions {
+        debug_natives: true,
+        ..VmOptions::default()
+    };
+    let mut vm = VM::with_options(Box::new(&mut output), options);
+    let greeting = vm.heap_mut().push_str("a greeting".to_string());
+    try_interpret("print heapDump();", &mut vm).unwrap();
+
+    let entries = vm.heap_mut().entries();
+    let entry = entries
+        .iter()
+        .find(|entry| entry.index == greeting.index())
+        .unwrap();
+    assert_eq!(entry.kind, "string");
+    assert_eq!(entry.preview, "a greeting");
+    assert!(entry.ref_count.is_some());
+
+    drop(vm);
+    let output = String::from_utf8_lossy(&output);
+    assert!(output.starts_with("HEAP ("));
+    assert!(output.contains(&entry.to_string()));
+}
+
+// Natives describe themselves, and are listed by name
+#[test]
+fn test_native_listing() {
+    let vm = VM::new(Box::new(io::sink()));
+    let natives = vm.natives();
+
+    let names: Vec<_> = natives.iter().map(|native| native.name).collect();
+    let mut sorted = names.clone();
+    sorted.sort();
+    assert_eq!(names, sorted);
+
+    let sqrt = natives.iter().find(|native| native.name == "sqrt").unwrap();
+    assert_eq!(sqrt.arity, 1);
+    assert!(natives.iter().all(|native| !native.doc.is_empty()));
+}
+
+// A REPL session saves the lines that ran without errors, and loading them repeats it
+#[test]
+fn test_repl_session() {
+    let mut vm = VM::new(Box::new(io::sink()));
+    vm.set_error_writer(Box::new(io::sink()));
+    let mut session = Session::new();
+    session.run("var a = 1;", &mut vm).unwrap();
+    assert!(session.run("var b = ;", &mut vm).is_err());
+    session.run("var b = a + 1;", &mut vm).unwrap();
+    session.run("var c = 3;", &mut vm).unwrap();
+    assert!(session.undo(&mut vm));
+    assert_eq!(session.transcript(), ["var a = 1;", "var b = a + 1;"]);
+
+    let path = std::env::temp_dir().join(format!("lox_session_{}.lox", std::process::id()));
+    session.save(&path).unwrap();
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "var a = 1;\nvar b = a + 1;\n"
+    );
+
+    let mut output = Vec::new();
+    let mut vm = VM::new(Box::new(&mut output));
+    let mut session = Session::new();
+    session.load(&path, &mut vm).unwrap().unwrap();
+    session.run("print b; print c;", &mut vm).unwrap_err();
+    assert_eq!(session.transcript().len(), 1);
+    fs::remove_file(&path).unwrap();
+    assert!(session.load(&path, &mut vm).is_err());
+    drop(vm);
+    assert_eq!(String::from_utf8_lossy(&output), "2\n");
+}
+
+// Timing a run counts the instructions it ran and the objects it allocated
+#[test]
+fn test_repl_time() {
+    let mut vm = VM::new(Box::new(io::sink()));
+    let mut session = Session::new();
+    let (result, short) = session.time("print 1;", &mut vm);
+    result.unwrap();
+    let (result, long) = session.time(
+        "for (var i = 0; i < 100; i = i + 1) print str(i) + \"!\";",
+        &mut vm,
+    );
+    result.unwrap();
+
+    assert!(short.instructions > 0 && short.instructions < long.instructions);
+    assert!(long.allocations >= 200, "{long}");
+    assert!(long.seconds >= 0.0);
+    assert_eq!(session.transcript().len(), 2);
+}
+
+// Highlighting classifies each token, skipping comments and what cannot be scanned
+#[test]
+fn test_highlight() {
+    use frontend::TokenClass::*;
+
+    let source = "var x = 1 + \"a\"; // done\nprint nil; @ x";
+    let classes: Vec<_> = frontend::highlight(source)
+        .into_iter()
+        .map(|(span, class)| (&source[span.start as usize..span.end as usize], class))
+        .collect();
+    assert_eq!(
+        classes,
+        [
+            ("var", Keyword),
+            ("x", Identifier),
+            ("=", Operator),
+            ("1", Number),
+            ("+", Operator),
+            ("\"a\"", String),
+            (";", Punctuation),
+            ("print", Keyword),
+            ("nil", Literal),
+            (";", Punctuation),
+            ("x", Identifier),
+        ]
+    );
+}
+
+// The REPL completes the names of globals and the keywords
+#[test]
+fn test_completion_names() {
+    let options = VmOptions {
+        load_stdlib: true,
+        ..VmOptions::default()
+    };
+    let mut vm = VM::with_options(Box::new(io::sink()), options);
+    try_interpret("var answer = 42;", &mut vm).unwrap();
+    let names = vm.global_names();
+    for name in ["answer", "sqrt", "PI", "abs"] {
+        assert!(names.iter().any(|n| n == name), "{name} is missing");
+    }
+
+    for &keyword in frontend::KEYWORDS {
+        let tokens = frontend::scan(keyword).unwrap();
+        assert_ne!(
+            tokens[0].token,
+            frontend::TokenType::Identifier,
+            "{keyword}"
+        );
+    }
+}
+
+// Every short instruction's long form reads the same kind of operand, only wider
+#[test]
+fn test_opcode_table() {
+    for byte in 0..OpCode::COUNT as u8 {
+        let op = OpCode::try_from(byte).unwrap();
+        let info = op.info();
+        assert_eq!(info.op, op);
+
+        if let Some(long) = info.long {
+            assert_eq!(long.info().operand, info.operand, "{op:?}");
+            assert_eq!(long.info().stack_effect, info.stack_effect, "{op:?}");
+            assert!(long.operand_width() > info.operand_width, "{op:?}");
+            assert_eq!(long.to_long(), long);
+        }
+    }
+    assert_eq!(OpCode::SetGlobal.to_long(), OpCode::SetGlobalLong);
+    assert_eq!(OpCode::GetUpvalue.to_long(), OpCode::GetUpvalue);
+}
+
+// Functions know the most stack they need, and calls that would take the stack
+// past its limit overflow before running
+#[test]
+fn test_max_stack() {
+    let source = "fun f(a, b) { var c = a + b; return c * 2; }";
+    let mut vm = VM::new(Box::new(io::sink()));
+    let main = compile_with_debug_symbols(source, &mut vm);
+    // [ f ] [ a ] [ b ] [ c ] [ c ] [ 2 ]
+    assert_eq!(nested_function(&main).max_stack, 6);
+    assert_eq!(main.max_stack, 2);
+
+    let locals: String = (0..600).map(|i| format!("var a{i} = {i};")).collect();
+    let source = format!("fun big() {{ {locals} print a599; }} big();");
+    let options = VmOptions {
+        max_stack: 512,
+        ..VmOptions::default()
+    };
+    let mut output = Vec::new();
+    let mut vm = VM::with_options(Box::new(&mut output), options);
+    let outcome = try_interpret(&source, &mut vm).unwrap_err();
+    drop(vm);
+    let error = outcome.errors()[0].to_string();
+    assert!(error.contains("Stack overflow."), "{error}");
+    assert!(output.is_empty());
+}
+
+// Bytecode the compiler would never emit is reported as an error rather than
+// crashing the VM
+#[test]
+fn test_malformed_bytecode() {
+    let programs: [&[u8]; 5] = [
+        &[OpCode::LoadConstant as u8],
+        &[OpCode::LoadConstant as u8, 5],
+        &[OpCode::GetUpvalue as u8, 0],
+        &[OpCode::Closure as u8, 200],
+        &[OpCode::Nil as u8, OpCode::SetLocal as u8, 50],
+    ];
+
+    for code in programs {
+        let mut function = Function::new("main".to_string(), 0);
+        for &byte in code {
+            function.chunk.write_byte(byte, 1);
+        }
+
+        let mut vm = VM::new(Box::new(io::sink()));
+        let error = runtime::execute(function, &mut vm).unwrap_err();
+        assert!(
+            error.to_string().contains("Malformed bytecode"),
+            "{code:?}: {error}"
+        );
+    }
+}
+
+// Enough globals that their symbol ids no longer fit in 1 byte operands
+#[test]
+fn test_many_globals() {
+    let definitions: String = (0..300).map(|i| format!("var g{i} = {i};\n")).collect();
+    let source = format!("{definitions}print g0 + g299;");
+
+    for kind in [EngineKind::Bytecode, EngineKind::TreeWalk] {
+        let actual = capture_output_from_interpret(&source, kind, VmOptions::default()).unwrap();
+        assert_eq!(actual.trim(), "299");
+    }
+}
+
+// Bodies too large for 2 byte jump operands, as machine-generated code may produce
+#[test]
+fn test_long_jump() {
+    let skipped = "x = x + 1;\n".repeat(10000);
+    // `skip` is only known to be false at runtime, so the skipped code is kept
+    let source = format!(
+        "var skip = false;
+        {{
+            var x = 0;
+            if (x == 1) {{ {skipped} }} else {{ print \"else\"; }}
+            var n = 0;
+            while (n < 2) {{ n = n + 1; if (skip) {{ {skipped} }} }}
+            print n;
+            for (var c in \"ab\") {{ if (skip) {{ {skipped} }} print c; }}
+            print x;
+        }}"
+    );
+
+    let actual =
+        capture_output_from_interpret(&source, EngineKind::Bytecode, VmOptions::default()).unwrap();
+    assert_eq!(actual.trim(), "else\n2\na\nb\n0");
+
+    // The code is walked directly, as disassembling this much of it is slow
+    let mut vm = VM::new(Box::new(io::sink()));
+    let code = compile_with_debug_symbols(&source, &mut vm).chunk.code;
+    let mut ops = vec![];
+    let mut offset = 0;
+    while offset < code.len() {
+        let op = OpCode::try_from(code[offset]).unwrap();
+        ops.push(op);
+        offset += 1 + op.operand_width() as usize;
+    }
+    for long in [
+        OpCode::JumpIfFalseLong,
+        OpCode::LoopLong,
+        OpCode::IterNextLong,
+    ] {
+        assert!(ops.contains(&long), "{long:?} is missing");
+    }
+}
+
+// Ending a scope closes each captured local by its slot
+#[test]
+fn test_close_upvalue() {
+    let source = "{ var a = 1; var b = 2; var c = 3; fun f() { return a + c; } print f() + b; }";
+    let mut vm = VM::new(Box::new(io::sink()));
+    let main = compile_with_debug_symbols(source, &mut vm);
+    let disassembly = main.chunk.disassembly("main", &vm);
+    // `f` is hoisted into slot 1, ahead of `a`, `b`, and `c`
+    let ends: Vec<_> = disassembly
+        .lines()
+        .skip_while(|line| !line.contains("Print"))
+        .skip(1)
+        .map(|line| {
+            line.split_whitespace()
+                .skip(2)
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect();
+    assert_eq!(
+        ends,
+        ["CloseUpvalue 4", "Pop", "CloseUpvalue 2", "Pop", "Return"]
+    );
+
+    let actual =
+        capture_output_from_interpret(source, EngineKind::Bytecode, VmOptions::default()).unwrap();
+    assert_eq!(actual.trim(), "6");
+}
+
 // Function to capture stdout and stderr during interpret execution
-fn capture_output_from_interpret(source: &str) -> io::Result<String> {
+fn capture_output_from_interpret(
+    source: &str,
+    kind: EngineKind,
+    options: VmOptions,
+) -> io::Result<String> {
     // Create buffers to capture stdout and stderr
     let mut stdout_buffer = Vec::new();
     let mut stderr_buffer = Vec::new();
 
-    // Create a VM instance
-    let mut vm = VM::new(Box::new(&mut stdout_buffer));
+    // Create an engine instance
+    let mut engine = Engine::with_options(kind, Box::new(&mut stdout_buffer), options);
+    engine.set_error_writer(Box::new(&mut stderr_buffer));
     // Run interpret (which will print to our redirected stdout/stderr)
 
-    interpret(source, &mut vm, &mut stderr_buffer);
+    engine.interpret(source);
 
-    drop(vm);
+    drop(engine);
 
     // Get the captured output
     let stdout_output = String::from_utf8_lossy(&stdout_buffer);
@@ -220,13 +1851,22 @@ fn get_expected_output(test_path: &Path) -> io::Result<String> {
 
 // Helper function to run a test suite
 fn run_test_suite(suite_name: &str) {
+    run_test_suite_with_options(suite_name, VmOptions::default());
+}
+
+fn run_test_suite_with_options(suite_name: &str, options: VmOptions) {
+    run_test_suite_on(suite_name, EngineKind::Bytecode, options);
+}
+
+// Function to list the scripts of a test suite
+fn suite_files(suite_name: &str) -> Vec<PathBuf> {
     let suite_path = PathBuf::from("tests/lox").join(suite_name);
 
     // Get and sort test files
     let test_files = fs::read_dir(&suite_path)
         .unwrap_or_else(|_| panic!("Failed to read test suite directory: {}", suite_name))
         .filter_map(Result::ok)
-        .filter(|entry| entry.path().extension().map_or(false, |ext| ext == "lox"))
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "lox"))
         .map(|entry| entry.path())
         .collect::<Vec<_>>();
 
@@ -235,6 +1875,16 @@ fn run_test_suite(suite_name: &str) {
         "No test files found in suite: {}",
         suite_name
     );
+    test_files
+}
+
+fn run_test_suite_on(suite_name: &str, kind: EngineKind, options: VmOptions) {
+    let test_files = suite_files(suite_name);
+    // Suites are run like the CLI runs scripts, with the standard library
+    let options = VmOptions {
+        load_stdlib: true,
+        ..options
+    };
 
     let expected = test_files.len();
     let mut passed = 0;
@@ -261,7 +1911,7 @@ fn run_test_suite(suite_name: &str) {
         });
 
         // Run test and capture output
-        let actual = capture_output_from_interpret(&source)
+        let actual = capture_output_from_interpret(&source, kind, options.clone())
             .unwrap_or_else(|e| panic!("Error capturing output: {}", e))
             .trim()
             .to_string();
@@ -285,3 +1935,145 @@ fn run_test_suite(suite_name: &str) {
         failed
     )
 }
+
+// An async run awaits the futures of async natives and yields between
+// batches, letting other tasks on the thread make progress
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_run_async() {
+    use lox_bytecode_vm::{AsyncNative, NativeFuture, RuntimeError};
+    use std::cell::Cell;
+    use std::time::Duration;
+
+    struct Delay;
+    impl AsyncNative for Delay {
+        fn name(&self) -> &str {
+            "delay"
+        }
+
+        fn arity(&self) -> u8 {
+            1
+        }
+
+        fn call(&self, _heap: &mut Heap, args: Vec<Value>) -> NativeFuture {
+            let ms = args[0].as_number();
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(ms as u64)).await;
+                Ok::<_, RuntimeError>(Value::number(ms * 2.0))
+            })
+        }
+    }
+
+    let source = "var sum = 0;\nfor (var i = 0; i < 100; i = i + 1) sum = sum + i;\nsum;";
+    let mut output = Vec::new();
+    let mut vm = VM::new(Box::new(&mut output));
+    vm.define_async_native(Delay);
+    let main = compile_with_debug_symbols(source, &mut vm);
+    let frame = vm.load(main);
+
+    let vm_done = Cell::new(false);
+    let (result, ticked_first) = tokio::join!(
+        async {
+            let result = vm.run_async(frame, 10).await;
+            vm_done.set(true);
+            result
+        },
+        async {
+            for _ in 0..10 {
+                tokio::task::yield_now().await;
+            }
+            !vm_done.get()
+        }
+    );
+    assert_eq!(result.unwrap(), Some(Value::number(4950.0)));
+    assert!(ticked_first);
+
+    let main = compile_with_debug_symbols("print delay(5);", &mut vm);
+    let frame = vm.load(main);
+    assert_eq!(vm.run_async(frame, 10).await.unwrap(), None);
+
+    // Anything but an async run cannot wait on the future
+    let main = compile_with_debug_symbols("delay(1);", &mut vm);
+    let frame = vm.load(main);
+    assert_eq!(
+        vm.run(frame).unwrap_err().to_string(),
+        "[line 1]: Error: Native function 'delay' can only be called by an async run."
+    );
+    drop(vm);
+    assert_eq!(String::from_utf8_lossy(&output), "10\n");
+
+    // The run is a `Send` future, so it can be spawned as a task of its own
+    let mut vm = VM::new(Box::new(std::io::sink()));
+    vm.define_async_native(Delay);
+    let main = compile_with_debug_symbols("delay(1) + 1;", &mut vm);
+    let task = tokio::spawn(async move {
+        let frame = vm.load(main);
+        vm.run_async(frame, 10).await.ok().flatten()
+    });
+    assert_eq!(task.await.unwrap(), Some(Value::number(3.0)));
+}
+
+// Spawned functions run in VMs of their own, getting copies of the values they
+// capture and passing plain values back through channels
+#[cfg(feature = "threads")]
+#[test]
+fn test_threads() {
+    let source = r#"
+{
+  var jobs = channel();
+  var results = channel();
+  var scale = 10;
+  fun worker() {
+    var total = 0;
+    var n = recv(jobs);
+    while (n != nil) {
+      total = total + n * scale;
+      n = recv(jobs);
+    }
+    send(results, "done");
+    return total;
+  }
+  var thread = spawn(worker);
+  for (var i = 1; i <= 4; i = i + 1) send(jobs, i);
+  send(jobs, nil);
+  print recv(results);
+  print join(thread);
+}
+"#;
+    let output =
+        capture_output_from_interpret(source, EngineKind::Bytecode, VmOptions::default()).unwrap();
+    assert_eq!(output, "done\n100\n");
+
+    // Only plain values cross between VMs
+    let output = capture_output_from_interpret(
+        "fun f() {} send(channel(), f);",
+        EngineKind::Bytecode,
+        VmOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        output,
+        "[line 0]: Error: '<fn f>' cannot be sent to another thread.\n"
+    );
+
+    // Globals belong to the VM that defined them
+    let output = capture_output_from_interpret(
+        "var x = 1;\nfun f() { return x; }\nprint join(spawn(f));",
+        EngineKind::Bytecode,
+        VmOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        output,
+        "[line 0]: Error: Thread failed: [line 2]: Error: 'x' is not defined.\n"
+    );
+
+    // A failed thread stops a recv waiting for what it would have sent
+    let source = "var x = 1;\n{\n  var ch = channel();\n  fun f() { send(ch, x); }\n  spawn(f);\n  print recv(ch);\n}";
+    let output =
+        capture_output_from_interpret(source, EngineKind::Bytecode, VmOptions::default()).unwrap();
+    assert_eq!(
+        output,
+        "[line 0]: Error: Thread failed: [line 4]: Error: 'x' is not defined.\n"
+    );
+}